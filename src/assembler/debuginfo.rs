@@ -0,0 +1,400 @@
+//! Debug info: the source file and line every word of a program comes from, through includes, and
+//! the scopes of its labels, so tools can show the source of the instruction at the PC.
+//!
+//! Scopes are the subroutines of the program, found from its labels: a label starts one when it is
+//! called with JSR, when it is the first label of a run of words from one file (like the entry point
+//! of the program), or when it comes after a RET and nothing branches to it or uses it as data (like
+//! a subroutine of an included file that the program does not call). The scope goes up to the next
+//! one, or to the end of the words of its file, so the labels inside it (loops, data) belong to it.
+//!
+//! `assemble --debug-info` writes it next to the object file, as text:
+//!
+//! ```text
+//! lc3-debug 1
+//! file 0 main.asm
+//! file 1 std/string.asm
+//! line x3000 1 0 2
+//! scope MAIN x3000 x3003 0
+//! ```
+//!
+//! `line` has the address and the number of words of a source line, the file, by its number, and
+//! the line in it. `scope` has the label, the first and last address of its scope and its file.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{
+    assembler::{Assembly, SourceLine},
+    instruction::Instruction,
+    stdlib,
+};
+
+const HEADER: &str = "lc3-debug 1";
+
+/// `RET`, which ends a subroutine.
+const RET: u16 = 0xC1C0;
+
+/// The words of a source line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineEntry {
+    pub address: u16,
+    pub size: u16,
+    pub source: SourceLine,
+}
+
+/// The addresses a subroutine label stands for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    pub name: String,
+    pub start: u16,
+    /// The last address of the scope, included.
+    pub end: u16,
+    pub file: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugInfo {
+    /// In address order, without overlaps, to look addresses up with a binary search.
+    pub lines: Vec<LineEntry>,
+    /// In address order, without overlaps.
+    pub scopes: Vec<Scope>,
+    /// The text of the source files, by path, once read with `read_sources`.
+    sources: BTreeMap<String, Vec<String>>,
+}
+
+impl DebugInfo {
+    /// The debug info of `assembly`, assembled from the source file at `path`.
+    pub fn new(assembly: &Assembly, path: &str) -> Self {
+        let lines: Vec<LineEntry> = assembly
+            .placements
+            .iter()
+            .filter(|placement| placement.size > 0)
+            .map(|placement| LineEntry {
+                address: placement.address,
+                size: placement.size,
+                source: placement.origin.clone().unwrap_or_else(|| SourceLine {
+                    file: path.to_string(),
+                    line: placement.line,
+                }),
+            })
+            .collect();
+        let word = |address: u16| assembly.words[address.wrapping_sub(assembly.origin) as usize];
+        // Labels called with JSR, and labels branched to or used as data, by the PC-relative
+        // instructions that name them.
+        let (mut called, mut used) = (Vec::new(), Vec::new());
+        for reference in &assembly.references {
+            let address = assembly.symbols[&reference.symbol].address;
+            let instruction = Instruction::decode(word(reference.address));
+            if instruction.target(reference.address) != Some(address) {
+                continue;
+            }
+            match instruction {
+                Instruction::Jsr { .. } => called.push(address),
+                _ => used.push(address),
+            }
+        }
+        let mut labels: BTreeMap<u16, &str> = BTreeMap::new();
+        for (name, symbol) in &assembly.symbols {
+            labels.entry(symbol.address).or_insert(name);
+        }
+
+        let mut scopes: Vec<Scope> = Vec::new();
+        for run in lines.chunk_by(|a, b| a.source.file == b.source.file) {
+            let (first, last) = (&run[0], &run[run.len() - 1]);
+            let end = last.address.wrapping_add(last.size - 1);
+            let mut starts: Vec<(u16, &str)> = Vec::new();
+            for (&address, &name) in labels.range(first.address..=end) {
+                let returned = starts.last().is_some_and(|&(start, _)| {
+                    (start..address).any(|word_at| word(word_at) == RET)
+                });
+                if starts.is_empty()
+                    || called.contains(&address)
+                    || (returned && !used.contains(&address))
+                {
+                    starts.push((address, name));
+                }
+            }
+            for (index, &(start, name)) in starts.iter().enumerate() {
+                let end = starts
+                    .get(index + 1)
+                    .map_or(end, |(next, _)| next.wrapping_sub(1));
+                scopes.push(Scope {
+                    name: name.to_string(),
+                    start,
+                    end,
+                    file: first.source.file.clone(),
+                });
+            }
+        }
+        Self {
+            lines,
+            scopes,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// The source line the word at `address` comes from.
+    pub fn line_at(&self, address: u16) -> Option<&SourceLine> {
+        let after = self.lines.partition_point(|entry| entry.address <= address);
+        let entry = &self.lines[after.checked_sub(1)?];
+        (address - entry.address < entry.size).then_some(&entry.source)
+    }
+
+    /// The scope `address` is in.
+    pub fn scope_at(&self, address: u16) -> Option<&Scope> {
+        let after = self.scopes.partition_point(|scope| scope.start <= address);
+        let scope = &self.scopes[after.checked_sub(1)?];
+        (address <= scope.end).then_some(scope)
+    }
+
+    /// Reads the source files the words come from, relative to the current directory, to show
+    /// their lines. The standard library is always there; other files that cannot be read are
+    /// left out.
+    pub fn read_sources(&mut self) {
+        for entry in &self.lines {
+            let file = &entry.source.file;
+            if self.sources.contains_key(file) {
+                continue;
+            }
+            if let Some(text) = source_text(file) {
+                let lines = text.lines().map(str::to_string).collect();
+                self.sources.insert(file.clone(), lines);
+            }
+        }
+    }
+
+    /// Where the word at `address` comes from, with the text of its line if the file was read and
+    /// the subroutine it is in: `main.asm:12 in PRINT: LOOP LDR R0, R1, #0`.
+    pub fn describe(&self, address: u16) -> Option<String> {
+        let source = self.line_at(address)?;
+        let mut text = format!("{}:{}", source.file, source.line);
+        if let Some(scope) = self.scope_at(address) {
+            text.push_str(&format!(" in {}", scope.name));
+        }
+        let line = self
+            .sources
+            .get(&source.file)
+            .and_then(|lines| lines.get(source.line.checked_sub(1)?));
+        if let Some(line) = line {
+            let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+            text.push_str(&format!(": {line}"));
+        }
+        Some(text)
+    }
+
+    /// The debug info file.
+    pub fn to_text(&self) -> String {
+        let mut files: Vec<&str> = Vec::new();
+        let mut index = |file| match files.iter().position(|known| *known == file) {
+            Some(index) => index,
+            None => {
+                files.push(file);
+                files.len() - 1
+            }
+        };
+        let mut body = String::new();
+        for entry in &self.lines {
+            let file = index(entry.source.file.as_str());
+            body.push_str(&format!(
+                "line x{:04X} {} {file} {}\n",
+                entry.address, entry.size, entry.source.line
+            ));
+        }
+        for scope in &self.scopes {
+            let file = index(scope.file.as_str());
+            body.push_str(&format!(
+                "scope {} x{:04X} x{:04X} {file}\n",
+                scope.name, scope.start, scope.end
+            ));
+        }
+        let mut text = format!("{HEADER}\n");
+        for (index, file) in files.iter().enumerate() {
+            text.push_str(&format!("file {index} {file}\n"));
+        }
+        text + &body
+    }
+
+    /// Reads a debug info file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(format!("not debug info: it does not start with `{HEADER}`"));
+        }
+        let mut info = DebugInfo::default();
+        let mut files: Vec<String> = Vec::new();
+        for (index, line) in lines {
+            let wrong = || format!("line {}: `{line}` is not well-formed", index + 1);
+            let address = |text: &str| {
+                text.strip_prefix('x')
+                    .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                    .ok_or_else(wrong)
+            };
+            let file = |text: &str| {
+                text.parse::<usize>()
+                    .ok()
+                    .and_then(|index| files.get(index).cloned())
+                    .ok_or_else(wrong)
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["file", number, _, ..] if number == files.len().to_string() => {
+                    let name = line.splitn(3, ' ').nth(2).ok_or_else(wrong)?;
+                    files.push(name.to_string());
+                }
+                ["line", start, size, source, number] => info.lines.push(LineEntry {
+                    address: address(start)?,
+                    size: size.parse().map_err(|_| wrong())?,
+                    source: SourceLine {
+                        file: file(source)?,
+                        line: number
+                            .parse()
+                            .ok()
+                            .filter(|&line| line > 0)
+                            .ok_or_else(wrong)?,
+                    },
+                }),
+                ["scope", name, start, end, source] => info.scopes.push(Scope {
+                    name: name.to_string(),
+                    start: address(start)?,
+                    end: address(end)?,
+                    file: file(source)?,
+                }),
+                [] => {}
+                _ => return Err(wrong()),
+            }
+        }
+        // Looking words up needs them in address order.
+        info.lines.sort_by_key(|entry| entry.address);
+        info.scopes.sort_by_key(|scope| scope.start);
+        Ok(info)
+    }
+
+    /// Reads the debug info file next to the object file `path` (`program.dbg` for `program.obj`),
+    /// if there is one.
+    pub fn load_for(path: &str) -> Result<Option<Self>, String> {
+        let path = Path::new(path).with_extension("dbg");
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text)
+                .map(Some)
+                .map_err(|e| format!("{}: {e}", path.display())),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// The text of the source file words come from, by its path as the assembler recorded it: a module
+/// of the standard library, or a file relative to the current directory.
+pub fn source_text(file: &str) -> Option<String> {
+    match stdlib::find(file) {
+        Ok(Some(module)) => Some(module.source.to_string()),
+        _ => fs::read_to_string(file).ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn maps_words_to_included_lines_and_scopes() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-debuginfo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.asm");
+        let source = "\
+        .ORIG x3000
+MAIN    LEA R0, TEXT
+        JSR PRINT
+        HALT
+TEXT    .STRINGZ \"hi\"
+PRINT   ST R7, SAVE
+        PUTS
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+        .INCLUDE \"std/string.asm\"
+        .END";
+        fs::write(&path, source).unwrap();
+        let assembly = assemble(source).unwrap();
+        let mut info = DebugInfo::new(&assembly, &path.to_string_lossy());
+        info.read_sources();
+        fs::remove_dir_all(&dir).unwrap();
+        let main = path.to_string_lossy();
+        assert_eq!(
+            info.describe(0x3001).unwrap(),
+            format!("{main}:3 in MAIN: JSR PRINT")
+        );
+        // SAVE is data of PRINT.
+        assert_eq!(
+            info.describe(0x300A).unwrap(),
+            format!("{main}:10 in PRINT: SAVE .BLKW 1")
+        );
+        let strlen = assembly.symbols["STD_STRLEN"].address;
+        assert_eq!(
+            info.describe(strlen).unwrap(),
+            "std/string.asm:10 in STD_STRLEN: STD_STRLEN ST R1, STD_STRLEN_R1"
+        );
+        // STD_STRCMP is not called, but comes after the RET of STD_STRLEN.
+        let strcmp = assembly.symbols["STD_STRCMP"].address;
+        assert_eq!(info.scope_at(strcmp - 1).unwrap().name, "STD_STRLEN");
+        assert_eq!(info.scope_at(strcmp).unwrap().name, "STD_STRCMP");
+        let parsed = DebugInfo::parse(&info.to_text()).unwrap();
+        assert_eq!(parsed.lines, info.lines);
+        assert_eq!(parsed.scopes, info.scopes);
+    }
+
+    const TEXT: &str = "\
+lc3-debug 1
+file 0 main.asm
+file 1 std/my string.asm
+line x3000 1 0 2
+line x3001 3 0 3
+line x3010 1 1 7
+scope MAIN x3000 x3003 0
+scope STD_PRINT x3010 x3010 1
+";
+
+    #[test]
+    fn parses_and_prints_back() {
+        let info = DebugInfo::parse(TEXT).unwrap();
+        assert_eq!(info.lines.len(), 3);
+        assert_eq!(info.lines[2].source.file, "std/my string.asm");
+        assert_eq!(info.to_text(), TEXT);
+        assert_eq!(DebugInfo::parse(&info.to_text()).unwrap(), info);
+    }
+
+    #[test]
+    fn looks_addresses_up() {
+        let info = DebugInfo::parse(TEXT).unwrap();
+        let line = |address| info.line_at(address).map(|source| source.line);
+        assert_eq!(line(0x2FFF), None);
+        assert_eq!(line(0x3000), Some(2));
+        assert_eq!(line(0x3003), Some(3));
+        assert_eq!(line(0x3004), None);
+        assert_eq!(line(0x3010), Some(7));
+        assert_eq!(line(0xFFFF), None);
+        let scope = |address| info.scope_at(address).map(|scope| scope.name.as_str());
+        assert_eq!(scope(0x2FFF), None);
+        assert_eq!(scope(0x3000), Some("MAIN"));
+        assert_eq!(scope(0x3003), Some("MAIN"));
+        assert_eq!(scope(0x3004), None);
+        assert_eq!(scope(0x3010), Some("STD_PRINT"));
+        assert_eq!(scope(0x3011), None);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(DebugInfo::parse("").is_err());
+        assert!(DebugInfo::parse("lc3-debug 2\n").is_err());
+        let with = |line: &str| DebugInfo::parse(&format!("{HEADER}\nfile 0 main.asm\n{line}\n"));
+        assert!(with("line x3000 1 0 1").is_ok());
+        // Line 0, a file that is not listed, addresses without `x` and missing fields.
+        assert_eq!(
+            with("line x3000 1 0 0").unwrap_err(),
+            "line 3: `line x3000 1 0 0` is not well-formed"
+        );
+        assert!(with("line x3000 1 1 1").is_err());
+        assert!(with("line 3000 1 0 1").is_err());
+        assert!(with("scope MAIN x3000 0").is_err());
+        assert!(with("file 2 other.asm").is_err());
+        assert!(with("label MAIN x3000").is_err());
+    }
+}
@@ -0,0 +1,499 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    assembler::{
+        Assembly, Diagnostic, Placement, Reference, Symbol,
+        include::{expand_includes, has_includes},
+        parser::{Line, Operand, OperandKind, Statement, branch_flags},
+    },
+    instruction::{self, Instruction},
+};
+
+/// Assembles parsed lines in two passes: the first one gives an address to every line and collects
+/// the labels, and the second one encodes every statement once all labels are known. Only the
+/// standard library can be included.
+pub fn assemble_lines(lines: &[Line]) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_lines_in(lines, None)
+}
+
+/// Like `assemble_lines`, including files relative to `dir` as well.
+pub fn assemble_lines_in(lines: &[Line], dir: Option<&Path>) -> Result<Assembly, Vec<Diagnostic>> {
+    let expanded;
+    let lines = if has_includes(lines) {
+        expanded = expand_includes(lines, dir)?;
+        &expanded
+    } else {
+        lines
+    };
+    let mut diagnostics = Vec::new();
+
+    // First pass: addresses and symbols.
+    let mut origin: Option<u16> = None;
+    let mut ended = false;
+    let mut address: u32 = 0;
+    let mut symbols: BTreeMap<String, Symbol> = BTreeMap::new();
+    // The lines that make it to the second pass, with the address of their first word.
+    let mut placed: Vec<(&Line, &Statement, u16)> = Vec::new();
+    for line in lines {
+        let statement = line.statement.as_ref();
+        let name = statement.map(Statement::name);
+        if name.as_deref() == Some(".ORIG") {
+            let statement = statement.expect("checked above");
+            if origin.is_some() {
+                diagnostics.push(Diagnostic::new(
+                    line.number,
+                    Some(statement.span),
+                    "only one .ORIG is allowed per file".to_string(),
+                ));
+                continue;
+            }
+            match expect_operands(line.number, statement, 1)
+                .and_then(|_| number(line.number, &statement.operands[0], 0, 0xFFFF))
+            {
+                Ok(value) => {
+                    origin = Some(value as u16);
+                    address = value as u32;
+                }
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    // Keep going from the usual origin to report as many errors as possible.
+                    origin = Some(0x3000);
+                    address = 0x3000;
+                }
+            }
+        } else if origin.is_none() && (line.label.is_some() || statement.is_some()) {
+            diagnostics.push(Diagnostic::new(
+                line.number,
+                None,
+                "expected .ORIG before any label or statement".to_string(),
+            ));
+            continue;
+        }
+
+        if let Some(label) = &line.label {
+            match symbols.get(&label.name) {
+                Some(previous) => diagnostics.push(Diagnostic::new(
+                    line.number,
+                    Some(label.span),
+                    format!(
+                        "label `{}` is already defined at line {}",
+                        label.name, previous.line
+                    ),
+                )),
+                None => {
+                    symbols.insert(
+                        label.name.clone(),
+                        Symbol {
+                            address: address as u16,
+                            line: line.number,
+                            origin: line.origin.clone(),
+                        },
+                    );
+                }
+            }
+        }
+
+        let Some(statement) = statement else {
+            continue;
+        };
+        let size = match name.as_deref() {
+            Some(".ORIG") => continue,
+            Some(".END") => {
+                ended = true;
+                break;
+            }
+            Some(".BLKW") => match expect_operands(line.number, statement, 1)
+                .and_then(|_| number(line.number, &statement.operands[0], 0, 0xFFFF))
+            {
+                Ok(count) => count as u32,
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    continue;
+                }
+            },
+            Some(".STRINGZ") => match statement.operands.first().map(|o| &o.kind) {
+                Some(OperandKind::String(text)) if statement.operands.len() == 1 => {
+                    text.chars().count() as u32 + 1
+                }
+                _ => {
+                    diagnostics.push(Diagnostic::new(
+                        line.number,
+                        Some(statement.span),
+                        ".STRINGZ expects a single string operand".to_string(),
+                    ));
+                    continue;
+                }
+            },
+            _ => 1,
+        };
+        if address + size > 0x10000 {
+            diagnostics.push(Diagnostic::new(
+                line.number,
+                Some(statement.span),
+                "the program does not fit in memory".to_string(),
+            ));
+            // Nothing after this is read, so a missing .END is not reported either.
+            ended = true;
+            break;
+        }
+        placed.push((line, statement, address as u16));
+        address += size;
+    }
+
+    let end = lines.last().map_or(1, |line| line.number);
+    if origin.is_none() && diagnostics.is_empty() {
+        diagnostics.push(Diagnostic::new(
+            end,
+            None,
+            "expected .ORIG at the start of the program".to_string(),
+        ));
+    } else if origin.is_some() && !ended {
+        diagnostics.push(Diagnostic::new(
+            end,
+            None,
+            "expected .END at the end of the program".to_string(),
+        ));
+    }
+
+    // Second pass: encoding.
+    let origin = origin.unwrap_or(0x3000);
+    let mut assembly = Assembly {
+        origin,
+        words: Vec::new(),
+        symbols,
+        references: Vec::new(),
+        placements: Vec::new(),
+    };
+    for (line, statement, address) in placed {
+        let mut encoder = Encoder {
+            line: line.number,
+            address,
+            symbols: &assembly.symbols,
+            references: Vec::new(),
+        };
+        match encoder.encode(statement) {
+            Ok(words) => {
+                assembly.references.append(&mut encoder.references);
+                assembly.placements.push(Placement {
+                    line: line.number,
+                    address,
+                    size: words.len() as u16,
+                    data: matches!(statement.name().as_str(), ".FILL" | ".BLKW" | ".STRINGZ"),
+                    origin: line.origin.clone(),
+                });
+                assembly.words.extend(words);
+            }
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(assembly)
+    } else {
+        diagnostics.sort_by_key(|d| d.line);
+        Err(diagnostics)
+    }
+}
+
+/// Encodes one statement on its own, placed at `address` and with the labels known so far. `line`
+/// is the line number its errors are reported on. `.ORIG`, `.END` and `.INCLUDE` only make sense
+/// in a whole file and are rejected.
+pub fn encode_statement(
+    line: usize,
+    statement: &Statement,
+    address: u16,
+    symbols: &BTreeMap<String, Symbol>,
+) -> Result<Vec<u16>, Diagnostic> {
+    let name = statement.name();
+    match name.as_str() {
+        ".ORIG" | ".END" | ".INCLUDE" => {
+            return Err(Diagnostic::new(
+                line,
+                Some(statement.span),
+                format!("{name} can only be used in a whole file"),
+            ));
+        }
+        ".BLKW" => expect_operands(line, statement, 1)?,
+        ".STRINGZ"
+            if !matches!(
+                statement.operands.as_slice(),
+                [Operand {
+                    kind: OperandKind::String(_),
+                    ..
+                }]
+            ) =>
+        {
+            return Err(Diagnostic::new(
+                line,
+                Some(statement.span),
+                ".STRINGZ expects a single string operand".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    let mut encoder = Encoder {
+        line,
+        address,
+        symbols,
+        references: Vec::new(),
+    };
+    encoder.encode(statement)
+}
+
+/// Encodes a single statement placed at `address`.
+struct Encoder<'a> {
+    line: usize,
+    address: u16,
+    symbols: &'a BTreeMap<String, Symbol>,
+    /// Labels used by the statement.
+    references: Vec<Reference>,
+}
+
+impl Encoder<'_> {
+    fn encode(&mut self, statement: &Statement) -> Result<Vec<u16>, Diagnostic> {
+        let name = statement.name();
+        let ops = &statement.operands;
+        let line = self.line;
+        let count = |n| expect_operands(line, statement, n);
+        let reg = |i: usize| register(line, &ops[i]);
+
+        let instruction = match name.as_str() {
+            "ADD" | "AND" => {
+                count(3)?;
+                let (dest, src) = (reg(0)?, reg(1)?);
+                let operand = match ops[2].kind {
+                    OperandKind::Register(reg) => instruction::Operand::Register(reg),
+                    _ => instruction::Operand::Immediate(self.immediate(&ops[2], 5)?),
+                };
+                match name.as_str() {
+                    "ADD" => Instruction::Add { dest, src, operand },
+                    _ => Instruction::And { dest, src, operand },
+                }
+            }
+            "NOT" => {
+                count(2)?;
+                Instruction::Not {
+                    dest: reg(0)?,
+                    src: reg(1)?,
+                }
+            }
+            "JMP" => {
+                count(1)?;
+                Instruction::Jmp { base: reg(0)? }
+            }
+            "RET" => {
+                count(0)?;
+                Instruction::Jmp { base: 7 }
+            }
+            "JSR" => {
+                count(1)?;
+                Instruction::Jsr {
+                    offset: self.pc_offset(&ops[0], 11)?,
+                }
+            }
+            "JSRR" => {
+                count(1)?;
+                Instruction::Jsrr { base: reg(0)? }
+            }
+            "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+                count(2)?;
+                let (register, offset) = (reg(0)?, self.pc_offset(&ops[1], 9)?);
+                match name.as_str() {
+                    "LD" => Instruction::Ld {
+                        dest: register,
+                        offset,
+                    },
+                    "LDI" => Instruction::Ldi {
+                        dest: register,
+                        offset,
+                    },
+                    "LEA" => Instruction::Lea {
+                        dest: register,
+                        offset,
+                    },
+                    "ST" => Instruction::St {
+                        src: register,
+                        offset,
+                    },
+                    _ => Instruction::Sti {
+                        src: register,
+                        offset,
+                    },
+                }
+            }
+            "LDR" | "STR" => {
+                count(3)?;
+                let (register, base) = (reg(0)?, reg(1)?);
+                let offset = self.immediate(&ops[2], 6)?;
+                match name.as_str() {
+                    "LDR" => Instruction::Ldr {
+                        dest: register,
+                        base,
+                        offset,
+                    },
+                    _ => Instruction::Str {
+                        src: register,
+                        base,
+                        offset,
+                    },
+                }
+            }
+            "TRAP" => {
+                count(1)?;
+                Instruction::Trap {
+                    vector: number(line, &ops[0], 0, 0xFF)? as u8,
+                }
+            }
+            "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP" | "HALT" => {
+                count(0)?;
+                let vector = match name.as_str() {
+                    "GETC" => 0x20,
+                    "OUT" => 0x21,
+                    "PUTS" => 0x22,
+                    "IN" => 0x23,
+                    "PUTSP" => 0x24,
+                    _ => 0x25,
+                };
+                Instruction::Trap { vector }
+            }
+            "RTI" => {
+                count(0)?;
+                Instruction::Rti(0x8000)
+            }
+            ".FILL" => {
+                count(1)?;
+                let word = match &ops[0].kind {
+                    OperandKind::Label(_) => self.label_address(&ops[0])?,
+                    _ => number(line, &ops[0], -0x8000, 0xFFFF)? as u16,
+                };
+                return Ok(vec![word]);
+            }
+            ".BLKW" => {
+                let size = number(line, &ops[0], 0, 0xFFFF)? as usize;
+                return Ok(vec![0; size]);
+            }
+            ".STRINGZ" => {
+                let OperandKind::String(text) = &ops[0].kind else {
+                    unreachable!("checked in the first pass");
+                };
+                let mut words: Vec<u16> = text.chars().map(|c| c as u16).collect();
+                words.push(0);
+                return Ok(words);
+            }
+            _ => match branch_flags(&name) {
+                Some(conditions) => {
+                    count(1)?;
+                    Instruction::Br {
+                        conditions,
+                        offset: self.pc_offset(&ops[0], 9)?,
+                    }
+                }
+                None => {
+                    return Err(Diagnostic::new(
+                        line,
+                        Some(statement.span),
+                        format!("`{}` cannot be used here", statement.mnemonic),
+                    ));
+                }
+            },
+        };
+        Ok(vec![instruction.encode()])
+    }
+
+    /// The address of the label in `operand`, recording the reference.
+    fn label_address(&mut self, operand: &Operand) -> Result<u16, Diagnostic> {
+        let OperandKind::Label(name) = &operand.kind else {
+            return Err(Diagnostic::new(
+                self.line,
+                Some(operand.span),
+                format!("expected a label, found `{}`", operand.text),
+            ));
+        };
+        let symbol = self.symbols.get(name).ok_or_else(|| {
+            Diagnostic::new(
+                self.line,
+                Some(operand.span),
+                format!("undefined label `{name}`"),
+            )
+        })?;
+        self.references.push(Reference {
+            symbol: name.clone(),
+            line: self.line,
+            address: self.address,
+        });
+        Ok(symbol.address)
+    }
+
+    /// A PC-relative offset of `bits` bits, given either as a label or as a literal offset.
+    fn pc_offset(&mut self, operand: &Operand, bits: u32) -> Result<i16, Diagnostic> {
+        let offset = match operand.kind {
+            OperandKind::Label(_) => {
+                let target = self.label_address(operand)?;
+                let offset = target as i32 - (self.address as i32 + 1);
+                let limit = 1 << (bits - 1);
+                if !(-limit..limit).contains(&offset) {
+                    return Err(Diagnostic::new(
+                        self.line,
+                        Some(operand.span),
+                        format!(
+                            "`{}` is too far away: offset {offset} does not fit in {bits} bits",
+                            operand.text
+                        ),
+                    ));
+                }
+                offset
+            }
+            _ => self.immediate(operand, bits)? as i32,
+        };
+        Ok(offset as i16)
+    }
+
+    /// A signed literal that fits in `bits` bits.
+    fn immediate(&self, operand: &Operand, bits: u32) -> Result<i16, Diagnostic> {
+        let limit = 1 << (bits - 1);
+        Ok(number(self.line, operand, -limit, limit - 1)? as i16)
+    }
+}
+
+/// Checks that `statement` has exactly `expected` operands.
+fn expect_operands(line: usize, statement: &Statement, expected: usize) -> Result<(), Diagnostic> {
+    if statement.operands.len() == expected {
+        return Ok(());
+    }
+    Err(Diagnostic::new(
+        line,
+        Some(statement.span),
+        format!(
+            "{} expects {expected} operand(s), found {}",
+            statement.name(),
+            statement.operands.len()
+        ),
+    ))
+}
+
+fn register(line: usize, operand: &Operand) -> Result<u8, Diagnostic> {
+    match operand.kind {
+        OperandKind::Register(reg) => Ok(reg),
+        _ => Err(Diagnostic::new(
+            line,
+            Some(operand.span),
+            format!("expected a register, found `{}`", operand.text),
+        )),
+    }
+}
+
+/// A numeric literal within `min..=max`.
+fn number(line: usize, operand: &Operand, min: i32, max: i32) -> Result<i32, Diagnostic> {
+    match operand.kind {
+        OperandKind::Number(value) if (min..=max).contains(&value) => Ok(value),
+        OperandKind::Number(value) => Err(Diagnostic::new(
+            line,
+            Some(operand.span),
+            format!("{value} is out of range, expected a value between {min} and {max}"),
+        )),
+        _ => Err(Diagnostic::new(
+            line,
+            Some(operand.span),
+            format!("expected a number, found `{}`", operand.text),
+        )),
+    }
+}
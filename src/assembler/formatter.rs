@@ -0,0 +1,206 @@
+use crate::assembler::{
+    Diagnostic,
+    lexer::parse_number,
+    parser::{Line, OperandKind, Statement, branch_flags, parse},
+};
+
+/// Column where instructions start when no label is longer than it.
+const MIN_INSTRUCTION_COLUMN: usize = 8;
+
+/// Formats a whole source file. Fails with the parse errors if any line cannot be parsed, since it
+/// could not be rebuilt.
+///
+/// The layout is the one the examples of this repository use:
+/// - labels start at the first column and instructions at a column shared by the whole file, far
+///   enough to fit the longest label;
+/// - operands start at a shared column after the longest mnemonic, and are separated by `, `;
+/// - mnemonics, directives and registers are written in upper case, BR conditions in `nzp` order
+///   (`BRzn` becomes `BRnz`), decimal literals as `#n` and hexadecimal ones as `xNNNN`, except
+///   negative hexadecimal ones, which become decimal (`x-1F` becomes `#-31`);
+/// - trailing comments are aligned one column after the longest instruction, and comment-only lines
+///   stay at the first column if they were there and move to the instruction column otherwise.
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (lines, diagnostics) = parse(source);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    Ok(format_lines(&lines))
+}
+
+/// Lays out parsed lines as described in [`format_source`]. Tools that rewrite programs use it to
+/// turn the lines they changed back into source.
+pub fn format_lines(lines: &[Line]) -> String {
+    let instruction_column = lines
+        .iter()
+        .filter_map(|line| line.label.as_ref())
+        .map(|label| label.name.len() + usize::from(label.colon) + 1)
+        .fold(MIN_INSTRUCTION_COLUMN, usize::max);
+    let mnemonic_width = lines
+        .iter()
+        .filter_map(|line| line.statement.as_ref())
+        .filter(|statement| !statement.operands.is_empty())
+        .map(|statement| statement.mnemonic.len())
+        .max()
+        .unwrap_or(0);
+
+    let code: Vec<String> = lines
+        .iter()
+        .map(|line| code_of(line, instruction_column, mnemonic_width))
+        .collect();
+    let comment_column = code.iter().map(String::len).max().unwrap_or(0) + 1;
+
+    let mut out = String::new();
+    for (line, code) in lines.iter().zip(code) {
+        let mut text = code;
+        if let Some(comment) = &line.comment {
+            let column = if !text.is_empty() {
+                comment_column
+            } else if comment.column == 0 {
+                0
+            } else {
+                instruction_column
+            };
+            text = format!("{text:<column$};{}", comment.text.trim_end());
+        }
+        out.push_str(text.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// The label and the statement of a line, laid out without the comment.
+fn code_of(line: &Line, instruction_column: usize, mnemonic_width: usize) -> String {
+    let label = match &line.label {
+        Some(label) if label.colon => format!("{}:", label.name),
+        Some(label) => label.name.clone(),
+        None => String::new(),
+    };
+    match &line.statement {
+        Some(statement) => format!(
+            "{label:<instruction_column$}{}",
+            statement_text(statement, mnemonic_width)
+        ),
+        None => label,
+    }
+}
+
+fn statement_text(statement: &Statement, mnemonic_width: usize) -> String {
+    let name = statement.name();
+    let mnemonic = match branch_flags(&name) {
+        Some(nzp) if name != "BR" => {
+            let flags: String = [('n', 0b100), ('z', 0b010), ('p', 0b001)]
+                .iter()
+                .filter(|(_, bit)| nzp & bit > 0)
+                .map(|(flag, _)| *flag)
+                .collect();
+            format!("BR{flags}")
+        }
+        _ => name,
+    };
+    if statement.operands.is_empty() {
+        return mnemonic;
+    }
+    let operands: Vec<String> = statement
+        .operands
+        .iter()
+        .map(|operand| match &operand.kind {
+            OperandKind::Register(reg) => format!("R{reg}"),
+            OperandKind::Number(_) => literal_text(&operand.text),
+            OperandKind::Label(_) | OperandKind::String(_) => operand.text.clone(),
+        })
+        .collect();
+    format!("{mnemonic:<mnemonic_width$} {}", operands.join(", "))
+}
+
+/// Rewrites a numeric literal in its canonical casing and prefix, keeping its base unless it is a
+/// negative hexadecimal one.
+fn literal_text(text: &str) -> String {
+    let hex = text
+        .strip_prefix("#x")
+        .or_else(|| text.strip_prefix("#X"))
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix(['x', 'X']));
+    match (hex, parse_number(text)) {
+        (Some(digits), _) if !digits.starts_with('-') => {
+            format!("x{}", digits.to_ascii_uppercase())
+        }
+        (_, Some(Ok(value))) => format!("#{value}"),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalises_layout_and_casing() {
+        let source = "\
+; Counts down
+   .orig 0x3000
+start:   and r1,r1,#0
+  add R1, r1,   10   ; ten
+loop add r1,R1,#-1
+\tbrpz loop
+ ; done
+ halt
+msg .stringz \"Hi, there\" ;greeting
+.end";
+        let expected = "\
+; Counts down
+        .ORIG    x3000
+start:  AND      R1, R1, #0
+        ADD      R1, R1, #10 ; ten
+loop    ADD      R1, R1, #-1
+        BRzp     loop
+        ; done
+        HALT
+msg     .STRINGZ \"Hi, there\" ;greeting
+        .END
+";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn widens_the_label_column() {
+        let formatted = format_source("very_long_label .FILL x1f\n").unwrap();
+        assert_eq!(formatted, "very_long_label .FILL x1F\n");
+    }
+
+    #[test]
+    fn writes_negative_hexadecimal_literals_in_decimal() {
+        let source = ".ORIG x3000\nADD R1, R1, x-1\nADD R1, R1, 0X-a\n.FILL x-1f\n.END";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted.lines().skip(1).take(3).collect::<Vec<_>>(),
+            [
+                "        ADD   R1, R1, #-1",
+                "        ADD   R1, R1, #-10",
+                "        .FILL #-31"
+            ]
+        );
+        assert_eq!(
+            crate::assembler::assemble(source).unwrap().words,
+            crate::assembler::assemble(&formatted).unwrap().words
+        );
+    }
+
+    #[test]
+    fn keeps_the_program_it_formats() {
+        let source = "  .ORIG x3000\nLOOP add r0,r0,#1\nbrnzp LOOP\n.END";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            crate::assembler::assemble(source).unwrap().words,
+            crate::assembler::assemble(&formatted).unwrap().words
+        );
+    }
+
+    #[test]
+    fn refuses_unparsable_sources() {
+        let errors = format_source(".ORIG x3000\nMOV R1, R2\n").unwrap_err();
+        assert_eq!(errors[0].line, 2);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assembler::{
+        Diagnostic, SourceLine,
+        parser::{Line, OperandKind, parse},
+    },
+    stdlib,
+};
+
+/// How deep includes can nest, to stop files that include each other.
+const MAX_DEPTH: usize = 16;
+
+/// Replaces every `.INCLUDE "name"` line with the lines of the file it names, recursively. Names
+/// starting with `std/` (or `std@version/`) are modules of the bundled standard library; anything
+/// else is a file relative to `dir`, the directory of the including file, which is only allowed
+/// when it is known. Every file is included once, however many times it is named.
+///
+/// Included lines keep the number of the top-level `.INCLUDE` line, so everything that refers to
+/// them (errors, listings, symbols) points at the line that brought them in. Their own file and line
+/// are kept as their origin, for debug info: the path of the file as found from the directory of
+/// the program, or `std/<module>.asm` for the standard library.
+pub fn expand_includes(lines: &[Line], dir: Option<&Path>) -> Result<Vec<Line>, Vec<Diagnostic>> {
+    let mut expansion = Expansion {
+        lines: Vec::new(),
+        diagnostics: Vec::new(),
+        included: BTreeSet::new(),
+    };
+    expansion.expand(lines, dir, None, 0);
+    if expansion.diagnostics.is_empty() {
+        Ok(expansion.lines)
+    } else {
+        Err(expansion.diagnostics)
+    }
+}
+
+/// Whether any line includes another file.
+pub fn has_includes(lines: &[Line]) -> bool {
+    lines.iter().any(|line| {
+        line.statement
+            .as_ref()
+            .is_some_and(|statement| statement.name() == ".INCLUDE")
+    })
+}
+
+/// A file to include, once found.
+struct Included {
+    /// What identifies the file, to include it only once.
+    key: String,
+    /// The path lines of the file are said to come from.
+    path: String,
+    text: String,
+    /// The directory its own includes are relative to.
+    dir: Option<PathBuf>,
+}
+
+struct Expansion {
+    lines: Vec<Line>,
+    diagnostics: Vec<Diagnostic>,
+    included: BTreeSet<String>,
+}
+
+impl Expansion {
+    /// Adds `lines` to the expansion. `parent` is the top-level `.INCLUDE` line, the name of the
+    /// file they come from, as it was included, and its path, when they were included.
+    fn expand(
+        &mut self,
+        lines: &[Line],
+        dir: Option<&Path>,
+        parent: Option<(&Line, &str, &str)>,
+        depth: usize,
+    ) {
+        for line in lines {
+            let Some(statement) = &line.statement else {
+                self.push(line, parent);
+                continue;
+            };
+            let name = statement.name();
+            // Errors inside included files are reported on the line that included them.
+            let (top, prefix) = match parent {
+                Some((top, file, _)) => (top, format!("in {file}:{}: ", line.number)),
+                None => (line, String::new()),
+            };
+            let span = top.statement.as_ref().map(|statement| statement.span);
+            let error =
+                |message: String| Diagnostic::new(top.number, span, prefix.clone() + &message);
+            if parent.is_some() && matches!(name.as_str(), ".ORIG" | ".END") {
+                self.diagnostics
+                    .push(error(format!("{name} is not allowed in an included file")));
+                continue;
+            }
+            if name != ".INCLUDE" {
+                self.push(line, parent);
+                continue;
+            }
+            if line.label.is_some() {
+                self.diagnostics
+                    .push(error(".INCLUDE cannot have a label".to_string()));
+                continue;
+            }
+            let file = match statement.operands.as_slice() {
+                [operand] => match &operand.kind {
+                    OperandKind::String(file) => file.clone(),
+                    _ => {
+                        self.diagnostics
+                            .push(error(".INCLUDE expects the name of a file".to_string()));
+                        continue;
+                    }
+                },
+                _ => {
+                    self.diagnostics
+                        .push(error(".INCLUDE expects a single operand".to_string()));
+                    continue;
+                }
+            };
+            if depth == MAX_DEPTH {
+                self.diagnostics.push(error(format!(
+                    "includes are nested more than {MAX_DEPTH} levels deep"
+                )));
+                continue;
+            }
+            let included = match resolve(&file, dir) {
+                Ok(included) => included,
+                Err(message) => {
+                    self.diagnostics.push(error(message));
+                    continue;
+                }
+            };
+            if !self.included.insert(included.key) {
+                continue;
+            }
+            let (included_lines, diagnostics) = parse(&included.text);
+            for diagnostic in diagnostics {
+                self.diagnostics
+                    .push(error(format!("in {file}:{diagnostic}")));
+            }
+            self.expand(
+                &included_lines,
+                included.dir.as_deref(),
+                Some((top, &file, &included.path)),
+                depth + 1,
+            );
+        }
+    }
+
+    fn push(&mut self, line: &Line, parent: Option<(&Line, &str, &str)>) {
+        let mut line = line.clone();
+        if let Some((top, _, path)) = parent {
+            line.origin = Some(SourceLine {
+                file: path.to_string(),
+                line: line.number,
+            });
+            line.number = top.number;
+        }
+        self.lines.push(line);
+    }
+}
+
+/// Finds the file an `.INCLUDE` names.
+fn resolve(name: &str, dir: Option<&Path>) -> Result<Included, String> {
+    if let Some(module) = stdlib::find(name)? {
+        return Ok(Included {
+            key: format!("std/{}", module.name),
+            path: format!("std/{}.asm", module.name),
+            text: module.source.to_string(),
+            dir: None,
+        });
+    }
+    let Some(dir) = dir else {
+        return Err(format!(
+            "cannot include `{name}`: only the standard library is available here"
+        ));
+    };
+    let path = dir.join(name);
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("cannot include `{}`: {e}", path.display()))?;
+    let key = fs::canonicalize(&path)
+        .unwrap_or_else(|_| path.clone())
+        .to_string_lossy()
+        .into_owned();
+    Ok(Included {
+        key,
+        path: path.to_string_lossy().into_owned(),
+        text,
+        dir: path.parent().map(Path::to_path_buf),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble_in;
+
+    #[test]
+    fn includes_files_once() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("lib/double.asm"),
+            "DOUBLE  ADD R0, R0, R0\n        RET\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/quad.asm"),
+            ".INCLUDE \"double.asm\"\nQUAD    ST R7, SAVE\n        JSR DOUBLE\n        JSR DOUBLE\n        LD R7, SAVE\n        RET\nSAVE    .BLKW 1\n",
+        )
+        .unwrap();
+        let source = "\
+        .ORIG x3000
+        JSR QUAD
+        HALT
+        .INCLUDE \"lib/double.asm\"
+        .INCLUDE \"lib/quad.asm\"
+        .END";
+        let assembly = assemble_in(source, Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(assembly.words.len(), 10);
+        assert_eq!(assembly.symbols["DOUBLE"].address, 0x3002);
+        assert_eq!(assembly.symbols["QUAD"].line, 5);
+    }
+
+    #[test]
+    fn reports_errors_on_the_including_line() {
+        let source = ".ORIG x3000\nHALT\n.INCLUDE \"missing.asm\"\n.END";
+        let errors = assemble_in(source, None).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "3:1: cannot include `missing.asm`: only the standard library is available here"
+        );
+        let source = ".ORIG x3000\nHALT\n.INCLUDE \"std/maths.asm\"\n.END";
+        let errors = assemble_in(source, None).unwrap_err();
+        assert!(errors[0].message.contains("no module `maths`"));
+        let source = ".ORIG x3000\nHALT\n.INCLUDE \"std@2/math.asm\"\n.END";
+        let errors = assemble_in(source, None).unwrap_err();
+        assert!(errors[0].message.contains("version"));
+    }
+}
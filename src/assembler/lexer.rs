@@ -0,0 +1,230 @@
+/// A range of columns (counted in characters, starting at zero) inside a source line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// Mnemonics, directives, registers and labels.
+    Word,
+    /// A numeric literal: `#10`, `#-3`, `x3000`, `0x3000` or a plain decimal number.
+    Number(i32),
+    /// A string literal, with its escape sequences already resolved.
+    String(String),
+    Comma,
+    Colon,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The token as it is written in the source.
+    pub text: String,
+    pub span: Span,
+}
+
+/// A comment found at the end of a line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    /// The text after the `;`.
+    pub text: String,
+    /// Column of the `;`.
+    pub column: usize,
+}
+
+/// An error found while splitting a line into tokens.
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+/// Splits one line of assembly source into tokens and its trailing comment.
+pub fn tokenize(line: &str) -> Result<(Vec<Token>, Option<Comment>), LexError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        match c {
+            ';' => {
+                let comment = Comment {
+                    text: chars[i + 1..].iter().collect(),
+                    column: i,
+                };
+                return Ok((tokens, Some(comment)));
+            }
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' | ':' => {
+                i += 1;
+                let kind = if c == ',' {
+                    TokenKind::Comma
+                } else {
+                    TokenKind::Colon
+                };
+                tokens.push(Token {
+                    kind,
+                    text: c.to_string(),
+                    span: Span { start, end: i },
+                });
+                continue;
+            }
+            '"' => {
+                let (value, end) = read_string(&chars, start)?;
+                i = end;
+                tokens.push(Token {
+                    kind: TokenKind::String(value),
+                    text: chars[start..end].iter().collect(),
+                    span: Span { start, end },
+                });
+                continue;
+            }
+            _ => {}
+        }
+        while i < chars.len() && !is_separator(chars[i]) {
+            i += 1;
+        }
+        let text: String = chars[start..i].iter().collect();
+        let span = Span { start, end: i };
+        let kind = match parse_number(&text) {
+            Some(Ok(value)) => TokenKind::Number(value),
+            Some(Err(())) => {
+                return Err(LexError {
+                    message: format!("invalid numeric literal `{text}`"),
+                    span,
+                });
+            }
+            None => TokenKind::Word,
+        };
+        tokens.push(Token { kind, text, span });
+    }
+    Ok((tokens, None))
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | ':' | ';' | '"')
+}
+
+/// Reads the string literal that starts with the quote at `start`, returning its value and the
+/// position right after the closing quote.
+fn read_string(chars: &[char], start: usize) -> Result<(String, usize), LexError> {
+    let mut value = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Ok((value, i + 1)),
+            '\\' if i + 1 < chars.len() => {
+                let escaped = match chars[i + 1] {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'e' => '\x1B',
+                    '0' => '\0',
+                    '\\' => '\\',
+                    '"' => '"',
+                    other => {
+                        return Err(LexError {
+                            message: format!("unknown escape sequence `\\{other}`"),
+                            span: Span {
+                                start: i,
+                                end: i + 2,
+                            },
+                        });
+                    }
+                };
+                value.push(escaped);
+                i += 2;
+            }
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(LexError {
+        message: "unterminated string literal".to_string(),
+        span: Span {
+            start,
+            end: chars.len(),
+        },
+    })
+}
+
+/// Parses a numeric literal. Returns `None` if the text does not look like a number at all (it is
+/// a word), and an error if it looks like one but is malformed.
+pub fn parse_number(text: &str) -> Option<Result<i32, ()>> {
+    let (digits, radix) = if let Some(rest) = text.strip_prefix('#') {
+        match rest.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16),
+            None => (rest, 10),
+        }
+    } else if let Some(rest) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (rest, 16)
+    } else if let Some(rest) = text.strip_prefix(['x', 'X']) {
+        // `x` followed by hex digits is a number, anything else (like `xLOOP`) is a word.
+        let body = rest.strip_prefix('-').unwrap_or(rest);
+        if body.is_empty() || !body.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        (rest, 16)
+    } else if text.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        (text, 10)
+    } else {
+        return None;
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits),
+    };
+    let value = i32::from_str_radix(digits, radix)
+        .ok()
+        .filter(|_| !digits.starts_with(['+', '-']));
+    Some(match value {
+        Some(value) if negative => Ok(-value),
+        Some(value) => Ok(value),
+        None => Err(()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tokenizes_instruction_with_comment() {
+        let (tokens, comment) = tokenize("LOOP ADD R1, R1, #-1 ; count down").unwrap();
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["LOOP", "ADD", "R1", ",", "R1", ",", "#-1"]);
+        assert_eq!(tokens[6].kind, TokenKind::Number(-1));
+        assert_eq!(tokens[6].span, Span { start: 17, end: 20 });
+        let comment = comment.unwrap();
+        assert_eq!(comment.text, " count down");
+        assert_eq!(comment.column, 21);
+    }
+
+    #[test]
+    fn parses_number_formats() {
+        assert_eq!(parse_number("#10"), Some(Ok(10)));
+        assert_eq!(parse_number("x3000"), Some(Ok(0x3000)));
+        assert_eq!(parse_number("0xFE00"), Some(Ok(0xFE00)));
+        assert_eq!(parse_number("x-1"), Some(Ok(-1)));
+        assert_eq!(parse_number("-7"), Some(Ok(-7)));
+        assert_eq!(parse_number("#1a"), Some(Err(())));
+        assert_eq!(parse_number("xLOOP"), None);
+        assert_eq!(parse_number("LOOP"), None);
+    }
+
+    #[test]
+    fn resolves_string_escapes() {
+        let (tokens, comment) = tokenize(r#".STRINGZ "a;b\n""#).unwrap();
+        assert_eq!(tokens[1].kind, TokenKind::String("a;b\n".to_string()));
+        assert!(comment.is_none());
+        assert!(tokenize(r#".STRINGZ "open"#).is_err());
+    }
+}
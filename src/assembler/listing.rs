@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use crate::assembler::{Assembly, Placement};
+
+/// Builds the listing of an assembled source: one row per word with its address, its value in
+/// hexadecimal and binary, and the source line that produced it. Lines that produce no words are
+/// listed with the columns of the word empty, and long runs of the same word (like the ones of
+/// `.BLKW`) are collapsed into a single row. The words of an `.INCLUDE` follow its line, each with
+/// the file and line it comes from.
+pub fn listing(source: &str, assembly: &Assembly) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<5}  {:<4}  {:<19}  {:>5}  Source",
+        "Addr", "Hex", "Binary", "Line"
+    );
+    let mut placements = assembly.placements.iter().peekable();
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let mut listed = false;
+        while let Some(placement) = placements.next_if(|p| p.line == number) {
+            let source = match &placement.origin {
+                None => format!("{number:>5}  {text}"),
+                Some(origin) => {
+                    if !listed {
+                        let _ =
+                            writeln!(out, "{:<5}  {:<4}  {:<19}  {number:>5}  {text}", "", "", "");
+                    }
+                    format!("{:>5}  {}:{}", "", origin.file, origin.line)
+                }
+            };
+            rows(&mut out, assembly, placement, &source);
+            listed = true;
+        }
+        if !listed {
+            let _ = writeln!(out, "{:<5}  {:<4}  {:<19}  {number:>5}  {text}", "", "", "");
+        }
+    }
+    out
+}
+
+/// The rows of the words of `placement`, the first one followed by its line and `source`.
+fn rows(out: &mut String, assembly: &Assembly, placement: &Placement, source: &str) {
+    let words = assembly.words_of(placement);
+    if words.is_empty() {
+        let _ = writeln!(out, "{:<5}  {:<4}  {:<19}  {source}", "", "", "");
+    }
+    let collapse = words.len() > 2 && words.iter().all(|w| *w == words[0]);
+    for (offset, word) in words.iter().enumerate() {
+        let address = placement.address.wrapping_add(offset as u16);
+        if offset == 0 {
+            let _ = writeln!(out, "{}  {source}", word_columns(address, *word));
+        } else if !collapse {
+            let _ = writeln!(out, "{}", word_columns(address, *word));
+        } else {
+            let last = placement.address.wrapping_add(placement.size - 1);
+            let _ = writeln!(
+                out,
+                "{:<5}  {:<4}  ({} more words up to x{last:04X})",
+                "...",
+                "",
+                words.len() - 1
+            );
+            break;
+        }
+    }
+}
+
+/// The address, hexadecimal and binary columns of a listing row.
+fn word_columns(address: u16, word: u16) -> String {
+    let binary = format!("{word:016b}");
+    let nibbles: Vec<&str> = (0..4).map(|i| &binary[i * 4..i * 4 + 4]).collect();
+    format!("x{address:04X}  {word:04X}  {}", nibbles.join(" "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn lists_words_next_to_their_source() {
+        let source = ".ORIG x3000\n; start\nLOOP ADD R1, R1, #-1\nBRp LOOP\nBUF .BLKW 5\n.END";
+        let assembly = assemble(source).unwrap();
+        let listing = listing(source, &assembly);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "Addr   Hex   Binary                Line  Source");
+        assert_eq!(
+            lines[1],
+            "                                      1  .ORIG x3000"
+        );
+        assert_eq!(lines[2], "                                      2  ; start");
+        assert_eq!(
+            lines[3],
+            "x3000  127F  0001 0010 0111 1111      3  LOOP ADD R1, R1, #-1"
+        );
+        assert_eq!(
+            lines[5],
+            "x3002  0000  0000 0000 0000 0000      5  BUF .BLKW 5"
+        );
+        assert_eq!(lines[6], "...          (4 more words up to x3006)");
+        assert_eq!(lines[7], "                                      6  .END");
+    }
+
+    #[test]
+    fn lists_included_words_and_the_lines_after_them() {
+        let source = ".ORIG x3000\nHALT\n.INCLUDE \"std/math.asm\"\nAFTER .FILL #42\n.END";
+        let assembly = assemble(source).unwrap();
+        let listing = listing(source, &assembly);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines[3],
+            "                                      3  .INCLUDE \"std/math.asm\""
+        );
+        let first = &assembly.placements[1];
+        let origin = first.origin.as_ref().unwrap();
+        assert_eq!(
+            lines[4],
+            format!(
+                "{}         {}:{}",
+                word_columns(first.address, assembly.words_of(first)[0]),
+                origin.file,
+                origin.line
+            )
+        );
+        let after = assembly.symbols["AFTER"].address;
+        assert_eq!(
+            lines[lines.len() - 2],
+            format!("{}      4  AFTER .FILL #42", word_columns(after, 42))
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Assembler for LC-3 assembly source.
+//!
+//! The source is split into tokens (`lexer`), parsed line by line (`parser`) and encoded in two passes
+//! (`encoder`). Besides the program words, the result keeps the symbol table, every use of a label and
+//! where each source line was placed in memory, which is what the listing (`listing`) and the
+//! cross-reference report (`xref`) are made of. The formatter (`formatter`) rebuilds the parsed
+//! lines in a consistent layout. `.INCLUDE "file"` lines are replaced with the lines of the file
+//! (`include`) before encoding, which is how programs use the bundled standard library. Included
+//! lines remember the file and line they come from, which debug info (`debuginfo`) is made of.
+pub mod debuginfo;
+pub mod encoder;
+pub mod formatter;
+pub mod include;
+pub mod lexer;
+pub mod listing;
+pub mod parser;
+pub mod xref;
+
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use crate::assembler::{encoder::assemble_lines_in, lexer::Span, parser::parse};
+
+/// An error found in the source, tied to a line and, when possible, to the columns it refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(line: usize, span: Option<Span>, message: String) -> Self {
+        Self {
+            line,
+            span,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{}:{}: {}", self.line, span.start + 1, self.message),
+            None => write!(f, "{}: {}", self.line, self.message),
+        }
+    }
+}
+
+/// A label definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub address: u16,
+    /// Line where it is defined.
+    pub line: usize,
+    /// The line of the included file it is defined in, if it was included.
+    pub origin: Option<SourceLine>,
+}
+
+/// A use of a label as an operand.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub symbol: String,
+    pub line: usize,
+    /// Address of the instruction (or data) that uses the label.
+    pub address: u16,
+}
+
+/// A line of a source file, by the path of the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    /// Line number, starting at 1.
+    pub line: usize,
+}
+
+/// Where the words of a source line were placed in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub line: usize,
+    pub address: u16,
+    /// Number of words the line produced.
+    pub size: u16,
+    /// Whether the words are data (`.FILL`, `.BLKW` or `.STRINGZ`) rather than instructions.
+    pub data: bool,
+    /// The line of the included file it comes from, if it was included.
+    pub origin: Option<SourceLine>,
+}
+
+/// The result of assembling a source file.
+#[derive(Debug)]
+pub struct Assembly {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: BTreeMap<String, Symbol>,
+    pub references: Vec<Reference>,
+    /// One entry per line that produced words, in address order.
+    pub placements: Vec<Placement>,
+}
+
+impl Assembly {
+    /// The content of an object file for the program: the origin followed by the words, in big endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+
+    /// The words produced by `placement`.
+    pub fn words_of(&self, placement: &Placement) -> &[u16] {
+        let start = placement.address.wrapping_sub(self.origin) as usize;
+        &self.words[start..start + placement.size as usize]
+    }
+}
+
+/// Assembles a whole source file, reporting every error found.
+#[cfg(test)]
+pub fn assemble(source: &str) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_in(source, None)
+}
+
+/// Assembles a whole source file that lives in `dir`, so it can include the files next to it.
+pub fn assemble_in(source: &str, dir: Option<&Path>) -> Result<Assembly, Vec<Diagnostic>> {
+    let (lines, mut diagnostics) = parse(source);
+    match assemble_lines_in(&lines, dir) {
+        Ok(assembly) if diagnostics.is_empty() => Ok(assembly),
+        Ok(_) => Err(diagnostics),
+        Err(more) => {
+            diagnostics.extend(more);
+            diagnostics.sort_by_key(|d| d.line);
+            Err(diagnostics)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        LEA R0, HELLO      ; string to print
+        PUTS
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        LDR R2, R6, #-2
+        JSR SUB
+        HALT
+SUB     RET
+HELLO   .STRINGZ \"Hi\"
+COUNT   .FILL LOOP
+BUF     .BLKW 2
+        .END
+";
+
+    #[test]
+    fn assembles_program() {
+        let assembly = assemble(PROGRAM).unwrap();
+        assert_eq!(assembly.origin, 0x3000);
+        assert_eq!(
+            assembly.words,
+            vec![
+                0xE009, // LEA R0, #9
+                0xF022, // PUTS
+                0x5260, // AND R1, R1, #0
+                0x1263, // ADD R1, R1, #3
+                0x127F, // ADD R1, R1, #-1
+                0x03FE, // BRp #-2
+                0x65BE, // LDR R2, R6, #-2
+                0x4801, // JSR #1
+                0xF025, // HALT
+                0xC1C0, // RET
+                0x0048, 0x0069, 0x0000, // "Hi"
+                0x3004, // .FILL LOOP
+                0x0000, 0x0000, // .BLKW 2
+            ]
+        );
+        assert_eq!(assembly.symbols["HELLO"].address, 0x300A);
+        assert_eq!(assembly.symbols["LOOP"].line, 7);
+        assert_eq!(
+            assembly.to_bytes()[..4].to_vec(),
+            vec![0x30, 0x00, 0xE0, 0x09]
+        );
+    }
+
+    #[test]
+    fn reports_all_errors() {
+        let source =
+            ".ORIG x3000\nADD R1, R2, #16\nBRz NOWHERE\nLOOP ADD R1, R1, R1\nLOOP HALT\n.END";
+        let errors = assemble(source).unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        assert_eq!(errors[1].message, "undefined label `NOWHERE`");
+        assert_eq!(errors[1].to_string(), "3:5: undefined label `NOWHERE`");
+    }
+
+    #[test]
+    fn requires_orig_and_end() {
+        for source in ["", "; nothing here\n"] {
+            let errors = assemble(source).unwrap_err();
+            assert_eq!(
+                errors[0].message,
+                "expected .ORIG at the start of the program"
+            );
+        }
+        let errors = assemble(".ORIG x3000\nHALT\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].message, "expected .END at the end of the program");
+    }
+
+    #[test]
+    fn rejects_branches_out_of_range() {
+        let source = ".ORIG x3000\nBR FAR\n.BLKW 300\nFAR HALT\n.END";
+        let errors = assemble(source).unwrap_err();
+        assert!(errors[0].message.contains("too far away"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::assembler::{
+    Diagnostic, SourceLine,
+    lexer::{Comment, Span, Token, TokenKind, tokenize},
+};
+
+/// Every instruction mnemonic the assembler understands, including the BR variants and the trap aliases.
+/// The condition flags of BR are also accepted in any other order (like `BRZN`).
+pub const MNEMONICS: [&str; 30] = [
+    "ADD", "AND", "BR", "BRN", "BRZ", "BRP", "BRNZ", "BRNP", "BRZP", "BRNZP", "GETC", "HALT", "IN",
+    "JMP", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "NOT", "OUT", "PUTS", "PUTSP", "RET", "RTI",
+    "ST", "STI", "STR", "TRAP",
+];
+
+/// Every directive (pseudo-op) the assembler understands.
+pub const DIRECTIVES: [&str; 6] = [".ORIG", ".FILL", ".BLKW", ".STRINGZ", ".INCLUDE", ".END"];
+
+/// Whether `word` (in any case) is an instruction mnemonic. Condition flags of BR may come in any order.
+pub fn is_mnemonic(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    MNEMONICS.contains(&upper.as_str()) || branch_flags(&upper).is_some()
+}
+
+/// The condition bits (`nzp`) of a BR mnemonic, or `None` if `mnemonic` (in upper case) is not one.
+/// A plain `BR` branches always.
+pub fn branch_flags(mnemonic: &str) -> Option<u16> {
+    let flags = mnemonic.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0b111);
+    }
+    let mut nzp = 0;
+    for c in flags.chars() {
+        let bit = match c {
+            'N' => 0b100,
+            'Z' => 0b010,
+            'P' => 0b001,
+            _ => return None,
+        };
+        if nzp & bit > 0 {
+            return None;
+        }
+        nzp |= bit;
+    }
+    Some(nzp)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperandKind {
+    Register(u8),
+    Number(i32),
+    Label(String),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operand {
+    pub kind: OperandKind,
+    /// The operand as it is written in the source.
+    pub text: String,
+    pub span: Span,
+}
+
+/// An instruction or a directive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    /// The mnemonic or directive as it is written in the source.
+    pub mnemonic: String,
+    pub span: Span,
+    pub operands: Vec<Operand>,
+}
+
+impl Statement {
+    /// The mnemonic in upper case, the form used to compare it.
+    pub fn name(&self) -> String {
+        self.mnemonic.to_ascii_uppercase()
+    }
+}
+
+/// A label definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub span: Span,
+    /// Whether it was written with a trailing colon.
+    pub colon: bool,
+}
+
+/// One line of source, split into its parts. Every part is optional: blank lines and comment-only
+/// lines are kept so tools like the formatter can rebuild the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    /// Line number, starting at 1.
+    pub number: usize,
+    pub label: Option<Label>,
+    pub statement: Option<Statement>,
+    pub comment: Option<Comment>,
+    /// The line of the file it comes from, when it was included; `number` is then the line of the
+    /// top-level `.INCLUDE`.
+    pub origin: Option<SourceLine>,
+}
+
+/// Parses a whole source file. Lines that cannot be parsed are reported and left out of the result.
+pub fn parse(source: &str) -> (Vec<Line>, Vec<Diagnostic>) {
+    let mut lines = Vec::new();
+    let mut diagnostics = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        match parse_line(index + 1, text) {
+            Ok(line) => lines.push(line),
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    (lines, diagnostics)
+}
+
+/// Parses one line of source: `[label[:]] [mnemonic operand, operand, ...] [; comment]`.
+pub fn parse_line(number: usize, text: &str) -> Result<Line, Diagnostic> {
+    let (tokens, comment) =
+        tokenize(text).map_err(|e| Diagnostic::new(number, Some(e.span), e.message))?;
+    let mut tokens = tokens.into_iter().peekable();
+    let mut line = Line {
+        number,
+        label: None,
+        statement: None,
+        comment,
+        origin: None,
+    };
+
+    let Some(first) = tokens.next() else {
+        return Ok(line);
+    };
+    let mnemonic = if first.kind == TokenKind::Word && !is_keyword(&first.text) {
+        if !is_identifier(&first.text) {
+            return Err(Diagnostic::new(
+                number,
+                Some(first.span),
+                format!("invalid label name `{}`", first.text),
+            ));
+        }
+        let colon = tokens.next_if(|t| t.kind == TokenKind::Colon).is_some();
+        line.label = Some(Label {
+            name: first.text,
+            span: first.span,
+            colon,
+        });
+        match tokens.next() {
+            Some(token) => token,
+            None => return Ok(line),
+        }
+    } else {
+        first
+    };
+
+    if mnemonic.kind != TokenKind::Word || !is_keyword(&mnemonic.text) {
+        let message = if mnemonic.kind == TokenKind::Word && line.label.is_some() {
+            format!("unknown instruction `{}`", mnemonic.text)
+        } else {
+            format!(
+                "expected an instruction or directive, found `{}`",
+                mnemonic.text
+            )
+        };
+        return Err(Diagnostic::new(number, Some(mnemonic.span), message));
+    }
+
+    let mut operands = Vec::new();
+    while let Some(token) = tokens.next() {
+        operands.push(operand(number, token)?);
+        if let Some(separator) = tokens.next() {
+            if separator.kind != TokenKind::Comma {
+                return Err(Diagnostic::new(
+                    number,
+                    Some(separator.span),
+                    format!("expected `,` between operands, found `{}`", separator.text),
+                ));
+            }
+            if tokens.peek().is_none() {
+                return Err(Diagnostic::new(
+                    number,
+                    Some(separator.span),
+                    "expected an operand after `,`".to_string(),
+                ));
+            }
+        }
+    }
+    line.statement = Some(Statement {
+        mnemonic: mnemonic.text,
+        span: mnemonic.span,
+        operands,
+    });
+    Ok(line)
+}
+
+/// Whether a word is a mnemonic or a directive, which cannot be used as a label.
+fn is_keyword(word: &str) -> bool {
+    word.starts_with('.') || is_mnemonic(word)
+}
+
+/// Labels start with a letter or an underscore and continue with letters, digits or underscores.
+pub fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && register_number(word).is_none()
+}
+
+/// The number of the register named by `word` (`R0`..`R7`, in any case).
+pub fn register_number(word: &str) -> Option<u8> {
+    let digit = word.strip_prefix(['R', 'r'])?;
+    match digit.parse::<u8>() {
+        Ok(n) if n < 8 && digit.len() == 1 => Some(n),
+        _ => None,
+    }
+}
+
+fn operand(number: usize, token: Token) -> Result<Operand, Diagnostic> {
+    let kind = match &token.kind {
+        TokenKind::Number(value) => OperandKind::Number(*value),
+        TokenKind::String(value) => OperandKind::String(value.clone()),
+        TokenKind::Word => match register_number(&token.text) {
+            Some(reg) => OperandKind::Register(reg),
+            None if is_identifier(&token.text) => OperandKind::Label(token.text.clone()),
+            None => {
+                return Err(Diagnostic::new(
+                    number,
+                    Some(token.span),
+                    format!("invalid operand `{}`", token.text),
+                ));
+            }
+        },
+        TokenKind::Comma | TokenKind::Colon => {
+            return Err(Diagnostic::new(
+                number,
+                Some(token.span),
+                format!("expected an operand, found `{}`", token.text),
+            ));
+        }
+    };
+    Ok(Operand {
+        kind,
+        text: token.text,
+        span: token.span,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_label_instruction_and_comment() {
+        let line = parse_line(3, "loop: add r1, r1, #-1 ; dec").unwrap();
+        let label = line.label.unwrap();
+        assert_eq!(label.name, "loop");
+        assert!(label.colon);
+        let statement = line.statement.unwrap();
+        assert_eq!(statement.name(), "ADD");
+        let kinds: Vec<OperandKind> = statement.operands.into_iter().map(|o| o.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                OperandKind::Register(1),
+                OperandKind::Register(1),
+                OperandKind::Number(-1)
+            ]
+        );
+        assert_eq!(line.comment.unwrap().text, " dec");
+    }
+
+    #[test]
+    fn recognises_branch_variants() {
+        assert_eq!(branch_flags("BRZN"), Some(0b110));
+        assert_eq!(branch_flags("BR"), Some(0b111));
+        assert_eq!(branch_flags("BRNN"), None);
+        assert!(is_mnemonic("brnzp"));
+        assert!(!is_mnemonic("BREAK"));
+    }
+
+    #[test]
+    fn reports_unknown_instructions() {
+        let error = parse_line(7, "START MOV R1, R2").unwrap_err();
+        assert_eq!(error.line, 7);
+        assert_eq!(error.message, "unknown instruction `MOV`");
+        assert!(parse_line(1, "ADD R1 R2").is_err());
+    }
+}
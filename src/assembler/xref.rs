@@ -0,0 +1,189 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::assembler::{Assembly, SourceLine, debuginfo::source_text, lexer::tokenize};
+
+/// Builds the cross-reference report of an assembled source. For every label it shows where it is
+/// defined and every line that uses it, followed by a summary of how many words each label spans
+/// (from its address up to the next label, or to the end of the program). Labels and references
+/// that come from an included file are shown at their line in that file.
+pub fn cross_reference(source: &str, assembly: &Assembly) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    // The lines of the included files, read when first needed.
+    let mut included: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut included_line = |origin: &SourceLine| {
+        let lines = included.entry(origin.file.clone()).or_insert_with(|| {
+            source_text(&origin.file)
+                .map(|text| text.lines().map(str::to_string).collect())
+                .unwrap_or_default()
+        });
+        let text = origin
+            .line
+            .checked_sub(1)
+            .and_then(|index| lines.get(index));
+        text.map(|text| code_of(text)).unwrap_or_default()
+    };
+    let name_width = assembly
+        .symbols
+        .keys()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("(unlabeled)".len());
+
+    let mut out = String::from("Cross-reference\n===============\n");
+    for (name, symbol) in &assembly.symbols {
+        let defined = match &symbol.origin {
+            Some(origin) => format!("{}:{}", origin.file, origin.line),
+            None => format!("line {}", symbol.line),
+        };
+        let _ = writeln!(
+            out,
+            "{name:<name_width$}  defined at {defined} (x{:04X})",
+            symbol.address
+        );
+        let mut any = false;
+        for reference in assembly.references.iter().filter(|r| &r.symbol == name) {
+            any = true;
+            let origin = assembly
+                .placements
+                .iter()
+                .find(|placement| placement.address == reference.address)
+                .and_then(|placement| placement.origin.as_ref());
+            let (place, text) = match origin {
+                Some(origin) => (
+                    format!("{}:{}", origin.file, origin.line),
+                    included_line(origin),
+                ),
+                None => (
+                    format!("line {}", reference.line),
+                    lines
+                        .get(reference.line - 1)
+                        .map(|text| code_of(text))
+                        .unwrap_or_default(),
+                ),
+            };
+            let _ = writeln!(
+                out,
+                "{:<name_width$}  {place:<10} x{:04X}  {text}",
+                "", reference.address
+            );
+        }
+        if !any {
+            let _ = writeln!(out, "{:<name_width$}  (no references)", "");
+        }
+    }
+
+    out.push_str("\nSymbol sizes\n============\n");
+    let _ = writeln!(out, "{:<name_width$}  Address  Words", "Symbol");
+    for (name, address, size) in symbol_sizes(assembly) {
+        let _ = writeln!(out, "{name:<name_width$}  x{address:04X}  {size:>6}");
+    }
+    let _ = writeln!(
+        out,
+        "{:<name_width$}  {:5}  {:>6}",
+        "Total",
+        "",
+        assembly.words.len()
+    );
+    out
+}
+
+/// The number of words each label spans, in address order. Words before the first label are
+/// reported as `(unlabeled)`.
+pub fn symbol_sizes(assembly: &Assembly) -> Vec<(String, u16, usize)> {
+    let mut by_address: Vec<(&String, u16)> = assembly
+        .symbols
+        .iter()
+        .map(|(name, symbol)| (name, symbol.address))
+        .collect();
+    by_address.sort_by_key(|(_, address)| *address);
+    let end = assembly.origin as usize + assembly.words.len();
+
+    let mut sizes = Vec::new();
+    if let Some((_, first)) = by_address.first()
+        && *first > assembly.origin
+    {
+        sizes.push((
+            "(unlabeled)".to_string(),
+            assembly.origin,
+            (*first - assembly.origin) as usize,
+        ));
+    }
+    for (name, address) in &by_address {
+        let next = by_address
+            .iter()
+            .map(|(_, other)| *other as usize)
+            .find(|other| *other > *address as usize)
+            .unwrap_or(end)
+            .min(end);
+        sizes.push((
+            name.to_string(),
+            *address,
+            next.saturating_sub(*address as usize),
+        ));
+    }
+    sizes
+}
+
+/// The code of a source line, without its comment and surrounding spaces.
+fn code_of(text: &str) -> String {
+    let code = match tokenize(text) {
+        Ok((_, Some(comment))) => text.chars().take(comment.column).collect(),
+        _ => text.to_string(),
+    };
+    code.trim().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn reports_definitions_references_and_sizes() {
+        let source = ".ORIG x3000\nAND R0, R0, #0\nLOOP ADD R0, R0, #1 ; inc\nBRp LOOP\nJSR SUB\nHALT\nSUB RET\nPTR .FILL LOOP\n.END";
+        let assembly = assemble(source).unwrap();
+        let report = cross_reference(source, &assembly);
+        let expected = "\
+Cross-reference
+===============
+LOOP         defined at line 3 (x3001)
+             line 4     x3002  BRp LOOP
+             line 8     x3006  PTR .FILL LOOP
+PTR          defined at line 8 (x3006)
+             (no references)
+SUB          defined at line 7 (x3005)
+             line 5     x3003  JSR SUB
+
+Symbol sizes
+============
+Symbol       Address  Words
+(unlabeled)  x3000       1
+LOOP         x3001       4
+SUB          x3005       1
+PTR          x3006       1
+Total                    7
+";
+        assert_eq!(report, expected);
+    }
+
+    #[test]
+    fn shows_included_labels_at_their_own_lines() {
+        let source = ".ORIG x3000\nJSR STD_MUL\nHALT\n.INCLUDE \"std/math.asm\"\n.END";
+        let assembly = assemble(source).unwrap();
+        let report = cross_reference(source, &assembly);
+        let lines: Vec<&str> = report.lines().collect();
+        let at = |start: &str| {
+            lines
+                .iter()
+                .position(|line| line.starts_with(start))
+                .unwrap()
+        };
+        let mul = at("STD_MUL ");
+        assert!(lines[mul].ends_with("defined at std/math.asm:14 (x3002)"));
+        assert!(lines[mul + 1].ends_with("line 2     x3000  JSR STD_MUL"));
+        let mul_loop = at("STD_MUL_LOOP ");
+        assert!(lines[mul_loop].ends_with("defined at std/math.asm:20 (x3008)"));
+        assert!(lines[mul_loop + 1].ends_with("std/math.asm:25 x300D  BRnp  STD_MUL_LOOP"));
+    }
+}
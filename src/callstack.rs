@@ -0,0 +1,331 @@
+//! The subroutine calls and traps the program is in.
+//!
+//! The LC-3 has no call stack in hardware: JSR and JSRR leave the return address in R7, and
+//! subroutines that call others save it wherever they see fit, often on a stack kept in R6 by
+//! convention. The VM keeps track of the calls (JSR, JSRR) and traps the program entered and did
+//! not return from yet, so errors and debuggers can tell how it got where it is. A RET (JMP R7)
+//! returns from the innermost call whose return address it jumps to, along with the calls inside it
+//! that never returned; one that jumps anywhere else is taken as a plain jump.
+//!
+//! Successive stacks share their frames, so the history can keep the stack of every instruction
+//! cheaply. A stack keeps up to `FRAMES_KEPT` frames, so a runaway recursion does not grow it
+//! forever: past that, its outermost frames are dropped. `stack_return_addresses` looks for return
+//! addresses saved on the R6 stack, for when the tracked calls are not enough, like for programs
+//! that unwind the stack themselves.
+use std::{collections::BTreeMap, fmt, rc::Rc};
+
+use crate::{
+    assembler::{Symbol, debuginfo::DebugInfo},
+    debugger::inspect,
+    instruction::Instruction,
+    registers::Register,
+};
+
+/// How many frames a stack keeps at most.
+pub const FRAMES_KEPT: usize = 1024;
+
+/// How many words above R6 `stack_return_addresses` looks at.
+const STACK_SCAN: u16 = 32;
+
+/// How a frame was entered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entry {
+    /// JSR or JSRR.
+    Call,
+    /// TRAP, with its vector.
+    Trap(u8),
+}
+
+/// A call or trap the program has not returned from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub entry: Entry,
+    /// The address of the instruction that made it.
+    pub call: u16,
+    /// The address of the subroutine, or the vector of the trap.
+    pub target: u16,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    top: Option<Rc<Node>>,
+}
+
+#[derive(Debug)]
+struct Node {
+    frame: Frame,
+    /// How many frames there are from this one out.
+    depth: usize,
+    caller: Option<Rc<Node>>,
+}
+
+impl Drop for Node {
+    // Deep stacks are dropped a frame at a time rather than recursively.
+    fn drop(&mut self) {
+        let mut caller = self.caller.take();
+        while let Some(node) = caller {
+            caller = match Rc::try_unwrap(node) {
+                Ok(mut node) => node.caller.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl CallStack {
+    /// The frames, innermost first.
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut node = self.top.as_deref();
+        while let Some(current) = node {
+            frames.push(current.frame);
+            node = current.caller.as_deref();
+        }
+        frames
+    }
+
+    /// Whether the program is inside a call to the subroutine at `target`.
+    pub fn is_in(&self, target: u16) -> bool {
+        let mut node = self.top.as_deref();
+        while let Some(current) = node {
+            if current.frame.entry == Entry::Call && current.frame.target == target {
+                return true;
+            }
+            node = current.caller.as_deref();
+        }
+        false
+    }
+
+    fn push(&mut self, frame: Frame) {
+        let caller = self.top.take();
+        let depth = caller.as_ref().map_or(0, |caller| caller.depth) + 1;
+        self.top = Some(Rc::new(Node {
+            frame,
+            depth,
+            caller,
+        }));
+        // Dropping the outermost frames takes a new copy of the others, so it is done for half of
+        // them at once.
+        if depth > FRAMES_KEPT {
+            let frames = self.frames();
+            self.top = None;
+            for &frame in frames[..FRAMES_KEPT / 2].iter().rev() {
+                self.push(frame);
+            }
+        }
+    }
+
+    /// Tracks the instruction at `address` that is about to run: a trap is entered.
+    pub fn enter(&mut self, address: u16, instruction: &Instruction) {
+        if let Instruction::Trap { vector } = *instruction {
+            self.push(Frame {
+                entry: Entry::Trap(vector),
+                call: address,
+                target: u16::from(vector),
+            });
+        }
+    }
+
+    /// Tracks the instruction at `address` that ran, leaving `registers`: calls are entered and
+    /// traps and subroutines returned from.
+    pub fn leave(
+        &mut self,
+        address: u16,
+        instruction: &Instruction,
+        registers: &[u16; Register::COUNT],
+    ) {
+        match instruction {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => self.push(Frame {
+                entry: Entry::Call,
+                call: address,
+                target: registers[Register::PC],
+            }),
+            Instruction::Trap { .. } => {
+                let caller = self.top.as_ref().and_then(|top| top.caller.clone());
+                self.top = caller;
+            }
+            Instruction::Jmp { base: 7 } => {
+                let pc = registers[Register::PC];
+                let mut node = self.top.as_ref();
+                while let Some(current) = node {
+                    if current.frame.call.wrapping_add(1) == pc {
+                        self.top = current.caller.clone();
+                        return;
+                    }
+                    node = current.caller.as_ref();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// How the program got to the instruction `word` at `pc`, with the labels in `symbols` and,
+    /// if there is debug info, the source lines of the instructions.
+    pub fn backtrace(
+        &self,
+        pc: u16,
+        word: u16,
+        symbols: &BTreeMap<String, Symbol>,
+        debug: Option<&DebugInfo>,
+    ) -> Backtrace {
+        // Binaries have no labels, but may have the scopes of their debug info.
+        let scope = |address: u16| debug.and_then(|debug| debug.scope_at(address));
+        let place = |address: u16| {
+            let location = inspect::location(address, symbols).or_else(|| {
+                scope(address).map(|scope| match address - scope.start {
+                    0 => scope.name.clone(),
+                    offset => format!("{}+{offset}", scope.name),
+                })
+            });
+            match location {
+                Some(location) => format!("x{address:04X} ({location})"),
+                None => format!("x{address:04X}"),
+            }
+        };
+        // The source line of an address, if it is known.
+        let source = |address: u16| match debug.and_then(|debug| debug.line_at(address)) {
+            Some(source) => format!(", {}:{}", source.file, source.line),
+            None => String::new(),
+        };
+        let mut lines = Vec::new();
+        let frames = self.frames();
+        // Traps run inside the VM, so a program is only ever inside one when it fails there.
+        if !matches!(frames.first(), Some(frame) if frame.entry != Entry::Call) {
+            let text = inspect::describe(word, pc, symbols)
+                .unwrap_or_else(|| format!(".FILL x{word:04X}"));
+            lines.push(format!("at {} {text}{}", place(pc), source(pc)));
+        }
+        for frame in frames {
+            let name = match frame.entry {
+                Entry::Call => inspect::name_of(frame.target, symbols)
+                    .or_else(|| {
+                        scope(frame.target)
+                            .filter(|scope| scope.start == frame.target)
+                            .map(|scope| scope.name.clone())
+                    })
+                    .unwrap_or_else(|| format!("x{:04X}", frame.target)),
+                Entry::Trap(vector) => format!("TRAP x{vector:02X}"),
+            };
+            lines.push(format!(
+                "in {name}, called from {}{}",
+                place(frame.call),
+                source(frame.call)
+            ));
+        }
+        Backtrace(lines)
+    }
+}
+
+/// How the program got to an instruction: where it is, then the calls it is in, innermost first,
+/// one per line.
+#[derive(Clone, PartialEq)]
+pub struct Backtrace(pub Vec<String>);
+
+impl fmt::Debug for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join("; "))
+    }
+}
+
+/// The words just above `r6` that look like return addresses, as the address they are at and
+/// the address of the call before them: words right after a JSR or JSRR.
+pub fn stack_return_addresses(memory: &[u16], r6: u16) -> Vec<(u16, u16)> {
+    (0..STACK_SCAN)
+        .map_while(|offset| r6.checked_add(offset))
+        .filter_map(|slot| {
+            let call = memory[slot as usize].checked_sub(1)?;
+            match Instruction::decode(memory[call as usize]) {
+                Instruction::Jsr { .. } | Instruction::Jsrr { .. } => Some((slot, call)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, error::VMError, vm::VMState};
+
+    /// MAIN calls OUTER, which saves R7 on the R6 stack to call INNER, which runs a trap that does
+    /// not exist.
+    const PROGRAM: &str = "
+        .ORIG x3000
+MAIN    LD R6, STACK
+        JSR OUTER
+        HALT
+OUTER   ADD R6, R6, #-1
+        STR R7, R6, #0
+        JSR INNER
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+INNER   TRAP x26
+        RET
+STACK   .FILL x4000
+        .END";
+
+    #[test]
+    fn backtraces_fatal_errors() {
+        let assembly = assemble(PROGRAM).unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        vm.debug = Some(DebugInfo::new(&assembly, "calls.asm"));
+        vm.symbols = assembly.symbols;
+        let Err(VMError::Crashed { error, backtrace }) = vm.execute() else {
+            panic!("the program did not crash");
+        };
+        assert!(matches!(*error, VMError::UnrecognizedTrapCode(0x26)));
+        assert_eq!(
+            backtrace.0,
+            [
+                "in TRAP x26, called from x3009 (INNER), calls.asm:12",
+                "in INNER, called from x3005 (OUTER+2), calls.asm:8",
+                "in OUTER, called from x3001 (MAIN+1), calls.asm:4",
+            ]
+        );
+        assert_eq!(
+            stack_return_addresses(&vm.memory, vm.registers[6]),
+            [(0x3FFF, 0x3001)]
+        );
+    }
+
+    #[test]
+    fn returns_to_the_frame_of_the_return_address() {
+        let mut calls = CallStack::default();
+        let mut registers = [0; Register::COUNT];
+        for (address, target) in [(0x3000, 0x3100), (0x3101, 0x3200)] {
+            registers[Register::PC] = target;
+            calls.leave(address, &Instruction::Jsr { offset: 0 }, &registers);
+        }
+        // A RET somewhere else is a jump.
+        registers[Register::PC] = 0x3500;
+        calls.leave(0x3201, &Instruction::Jmp { base: 7 }, &registers);
+        assert_eq!(calls.frames().len(), 2);
+        // Returning to the outer caller unwinds the inner call too.
+        registers[Register::PC] = 0x3001;
+        let before = calls.clone();
+        calls.leave(0x3501, &Instruction::Jmp { base: 7 }, &registers);
+        assert!(calls.frames().is_empty());
+        assert_eq!(before.frames()[0].call, 0x3101);
+        assert!(before.is_in(0x3200) && !calls.is_in(0x3200));
+    }
+
+    #[test]
+    fn drops_the_outermost_frames_of_deep_stacks() {
+        let mut calls = CallStack::default();
+        let mut registers = [0; Register::COUNT];
+        registers[Register::PC] = 0x4000;
+        for call in 0..=FRAMES_KEPT as u16 {
+            calls.leave(call, &Instruction::Jsr { offset: 0 }, &registers);
+        }
+        let frames = calls.frames();
+        assert_eq!(frames.len(), FRAMES_KEPT / 2);
+        assert_eq!(frames[0].call, FRAMES_KEPT as u16);
+        // The calls kept can still be returned from.
+        registers[Register::PC] = FRAMES_KEPT as u16 + 1;
+        calls.leave(0x4000, &Instruction::Jmp { base: 7 }, &registers);
+        assert_eq!(calls.frames().len(), FRAMES_KEPT / 2 - 1);
+    }
+}
@@ -0,0 +1,942 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, BufWriter, IsTerminal, Write},
+    net::TcpListener,
+    path::Path,
+};
+
+use crate::{
+    assembler::{
+        Assembly, assemble_in, debuginfo::DebugInfo, formatter::format_source, listing::listing,
+        xref::cross_reference,
+    },
+    compiler::compile,
+    debugger::{
+        Debugger, Program,
+        commands::{self, Session},
+        dap, gdb, inspect, tui,
+    },
+    decompiler::decompile,
+    error::VMError,
+    image::Image,
+    json::Json,
+    lint::{Check, lint_image, lint_source},
+    lsp,
+    optimizer::{Optimized, optimize_image, optimize_source},
+    recording::{
+        Recording, RecordingWriter,
+        diff::{self, Run},
+        query,
+        trace::{self, Tracer},
+        vcd::{Probe, VcdWriter},
+    },
+    repl::{Repl, Reply},
+    stdlib,
+    utils::{disable_input_buffering, read_file, restore_terminal},
+    vm::VMState,
+    watch,
+    watchpoint::{self, Watchpoint},
+};
+
+/// `run <path> [--watch] [--strict] [--watchpoint <read|write|change>:<range>]... [--gdb <port>]`:
+/// runs a binary, or an assembly source (`.asm`) assembled in memory, on a fresh VM. With
+/// `--watch`, runs it again every time the file changes. With `--strict`, words that are not
+/// well-formed instructions stop the program. Every `--watchpoint` logs the accesses of the program
+/// to memory in its range. With `--gdb`, the program waits for a GDB remote protocol client on the
+/// local `port` and runs under its control. With `--record`, what every instruction did is written
+/// to `file`, for `query`. With `--trace`, every executed instruction is logged to `file` as text,
+/// or as JSON Lines with `--trace-format jsonl` or a `.jsonl` file, only those in the ranges of
+/// `--trace-range` or run while the subroutines of `--trace-symbol` are active if any is given.
+/// With `--vcd`, the registers, the words in the ranges of `--vcd-memory` and the keyboard
+/// registers are dumped to `file` as a Value Change Dump, one time step per instruction.
+pub fn run_command(args: &[String]) -> Result<(), VMError> {
+    let mut path = None;
+    let mut watching = false;
+    let mut strict = false;
+    let mut watchpoints = Vec::new();
+    let mut gdb_port = None;
+    let mut record = None;
+    let mut trace = None;
+    let mut trace_format = None;
+    let mut trace_ranges = Vec::new();
+    let mut trace_symbols = Vec::new();
+    let mut vcd = None;
+    let mut vcd_memory = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watching = true,
+            "--gdb" => {
+                let port = args.next().and_then(|port| port.parse::<u16>().ok());
+                gdb_port = Some(port.ok_or_else(|| {
+                    VMError::InvalidArgument("--gdb expects a port number".to_string())
+                })?);
+            }
+            "--strict" => strict = true,
+            "--watchpoint" => {
+                let watchpoint = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument(
+                        "--watchpoint expects <read|write|change>:<range>".to_string(),
+                    )
+                })?;
+                watchpoints.push(watchpoint.as_str());
+            }
+            "--record" => {
+                let file = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--record expects the file to write".to_string())
+                })?;
+                record = Some(file.as_str());
+            }
+            "--trace" => {
+                let file = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--trace expects the file to write".to_string())
+                })?;
+                trace = Some(file.as_str());
+            }
+            "--trace-format" => {
+                let format = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--trace-format expects text or jsonl".to_string())
+                })?;
+                trace_format =
+                    Some(trace::Format::parse(format).map_err(VMError::InvalidArgument)?);
+            }
+            "--trace-range" => {
+                let range = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--trace-range expects a range".to_string())
+                })?;
+                trace_ranges.push(range.as_str());
+            }
+            "--trace-symbol" => {
+                let symbol = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--trace-symbol expects a label".to_string())
+                })?;
+                trace_symbols.push(symbol.as_str());
+            }
+            "--vcd" => {
+                let file = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--vcd expects the file to write".to_string())
+                })?;
+                vcd = Some(file.as_str());
+            }
+            "--vcd-memory" => {
+                let range = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--vcd-memory expects a range".to_string())
+                })?;
+                vcd_memory.push(range.as_str());
+            }
+            _ if path.is_none() => path = Some(arg.as_str()),
+            _ => {
+                return Err(VMError::InvalidArgument(format!(
+                    "unexpected argument `{arg}`"
+                )));
+            }
+        }
+    }
+    let path =
+        path.ok_or_else(|| VMError::InvalidArgument("missing the file to run".to_string()))?;
+    let trace = match trace {
+        Some(file) => Some(TraceOptions {
+            file,
+            format: trace_format.unwrap_or(match file.ends_with(".jsonl") {
+                true => trace::Format::JsonLines,
+                false => trace::Format::Text,
+            }),
+            ranges: trace_ranges,
+            symbols: trace_symbols,
+        }),
+        None if trace_format.is_some() || !trace_ranges.is_empty() || !trace_symbols.is_empty() => {
+            return Err(VMError::InvalidArgument(
+                "--trace-format, --trace-range and --trace-symbol need --trace".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let vcd = match vcd {
+        Some(file) => Some((file, vcd_memory)),
+        None if !vcd_memory.is_empty() => {
+            return Err(VMError::InvalidArgument(
+                "--vcd-memory needs --vcd".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let recorded = record.is_some() || trace.is_some() || vcd.is_some();
+    if watching && (!watchpoints.is_empty() || gdb_port.is_some() || recorded) {
+        return Err(VMError::InvalidArgument(
+            "--watchpoint, --gdb, --record, --trace and --vcd are not supported with --watch"
+                .to_string(),
+        ));
+    }
+    if gdb_port.is_some() && recorded {
+        return Err(VMError::InvalidArgument(
+            "--record, --trace and --vcd are not supported with --gdb".to_string(),
+        ));
+    }
+    if let Some(port) = gdb_port {
+        gdb(path, strict, port)
+    } else if watching {
+        watch::watch(path, strict, load_program)
+    } else {
+        run(path, strict, &watchpoints, record, trace, vcd)
+    }
+}
+
+/// Runs the program in `path` on a fresh VM, in strict mode if `strict`, logging the accesses that
+/// match `watchpoints` (`<read|write|change>:<range>`) to stderr and recording the run to the file
+/// `record`, tracing it as `trace` says and dumping it to the file of `vcd` with the memory ranges
+/// next to it if given.
+pub fn run(
+    path: &str,
+    strict: bool,
+    watchpoints: &[&str],
+    record: Option<&str>,
+    trace: Option<TraceOptions>,
+    vcd: Option<(&str, Vec<&str>)>,
+) -> Result<(), VMError> {
+    // Read the file, with its labels for the watchpoints and backtraces.
+    let program = load_debug_program(path)?;
+
+    // Initialize VM state with default values
+    let mut vm = VMState::init()?;
+    vm.strict = strict;
+    vm.watchpoints = parse_watchpoints(watchpoints, &program)?;
+    if let Some(record) = record {
+        let file =
+            fs::File::create(record).map_err(|e| VMError::CouldNotWriteFile(e.to_string()))?;
+        let writer = RecordingWriter::new(BufWriter::new(file), &program.symbols)?;
+        vm.recorders.push(Box::new(writer));
+    }
+    if let Some(trace) = trace {
+        let filter = trace::Filter {
+            ranges: trace
+                .ranges
+                .iter()
+                .map(|text| watchpoint::parse_range(text, &program.symbols))
+                .collect::<Result<_, _>>()
+                .map_err(VMError::InvalidArgument)?,
+            subroutines: trace
+                .symbols
+                .iter()
+                .map(|name| {
+                    program
+                        .symbols
+                        .get(*name)
+                        .map(|symbol| symbol.address)
+                        .ok_or_else(|| {
+                            VMError::InvalidArgument(format!(
+                                "`{name}` is not a label of the program"
+                            ))
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        let file =
+            fs::File::create(trace.file).map_err(|e| VMError::CouldNotWriteFile(e.to_string()))?;
+        let tracer = Tracer::new(
+            BufWriter::new(file),
+            trace.format,
+            filter,
+            program.symbols.clone(),
+            program.debug.clone(),
+        );
+        vm.recorders.push(Box::new(tracer));
+    }
+    if let Some((path, ranges)) = vcd {
+        let image = Image::from_bytes(&program.image)?;
+        let mut probes = Vec::new();
+        for text in ranges {
+            let (start, end) = watchpoint::parse_range(text, &program.symbols)
+                .map_err(VMError::InvalidArgument)?;
+            probes.extend((start..=end).map(|address| {
+                Probe {
+                    name: inspect::name_of(address, &program.symbols)
+                        .unwrap_or_else(|| format!("x{address:04X}")),
+                    address,
+                    initial: image.get(address).unwrap_or(0),
+                }
+            }));
+        }
+        let file = fs::File::create(path).map_err(|e| VMError::CouldNotWriteFile(e.to_string()))?;
+        vm.recorders
+            .push(Box::new(VcdWriter::new(BufWriter::new(file), probes)));
+    }
+
+    // The labels and source lines show where backtraces are.
+    vm.symbols = program.symbols;
+    vm.debug = program.debug;
+    vm.run(program.image)
+}
+
+/// How `run --trace` traces the program.
+pub struct TraceOptions<'a> {
+    /// The file to write the trace to.
+    pub file: &'a str,
+    pub format: trace::Format,
+    /// The `--trace-range` and `--trace-symbol` options, which may use the labels of the program.
+    pub ranges: Vec<&'a str>,
+    pub symbols: Vec<&'a str>,
+}
+
+/// `query <recording> <question>`: answers a question about the run recorded with `run --record`.
+pub fn query_command(args: &[String]) -> Result<(), VMError> {
+    let [path, question @ ..] = args else {
+        return Err(VMError::WrongArgumentsLen(2, args.len()));
+    };
+    if question.is_empty() {
+        return Err(VMError::InvalidArgument(format!(
+            "missing the question; the questions are {}",
+            query::QUESTIONS
+        )));
+    }
+    let text = fs::read_to_string(path).map_err(|e| VMError::CouldNotReadFile(e.to_string()))?;
+    let recording = Recording::parse(&text).map_err(VMError::MalformedRecording)?;
+    let question: Vec<&str> = question.iter().map(String::as_str).collect();
+    let answer = query::answer(&recording, &question).map_err(VMError::InvalidArgument)?;
+    println!("{answer}");
+    Ok(())
+}
+
+/// `trace-diff <recording> <recording> [--context <count>]`: shows where two runs recorded with
+/// `run --record` diverge, with `count` instructions around it (3 by default). Like `diff`, it fails
+/// when they do.
+pub fn trace_diff_command(args: &[String]) -> Result<(), VMError> {
+    let mut paths = Vec::new();
+    let mut context = 3;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                let count = args.next().and_then(|count| count.parse().ok());
+                context = count.ok_or_else(|| {
+                    VMError::InvalidArgument(
+                        "--context expects a number of instructions".to_string(),
+                    )
+                })?;
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    let [path_a, path_b] = paths[..] else {
+        return Err(VMError::WrongArgumentsLen(2, paths.len()));
+    };
+    let read = |path: &str| {
+        let text =
+            fs::read_to_string(path).map_err(|e| VMError::CouldNotReadFile(e.to_string()))?;
+        Recording::parse(&text).map_err(|e| VMError::MalformedRecording(format!("{path}: {e}")))
+    };
+    let (recording_a, recording_b) = (read(path_a)?, read(path_b)?);
+    let a = Run {
+        name: path_a,
+        recording: &recording_a,
+    };
+    let b = Run {
+        name: path_b,
+        recording: &recording_b,
+    };
+    match diff::diff(&a, &b, context) {
+        Ok(same) => {
+            println!("{same}");
+            Ok(())
+        }
+        Err(divergence) => {
+            println!("{divergence}");
+            Err(VMError::RunsDiverge)
+        }
+    }
+}
+
+/// Runs the program in `path` under the control of a GDB remote protocol client, which connects to
+/// the local `port`. The program's console stays the terminal.
+pub fn gdb(path: &str, strict: bool, port: u16) -> Result<(), VMError> {
+    let program = load_debug_program(path)?;
+    let mut vm = VMState::init()?;
+    vm.strict = strict;
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| VMError::ProtocolError(format!("could not listen on port {port}: {e}")))?;
+    let address = listener
+        .local_addr()
+        .map_err(|e| VMError::ProtocolError(e.to_string()))?;
+    eprintln!("waiting for a GDB client on {address}");
+    let terminal_setup = match std::io::stdin().is_terminal() {
+        true => Some(disable_input_buffering()?),
+        false => None,
+    };
+    let result = gdb::serve(Debugger::new(vm, program), &listener);
+    if let Some(setup) = terminal_setup {
+        restore_terminal(setup)?;
+    }
+    result
+}
+
+/// Parses the `--watchpoint` options, which may use the labels of `program`.
+fn parse_watchpoints(watchpoints: &[&str], program: &Program) -> Result<Vec<Watchpoint>, VMError> {
+    watchpoints
+        .iter()
+        .map(|text| Watchpoint::parse(text, &program.symbols).map_err(VMError::InvalidArgument))
+        .collect()
+}
+
+/// `debug <path> [--strict] [--watchpoint <read|write|change>:<range>]... [--tui]`: runs a binary
+/// or an assembly source under the command line debugger, reading commands from stdin, or under the
+/// full-screen one with `--tui`. The labels of assembly sources can be used as addresses.
+pub fn debug_command(args: &[String]) -> Result<(), VMError> {
+    let mut path = None;
+    let mut strict = false;
+    let mut watchpoints = Vec::new();
+    let mut full_screen = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict" => strict = true,
+            "--watchpoint" => {
+                let watchpoint = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument(
+                        "--watchpoint expects <read|write|change>:<range>".to_string(),
+                    )
+                })?;
+                watchpoints.push(watchpoint.as_str());
+            }
+            "--tui" => full_screen = true,
+            _ if path.is_none() => path = Some(arg.as_str()),
+            _ => {
+                return Err(VMError::InvalidArgument(format!(
+                    "unexpected argument `{arg}`"
+                )));
+            }
+        }
+    }
+    let path =
+        path.ok_or_else(|| VMError::InvalidArgument("missing the file to debug".to_string()))?;
+    let program = load_debug_program(path)?;
+    let mut vm = VMState::init()?;
+    vm.strict = strict;
+    vm.watchpoints = parse_watchpoints(&watchpoints, &program)?;
+    if full_screen {
+        if !std::io::stdin().is_terminal() {
+            return Err(VMError::InvalidArgument(
+                "--tui needs a terminal".to_string(),
+            ));
+        }
+        return tui::run(Debugger::new(vm, program));
+    }
+    let terminal = std::io::stdin().is_terminal();
+    let mut session = Session::new(Debugger::new(vm, program), terminal);
+    println!("Debugging {path}. Type help for the commands.");
+    println!("{}", session.location());
+    loop {
+        print!("(debug) ");
+        std::io::stdout()
+            .flush()
+            .map_err(|e| VMError::InvalidArgument(e.to_string()))?;
+        // Stdin is not kept locked, as the program reads its own input from it while it runs.
+        let mut line = String::new();
+        let read = std::io::stdin()
+            .read_line(&mut line)
+            .map_err(|e| VMError::InvalidArgument(e.to_string()))?;
+        if read == 0 {
+            println!();
+            return Ok(());
+        }
+        match session.execute(&line) {
+            Ok(commands::Reply::Show(text)) if text.is_empty() => {}
+            Ok(commands::Reply::Show(text)) => println!("{text}"),
+            Ok(commands::Reply::Quit) => return Ok(()),
+            Err(message) => eprintln!("error: {message}"),
+        }
+    }
+}
+
+/// `dap`: runs the debug adapter, talking to the editor over stdin and stdout with the Debug Adapter
+/// Protocol. The program to debug is given by the editor's `launch` request.
+pub fn dap_command(args: &[String]) -> Result<(), VMError> {
+    if !args.is_empty() {
+        return Err(VMError::WrongArgumentsLen(0, args.len()));
+    }
+    dap::serve(BufReader::new(std::io::stdin()), std::io::stdout().lock())
+}
+
+/// Reads the program in `path` for the debugger, with the labels of assembly sources and the debug
+/// info of sources and of binaries assembled with it.
+pub fn load_debug_program(path: &str) -> Result<Program, VMError> {
+    if path.ends_with(".asm") {
+        let source = read_source(path)?;
+        let assembly = assemble_source(path, &source)?;
+        return Ok(Program::assembled(assembly, path));
+    }
+    Program::binary(read_file(path)?, path).map_err(VMError::InvalidArgument)
+}
+
+/// Reads the program image in `path`, assembling it first when it is an assembly source, along with
+/// the paths of the local files it includes.
+pub fn load_program(path: &str) -> Result<(Vec<u8>, Vec<String>), VMError> {
+    if !path.ends_with(".asm") {
+        return Ok((read_file(path)?, Vec::new()));
+    }
+    let assembly = assemble_source(path, &read_source(path)?)?;
+    let mut included = Vec::new();
+    for origin in assembly.placements.iter().filter_map(|p| p.origin.as_ref()) {
+        let bundled = matches!(stdlib::find(&origin.file), Ok(Some(_)));
+        if !bundled && !included.contains(&origin.file) {
+            included.push(origin.file.clone());
+        }
+    }
+    Ok((assembly.to_bytes(), included))
+}
+
+/// `decompile <path>`: prints the pseudocode for the binary in `path`.
+pub fn decompile_command(args: &[String]) -> Result<(), VMError> {
+    let [path] = args else {
+        return Err(VMError::WrongArgumentsLen(1, args.len()));
+    };
+    let image = Image::from_bytes(&read_file(path)?)?;
+    print!("{}", decompile(&image));
+    Ok(())
+}
+
+/// `assemble <source> [-o <object>] [--listing] [--xref] [--debug-info]`: assembles `source` into an
+/// object file, which by default is written next to it with the `.obj` extension. `--listing` also
+/// writes a `.lst` listing, `--xref` a `.xref` cross-reference report and `--debug-info` (`-g`) a
+/// `.dbg` file with the source lines of the words, all next to the object file.
+pub fn assemble_command(args: &[String]) -> Result<(), VMError> {
+    let mut source_path = None;
+    let mut output = None;
+    let mut with_listing = false;
+    let mut with_xref = false;
+    let mut with_debug_info = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let path = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("-o expects the path of the output file".to_string())
+                })?;
+                output = Some(path.clone());
+            }
+            "--listing" => with_listing = true,
+            "--xref" => with_xref = true,
+            "--debug-info" | "-g" => with_debug_info = true,
+            _ if source_path.is_none() => source_path = Some(arg.clone()),
+            _ => {
+                return Err(VMError::InvalidArgument(format!(
+                    "unexpected argument `{arg}`"
+                )));
+            }
+        }
+    }
+    let source_path = source_path
+        .ok_or_else(|| VMError::InvalidArgument("missing the source file".to_string()))?;
+    let output = output.unwrap_or_else(|| with_extension(&source_path, "obj"));
+
+    let source = read_source(&source_path)?;
+    let assembly = assemble_source(&source_path, &source)?;
+    write_file(&output, &assembly.to_bytes())?;
+    if with_listing {
+        write_file(
+            &with_extension(&output, "lst"),
+            listing(&source, &assembly).as_bytes(),
+        )?;
+    }
+    if with_xref {
+        write_file(
+            &with_extension(&output, "xref"),
+            cross_reference(&source, &assembly).as_bytes(),
+        )?;
+    }
+    if with_debug_info {
+        write_file(
+            &with_extension(&output, "dbg"),
+            DebugInfo::new(&assembly, &source_path).to_text().as_bytes(),
+        )?;
+    }
+    Ok(())
+}
+
+/// `lint <path> [--format text|json] [--allow <check>[,<check>...]]`: runs the linter over `path`, which is
+/// treated as an object file if it has the `.obj` extension and as assembly source otherwise. Fails if
+/// anything is found.
+pub fn lint_command(args: &[String]) -> Result<(), VMError> {
+    let mut path = None;
+    let mut json = false;
+    let mut allowed = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().map(String::as_str) {
+                Some("text") => json = false,
+                Some("json") => json = true,
+                _ => {
+                    return Err(VMError::InvalidArgument(
+                        "--format expects `text` or `json`".to_string(),
+                    ));
+                }
+            },
+            "--allow" => {
+                let names = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("--allow expects the names of the checks".to_string())
+                })?;
+                for name in names.split(',') {
+                    let check = Check::from_name(name).ok_or_else(|| {
+                        VMError::InvalidArgument(format!("unknown check `{name}`"))
+                    })?;
+                    allowed.push(check);
+                }
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => {
+                return Err(VMError::InvalidArgument(format!(
+                    "unexpected argument `{arg}`"
+                )));
+            }
+        }
+    }
+    let path =
+        path.ok_or_else(|| VMError::InvalidArgument("missing the file to lint".to_string()))?;
+
+    let mut findings = if Path::new(&path).extension().is_some_and(|e| e == "obj") {
+        lint_image(&Image::from_bytes(&read_file(&path)?)?)
+    } else {
+        let source = read_source(&path)?;
+        lint_source(&source, Path::new(&path).parent()).map_err(|diagnostics| {
+            for diagnostic in diagnostics {
+                eprintln!("{path}:{diagnostic}");
+            }
+            VMError::AssemblyFailed(path.clone())
+        })?
+    };
+    findings.retain(|finding| !allowed.contains(&finding.check));
+
+    if json {
+        println!(
+            "{}",
+            Json::Array(findings.iter().map(|f| f.to_json()).collect())
+        );
+    } else {
+        for finding in &findings {
+            let location = match (finding.line, finding.address) {
+                (Some(line), _) => line.to_string(),
+                (None, Some(address)) => format!("x{address:04X}"),
+                (None, None) => String::new(),
+            };
+            println!(
+                "{path}:{location}: warning[{}]: {}",
+                finding.check.name(),
+                finding.message
+            );
+        }
+    }
+    if findings.is_empty() {
+        Ok(())
+    } else {
+        Err(VMError::LintFindings(findings.len()))
+    }
+}
+
+/// `fmt [--check] <source>...`: rewrites every source file in the standard layout. With `--check` the files
+/// are left untouched, the ones that are not formatted are listed and the command fails if there is any.
+pub fn fmt_command(args: &[String]) -> Result<(), VMError> {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        return Err(VMError::InvalidArgument(
+            "missing the source files to format".to_string(),
+        ));
+    }
+
+    let mut unformatted = 0;
+    for path in paths {
+        let source = read_source(path)?;
+        let formatted = format_source(&source).map_err(|diagnostics| {
+            for diagnostic in diagnostics {
+                eprintln!("{path}:{diagnostic}");
+            }
+            VMError::AssemblyFailed(path.clone())
+        })?;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{path} is not formatted");
+            unformatted += 1;
+        } else {
+            write_file(path, formatted.as_bytes())?;
+        }
+    }
+    if unformatted > 0 {
+        Err(VMError::NotFormatted(unformatted))
+    } else {
+        Ok(())
+    }
+}
+
+/// `opt <path> [-o <output>]`: optimizes the program in `path`, an object file if it has the `.obj`
+/// extension and assembly source otherwise, and writes the result in the same form (by default next to
+/// it, as `<name>.opt.obj` or `<name>.opt.asm`). Prints every rewrite and how much smaller the program
+/// got.
+pub fn opt_command(args: &[String]) -> Result<(), VMError> {
+    let mut path = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let out = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("-o expects the path of the output file".to_string())
+                })?;
+                output = Some(out.clone());
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => {
+                return Err(VMError::InvalidArgument(format!(
+                    "unexpected argument `{arg}`"
+                )));
+            }
+        }
+    }
+    let path =
+        path.ok_or_else(|| VMError::InvalidArgument("missing the file to optimize".to_string()))?;
+    let is_object = Path::new(&path).extension().is_some_and(|e| e == "obj");
+
+    let optimized = if is_object {
+        optimize_image(&Image::from_bytes(&read_file(&path)?)?)
+    } else {
+        optimize_source(&read_source(&path)?, Path::new(&path).parent())
+    }
+    .map_err(|diagnostics| {
+        for diagnostic in diagnostics {
+            eprintln!("{path}:{diagnostic}");
+        }
+        VMError::AssemblyFailed(path.clone())
+    })?;
+
+    let Optimized {
+        source,
+        assembly,
+        rewrites,
+        before,
+        after,
+    } = optimized;
+    for (rewrite, address) in &rewrites {
+        match (is_object, address) {
+            (true, Some(address)) => println!("{path}:x{address:04X}: {}", rewrite.description),
+            _ => println!("{path}:{}: {}", rewrite.line, rewrite.description),
+        }
+    }
+    println!(
+        "instructions: {} -> {} (-{})",
+        before.instructions,
+        after.instructions,
+        before.instructions.saturating_sub(after.instructions)
+    );
+    println!(
+        "size: {} -> {} words (-{})",
+        before.words,
+        after.words,
+        before.words.saturating_sub(after.words)
+    );
+
+    if is_object {
+        let output = output.unwrap_or_else(|| with_extension(&path, "opt.obj"));
+        write_file(&output, &assembly.to_bytes())
+    } else {
+        let output = output.unwrap_or_else(|| with_extension(&path, "opt.asm"));
+        write_file(&output, source.as_bytes())
+    }
+}
+
+/// `compile <source> [-o <output>]`: compiles a program in the C-like language to LC-3 assembly,
+/// written next to the source with the `.asm` extension unless `-o` says otherwise.
+pub fn compile_command(args: &[String]) -> Result<(), VMError> {
+    let mut path = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let out = args.next().ok_or_else(|| {
+                    VMError::InvalidArgument("-o expects the path of the output file".to_string())
+                })?;
+                output = Some(out.clone());
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => {
+                return Err(VMError::InvalidArgument(format!(
+                    "unexpected argument `{arg}`"
+                )));
+            }
+        }
+    }
+    let path =
+        path.ok_or_else(|| VMError::InvalidArgument("missing the file to compile".to_string()))?;
+    let assembly = compile(&read_source(&path)?).map_err(|diagnostics| {
+        for diagnostic in diagnostics {
+            eprintln!("{path}:{diagnostic}");
+        }
+        VMError::AssemblyFailed(path.clone())
+    })?;
+    let output = output.unwrap_or_else(|| with_extension(&path, "asm"));
+    write_file(&output, assembly.as_bytes())
+}
+
+/// `stdlib [module]`: lists the modules of the bundled standard library and their routines, or
+/// prints the source of one of them.
+pub fn stdlib_command(args: &[String]) -> Result<(), VMError> {
+    match args {
+        [] => {
+            println!("LC-3 standard library {}", stdlib::VERSION);
+            for module in &stdlib::MODULES {
+                println!();
+                println!("std/{}.asm: {}", module.name, module.summary());
+                for (routine, description) in module.routines() {
+                    println!("    {routine:<14} {description}");
+                }
+            }
+            Ok(())
+        }
+        [name] => {
+            let include = if name.starts_with("std") {
+                name.clone()
+            } else {
+                format!("std/{name}")
+            };
+            match stdlib::find(&include) {
+                Ok(Some(module)) => {
+                    print!("{}", module.source);
+                    Ok(())
+                }
+                Ok(None) => Err(VMError::InvalidArgument(format!(
+                    "`{name}` is not a module of the standard library"
+                ))),
+                Err(message) => Err(VMError::InvalidArgument(message)),
+            }
+        }
+        _ => Err(VMError::WrongArgumentsLen(1, args.len())),
+    }
+}
+
+/// `lsp`: runs the language server for LC-3 assembly, talking to the editor over stdin and stdout.
+pub fn lsp_command(args: &[String]) -> Result<(), VMError> {
+    if !args.is_empty() {
+        return Err(VMError::WrongArgumentsLen(0, args.len()));
+    }
+    lsp::serve(std::io::stdin().lock(), std::io::stdout().lock())
+}
+
+/// `repl`: reads LC-3 assembly from stdin a line at a time, running each instruction as soon as it is
+/// typed and showing what it changed.
+pub fn repl_command(args: &[String]) -> Result<(), VMError> {
+    if !args.is_empty() {
+        return Err(VMError::WrongArgumentsLen(0, args.len()));
+    }
+    let mut repl = Repl::new(VMState::init()?);
+    println!("LC-3 REPL. Type :help for the commands, :quit to leave.");
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("{}", repl.prompt());
+        std::io::stdout()
+            .flush()
+            .map_err(|e| VMError::InvalidArgument(e.to_string()))?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        let line = line.map_err(|e| VMError::InvalidArgument(e.to_string()))?;
+        match repl.eval(&line) {
+            Ok(Reply::Show(text)) if text.is_empty() => {}
+            Ok(Reply::Show(text)) => println!("{text}"),
+            Ok(Reply::Quit) => return Ok(()),
+            Err(message) => eprintln!("error: {message}"),
+        }
+    }
+}
+
+/// Reads a source file as text.
+pub fn read_source(path: &str) -> Result<String, VMError> {
+    fs::read_to_string(path).map_err(|e| VMError::CouldNotReadFile(e.to_string()))
+}
+
+/// Assembles `source`, reporting its errors on stderr prefixed with `path`. Files it includes are
+/// looked up next to `path`.
+pub fn assemble_source(path: &str, source: &str) -> Result<Assembly, VMError> {
+    assemble_in(source, Path::new(path).parent()).map_err(|diagnostics| {
+        for diagnostic in diagnostics {
+            eprintln!("{path}:{diagnostic}");
+        }
+        VMError::AssemblyFailed(path.to_string())
+    })
+}
+
+fn write_file(path: &str, content: &[u8]) -> Result<(), VMError> {
+    fs::write(path, content).map_err(|e| VMError::CouldNotWriteFile(e.to_string()))
+}
+
+/// `path` with its extension replaced by (or extended with) `extension`.
+fn with_extension(path: &str, extension: &str) -> String {
+    Path::new(path)
+        .with_extension(extension)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const SOURCE: &str = ".ORIG x3000\nMAIN LEA R0, TEXT\nPUTS\nHALT\nTEXT .STRINGZ \"hi\"\n.END\n";
+
+    /// Writes `content` to a file of the temporary directory named `name`, and returns its path.
+    fn temp_file(name: &str, content: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("basic-vm-cli-{}-{name}", std::process::id()));
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn assembles_sources_and_reads_binaries() {
+        let image = assemble(SOURCE).unwrap().to_bytes();
+        let source = temp_file("program.asm", SOURCE.as_bytes());
+        let binary = temp_file("program.obj", &image);
+        assert_eq!(load_program(&source).unwrap(), (image.clone(), Vec::new()));
+        assert_eq!(load_program(&binary).unwrap().0, image);
+        let program = load_debug_program(&source).unwrap();
+        assert_eq!(program.image, image);
+        assert_eq!(program.symbols["TEXT"].address, 0x3003);
+        assert!(program.debug.is_some());
+        // A binary is read as it is, whatever it contains.
+        let text = temp_file("text.obj", SOURCE.as_bytes());
+        assert_eq!(load_program(&text).unwrap().0, SOURCE.as_bytes());
+        for path in [source, binary, text] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn lists_the_local_files_a_source_includes() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-cli-include-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("dbl.asm"), "DBL ADD R0, R0, R0\n    RET\n").unwrap();
+        let source = dir.join("main.asm");
+        fs::write(
+            &source,
+            ".ORIG x3000\nJSR DBL\nHALT\n.INCLUDE \"dbl.asm\"\n.INCLUDE \"std/math.asm\"\n.END",
+        )
+        .unwrap();
+        let (_, included) = load_program(&source.to_string_lossy()).unwrap();
+        assert_eq!(included, vec![dir.join("dbl.asm").to_string_lossy()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_sources_that_do_not_assemble() {
+        let source = temp_file("broken.asm", b".ORIG x3000\nADD R9, R0, R0\n.END\n");
+        assert!(matches!(
+            load_program(&source),
+            Err(VMError::AssemblyFailed(path)) if path == source
+        ));
+        fs::remove_file(&source).unwrap();
+        assert!(matches!(
+            load_program(&source),
+            Err(VMError::CouldNotReadFile(_))
+        ));
+    }
+}
@@ -0,0 +1,698 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::Diagnostic,
+    compiler::{
+        parser::{BinaryOp, Declaration, Expr, ExprKind, Function, Item, Position, Stmt, UnaryOp},
+        runtime::RUNTIME,
+    },
+};
+
+/// Functions the VM provides through its trap routines, with the number of arguments they take.
+const BUILTINS: [(&str, usize); 4] = [("getc", 0), ("putc", 1), ("puts", 1), ("halt", 0)];
+
+/// Where the stack starts: it grows down from right below the device registers.
+const STACK_TOP: u16 = 0xFE00;
+
+#[derive(Clone, Copy)]
+struct Local {
+    /// Offset from the frame pointer (R5) of the variable, or of the first element of an array.
+    offset: i32,
+    is_array: bool,
+}
+
+/// Generates the assembly of a whole program.
+pub fn generate(items: &[Item]) -> Result<String, Diagnostic> {
+    let mut generator = Generator::default();
+    generator.declare(items)?;
+    generator.lines.push(".ORIG x3000".to_string());
+    generator.load_literal(6, &format!("x{STACK_TOP:04X}"));
+    generator.emit("ADD R5, R6, #0");
+    generator.call("F_main");
+    generator.emit("HALT");
+    for item in items {
+        if let Item::Function(function) = item {
+            generator.function(function)?;
+        }
+    }
+    generator.lines.extend(RUNTIME.lines().map(str::to_string));
+    for item in items {
+        if let Item::Global(declaration) = item {
+            let directive = match &declaration.size {
+                Some(size) => format!(".BLKW #{}", generator.array_size(size)?),
+                None => {
+                    let value = match &declaration.value {
+                        Some(value) => generator.constant(value)?,
+                        None => 0,
+                    };
+                    format!(".FILL x{:04X}", value as u16)
+                }
+            };
+            generator
+                .lines
+                .push(format!("G_{} {directive}", declaration.name));
+        }
+    }
+    for (index, text) in generator.strings.iter().enumerate() {
+        generator
+            .lines
+            .push(format!("S_{index} .STRINGZ \"{}\"", escape(text)));
+    }
+    generator.lines.push(".END".to_string());
+    Ok(generator.lines.join("\n"))
+}
+
+#[derive(Default)]
+struct Generator {
+    lines: Vec<String>,
+    /// How many labels were made up so far, to keep them unique.
+    labels: usize,
+    constants: BTreeMap<String, i32>,
+    /// Global variables, and whether they are arrays.
+    globals: BTreeMap<String, bool>,
+    /// Functions and the number of parameters they take.
+    functions: BTreeMap<String, usize>,
+    strings: Vec<String>,
+
+    // The function being generated.
+    function: String,
+    scopes: Vec<BTreeMap<String, Local>>,
+    /// How many words of locals the frame holds.
+    frame_size: i32,
+    /// The labels `continue` and `break` go to in the loops around the current statement.
+    loops: Vec<(String, String)>,
+}
+
+impl Generator {
+    /// Collects every top-level name, so functions can be called before they are defined.
+    fn declare(&mut self, items: &[Item]) -> Result<(), Diagnostic> {
+        for item in items {
+            let (name, position) = match item {
+                Item::Const(name, _, position) => (name, position),
+                Item::Global(declaration) => (&declaration.name, &declaration.position),
+                Item::Function(function) => (&function.name, &function.position),
+            };
+            if self.constants.contains_key(name)
+                || self.globals.contains_key(name)
+                || self.functions.contains_key(name)
+                || BUILTINS.iter().any(|(builtin, _)| builtin == name)
+            {
+                return Err(error(*position, format!("`{name}` is already defined")));
+            }
+            match item {
+                Item::Const(name, value, _) => {
+                    let value = self.constant(value)?;
+                    self.constants.insert(name.clone(), value);
+                }
+                Item::Global(declaration) => {
+                    self.globals
+                        .insert(name.clone(), declaration.size.is_some());
+                }
+                Item::Function(function) => {
+                    self.functions.insert(name.clone(), function.params.len());
+                }
+            }
+        }
+        match self.functions.get("main") {
+            Some(0) => Ok(()),
+            Some(_) => {
+                let position = items
+                    .iter()
+                    .find_map(|item| match item {
+                        Item::Function(function) if function.name == "main" => {
+                            Some(function.position)
+                        }
+                        _ => None,
+                    })
+                    .expect("main is defined");
+                Err(error(position, "`main` takes no parameters".to_string()))
+            }
+            None => Err(Diagnostic::new(
+                1,
+                None,
+                "missing the `main` function".to_string(),
+            )),
+        }
+    }
+
+    /// Evaluates an expression made only of numbers and constants.
+    fn constant(&self, expr: &Expr) -> Result<i32, Diagnostic> {
+        let value = match &expr.kind {
+            ExprKind::Number(value) => *value,
+            ExprKind::Variable(name) if self.constants.contains_key(name) => self.constants[name],
+            ExprKind::Unary(op, operand) => {
+                let value = self.constant(operand)? as i16;
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as i16,
+                    UnaryOp::Complement => !value,
+                }
+                .into()
+            }
+            ExprKind::Binary(op, left, right) => {
+                let (a, b) = (self.constant(left)? as i16, self.constant(right)? as i16);
+                match op {
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div if b == 0 => 0,
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Mod if b == 0 => a,
+                    BinaryOp::Mod => a.wrapping_rem(b),
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::Equal => (a == b) as i16,
+                    BinaryOp::NotEqual => (a != b) as i16,
+                    BinaryOp::Less => (a < b) as i16,
+                    BinaryOp::LessEqual => (a <= b) as i16,
+                    BinaryOp::Greater => (a > b) as i16,
+                    BinaryOp::GreaterEqual => (a >= b) as i16,
+                    BinaryOp::And => (a != 0 && b != 0) as i16,
+                    BinaryOp::Or => (a != 0 || b != 0) as i16,
+                }
+                .into()
+            }
+            _ => {
+                return Err(error(
+                    expr.position,
+                    "expected a constant expression".to_string(),
+                ));
+            }
+        };
+        Ok(value)
+    }
+
+    fn array_size(&self, size: &Expr) -> Result<i32, Diagnostic> {
+        match self.constant(size)? {
+            size @ 1..=0x7FFF => Ok(size),
+            _ => Err(error(
+                size.position,
+                "the size of an array must be positive".to_string(),
+            )),
+        }
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        self.lines.push(format!("    {instruction}"));
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("L_{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.lines.push(label.to_string());
+    }
+
+    /// Loads a word that does not fit in an instruction, like an address or a big number, by
+    /// placing it right after the LD that reads it and jumping over it.
+    fn load_literal(&mut self, reg: u8, value: &str) {
+        let literal = self.new_label();
+        let after = self.new_label();
+        self.emit(&format!("LD R{reg}, {literal}"));
+        self.emit(&format!("BRnzp {after}"));
+        self.lines.push(format!("{literal} .FILL {value}"));
+        self.place(&after);
+    }
+
+    fn load_constant(&mut self, reg: u8, value: i32) {
+        self.emit(&format!("AND R{reg}, R{reg}, #0"));
+        if (-16..=15).contains(&value) {
+            if value != 0 {
+                self.emit(&format!("ADD R{reg}, R{reg}, #{value}"));
+            }
+        } else {
+            self.load_literal(reg, &format!("x{:04X}", value as u16));
+        }
+    }
+
+    /// Calls a subroutine through a register, so it can be anywhere in memory.
+    fn call(&mut self, label: &str) {
+        self.load_literal(3, label);
+        self.emit("JSRR R3");
+    }
+
+    fn push(&mut self) {
+        self.emit("ADD R6, R6, #-1");
+        self.emit("STR R0, R6, #0");
+    }
+
+    fn pop(&mut self, reg: u8) {
+        self.emit(&format!("LDR R{reg}, R6, #0"));
+        self.emit("ADD R6, R6, #1");
+    }
+
+    /// Moves the stack pointer by `words`.
+    fn adjust_stack(&mut self, words: i32) {
+        if (-16..=15).contains(&words) {
+            if words != 0 {
+                self.emit(&format!("ADD R6, R6, #{words}"));
+            }
+        } else {
+            self.load_constant(3, words);
+            self.emit("ADD R6, R6, R3");
+        }
+    }
+
+    /// Puts the address of a frame slot in `reg`.
+    fn frame_address(&mut self, reg: u8, offset: i32) {
+        if (-16..=15).contains(&offset) {
+            self.emit(&format!("ADD R{reg}, R5, #{offset}"));
+        } else {
+            self.load_constant(reg, offset);
+            self.emit(&format!("ADD R{reg}, R{reg}, R5"));
+        }
+    }
+
+    /// Reads (`LDR`) or writes (`STR`) R0 from or to a frame slot.
+    fn frame_access(&mut self, instruction: &str, offset: i32) {
+        if (-32..=31).contains(&offset) {
+            self.emit(&format!("{instruction} R0, R5, #{offset}"));
+        } else {
+            self.frame_address(3, offset);
+            self.emit(&format!("{instruction} R0, R3, #0"));
+        }
+    }
+
+    /// Sets R0 to 1 when the condition codes match `flags`, and to 0 otherwise.
+    fn set_if(&mut self, flags: &str) {
+        let yes = self.new_label();
+        let done = self.new_label();
+        self.emit(&format!("BR{flags} {yes}"));
+        self.emit("AND R0, R0, #0");
+        self.emit(&format!("BRnzp {done}"));
+        self.place(&yes);
+        self.emit("AND R0, R0, #0");
+        self.emit("ADD R0, R0, #1");
+        self.place(&done);
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), Diagnostic> {
+        self.function = function.name.clone();
+        self.frame_size = 0;
+        let mut params = BTreeMap::new();
+        for (index, param) in function.params.iter().enumerate() {
+            let local = Local {
+                offset: 2 + index as i32,
+                is_array: false,
+            };
+            if params.insert(param.clone(), local).is_some() {
+                return Err(error(
+                    function.position,
+                    format!("parameter `{param}` is repeated"),
+                ));
+            }
+        }
+        self.scopes = vec![params];
+
+        // The size of the frame is only known after the body, so the body goes in first and the
+        // prologue is put in front of it afterwards.
+        let start = self.lines.len();
+        self.block(&function.body)?;
+        let body = self.lines.split_off(start);
+
+        self.place(&format!("F_{}", function.name));
+        self.emit("ADD R6, R6, #-1");
+        self.emit("STR R7, R6, #0");
+        self.emit("ADD R6, R6, #-1");
+        self.emit("STR R5, R6, #0");
+        self.emit("ADD R5, R6, #0");
+        let frame_size = self.frame_size;
+        self.adjust_stack(-frame_size);
+        self.lines.extend(body);
+        self.place(&format!("R_{}", function.name));
+        self.emit("ADD R6, R5, #0");
+        self.emit("LDR R5, R6, #0");
+        self.emit("LDR R7, R6, #1");
+        self.emit("ADD R6, R6, #2");
+        self.emit("RET");
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<(), Diagnostic> {
+        self.scopes.push(BTreeMap::new());
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), Diagnostic> {
+        match statement {
+            Stmt::Var(declaration) => self.local(declaration)?,
+            Stmt::Assign(target, value) => self.assign(target, value)?,
+            Stmt::Expr(expr) => self.expr(expr)?,
+            Stmt::If(condition, then, otherwise) => {
+                let otherwise_label = self.new_label();
+                let done = self.new_label();
+                self.condition(condition, &otherwise_label)?;
+                self.block(then)?;
+                if !otherwise.is_empty() {
+                    self.emit(&format!("BRnzp {done}"));
+                }
+                self.place(&otherwise_label);
+                self.block(otherwise)?;
+                self.place(&done);
+            }
+            Stmt::While(condition, body) => {
+                let start = self.new_label();
+                let done = self.new_label();
+                self.place(&start);
+                self.condition(condition, &done)?;
+                self.loops.push((start.clone(), done.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.emit(&format!("BRnzp {start}"));
+                self.place(&done);
+            }
+            Stmt::Return(value, _) => {
+                if let Some(value) = value {
+                    self.expr(value)?;
+                }
+                self.emit(&format!("BRnzp R_{}", self.function));
+            }
+            Stmt::Break(position) | Stmt::Continue(position) => {
+                let Some((start, done)) = self.loops.last() else {
+                    return Err(error(*position, "not inside a loop".to_string()));
+                };
+                let target = match statement {
+                    Stmt::Break(_) => done.clone(),
+                    _ => start.clone(),
+                };
+                self.emit(&format!("BRnzp {target}"));
+            }
+            Stmt::Block(statements) => self.block(statements)?,
+        }
+        Ok(())
+    }
+
+    /// Evaluates `condition` and goes to `otherwise` when it is false (zero).
+    fn condition(&mut self, condition: &Expr, otherwise: &str) -> Result<(), Diagnostic> {
+        self.expr(condition)?;
+        self.emit("ADD R0, R0, #0");
+        self.emit(&format!("BRz {otherwise}"));
+        Ok(())
+    }
+
+    fn local(&mut self, declaration: &Declaration) -> Result<(), Diagnostic> {
+        let scope = self.scopes.last().expect("inside a function");
+        if scope.contains_key(&declaration.name) {
+            return Err(error(
+                declaration.position,
+                format!("`{}` is already defined", declaration.name),
+            ));
+        }
+        let local = match &declaration.size {
+            Some(size) => {
+                self.frame_size += self.array_size(size)?;
+                Local {
+                    offset: -self.frame_size,
+                    is_array: true,
+                }
+            }
+            None => {
+                match &declaration.value {
+                    Some(value) => self.expr(value)?,
+                    None => self.emit("AND R0, R0, #0"),
+                }
+                self.frame_size += 1;
+                let offset = -self.frame_size;
+                self.frame_access("STR", offset);
+                Local {
+                    offset,
+                    is_array: false,
+                }
+            }
+        };
+        self.scopes
+            .last_mut()
+            .expect("inside a function")
+            .insert(declaration.name.clone(), local);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<Local> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn assign(&mut self, target: &Expr, value: &Expr) -> Result<(), Diagnostic> {
+        match &target.kind {
+            ExprKind::Variable(name) => {
+                if let Some(local) = self.lookup(name) {
+                    if local.is_array {
+                        return Err(error(
+                            target.position,
+                            format!("cannot assign to the array `{name}`"),
+                        ));
+                    }
+                    self.expr(value)?;
+                    self.frame_access("STR", local.offset);
+                } else if let Some(&is_array) = self.globals.get(name) {
+                    if is_array {
+                        return Err(error(
+                            target.position,
+                            format!("cannot assign to the array `{name}`"),
+                        ));
+                    }
+                    self.expr(value)?;
+                    self.load_literal(1, &format!("G_{name}"));
+                    self.emit("STR R0, R1, #0");
+                } else {
+                    return Err(self.unknown(name, target.position));
+                }
+            }
+            ExprKind::Index(base, index) => {
+                self.element_address(base, index)?;
+                self.push();
+                self.expr(value)?;
+                self.pop(1);
+                self.emit("STR R0, R1, #0");
+            }
+            _ => unreachable!("the parser only accepts variables and elements"),
+        }
+        Ok(())
+    }
+
+    /// Puts the address of `base[index]` in R0.
+    fn element_address(&mut self, base: &Expr, index: &Expr) -> Result<(), Diagnostic> {
+        self.expr(base)?;
+        if let ExprKind::Number(value @ -16..=15) = index.kind {
+            self.emit(&format!("ADD R0, R0, #{value}"));
+        } else {
+            self.push();
+            self.expr(index)?;
+            self.pop(1);
+            self.emit("ADD R0, R0, R1");
+        }
+        Ok(())
+    }
+
+    fn unknown(&self, name: &str, position: Position) -> Diagnostic {
+        let message = if self.functions.contains_key(name) {
+            format!("`{name}` is a function and can only be called")
+        } else {
+            format!("`{name}` is not defined")
+        };
+        error(position, message)
+    }
+
+    /// Generates the code that leaves the value of `expr` in R0. Registers R1 to R4 and R7 may be
+    /// changed as well.
+    fn expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match &expr.kind {
+            ExprKind::Number(value) => self.load_constant(0, *value),
+            ExprKind::String(text) => {
+                self.strings.push(text.clone());
+                let label = format!("S_{}", self.strings.len() - 1);
+                self.load_literal(0, &label);
+            }
+            ExprKind::Variable(name) => {
+                if let Some(local) = self.lookup(name) {
+                    if local.is_array {
+                        self.frame_address(0, local.offset);
+                    } else {
+                        self.frame_access("LDR", local.offset);
+                    }
+                } else if let Some(&value) = self.constants.get(name) {
+                    self.load_constant(0, value);
+                } else if let Some(&is_array) = self.globals.get(name) {
+                    self.load_literal(0, &format!("G_{name}"));
+                    if !is_array {
+                        self.emit("LDR R0, R0, #0");
+                    }
+                } else {
+                    return Err(self.unknown(name, expr.position));
+                }
+            }
+            ExprKind::Index(base, index) => {
+                self.element_address(base, index)?;
+                self.emit("LDR R0, R0, #0");
+            }
+            ExprKind::Call(name, args) => self.call_function(name, args, expr.position)?,
+            ExprKind::Unary(op, operand) => {
+                self.expr(operand)?;
+                match op {
+                    UnaryOp::Negate => {
+                        self.emit("NOT R0, R0");
+                        self.emit("ADD R0, R0, #1");
+                    }
+                    UnaryOp::Not => {
+                        self.emit("ADD R0, R0, #0");
+                        self.set_if("z");
+                    }
+                    UnaryOp::Complement => self.emit("NOT R0, R0"),
+                }
+            }
+            ExprKind::Binary(op @ (BinaryOp::And | BinaryOp::Or), left, right) => {
+                // `&&` stops at the first false operand, and `||` at the first true one.
+                let (stop, stop_flags, result) = if *op == BinaryOp::And {
+                    (self.new_label(), "z", 0)
+                } else {
+                    (self.new_label(), "np", 1)
+                };
+                let done = self.new_label();
+                for operand in [left, right] {
+                    self.expr(operand)?;
+                    self.emit("ADD R0, R0, #0");
+                    self.emit(&format!("BR{stop_flags} {stop}"));
+                }
+                self.load_constant(0, 1 - result);
+                self.emit(&format!("BRnzp {done}"));
+                self.place(&stop);
+                self.load_constant(0, result);
+                self.place(&done);
+            }
+            ExprKind::Binary(op, left, right) => {
+                self.expr(left)?;
+                if let (BinaryOp::Add | BinaryOp::Sub, ExprKind::Number(value)) = (op, &right.kind)
+                {
+                    let value = if *op == BinaryOp::Add { *value } else { -value };
+                    if (-16..=15).contains(&value) {
+                        self.emit(&format!("ADD R0, R0, #{value}"));
+                        return Ok(());
+                    }
+                }
+                self.push();
+                self.expr(right)?;
+                self.emit("ADD R1, R0, #0");
+                self.pop(0);
+                self.binary(*op);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `op` to R0 (left) and R1 (right), leaving the result in R0.
+    fn binary(&mut self, op: BinaryOp) {
+        match op {
+            BinaryOp::Add => self.emit("ADD R0, R0, R1"),
+            BinaryOp::Sub => {
+                self.emit("NOT R1, R1");
+                self.emit("ADD R1, R1, #1");
+                self.emit("ADD R0, R0, R1");
+            }
+            BinaryOp::Mul => self.call("RT_MUL"),
+            BinaryOp::Div => self.call("RT_DIV"),
+            BinaryOp::Mod => {
+                self.call("RT_DIV");
+                self.emit("ADD R0, R1, #0");
+            }
+            BinaryOp::BitAnd => self.emit("AND R0, R0, R1"),
+            BinaryOp::BitOr => {
+                self.emit("NOT R0, R0");
+                self.emit("NOT R1, R1");
+                self.emit("AND R0, R0, R1");
+                self.emit("NOT R0, R0");
+            }
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                self.binary(BinaryOp::Sub);
+                self.set_if(if op == BinaryOp::Equal { "z" } else { "np" });
+            }
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                // A plain subtraction can overflow, so the comparison goes through the runtime.
+                self.call("RT_CMP");
+                self.emit("ADD R0, R0, #0");
+                self.set_if(match op {
+                    BinaryOp::Less => "n",
+                    BinaryOp::LessEqual => "nz",
+                    BinaryOp::Greater => "p",
+                    _ => "zp",
+                });
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                unreachable!("short-circuit operators are handled apart")
+            }
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        position: Position,
+    ) -> Result<(), Diagnostic> {
+        let expected = BUILTINS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, count)| *count)
+            .or_else(|| self.functions.get(name).copied());
+        let Some(expected) = expected else {
+            return Err(error(position, format!("there is no function `{name}`")));
+        };
+        if args.len() != expected {
+            return Err(error(
+                position,
+                format!(
+                    "`{name}` takes {expected} argument{}, but {} {} given",
+                    if expected == 1 { "" } else { "s" },
+                    args.len(),
+                    if args.len() == 1 { "was" } else { "were" }
+                ),
+            ));
+        }
+        match name {
+            "getc" => self.emit("GETC"),
+            "putc" | "puts" => {
+                self.expr(&args[0])?;
+                self.emit(if name == "putc" { "OUT" } else { "PUTS" });
+            }
+            "halt" => self.emit("HALT"),
+            _ => {
+                for arg in args.iter().rev() {
+                    self.expr(arg)?;
+                    self.push();
+                }
+                self.call(&format!("F_{name}"));
+                self.adjust_stack(args.len() as i32);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn error(position: Position, message: String) -> Diagnostic {
+    Diagnostic::new(position.line, Some(position.span), message)
+}
+
+/// Writes a string the way `.STRINGZ` reads it.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\x1B' => escaped.push_str("\\e"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
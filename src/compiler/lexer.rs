@@ -0,0 +1,167 @@
+use crate::assembler::{Diagnostic, lexer::Span};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// Names and keywords.
+    Identifier(String),
+    /// Numbers and character literals.
+    Number(i32),
+    String(String),
+    /// Operators and punctuation.
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub span: Span,
+}
+
+/// Symbols, longest first so `<=` is not read as `<` followed by `=`.
+pub const SYMBOLS: [&str; 26] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|",
+    "(", ")", "{", "}", "[", "]", ",", ";",
+];
+
+/// Splits a whole source file into tokens. The last one is always `End`.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            let c = chars[i];
+            let error = |message: String, end: usize| {
+                Diagnostic::new(line, Some(Span { start, end }), message)
+            };
+            let kind = if c.is_whitespace() {
+                i += 1;
+                continue;
+            } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+                break;
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                TokenKind::Identifier(chars[start..i].iter().collect())
+            } else if c.is_ascii_digit() {
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => i32::from_str_radix(hex, 16),
+                    None => text.parse::<i32>(),
+                };
+                match value {
+                    Ok(value) if value <= 0xFFFF => TokenKind::Number(value),
+                    _ => return Err(error(format!("invalid number `{text}`"), i)),
+                }
+            } else if c == '\'' || c == '"' {
+                let (value, end) =
+                    read_quoted(&chars, start).map_err(|message| error(message, chars.len()))?;
+                i = end;
+                if c == '"' {
+                    TokenKind::String(value)
+                } else {
+                    let mut value = value.chars();
+                    match (value.next(), value.next()) {
+                        (Some(char), None) => TokenKind::Number(char as i32),
+                        _ => {
+                            return Err(error(
+                                "a character literal holds exactly one character".to_string(),
+                                i,
+                            ));
+                        }
+                    }
+                }
+            } else {
+                let rest: String = chars[i..].iter().collect();
+                let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) else {
+                    return Err(error(format!("unexpected character `{c}`"), i + 1));
+                };
+                i += symbol.len();
+                TokenKind::Symbol(symbol)
+            };
+            tokens.push(Token {
+                kind,
+                line,
+                span: Span { start, end: i },
+            });
+        }
+    }
+    let line = source.lines().count().max(1);
+    tokens.push(Token {
+        kind: TokenKind::End,
+        line,
+        span: Span { start: 0, end: 0 },
+    });
+    Ok(tokens)
+}
+
+/// Reads the literal that starts with the quote at `start`, returning its value with the escape
+/// sequences resolved and the position right after the closing quote.
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let quote = chars[start];
+    let mut value = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == quote => return Ok((value, i + 1)),
+            '\\' if i + 1 < chars.len() => {
+                value.push(match chars[i + 1] {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'e' => '\x1B',
+                    '0' => '\0',
+                    '\\' => '\\',
+                    '\'' => '\'',
+                    '"' => '"',
+                    other => return Err(format!("unknown escape sequence `\\{other}`")),
+                });
+                i += 2;
+            }
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err("unterminated literal".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_source_into_tokens() {
+        let tokens = tokenize("x = 0x1F <= 'a'; // comment\nputs(\"hi\\n\");").unwrap();
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Symbol("="),
+                TokenKind::Number(31),
+                TokenKind::Symbol("<="),
+                TokenKind::Number(97),
+                TokenKind::Symbol(";"),
+                TokenKind::Identifier("puts".to_string()),
+                TokenKind::Symbol("("),
+                TokenKind::String("hi\n".to_string()),
+                TokenKind::Symbol(")"),
+                TokenKind::Symbol(";"),
+                TokenKind::End,
+            ]
+        );
+        assert!(tokenize("x = 1 @ 2;").is_err());
+        assert!(tokenize("x = 99999;").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Compiler for a small C-like language, producing LC-3 assembly for the assembler.
+//!
+//! A program is a list of constants (`const N = 10;`), global variables (`var total;`,
+//! `var count = 3;`, `var buffer[N];`) and functions (`fn add(a, b) { return a + b; }`), and runs
+//! `main`. Every value is a 16-bit word. Statements are `var`, assignments to variables and to
+//! elements (`a[i] = x;`), `if`/`else`, `while`, `break`, `continue`, `return` and expressions.
+//! Expressions have the usual C operators except shifts: `* / %`, `+ -`, comparisons, `== !=`,
+//! `& |` and the short-circuit `&& ||`, with `- ! ~` in front. An array name or a string literal
+//! is the address of its first word, and `p[i]` is the word at `p + i`, so arrays and strings can
+//! be passed around. `getc()`, `putc(c)`, `puts(s)` and `halt()` use the VM's trap routines.
+//!
+//! The source is split into tokens (`lexer`) and parsed into a syntax tree (`parser`), which
+//! `codegen` turns into assembly. Values are computed in R0, with temporaries pushed on the stack.
+//! R6 is the stack pointer and R5 the frame pointer: the caller pushes the arguments from last to
+//! first and pops them after the call, and the callee saves R7 and R5 under them and keeps its
+//! locals below. The result is returned in R0. Multiplication, division and ordered comparisons go
+//! through small subroutines (`runtime`).
+//!
+//! Branches inside a function are PC-relative, so a single function must fit in about 256 words;
+//! the assembler reports the ones that do not.
+pub mod codegen;
+pub mod lexer;
+pub mod parser;
+pub mod runtime;
+
+use crate::{
+    assembler::{Diagnostic, formatter::format_lines, parser::parse as parse_assembly},
+    compiler::{codegen::generate, lexer::tokenize, parser::parse},
+};
+
+/// Compiles a whole program to formatted assembly source.
+pub fn compile(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let tokens = tokenize(source).map_err(|diagnostic| vec![diagnostic])?;
+    let items = parse(tokens).map_err(|diagnostic| vec![diagnostic])?;
+    let assembly = generate(&items).map_err(|diagnostic| vec![diagnostic])?;
+    let (lines, diagnostics) = parse_assembly(&assembly);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    Ok(format_lines(&lines))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, vm::VMState};
+
+    /// Compiles and runs `source`, typing `input`, and returns what it printed.
+    fn run(source: &str, input: &str) -> String {
+        let assembly = assemble(&compile(source).unwrap()).unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        let (console, output) = BufferConsole::new(input);
+        vm.console = Box::new(console);
+        vm.execute().unwrap();
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        output
+            .strip_suffix("Halt execution\n")
+            .expect("the program halts")
+            .to_string()
+    }
+
+    const PRINT_NUMBER: &str = "
+fn print(n) {
+    if (n < 0) {
+        putc('-');
+        n = -n;
+    }
+    if (n >= 10) {
+        print(n / 10);
+    }
+    putc('0' + n % 10);
+}";
+
+    #[test]
+    fn runs_recursive_functions() {
+        let source = format!(
+            "{PRINT_NUMBER}
+fn factorial(n) {{
+    if (n <= 1) {{ return 1; }}
+    return n * factorial(n - 1);
+}}
+fn main() {{
+    print(factorial(7));
+    putc(' ');
+    print(-1234);
+}}"
+        );
+        assert_eq!(run(&source, ""), "5040 -1234");
+    }
+
+    #[test]
+    fn computes_arithmetic_like_c() {
+        let source = format!(
+            "{PRINT_NUMBER}
+const BIG = 30000;
+fn main() {{
+    var values[8];
+    values[0] = 17 / 5;
+    values[1] = -17 / 5;
+    values[2] = 17 % -5;
+    values[3] = -17 % 5;
+    values[4] = -6 * 7;
+    values[5] = BIG < -BIG;
+    values[6] = (12 & 10) | 1;
+    values[7] = ~0 == -1 && !(3 != 3) || 0;
+    var i = 0;
+    while (i < 8) {{
+        print(values[i]);
+        putc(' ');
+        i = i + 1;
+    }}
+}}"
+        );
+        assert_eq!(run(&source, ""), "3 -3 2 -2 -42 0 9 1 ");
+    }
+
+    #[test]
+    fn runs_loops_with_break_and_continue() {
+        let source = format!(
+            "{PRINT_NUMBER}
+var total;
+fn main() {{
+    var i = 0;
+    while (1) {{
+        i = i + 1;
+        if (i > 10) {{ break; }}
+        if (i % 2 == 0) {{ continue; }}
+        total = total + i;
+    }}
+    print(total);
+}}"
+        );
+        assert_eq!(run(&source, ""), "25");
+    }
+
+    #[test]
+    fn reads_input_and_prints_strings() {
+        let source = "
+var line[20];
+fn read_line(buffer) {
+    var length = 0;
+    var c = getc();
+    while (c != '\\n' && c != 0) {
+        buffer[length] = c;
+        length = length + 1;
+        c = getc();
+    }
+    buffer[length] = 0;
+    return length;
+}
+fn main() {
+    read_line(line);
+    puts(\"Hello, \");
+    puts(line);
+    puts(\"!\\n\");
+}";
+        assert_eq!(run(source, "World\n"), "Hello, World!\n");
+    }
+
+    #[test]
+    fn reports_semantic_errors() {
+        let errors = compile("fn main() {\n  x = 1;\n}").unwrap_err();
+        assert_eq!(errors[0].to_string(), "2:3: `x` is not defined");
+        let errors = compile("fn f(a) {}\nfn main() {\n  f();\n}").unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "3:3: `f` takes 1 argument, but 0 were given"
+        );
+        let errors = compile("fn main() {\n  break;\n}").unwrap_err();
+        assert_eq!(errors[0].to_string(), "2:3: not inside a loop");
+        assert!(compile("fn start() {}").is_err());
+    }
+}
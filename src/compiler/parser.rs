@@ -0,0 +1,517 @@
+use crate::{
+    assembler::{Diagnostic, lexer::Span},
+    compiler::lexer::{SYMBOLS, Token, TokenKind},
+};
+
+/// Where something is in the source, to report errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    Number(i32),
+    /// A string literal, which evaluates to the address of its first character.
+    String(String),
+    Variable(String),
+    /// `base[index]`: the word at the address `base + index`.
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub position: Position,
+}
+
+/// A variable declaration: `var name;`, `var name = value;` or `var name[size];`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Declaration {
+    pub name: String,
+    pub size: Option<Expr>,
+    pub value: Option<Expr>,
+    pub position: Position,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    Var(Declaration),
+    Assign(Expr, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>, Position),
+    Break(Position),
+    Continue(Position),
+    Block(Vec<Stmt>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub position: Position,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    Const(String, Expr, Position),
+    Global(Declaration),
+    Function(Function),
+}
+
+const KEYWORDS: [&str; 9] = [
+    "const", "var", "fn", "if", "else", "while", "return", "break", "continue",
+];
+
+/// Parses the tokens of a whole program. Stops at the first error.
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Item>, Diagnostic> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let mut items = Vec::new();
+    while parser.peek().kind != TokenKind::End {
+        items.push(parser.item()?);
+    }
+    Ok(items)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn here(&self) -> Position {
+        let token = self.peek();
+        Position {
+            line: token.line,
+            span: token.span,
+        }
+    }
+
+    fn error(&self, message: String) -> Diagnostic {
+        let token = self.peek();
+        Diagnostic::new(token.line, Some(token.span), message)
+    }
+
+    fn found(&self) -> String {
+        match &self.peek().kind {
+            TokenKind::Identifier(name) => format!("`{name}`"),
+            TokenKind::Number(value) => format!("`{value}`"),
+            TokenKind::String(_) => "a string".to_string(),
+            TokenKind::Symbol(symbol) => format!("`{symbol}`"),
+            TokenKind::End => "the end of the file".to_string(),
+        }
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        self.peek().kind == TokenKind::Symbol(symbol_of(symbol))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Identifier(name) if name == keyword)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), Diagnostic> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{symbol}`, found {}", self.found())))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<Position, Diagnostic> {
+        let position = self.here();
+        if self.is_keyword(keyword) {
+            self.next();
+            Ok(position)
+        } else {
+            Err(self.error(format!("expected `{keyword}`, found {}", self.found())))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<(String, Position), Diagnostic> {
+        let position = self.here();
+        match &self.peek().kind {
+            TokenKind::Identifier(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.next();
+                Ok((name, position))
+            }
+            _ => Err(self.error(format!("expected a name, found {}", self.found()))),
+        }
+    }
+
+    fn item(&mut self) -> Result<Item, Diagnostic> {
+        if self.is_keyword("const") {
+            let position = self.expect_keyword("const")?;
+            let (name, _) = self.identifier()?;
+            self.expect_symbol("=")?;
+            let value = self.expr()?;
+            self.expect_symbol(";")?;
+            Ok(Item::Const(name, value, position))
+        } else if self.is_keyword("var") {
+            Ok(Item::Global(self.declaration()?))
+        } else if self.is_keyword("fn") {
+            self.expect_keyword("fn")?;
+            let (name, position) = self.identifier()?;
+            self.expect_symbol("(")?;
+            let mut params = Vec::new();
+            if !self.eat_symbol(")") {
+                loop {
+                    params.push(self.identifier()?.0);
+                    if self.eat_symbol(")") {
+                        break;
+                    }
+                    self.expect_symbol(",")?;
+                }
+            }
+            let body = self.block()?;
+            Ok(Item::Function(Function {
+                name,
+                params,
+                body,
+                position,
+            }))
+        } else {
+            Err(self.error(format!(
+                "expected `const`, `var` or `fn`, found {}",
+                self.found()
+            )))
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Declaration, Diagnostic> {
+        self.expect_keyword("var")?;
+        let (name, position) = self.identifier()?;
+        let size = if self.eat_symbol("[") {
+            let size = self.expr()?;
+            self.expect_symbol("]")?;
+            Some(size)
+        } else {
+            None
+        };
+        let value = if size.is_none() && self.eat_symbol("=") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        self.expect_symbol(";")?;
+        Ok(Declaration {
+            name,
+            size,
+            value,
+            position,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, Diagnostic> {
+        self.expect_symbol("{")?;
+        let mut statements = Vec::new();
+        while !self.eat_symbol("}") {
+            if self.peek().kind == TokenKind::End {
+                return Err(self.error("expected `}`, found the end of the file".to_string()));
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, Diagnostic> {
+        if self.is_keyword("var") {
+            return Ok(Stmt::Var(self.declaration()?));
+        }
+        if self.is_symbol("{") {
+            return Ok(Stmt::Block(self.block()?));
+        }
+        if self.is_keyword("if") {
+            return self.if_statement();
+        }
+        if self.is_keyword("while") {
+            self.expect_keyword("while")?;
+            self.expect_symbol("(")?;
+            let condition = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(Stmt::While(condition, self.block()?));
+        }
+        if self.is_keyword("return") {
+            let position = self.expect_keyword("return")?;
+            let value = if self.is_symbol(";") {
+                None
+            } else {
+                Some(self.expr()?)
+            };
+            self.expect_symbol(";")?;
+            return Ok(Stmt::Return(value, position));
+        }
+        if self.is_keyword("break") || self.is_keyword("continue") {
+            let position = self.here();
+            let is_break = self.is_keyword("break");
+            self.next();
+            self.expect_symbol(";")?;
+            return Ok(if is_break {
+                Stmt::Break(position)
+            } else {
+                Stmt::Continue(position)
+            });
+        }
+        let target = self.expr()?;
+        let statement = if self.eat_symbol("=") {
+            if !matches!(target.kind, ExprKind::Variable(_) | ExprKind::Index(..)) {
+                return Err(Diagnostic::new(
+                    target.position.line,
+                    Some(target.position.span),
+                    "only variables and array elements can be assigned to".to_string(),
+                ));
+            }
+            Stmt::Assign(target, self.expr()?)
+        } else {
+            Stmt::Expr(target)
+        };
+        self.expect_symbol(";")?;
+        Ok(statement)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        self.expect_keyword("if")?;
+        self.expect_symbol("(")?;
+        let condition = self.expr()?;
+        self.expect_symbol(")")?;
+        let then = self.block()?;
+        let otherwise = if self.is_keyword("else") {
+            self.next();
+            if self.is_keyword("if") {
+                vec![self.if_statement()?]
+            } else {
+                self.block()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(Stmt::If(condition, then, otherwise))
+    }
+
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary(0)
+    }
+
+    /// Parses binary operators from `level` of precedence up.
+    fn binary(&mut self, level: usize) -> Result<Expr, Diagnostic> {
+        const LEVELS: [&[(&str, BinaryOp)]; 7] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("|", BinaryOp::BitOr), ("&", BinaryOp::BitAnd)],
+            &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual)],
+            &[
+                ("<", BinaryOp::Less),
+                ("<=", BinaryOp::LessEqual),
+                (">", BinaryOp::Greater),
+                (">=", BinaryOp::GreaterEqual),
+            ],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Mod),
+            ],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some((_, op)) = LEVELS[level]
+            .iter()
+            .find(|(symbol, _)| self.is_symbol(symbol))
+        {
+            let position = self.here();
+            self.next();
+            let right = self.binary(level + 1)?;
+            left = Expr {
+                kind: ExprKind::Binary(*op, Box::new(left), Box::new(right)),
+                position,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        let position = self.here();
+        let op = [
+            ("-", UnaryOp::Negate),
+            ("!", UnaryOp::Not),
+            ("~", UnaryOp::Complement),
+        ]
+        .into_iter()
+        .find(|(symbol, _)| self.is_symbol(symbol));
+        if let Some((_, op)) = op {
+            self.next();
+            let operand = self.unary()?;
+            return Ok(Expr {
+                kind: ExprKind::Unary(op, Box::new(operand)),
+                position,
+            });
+        }
+        let mut expr = self.primary()?;
+        while self.is_symbol("[") {
+            let position = self.here();
+            self.next();
+            let index = self.expr()?;
+            self.expect_symbol("]")?;
+            expr = Expr {
+                kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                position,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
+        let position = self.here();
+        let kind = match self.peek().kind.clone() {
+            TokenKind::Number(value) => {
+                self.next();
+                ExprKind::Number(value)
+            }
+            TokenKind::String(value) => {
+                self.next();
+                ExprKind::String(value)
+            }
+            TokenKind::Symbol("(") => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                return Ok(expr);
+            }
+            TokenKind::Identifier(_) => {
+                let (name, _) = self.identifier()?;
+                if self.eat_symbol("(") {
+                    let mut args = Vec::new();
+                    if !self.eat_symbol(")") {
+                        loop {
+                            args.push(self.expr()?);
+                            if self.eat_symbol(")") {
+                                break;
+                            }
+                            self.expect_symbol(",")?;
+                        }
+                    }
+                    ExprKind::Call(name, args)
+                } else {
+                    ExprKind::Variable(name)
+                }
+            }
+            _ => {
+                return Err(self.error(format!("expected an expression, found {}", self.found())));
+            }
+        };
+        Ok(Expr { kind, position })
+    }
+}
+
+/// The static string the lexer uses for `symbol`.
+fn symbol_of(symbol: &str) -> &'static str {
+    SYMBOLS
+        .iter()
+        .find(|s| **s == symbol)
+        .copied()
+        .unwrap_or("")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::lexer::tokenize;
+
+    fn parse_source(source: &str) -> Result<Vec<Item>, Diagnostic> {
+        parse(tokenize(source).unwrap())
+    }
+
+    #[test]
+    fn follows_operator_precedence() {
+        let items = parse_source("const X = 1 + 2 * 3 == 7 && !0;").unwrap();
+        let Item::Const(_, value, _) = &items[0] else {
+            panic!("expected a constant");
+        };
+        let ExprKind::Binary(BinaryOp::And, left, right) = &value.kind else {
+            panic!("expected `&&` at the top");
+        };
+        assert!(matches!(left.kind, ExprKind::Binary(BinaryOp::Equal, ..)));
+        assert!(matches!(right.kind, ExprKind::Unary(UnaryOp::Not, _)));
+    }
+
+    #[test]
+    fn parses_functions_and_statements() {
+        let items = parse_source(
+            "var total;\nfn main() {\n  var a[3];\n  a[1] = 2;\n  if (a[1]) { total = 1; } else if (total) { return; }\n  while (1) { break; }\n}",
+        )
+        .unwrap();
+        let Item::Function(main) = &items[1] else {
+            panic!("expected a function");
+        };
+        assert_eq!(main.name, "main");
+        assert_eq!(main.body.len(), 4);
+        assert!(matches!(main.body[1], Stmt::Assign(..)));
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let error = parse_source("fn main() {\n  x = ;\n}").unwrap_err();
+        assert_eq!(error.to_string(), "2:7: expected an expression, found `;`");
+        let error = parse_source("fn main() {\n  f() = 1;\n}").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+}
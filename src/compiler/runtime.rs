@@ -0,0 +1,75 @@
+/// Subroutines the generated code calls for what LC-3 has no instruction for. They take their
+/// operands in R0 and R1, may change R1 to R4 and return with RET.
+///
+/// - `RT_MUL`: R0 = R0 * R1, by shifting and adding.
+/// - `RT_DIV`: R0 = R0 / R1 and R1 = R0 % R1, rounding toward zero like C. Dividing by zero gives a
+///   quotient of 0 and leaves the dividend as the remainder. It subtracts repeatedly, so it takes
+///   as many steps as the quotient.
+/// - `RT_CMP`: R0 = a negative number, 0 or a positive number when R0 is less than, equal to or
+///   greater than R1, even when R0 - R1 would overflow.
+pub const RUNTIME: &str = "\
+RT_MUL      AND R2, R2, #0
+            AND R3, R3, #0
+            ADD R3, R3, #1
+RT_MUL_LOOP AND R4, R1, R3
+            BRz RT_MUL_NEXT
+            ADD R2, R2, R0
+RT_MUL_NEXT ADD R0, R0, R0
+            ADD R3, R3, R3
+            BRnp RT_MUL_LOOP
+            ADD R0, R2, #0
+            RET
+RT_DIV      AND R2, R2, #0
+            AND R4, R4, #0
+            ADD R1, R1, #0
+            BRz RT_DIV_ZERO
+            BRp RT_DIV_B
+            NOT R1, R1
+            ADD R1, R1, #1
+            NOT R2, R2
+RT_DIV_B    ADD R0, R0, #0
+            BRzp RT_DIV_A
+            NOT R0, R0
+            ADD R0, R0, #1
+            NOT R2, R2
+            ADD R4, R4, #1
+RT_DIV_A    AND R3, R3, #0
+            NOT R1, R1
+            ADD R1, R1, #1
+RT_DIV_LOOP ADD R0, R0, R1
+            BRn RT_DIV_DONE
+            ADD R3, R3, #1
+            BRnzp RT_DIV_LOOP
+RT_DIV_DONE NOT R1, R1
+            ADD R1, R1, #1
+            ADD R1, R0, R1
+            ADD R0, R3, #0
+            ADD R2, R2, #0
+            BRz RT_DIV_Q
+            NOT R0, R0
+            ADD R0, R0, #1
+RT_DIV_Q    ADD R4, R4, #0
+            BRz RT_DIV_R
+            NOT R1, R1
+            ADD R1, R1, #1
+RT_DIV_R    RET
+RT_DIV_ZERO ADD R1, R0, #0
+            AND R0, R0, #0
+            RET
+RT_CMP      ADD R1, R1, #0
+            BRn RT_CMP_B
+            ADD R0, R0, #0
+            BRn RT_CMP_LESS
+            BRnzp RT_CMP_SUB
+RT_CMP_B    ADD R0, R0, #0
+            BRzp RT_CMP_MORE
+RT_CMP_SUB  NOT R1, R1
+            ADD R1, R1, #1
+            ADD R0, R0, R1
+            RET
+RT_CMP_LESS AND R0, R0, #0
+            ADD R0, R0, #-1
+            RET
+RT_CMP_MORE AND R0, R0, #0
+            ADD R0, R0, #1
+            RET";
@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::{error::VMError, utils::get_char};
+
+/// Where the VM reads the keyboard from and writes its console output to.
+pub trait Console {
+    /// Reads one character, waiting until there is one.
+    fn read_char(&mut self) -> Result<u16, VMError>;
+    /// Writes one character.
+    fn write_char(&mut self, char: u8) -> Result<(), VMError>;
+    /// Makes sure everything written so far is delivered.
+    fn flush(&mut self) -> Result<(), VMError>;
+}
+
+/// The terminal the VM runs in, through stdin and stdout. Since input buffering is disabled while the
+/// VM runs, new lines are written as `\n\r` so the cursor goes back to the first column.
+pub struct Terminal;
+
+impl Console for Terminal {
+    fn read_char(&mut self) -> Result<u16, VMError> {
+        get_char()
+    }
+
+    fn write_char(&mut self, char: u8) -> Result<(), VMError> {
+        if char == 0x0A {
+            print!("\n\r");
+        } else {
+            print!("{}", char as char);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), VMError> {
+        std::io::stdout()
+            .flush()
+            .map_err(|e| VMError::ErrorFlushinStdout(e.to_string()))
+    }
+}
+
+/// A console backed by memory: the keyboard input is given up front and the output is collected in
+/// a buffer shared with whoever created it. Reading past the end of the input gives 0, as if no key
+/// was pressed.
+pub struct BufferConsole {
+    pub input: VecDeque<u8>,
+    pub output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl BufferConsole {
+    /// A console that will be typed `input`, with the buffer its output goes to.
+    pub fn new(input: &str) -> (Self, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let console = BufferConsole {
+            input: input.bytes().collect(),
+            output: Rc::clone(&output),
+        };
+        (console, output)
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_char(&mut self) -> Result<u16, VMError> {
+        Ok(self.input.pop_front().map_or(0, u16::from))
+    }
+
+    fn write_char(&mut self, char: u8) -> Result<(), VMError> {
+        self.output.borrow_mut().push(char);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), VMError> {
+        Ok(())
+    }
+}
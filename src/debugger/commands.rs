@@ -0,0 +1,580 @@
+//! The commands of the command line debugger (`debug`), read one line at a time.
+use crate::{
+    callstack,
+    debugger::{
+        Breakpoint, Debugger, Resume, Stop,
+        expression::{Expression, Message},
+        inspect,
+    },
+    disassembler::disassemble,
+    registers::Register,
+    utils::{disable_input_buffering, restore_terminal},
+    watchpoint::{Watchpoint, parse_access, parse_range},
+};
+
+pub const HELP: &str = "\
+Commands:
+  break <addr> [if <cond>] (b) stops when the PC gets to `addr`, if `cond` holds
+  condition <addr> [cond]  sets or removes the condition of a breakpoint
+  ignore <addr> <count>    lets the next `count` hits of a breakpoint through
+  log <addr> <message>     prints `message` when the PC gets to `addr`, without stopping; values
+                           of expressions go between braces: {R0}, {mem[x4000]:d}, {R0:c}
+  delete <addr>            (d) removes the breakpoint or logpoint at `addr`
+  breakpoints              lists the breakpoints and logpoints with their hit counts
+  watch <kind> <range>     (w) stops after the program reads, writes or changes (`kind`) memory in
+                           `range`, an address or `start-end`
+  unwatch <number>         removes a watchpoint
+  watchpoints              lists the watchpoints, numbered
+  step [count]             (s) executes `count` instructions (1 by default)
+  next                     (n) executes one instruction, running subroutine calls through
+  finish                   (f) runs until the current subroutine returns
+  continue                 (c) runs until a breakpoint, a watchpoint or the end of the program
+  reverse-step [count]     (rs) undoes `count` instructions (1 by default)
+  reverse-continue         (rc) runs backwards until a breakpoint, a watchpoint or the start of
+                           the history
+  goto <count>             takes the program to after `count` instructions, back or forward
+  history                  shows how many instructions ran and how far back they can be undone
+  restart                  starts the program again, keeping the breakpoints
+  regs                     (r) shows the registers and the condition codes
+  set <reg> <value>        sets R0-R7, PC or CC (to n, z or p)
+  mem <addr> [count]       (x) shows `count` words of memory from `addr` (8 by default)
+  poke <addr> <value>      sets a word of memory
+  list [addr]              (l) disassembles around `addr` (the PC by default)
+  backtrace [stack]        (bt) shows the calls and traps the program is in; `stack` adds the
+                           return addresses saved on the stack at R6
+  help                     (h) shows this help
+  quit                     (q) leaves
+Addresses and values are numbers (x3000, #10, 10) or labels. Conditions are expressions over
+R0-R7, PC, n, z, p, mem[addr], numbers and labels, like `R0 == x41 && mem[x4000] > 10`.
+An empty line repeats a step command.";
+
+/// How many instructions `list` shows before and after the address.
+const LIST_CONTEXT: u16 = 4;
+
+/// What a command did.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Text to show, which may be empty.
+    Show(String),
+    Quit,
+}
+
+/// A debugging session driven by text commands.
+pub struct Session {
+    pub debugger: Debugger,
+    /// Whether the program runs in the terminal, which then gets input buffering disabled while it
+    /// runs, as it would outside the debugger.
+    pub terminal: bool,
+    /// The last step command, repeated by an empty line.
+    last: Option<String>,
+}
+
+impl Session {
+    pub fn new(debugger: Debugger, terminal: bool) -> Self {
+        Self {
+            debugger,
+            terminal,
+            last: None,
+        }
+    }
+
+    /// Where the program is, shown when the session starts: the instruction at the PC and, with
+    /// debug info, the source line it comes from.
+    pub fn location(&self) -> String {
+        let pc = self.debugger.vm.registers[Register::PC];
+        let source = self.debugger.program.debug.as_ref();
+        match source.and_then(|debug| debug.describe(pc)) {
+            Some(source) => format!("{}\n   {source}", self.line(pc)),
+            None => self.line(pc),
+        }
+    }
+
+    /// Runs one command. Errors are returned as the text to show.
+    pub fn execute(&mut self, input: &str) -> Result<Reply, String> {
+        let input = match input.trim() {
+            "" => match &self.last {
+                Some(last) => last.clone(),
+                None => return Ok(Reply::Show(String::new())),
+            },
+            input => input.to_string(),
+        };
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let symbols = &self.debugger.program.symbols;
+        let value = |text: &str| inspect::value(text, symbols);
+        let (name, args) = (words[0], &words[1..]);
+        self.last = None;
+        let show = match (name, args) {
+            ("help" | "h", []) => HELP.to_string(),
+            ("quit" | "q", []) => return Ok(Reply::Quit),
+            ("break" | "b", [address] | [address, "if", _, ..]) => {
+                let address = value(address)?;
+                let condition = match args {
+                    [_, "if", ..] => Some(Expression::parse(rest(&input, 3), symbols)?),
+                    _ => None,
+                };
+                let breakpoint = Breakpoint {
+                    condition,
+                    ..Breakpoint::default()
+                };
+                self.debugger.breakpoints.insert(address, breakpoint);
+                format!("breakpoint at {}", self.describe_breakpoint(address))
+            }
+            ("log", [address, _, ..]) => {
+                let address = value(address)?;
+                let breakpoint = Breakpoint {
+                    log: Some(Message::parse(rest(&input, 2), symbols)?),
+                    ..Breakpoint::default()
+                };
+                self.debugger.breakpoints.insert(address, breakpoint);
+                format!("logpoint at {}", self.describe_breakpoint(address))
+            }
+            ("condition", [address, ..]) => {
+                let address = value(address)?;
+                let condition = match args {
+                    [_] => None,
+                    _ => Some(Expression::parse(rest(&input, 2), symbols)?),
+                };
+                self.breakpoint(address)?.condition = condition;
+                self.describe_breakpoint(address)
+            }
+            ("ignore", [address, count]) => {
+                let address = value(address)?;
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("`{count}` is not a number of hits"))?;
+                self.breakpoint(address)?.ignore = count;
+                self.describe_breakpoint(address)
+            }
+            ("delete" | "d", [address]) => {
+                let address = value(address)?;
+                self.breakpoint(address)?;
+                let shown = format!("removed {}", self.describe_breakpoint(address));
+                self.debugger.breakpoints.remove(&address);
+                shown
+            }
+            ("breakpoints", []) => self
+                .debugger
+                .breakpoints
+                .keys()
+                .map(|&address| self.describe_breakpoint(address))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ("watch" | "w", [access, range]) => {
+                let access = parse_access(access)?;
+                let (start, end) = parse_range(range, symbols)?;
+                let watchpoint = Watchpoint { access, start, end };
+                let shown = format!(
+                    "watchpoint {}: {watchpoint}",
+                    self.debugger.vm.watchpoints.len() + 1
+                );
+                self.debugger.vm.watchpoints.push(watchpoint);
+                shown
+            }
+            ("unwatch", [number]) => {
+                let watchpoints = &mut self.debugger.vm.watchpoints;
+                let index = match number.parse::<usize>() {
+                    Ok(number) if (1..=watchpoints.len()).contains(&number) => number - 1,
+                    _ => return Err(format!("there is no watchpoint {number}")),
+                };
+                format!("removed watchpoint {number}: {}", watchpoints.remove(index))
+            }
+            ("watchpoints", []) => self
+                .debugger
+                .vm
+                .watchpoints
+                .iter()
+                .enumerate()
+                .map(|(index, watchpoint)| format!("{}: {watchpoint}", index + 1))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ("step" | "s", [] | [_]) => {
+                let count = match args {
+                    [count] => value(count)?,
+                    _ => 1,
+                };
+                self.last = Some(input.clone());
+                self.resume(Resume::Step, count)?
+            }
+            ("next" | "n", []) => {
+                self.last = Some(input.clone());
+                self.resume(Resume::StepOver, 1)?
+            }
+            ("finish" | "f", []) => self.resume(Resume::StepOut, 1)?,
+            ("continue" | "c", []) => self.resume(Resume::Continue, 1)?,
+            ("reverse-step" | "rs", [] | [_]) => {
+                let count = match args {
+                    [count] => value(count)?,
+                    _ => 1,
+                };
+                self.last = Some(input.clone());
+                self.resume(Resume::ReverseStep, count)?
+            }
+            ("reverse-continue" | "rc", []) => self.resume(Resume::ReverseContinue, 1)?,
+            ("goto", [count]) => {
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("`{count}` is not an instruction count"))?;
+                let stop = self.debugger.go_to(count);
+                self.describe_stop(stop)
+            }
+            ("history", []) => match &self.debugger.vm.history {
+                Some(history) => format!(
+                    "instruction {} of {}, undoable back to {}",
+                    history.count(),
+                    history.end(),
+                    history.start()
+                ),
+                None => "no history is recorded".to_string(),
+            },
+            ("restart", []) => {
+                self.debugger.restart().map_err(|e| format!("{e:?}"))?;
+                self.location()
+            }
+            ("regs" | "r", []) => inspect::registers(&self.debugger.vm),
+            ("set", [register, text]) => {
+                inspect::set_register(&mut self.debugger.vm, register, text, symbols)?;
+                inspect::registers(&self.debugger.vm)
+            }
+            ("mem" | "x", [address] | [address, _]) => {
+                let address = value(address)?;
+                let count = match args {
+                    [_, count] => value(count)?,
+                    _ => 8,
+                };
+                inspect::memory(&self.debugger.vm, address, count, symbols)
+            }
+            ("poke", [address, text]) => {
+                let (address, word) = (value(address)?, value(text)?);
+                // Written as a tool, which watchpoints do not report.
+                self.debugger.vm.memory[address as usize] = word;
+                inspect::memory(&self.debugger.vm, address, 1, symbols)
+            }
+            ("list" | "l", [] | [_]) => {
+                let address = match args {
+                    [address] => value(address)?,
+                    _ => self.debugger.vm.registers[Register::PC],
+                };
+                self.list(address)
+            }
+            ("backtrace" | "bt", [] | ["stack"]) => {
+                let vm = &self.debugger.vm;
+                let pc = vm.registers[Register::PC];
+                let word = vm.memory[pc as usize];
+                let debug = self.debugger.program.debug.as_ref();
+                let mut lines = vm.calls.backtrace(pc, word, symbols, debug).0;
+                if let ["stack"] = args {
+                    let r6 = vm.registers[6];
+                    let saved = callstack::stack_return_addresses(&vm.memory, r6);
+                    if saved.is_empty() {
+                        lines.push(format!("no return addresses on the stack at x{r6:04X}"));
+                    }
+                    for (slot, call) in saved {
+                        let from = match inspect::location(call, symbols) {
+                            Some(location) => format!("x{call:04X} ({location})"),
+                            None => format!("x{call:04X}"),
+                        };
+                        lines.push(format!("saved at x{slot:04X}: a return from {from}"));
+                    }
+                }
+                lines.join("\n")
+            }
+            _ => return Err(format!("unknown command `{input}`; try help")),
+        };
+        Ok(Reply::Show(show))
+    }
+
+    /// Resumes the program `count` times, stopping early when it stops for another reason, and
+    /// tells where it stopped.
+    fn resume(&mut self, resume: Resume, count: u16) -> Result<String, String> {
+        let reverse = matches!(resume, Resume::ReverseStep | Resume::ReverseContinue);
+        if self.debugger.finished && !reverse {
+            return Err("the program is not running; restart it first".to_string());
+        }
+        let terminal_setup = match self.terminal {
+            true => Some(disable_input_buffering().map_err(|e| format!("{e:?}"))?),
+            false => None,
+        };
+        let mut stop = Stop::Stepped;
+        for _ in 0..count.max(1) {
+            stop = self.debugger.resume(resume);
+            if !matches!(stop, Stop::Stepped) {
+                break;
+            }
+        }
+        if let Some(setup) = terminal_setup {
+            restore_terminal(setup).map_err(|e| format!("{e:?}"))?;
+        }
+        Ok(self.describe_stop(stop))
+    }
+
+    /// Why the program stopped, with where it is if it can go on.
+    fn describe_stop(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Stepped => self.location(),
+            Stop::Breakpoint(address) => {
+                format!("breakpoint at {}\n{}", self.name(address), self.location())
+            }
+            Stop::Watchpoint(hits) => {
+                let hits: Vec<_> = hits
+                    .iter()
+                    .map(|hit| format!("watchpoint: {hit}"))
+                    .collect();
+                format!("{}\n{}", hits.join("\n"), self.location())
+            }
+            Stop::Halted => "the program halted".to_string(),
+            Stop::Failed(error) => format!("the program failed: {error:?}"),
+            Stop::Paused => self.location(),
+            Stop::HistoryStart => format!("at the start of the history\n{}", self.location()),
+        }
+    }
+
+    /// The instructions around `address`, with the PC and the breakpoints marked.
+    fn list(&self, address: u16) -> String {
+        let start = address.saturating_sub(LIST_CONTEXT);
+        (start..=address.saturating_add(LIST_CONTEXT))
+            .map(|address| self.line(address))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// One line of a listing: the address, its label, the word and the instruction it holds.
+    pub fn line(&self, address: u16) -> String {
+        let vm = &self.debugger.vm;
+        let symbols = &self.debugger.program.symbols;
+        let marker = match (
+            address == vm.registers[Register::PC],
+            self.debugger.breakpoints.contains_key(&address),
+        ) {
+            (true, true) => "*>",
+            (true, false) => "=>",
+            (false, true) => "* ",
+            (false, false) => "  ",
+        };
+        let word = vm.memory[address as usize];
+        let label = inspect::name_of(address, symbols).map_or(String::new(), |name| name + ":");
+        let text = disassemble(word, address, |target| inspect::name_of(target, symbols))
+            .unwrap_or_else(|| format!(".FILL x{word:04X}"));
+        format!("{marker} x{address:04X}: {word:04X}  {label:<12}{text}")
+            .trim_end()
+            .to_string()
+    }
+
+    /// The breakpoint at `address`, or an error if there is none.
+    fn breakpoint(&mut self, address: u16) -> Result<&mut Breakpoint, String> {
+        let name = self.name(address);
+        self.debugger
+            .breakpoints
+            .get_mut(&address)
+            .ok_or(format!("no breakpoint at {name}"))
+    }
+
+    /// The address of a breakpoint with what it does and how many times it was hit.
+    fn describe_breakpoint(&self, address: u16) -> String {
+        let breakpoint = &self.debugger.breakpoints[&address];
+        let mut text = self.name(address);
+        if let Some(condition) = &breakpoint.condition {
+            text.push_str(&format!(" if {}", condition.source));
+        }
+        if let Some(message) = &breakpoint.log {
+            text.push_str(&format!(" logs \"{}\"", message.source));
+        }
+        if breakpoint.hits > 0 {
+            text.push_str(&format!(", hits: {}", breakpoint.hits));
+        }
+        if breakpoint.ignore > 0 {
+            text.push_str(&format!(", ignoring the next {}", breakpoint.ignore));
+        }
+        text
+    }
+
+    /// An address with its label, if it has one.
+    fn name(&self, address: u16) -> String {
+        match inspect::name_of(address, &self.debugger.program.symbols) {
+            Some(name) => format!("x{address:04X} ({name})"),
+            None => format!("x{address:04X}"),
+        }
+    }
+}
+
+/// What follows the first `words` words of `input`.
+fn rest(input: &str, words: usize) -> &str {
+    let mut rest = input.trim_start();
+    for _ in 0..words {
+        rest = rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..].trim_start();
+    }
+    rest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, debugger::Program, vm::VMState};
+
+    fn session(source: &str, input: &str) -> (Session, std::rc::Rc<std::cell::RefCell<Vec<u8>>>) {
+        let assembly = assemble(source).unwrap();
+        let mut vm = VMState::init().unwrap();
+        let (console, output) = BufferConsole::new(input);
+        vm.console = Box::new(console);
+        let program = Program {
+            image: assembly.to_bytes(),
+            symbols: assembly.symbols,
+            placements: assembly.placements,
+            debug: None,
+        };
+        (Session::new(Debugger::new(vm, program), false), output)
+    }
+
+    fn show(session: &mut Session, input: &str) -> String {
+        match session.execute(input) {
+            Ok(Reply::Show(text)) => text,
+            other => panic!("unexpected reply to `{input}`: {other:?}"),
+        }
+    }
+
+    const ECHO: &str = "
+        .ORIG x3000
+LOOP    GETC
+        ADD R1, R0, #-10
+        BRz DONE
+        OUT
+        BRnzp LOOP
+DONE    LEA R0, BYE
+        PUTS
+        HALT
+BYE     .STRINGZ \"bye\"
+        .END";
+
+    #[test]
+    fn shows_backtraces() {
+        let source = "
+        .ORIG x3000
+MAIN    LD R6, STACK
+        JSR SAVE
+        HALT
+SAVE    ADD R6, R6, #-1
+        STR R7, R6, #0
+        JSR PRINT
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+PRINT   RET
+STACK   .FILL x4000
+        .END";
+        let (mut session, _) = session(source, "");
+        show(&mut session, "b PRINT");
+        show(&mut session, "c");
+        assert_eq!(
+            show(&mut session, "bt stack"),
+            "at x3009 (PRINT) RET
+\
+             in PRINT, called from x3005 (SAVE+2)
+\
+             in SAVE, called from x3001 (MAIN+1)
+\
+             saved at x3FFF: a return from x3001 (MAIN+1)"
+        );
+        show(&mut session, "s");
+        assert_eq!(
+            show(&mut session, "bt"),
+            "at x3006 (SAVE+3) LDR R7, R6, #0
+in SAVE, called from x3001 (MAIN+1)"
+        );
+    }
+
+    #[test]
+    fn stops_at_breakpoints_with_console_io() {
+        let (mut session, output) = session(ECHO, "ab\n");
+        assert_eq!(session.location(), "=> x3000: F020  LOOP:       GETC");
+        assert_eq!(show(&mut session, "b DONE"), "breakpoint at x3005 (DONE)");
+        assert_eq!(
+            show(&mut session, "c"),
+            "breakpoint at x3005 (DONE)\n*> x3005: E002  DONE:       LEA R0, BYE"
+        );
+        assert_eq!(output.borrow().as_slice(), b"ab");
+        assert!(show(&mut session, "regs").contains("R1: x0000 (#0)\nR2"));
+        assert!(show(&mut session, "regs").ends_with("CC: z"));
+        assert_eq!(show(&mut session, "c"), "the program halted");
+        assert!(output.borrow().starts_with(b"abbye"));
+        assert!(session.execute("s").is_err());
+        show(&mut session, "restart");
+        assert_eq!(show(&mut session, "breakpoints"), "x3005 (DONE), hits: 1");
+    }
+
+    #[test]
+    fn breaks_on_conditions_and_logs() {
+        let (mut session, output) = session(ECHO, "abcd\n");
+        assert_eq!(
+            show(&mut session, "b x3003 if R0 == x63 || R0 == x64"),
+            "breakpoint at x3003 if R0 == x63 || R0 == x64"
+        );
+        show(&mut session, "ignore x3003 1");
+        show(&mut session, "log LOOP read {R0:c} at {PC}");
+        assert!(show(&mut session, "c").starts_with("breakpoint at x3003\n"));
+        assert_eq!(session.debugger.vm.registers[0], u16::from(b'd'));
+        assert_eq!(
+            show(&mut session, "breakpoints"),
+            "x3000 (LOOP) logs \"read {R0:c} at {PC}\", hits: 4\n\
+             x3003 if R0 == x63 || R0 == x64, hits: 2"
+        );
+        // The logpoint runs before the instruction it is on, when R0 still holds the last key.
+        assert_eq!(
+            String::from_utf8_lossy(&output.borrow()),
+            "read \0 at x3000\naread a at x3000\nbread b at x3000\ncread c at x3000\n"
+        );
+        show(&mut session, "condition x3003");
+        assert_eq!(
+            show(&mut session, "breakpoints").lines().last(),
+            Some("x3003, hits: 2")
+        );
+        assert!(session.execute("ignore DONE 1").is_err());
+        assert!(session.execute("b DONE if R0 ==").is_err());
+        show(&mut session, "d LOOP");
+        assert!(session.execute("d LOOP").is_err());
+    }
+
+    #[test]
+    fn steps_and_edits_state() {
+        let (mut session, _) = session(ECHO, "z");
+        assert_eq!(
+            show(&mut session, "s"),
+            "=> x3001: 1236              ADD R1, R0, #-10"
+        );
+        // An empty line repeats the step.
+        assert_eq!(
+            show(&mut session, ""),
+            "=> x3002: 0402              BRz DONE"
+        );
+        show(&mut session, "set R1 #0");
+        show(&mut session, "set cc z");
+        assert_eq!(
+            show(&mut session, "n"),
+            "=> x3005: E002  DONE:       LEA R0, BYE"
+        );
+        show(&mut session, "poke BYE x1041");
+        assert_eq!(
+            show(&mut session, "x BYE 1"),
+            "x3008: x1041 (#4161)  BYE: ADD R0, R1, R1"
+        );
+        let listing = show(&mut session, "l");
+        assert!(listing.contains("=> x3005"), "{listing}");
+        assert!(listing.starts_with("   x3001"), "{listing}");
+        assert!(session.execute("b NOWHERE").is_err());
+        assert_eq!(
+            show(&mut session, "w read BYE-x300B"),
+            "watchpoint 1: read x3008-x300B"
+        );
+        assert_eq!(show(&mut session, "n"), "=> x3006: F022              PUTS");
+        assert_eq!(
+            show(&mut session, "n"),
+            "watchpoint: x3006 (PUTS) read x3008: x1041\n\
+             watchpoint: x3006 (PUTS) read x3009: x0079\n\
+             watchpoint: x3006 (PUTS) read x300A: x0065\n\
+             watchpoint: x3006 (PUTS) read x300B: x0000\n\
+             => x3007: F025              HALT"
+        );
+        assert_eq!(show(&mut session, "watchpoints"), "1: read x3008-x300B");
+        assert!(session.execute("unwatch 2").is_err());
+        show(&mut session, "unwatch 1");
+        assert_eq!(session.execute("q"), Ok(Reply::Quit));
+    }
+}
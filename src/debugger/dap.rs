@@ -0,0 +1,1046 @@
+//! Debug adapter for LC-3 programs, speaking the Debug Adapter Protocol over stdio.
+//!
+//! The adapter launches one program (`.obj`, or `.asm` assembled in memory) and drives it through
+//! `Debugger`. Breakpoints can be set by source line, using the lines the assembler placed the
+//! words of, by address (instruction breakpoints) or by label or address (function breakpoints),
+//! with conditions, hit counts and log messages. There is a single thread and a single stack frame.
+//! The variables view has the registers, and memory regions (the program and the device registers)
+//! word by word. The program can be stepped and continued backwards, through its history.
+//!
+//! Messages are read by a thread of their own, so a `pause` can stop a running program. The program
+//! writes to an output buffer that is forwarded as `output` events, and reads its keys from the
+//! `input` launch argument, reading 0 once it runs out.
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs,
+    io::{BufRead, Write},
+    path::Path,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use crate::{
+    assembler::assemble_in,
+    console::BufferConsole,
+    debugger::{
+        Breakpoint, Debugger, Program, Resume, Stop,
+        expression::{Expression, Message},
+        inspect,
+    },
+    error::VMError,
+    json::Json,
+    lsp::read_message,
+    registers::{MemoryRegister, Register},
+    utils::read_file,
+    vm::VMState,
+};
+
+/// The only thread, as far as the protocol is concerned.
+const THREAD_ID: i64 = 1;
+/// `variablesReference`s of the scopes. Memory regions are numbered from `REGIONS`.
+const REGISTERS: i64 = 1;
+const MEMORY: i64 = 2;
+const REGIONS: i64 = 1000;
+
+/// Serves the client connected to `input` and `output` until it disconnects or closes the input.
+pub fn serve(input: impl BufRead + Send + 'static, output: impl Write) -> Result<(), VMError> {
+    let mut adapter = Adapter {
+        sender: Sender { output, seq: 0 },
+        messages: spawn_reader(input),
+        pending: VecDeque::new(),
+        session: None,
+    };
+    adapter.serve()
+}
+
+/// Starts a thread that sends every message read from `input`.
+fn spawn_reader(mut input: impl BufRead + Send + 'static) -> Receiver<Result<Json, VMError>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            let message = match read_message(&mut input) {
+                Ok(Some(message)) => Ok(message),
+                Ok(None) => break,
+                Err(error) => Err(error),
+            };
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// What to do once a request is answered.
+enum Then {
+    Nothing,
+    /// Tell the client it can configure the session.
+    Initialized,
+    /// Start the program, once the client configured the session.
+    Start,
+    Run(Resume),
+    /// Report that the program stopped, for the reason inside.
+    Stopped(&'static str),
+    /// End the session.
+    End,
+}
+
+/// Writes the messages of the adapter, numbering them.
+struct Sender<W: Write> {
+    output: W,
+    seq: i64,
+}
+
+impl<W: Write> Sender<W> {
+    fn send(&mut self, message: Vec<(String, Json)>) -> Result<(), VMError> {
+        self.seq += 1;
+        let mut fields = vec![("seq".to_string(), Json::Number(self.seq))];
+        fields.extend(message);
+        let body = Json::Object(fields).to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| VMError::ProtocolError(e.to_string()))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), VMError> {
+        self.send(vec![
+            ("type".to_string(), "event".into()),
+            ("event".to_string(), event.into()),
+            ("body".to_string(), body),
+        ])
+    }
+
+    fn response(&mut self, request: &Json, result: Result<Json, String>) -> Result<(), VMError> {
+        let mut message = vec![
+            ("type".to_string(), "response".into()),
+            (
+                "request_seq".to_string(),
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success".to_string(), result.is_ok().into()),
+            (
+                "command".to_string(),
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+        ];
+        match result {
+            Ok(body) => message.push(("body".to_string(), body)),
+            Err(error) => message.push(("message".to_string(), error.into())),
+        }
+        self.send(message)
+    }
+
+    /// Sends what the program wrote since the last time as an `output` event.
+    fn forward(&mut self, console_output: &RefCell<Vec<u8>>) -> Result<(), VMError> {
+        let bytes = std::mem::take(&mut *console_output.borrow_mut());
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        self.event(
+            "output",
+            Json::object([("category", "stdout".into()), ("output", text.into())]),
+        )
+    }
+}
+
+/// The launched program and the breakpoints the client set on it.
+struct Session {
+    debugger: Debugger,
+    /// What the program wrote and was not forwarded yet.
+    output: Rc<RefCell<Vec<u8>>>,
+    /// The assembly source the program was launched from, if it was one.
+    source: Option<String>,
+    stop_on_entry: bool,
+    /// The breakpoints of each kind, as each request replaces all those of its kind.
+    line_breakpoints: Vec<(u16, Breakpoint)>,
+    instruction_breakpoints: Vec<(u16, Breakpoint)>,
+    function_breakpoints: Vec<(u16, Breakpoint)>,
+}
+
+struct Adapter<W: Write> {
+    sender: Sender<W>,
+    messages: Receiver<Result<Json, VMError>>,
+    /// Messages that arrived while the program ran, to handle once it stops.
+    pending: VecDeque<Json>,
+    /// Boxed, as the memory of the VM is too large to move around on the stack.
+    session: Option<Box<Session>>,
+}
+
+impl<W: Write> Adapter<W> {
+    fn serve(&mut self) -> Result<(), VMError> {
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None => match self.messages.recv() {
+                    Ok(message) => message?,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if message.get("type").and_then(Json::as_str) != Some("request") {
+                continue;
+            }
+            let command = message.get("command").and_then(Json::as_str).unwrap_or("");
+            let arguments = message.get("arguments").unwrap_or(&Json::Null);
+            let (result, then) = match self.answer(command, arguments) {
+                Ok((body, then)) => (Ok(body), then),
+                Err(error) => (Err(error), Then::Nothing),
+            };
+            self.sender.response(&message, result)?;
+            match then {
+                Then::Nothing => {}
+                Then::Initialized => self.sender.event("initialized", Json::Null)?,
+                Then::Start => match self.session.as_ref().is_some_and(|s| s.stop_on_entry) {
+                    true => self.stopped("entry", None)?,
+                    false => self.run(Resume::Continue)?,
+                },
+                Then::Run(resume) => self.run(resume)?,
+                Then::Stopped(reason) => self.stopped(reason, None)?,
+                Then::End => return Ok(()),
+            }
+        }
+    }
+
+    fn answer(&mut self, command: &str, arguments: &Json) -> Result<(Json, Then), String> {
+        let body = match command {
+            "initialize" => capabilities(),
+            "launch" => {
+                self.session = Some(self.launch(arguments)?);
+                return Ok((Json::Null, Then::Initialized));
+            }
+            "disconnect" | "terminate" => return Ok((Json::Null, Then::End)),
+            _ => {
+                let session = self
+                    .session
+                    .as_mut()
+                    .ok_or_else(|| format!("`{command}` needs a launched program"))?;
+                return session.answer(command, arguments);
+            }
+        };
+        Ok((body, Then::Nothing))
+    }
+
+    fn launch(&self, arguments: &Json) -> Result<Box<Session>, String> {
+        let path = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("`launch` needs the path of the program")?;
+        let program = load(path)?;
+        let mut vm = VMState::init().map_err(|e| format!("{e:?}"))?;
+        vm.strict = arguments
+            .get("strict")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        let input = arguments.get("input").and_then(Json::as_str).unwrap_or("");
+        let (console, output) = BufferConsole::new(input);
+        vm.console = Box::new(console);
+        Ok(Box::new(Session {
+            debugger: Debugger::new(vm, program),
+            output,
+            source: path.ends_with(".asm").then(|| path.to_string()),
+            stop_on_entry: arguments
+                .get("stopOnEntry")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            line_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+        }))
+    }
+
+    /// Runs the program until it stops, answering `pause` and keeping other requests for later.
+    fn run(&mut self, resume: Resume) -> Result<(), VMError> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let (sender, messages, pending) = (&mut self.sender, &self.messages, &mut self.pending);
+        let console_output = &session.output;
+        let mut pause_request = None;
+        let stop = session.debugger.resume_until(resume, || {
+            // A client that cannot be told about the output will not ask for anything else either.
+            if sender.forward(console_output).is_err() {
+                return true;
+            }
+            loop {
+                match messages.try_recv() {
+                    Ok(Ok(message)) => {
+                        let command = message.get("command").and_then(Json::as_str);
+                        match command {
+                            Some("pause") => {
+                                pause_request = Some(message);
+                                return true;
+                            }
+                            Some("disconnect" | "terminate") => {
+                                pending.push_back(message);
+                                return true;
+                            }
+                            _ => pending.push_back(message),
+                        }
+                    }
+                    Ok(Err(_)) | Err(TryRecvError::Disconnected) => return true,
+                    Err(TryRecvError::Empty) => return false,
+                }
+            }
+        });
+        self.sender.forward(&session.output)?;
+        if let Some(request) = pause_request {
+            self.sender.response(&request, Ok(Json::Null))?;
+        }
+        match stop {
+            Stop::Stepped => self.stopped("step", None),
+            Stop::Breakpoint(_) => self.stopped("breakpoint", None),
+            Stop::Watchpoint(hits) => {
+                let description = hits
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.stopped("data breakpoint", Some(description))
+            }
+            Stop::Paused => self.stopped("pause", None),
+            Stop::HistoryStart => {
+                self.stopped("step", Some("at the start of the history".to_string()))
+            }
+            Stop::Halted => self.exited(0),
+            Stop::Failed(error) => {
+                self.sender.event(
+                    "output",
+                    Json::object([
+                        ("category", "stderr".into()),
+                        ("output", format!("the program failed: {error:?}\n").into()),
+                    ]),
+                )?;
+                self.exited(1)
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> Result<(), VMError> {
+        let mut body = vec![
+            ("reason".to_string(), reason.into()),
+            ("threadId".to_string(), Json::Number(THREAD_ID)),
+            ("allThreadsStopped".to_string(), true.into()),
+        ];
+        if let Some(description) = description {
+            body.push(("description".to_string(), description.into()));
+        }
+        self.sender.event("stopped", Json::Object(body))
+    }
+
+    fn exited(&mut self, code: i64) -> Result<(), VMError> {
+        self.sender
+            .event("exited", Json::object([("exitCode", Json::Number(code))]))?;
+        self.sender.event("terminated", Json::Null)
+    }
+}
+
+impl Session {
+    fn answer(&mut self, command: &str, arguments: &Json) -> Result<(Json, Then), String> {
+        let body = match command {
+            "setBreakpoints" => self.set_line_breakpoints(arguments)?,
+            "setInstructionBreakpoints" => self.set_address_breakpoints(arguments, true)?,
+            "setFunctionBreakpoints" => self.set_address_breakpoints(arguments, false)?,
+            "configurationDone" => return Ok((Json::Null, Then::Start)),
+            "threads" => Json::object([(
+                "threads",
+                Json::Array(vec![Json::object([
+                    ("id", Json::Number(THREAD_ID)),
+                    ("name", "LC-3".into()),
+                ])]),
+            )]),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Json::object([(
+                "scopes",
+                Json::Array(vec![scope("Registers", REGISTERS), scope("Memory", MEMORY)]),
+            )]),
+            "variables" => self.variables(arguments)?,
+            "setVariable" => self.set_variable(arguments)?,
+            "evaluate" => {
+                let text = arguments
+                    .get("expression")
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                let symbols = &self.debugger.program.symbols;
+                let value = Expression::parse(text, symbols)?.evaluate(&self.debugger.vm);
+                Json::object([
+                    ("result", word(value).into()),
+                    ("variablesReference", Json::Number(0)),
+                ])
+            }
+            "continue" | "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" => {
+                let resume = match command {
+                    "continue" => Resume::Continue,
+                    "next" => Resume::StepOver,
+                    "stepIn" => Resume::Step,
+                    "stepOut" => Resume::StepOut,
+                    "stepBack" => Resume::ReverseStep,
+                    _ => Resume::ReverseContinue,
+                };
+                let reverse = matches!(resume, Resume::ReverseStep | Resume::ReverseContinue);
+                if self.debugger.finished && !reverse {
+                    return Err("the program is not running".to_string());
+                }
+                let body = Json::object([("allThreadsContinued", true.into())]);
+                return Ok((body, Then::Run(resume)));
+            }
+            "pause" => return Ok((Json::Null, Then::Stopped("pause"))),
+            _ => return Err(format!("`{command}` is not supported")),
+        };
+        Ok((body, Then::Nothing))
+    }
+
+    /// Replaces the breakpoints set by source line. Lines that produced no words get the breakpoint
+    /// on the next one that did.
+    fn set_line_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str);
+        let ours = match (&self.source, path) {
+            (Some(source), Some(path)) => same_file(source, path),
+            _ => false,
+        };
+        let mut breakpoints = Vec::new();
+        let mut results = Vec::new();
+        for requested in list(arguments, "breakpoints") {
+            let line = requested.get("line").and_then(Json::as_i64).unwrap_or(0);
+            let placed = match ours {
+                true => self.debugger.program.address_of_line(line.max(0) as usize),
+                false => None,
+            };
+            let Some((line, address)) = placed else {
+                results.push(unverified("no code at this line"));
+                continue;
+            };
+            match self.breakpoint(requested) {
+                Ok(breakpoint) => {
+                    breakpoints.push((address, breakpoint));
+                    results.push(Json::object([
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                        ("instructionReference", reference(address).into()),
+                    ]));
+                }
+                Err(error) => results.push(unverified(&error)),
+            }
+        }
+        self.line_breakpoints = breakpoints;
+        self.apply_breakpoints();
+        Ok(Json::object([("breakpoints", Json::Array(results))]))
+    }
+
+    /// Replaces the instruction breakpoints (`instructionReference` plus `offset`) or the function
+    /// breakpoints (`name`, a label or an address).
+    fn set_address_breakpoints(
+        &mut self,
+        arguments: &Json,
+        by_instruction: bool,
+    ) -> Result<Json, String> {
+        let symbols = &self.debugger.program.symbols;
+        let mut breakpoints = Vec::new();
+        let mut results = Vec::new();
+        for requested in list(arguments, "breakpoints") {
+            let address = match by_instruction {
+                true => requested
+                    .get("instructionReference")
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| "missing the instruction reference".to_string())
+                    .and_then(|reference| inspect::value(reference, symbols))
+                    .map(|address| {
+                        let offset = requested.get("offset").and_then(Json::as_i64).unwrap_or(0);
+                        address.wrapping_add(offset as u16)
+                    }),
+                false => requested
+                    .get("name")
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| "missing the name".to_string())
+                    .and_then(|name| inspect::value(name, symbols)),
+            };
+            match address.and_then(|address| Ok((address, self.breakpoint(requested)?))) {
+                Ok((address, breakpoint)) => {
+                    breakpoints.push((address, breakpoint));
+                    let mut result = vec![
+                        ("verified".to_string(), true.into()),
+                        (
+                            "instructionReference".to_string(),
+                            reference(address).into(),
+                        ),
+                    ];
+                    if let Some(line) = self.debugger.program.line_of(address) {
+                        result.push(("line".to_string(), line.into()));
+                    }
+                    results.push(Json::Object(result));
+                }
+                Err(error) => results.push(unverified(&error)),
+            }
+        }
+        match by_instruction {
+            true => self.instruction_breakpoints = breakpoints,
+            false => self.function_breakpoints = breakpoints,
+        }
+        self.apply_breakpoints();
+        Ok(Json::object([("breakpoints", Json::Array(results))]))
+    }
+
+    /// A breakpoint with the `condition`, `hitCondition` (stop from that hit on) and `logMessage` of
+    /// a request.
+    fn breakpoint(&self, requested: &Json) -> Result<Breakpoint, String> {
+        let symbols = &self.debugger.program.symbols;
+        let text = |key| {
+            requested
+                .get(key)
+                .and_then(Json::as_str)
+                .filter(|text| !text.is_empty())
+        };
+        let ignore = match text("hitCondition") {
+            Some(count) => count
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("the hit condition `{count}` is not a number"))?
+                .saturating_sub(1),
+            None => 0,
+        };
+        Ok(Breakpoint {
+            condition: text("condition")
+                .map(|condition| Expression::parse(condition, symbols))
+                .transpose()?,
+            ignore,
+            hits: 0,
+            log: text("logMessage")
+                .map(|message| Message::parse(message, symbols))
+                .transpose()?,
+        })
+    }
+
+    /// Sets the breakpoints of the debugger to those of every kind.
+    fn apply_breakpoints(&mut self) {
+        self.debugger.breakpoints = self
+            .line_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+            .chain(&self.function_breakpoints)
+            .cloned()
+            .collect();
+    }
+
+    /// The frame at the PC, then one per call the program is in, at the instruction that made it.
+    fn stack_trace(&self) -> Json {
+        let pc = self.debugger.vm.registers[Register::PC];
+        let calls = self
+            .debugger
+            .vm
+            .calls
+            .frames()
+            .into_iter()
+            .map(|frame| frame.call);
+        let frames: Vec<_> = std::iter::once(pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, address)| self.frame(id, address))
+            .collect();
+        let total = Json::Number(frames.len() as i64);
+        Json::object([("stackFrames", Json::Array(frames)), ("totalFrames", total)])
+    }
+
+    fn frame(&self, id: usize, address: u16) -> Json {
+        let program = &self.debugger.program;
+        let debug = program.debug.as_ref();
+        // The frame is named after the closest label before the address, usually its subroutine.
+        let name = program
+            .symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.address <= address)
+            .max_by_key(|(_, symbol)| symbol.address)
+            .map_or_else(|| format!("x{address:04X}"), |(name, _)| name.clone());
+        // Words of included files are shown in those files.
+        let source = match debug.and_then(|debug| debug.line_at(address)) {
+            Some(source) => Some((source.file.clone(), source.line)),
+            None => self.source.clone().zip(program.line_of(address)),
+        };
+        let mut frame = vec![
+            ("id".to_string(), Json::Number(id as i64)),
+            ("name".to_string(), name.into()),
+            (
+                "line".to_string(),
+                source.as_ref().map_or(0, |(_, line)| *line).into(),
+            ),
+            ("column".to_string(), Json::Number(1)),
+            (
+                "instructionPointerReference".to_string(),
+                reference(address).into(),
+            ),
+        ];
+        if let Some((path, _)) = source {
+            let name = Path::new(&path)
+                .file_name()
+                .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+            frame.push((
+                "source".to_string(),
+                Json::object([("name", name.into()), ("path", path.into())]),
+            ));
+        }
+        Json::Object(frame)
+    }
+
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let vm = &self.debugger.vm;
+        let reference = arguments
+            .get("variablesReference")
+            .and_then(Json::as_i64)
+            .unwrap_or(0);
+        let variables = match reference {
+            REGISTERS => {
+                let mut variables: Vec<_> = (0..8)
+                    .map(|index| variable(&format!("R{index}"), word(vm.registers[index]), 0))
+                    .collect();
+                variables.push(variable("PC", word(vm.registers[Register::PC]), 0));
+                let cond = inspect::condition(vm.registers[Register::Cond]);
+                variables.push(variable("CC", cond.to_string(), 0));
+                variables
+            }
+            MEMORY => self
+                .regions()
+                .iter()
+                .enumerate()
+                .map(|(index, (name, addresses))| {
+                    let (first, last) = (addresses[0], addresses[addresses.len() - 1]);
+                    let mut region = variable(
+                        name,
+                        format!("x{first:04X}-x{last:04X}"),
+                        REGIONS + index as i64,
+                    );
+                    if let Json::Object(fields) = &mut region {
+                        fields.push(("indexedVariables".to_string(), addresses.len().into()));
+                    }
+                    region
+                })
+                .collect(),
+            _ => {
+                let regions = self.regions();
+                let (_, addresses) = usize::try_from(reference - REGIONS)
+                    .ok()
+                    .and_then(|index| regions.get(index))
+                    .ok_or_else(|| format!("there are no variables with reference {reference}"))?;
+                let start = arguments.get("start").and_then(Json::as_i64).unwrap_or(0) as usize;
+                let count = arguments
+                    .get("count")
+                    .and_then(Json::as_i64)
+                    .filter(|&count| count > 0)
+                    .map_or(addresses.len(), |count| count as usize);
+                addresses
+                    .iter()
+                    .skip(start)
+                    .take(count)
+                    .map(|&address| self.memory_variable(address))
+                    .collect()
+            }
+        };
+        Ok(Json::object([("variables", Json::Array(variables))]))
+    }
+
+    /// The memory regions of the variables view: the program and the device registers.
+    fn regions(&self) -> Vec<(&'static str, Vec<u16>)> {
+        let image = &self.debugger.program.image;
+        let mut regions = Vec::new();
+        if let [high, low, ..] = image[..] {
+            let origin = u16::from_be_bytes([high, low]);
+            let size = (image.len() / 2).saturating_sub(1).max(1) as u16;
+            regions.push((
+                "Program",
+                (0..size)
+                    .map(|offset| origin.wrapping_add(offset))
+                    .collect(),
+            ));
+        }
+        let devices = [
+            MemoryRegister::Kbsr as u16,
+            MemoryRegister::Kbdr as u16,
+            0xFE04,
+            0xFE06,
+            0xFFFE,
+        ];
+        regions.push(("Devices", devices.to_vec()));
+        regions
+    }
+
+    fn memory_variable(&self, address: u16) -> Json {
+        let symbols = &self.debugger.program.symbols;
+        let value = self.debugger.vm.memory[address as usize];
+        let name = match inspect::name_of(address, symbols) {
+            Some(label) => format!("x{address:04X} {label}"),
+            None => format!("x{address:04X}"),
+        };
+        let shown = match inspect::describe(value, address, symbols) {
+            Some(instruction) => format!("{}  {instruction}", word(value)),
+            None => word(value),
+        };
+        let mut variable = variable(&name, shown, 0);
+        if let Json::Object(fields) = &mut variable {
+            fields.push(("memoryReference".to_string(), reference(address).into()));
+        }
+        variable
+    }
+
+    /// Sets a register, or a word of memory named by its address.
+    fn set_variable(&mut self, arguments: &Json) -> Result<Json, String> {
+        let name = arguments.get("name").and_then(Json::as_str).unwrap_or("");
+        let text = arguments.get("value").and_then(Json::as_str).unwrap_or("");
+        let reference = arguments
+            .get("variablesReference")
+            .and_then(Json::as_i64)
+            .unwrap_or(0);
+        let symbols = &self.debugger.program.symbols;
+        let vm = &mut self.debugger.vm;
+        let value = if reference == REGISTERS {
+            inspect::set_register(vm, name, text, symbols)?;
+            match name.eq_ignore_ascii_case("cc") {
+                true => inspect::condition(vm.registers[Register::Cond]).to_string(),
+                false => word(inspect::value(text, symbols)?),
+            }
+        } else if reference >= REGIONS {
+            let address = name.split_whitespace().next().unwrap_or(name);
+            let address = inspect::value(address, symbols)?;
+            let value = inspect::value(text, symbols)?;
+            // Written as a tool, which watchpoints do not report.
+            vm.memory[address as usize] = value;
+            word(value)
+        } else {
+            return Err(format!("`{name}` cannot be set"));
+        };
+        Ok(Json::object([("value", value.into())]))
+    }
+}
+
+/// Reads a program to launch, assembling it first when it is an assembly source.
+fn load(path: &str) -> Result<Program, String> {
+    if !path.ends_with(".asm") {
+        let image = read_file(path).map_err(|e| format!("could not read {path}: {e:?}"))?;
+        if image.len() < 2 {
+            return Err(format!("{path} is not a program image"));
+        }
+        return Program::binary(image, path);
+    }
+    let source = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let assembly = assemble_in(&source, Path::new(path).parent()).map_err(|diagnostics| {
+        diagnostics
+            .iter()
+            .map(|diagnostic| format!("{path}:{diagnostic}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    Ok(Program::assembled(assembly, path))
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsFunctionBreakpoints", true.into()),
+        ("supportsConditionalBreakpoints", true.into()),
+        ("supportsHitConditionalBreakpoints", true.into()),
+        ("supportsLogPoints", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsSetVariable", true.into()),
+        ("supportsEvaluateForHovers", true.into()),
+        ("supportsTerminateRequest", true.into()),
+        ("supportsStepBack", true.into()),
+    ])
+}
+
+fn list<'a>(arguments: &'a Json, key: &str) -> &'a [Json] {
+    arguments.get(key).and_then(Json::as_array).unwrap_or(&[])
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn scope(name: &str, reference: i64) -> Json {
+    Json::object([
+        ("name", name.into()),
+        ("variablesReference", Json::Number(reference)),
+        ("expensive", false.into()),
+    ])
+}
+
+fn variable(name: &str, value: String, reference: i64) -> Json {
+    Json::object([
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", Json::Number(reference)),
+    ])
+}
+
+fn unverified(message: &str) -> Json {
+    Json::object([("verified", false.into()), ("message", message.into())])
+}
+
+/// A word as the protocol shows values: `x0041 (#65)`.
+fn word(value: u16) -> String {
+    format!("x{value:04X} (#{})", value as i16)
+}
+
+/// An address as a memory or instruction reference.
+fn reference(address: u16) -> String {
+    format!("0x{address:04X}")
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const ECHO: &str = "        .ORIG x3000
+LOOP    GETC
+        ADD R1, R0, #-10
+        BRz DONE
+        OUT
+        BRnzp LOOP
+
+DONE    LEA R0, BYE
+        PUTS
+        HALT
+BYE     .STRINGZ \"bye\"
+        .END
+";
+
+    fn request(seq: i64, command: &str, arguments: Json) -> String {
+        let body = Json::object([
+            ("seq", Json::Number(seq)),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", arguments),
+        ])
+        .to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    /// Serves `requests` and returns the messages sent back.
+    fn exchange(requests: Vec<String>) -> Vec<Json> {
+        let mut output = Vec::new();
+        serve(Cursor::new(requests.concat().into_bytes()), &mut output).unwrap();
+        let mut output = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// A message as `kind command-or-event`, to check the order of the conversation.
+    fn summary(message: &Json) -> String {
+        let kind = message.get("type").and_then(Json::as_str).unwrap();
+        let name = message
+            .get("command")
+            .or_else(|| message.get("event"))
+            .and_then(Json::as_str)
+            .unwrap();
+        format!("{kind} {name}")
+    }
+
+    #[test]
+    fn debugs_a_launched_source() {
+        let path = std::env::temp_dir().join(format!("dap-{}.asm", std::process::id()));
+        fs::write(&path, ECHO).unwrap();
+        let path = path.to_str().unwrap();
+        let source = Json::object([("path", path.into())]);
+        let messages = exchange(vec![
+            request(1, "initialize", Json::object([("adapterID", "lc3".into())])),
+            request(
+                2,
+                "launch",
+                Json::object([("program", path.into()), ("input", "ab\n".into())]),
+            ),
+            request(
+                3,
+                "setBreakpoints",
+                Json::object([
+                    ("source", source),
+                    (
+                        "breakpoints",
+                        Json::Array(vec![
+                            Json::object([("line", Json::Number(7))]),
+                            Json::object([
+                                ("line", Json::Number(2)),
+                                ("condition", "R0 ==".into()),
+                            ]),
+                        ]),
+                    ),
+                ]),
+            ),
+            request(4, "configurationDone", Json::Null),
+            request(
+                5,
+                "stackTrace",
+                Json::object([("threadId", Json::Number(1))]),
+            ),
+            request(
+                6,
+                "variables",
+                Json::object([("variablesReference", Json::Number(1))]),
+            ),
+            request(
+                7,
+                "evaluate",
+                Json::object([("expression", "R0 + 1".into())]),
+            ),
+            request(
+                8,
+                "setVariable",
+                Json::object([
+                    ("variablesReference", Json::Number(REGIONS)),
+                    ("name", "x3008 BYE".into()),
+                    ("value", "x4E".into()),
+                ]),
+            ),
+            request(9, "next", Json::object([("threadId", Json::Number(1))])),
+            request(
+                10,
+                "continue",
+                Json::object([("threadId", Json::Number(1))]),
+            ),
+            request(11, "disconnect", Json::Null),
+        ]);
+        fs::remove_file(path).unwrap();
+
+        let summaries: Vec<_> = messages.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            [
+                "response initialize",
+                "response launch",
+                "event initialized",
+                "response setBreakpoints",
+                "response configurationDone",
+                "event output",
+                "event stopped",
+                "response stackTrace",
+                "response variables",
+                "response evaluate",
+                "response setVariable",
+                "response next",
+                "event stopped",
+                "response continue",
+                "event output",
+                "event exited",
+                "event terminated",
+                "response disconnect",
+            ]
+        );
+        let body = |index: usize| messages[index].get("body").unwrap().to_string();
+        // Line 7 is empty, so the breakpoint goes on DONE, on line 8.
+        assert_eq!(
+            body(3),
+            "{\"breakpoints\":[{\"verified\":true,\"line\":8,\"instructionReference\":\"0x3005\"},\
+             {\"verified\":false,\"message\":\"the expression ends too soon\"}]}"
+        );
+        assert_eq!(body(5), "{\"category\":\"stdout\",\"output\":\"ab\"}");
+        assert!(body(6).contains("\"reason\":\"breakpoint\""));
+        assert!(
+            body(7).contains("\"name\":\"DONE\",\"line\":8"),
+            "{}",
+            body(7)
+        );
+        assert!(body(8).contains("{\"name\":\"R1\",\"value\":\"x0000 (#0)\""));
+        assert!(body(8).contains("{\"name\":\"CC\",\"value\":\"z\""));
+        assert_eq!(
+            body(9),
+            "{\"result\":\"x000B (#11)\",\"variablesReference\":0}"
+        );
+        assert_eq!(
+            body(14),
+            "{\"category\":\"stdout\",\"output\":\"NyeHalt execution\\n\"}"
+        );
+    }
+
+    #[test]
+    fn keeps_messages_of_unused_opcodes_inside_frames() {
+        let path = std::env::temp_dir().join(format!("dap-rti-{}.asm", std::process::id()));
+        fs::write(&path, ".ORIG x3000\n.FILL x8000\n.FILL xD000\nHALT\n.END\n").unwrap();
+        let path = path.to_str().unwrap();
+        // Everything sent back must read as messages, so nothing is written around the frames.
+        let messages = exchange(vec![
+            request(1, "launch", Json::object([("program", path.into())])),
+            request(2, "configurationDone", Json::Null),
+        ]);
+        fs::remove_file(path).unwrap();
+        let output: String = messages
+            .iter()
+            .filter(|message| summary(message) == "event output")
+            .filter_map(|message| message.get("body")?.get("output")?.as_str())
+            .collect();
+        assert!(output.starts_with("Opcode is RTI\nOpcode is RES\n"));
+    }
+
+    #[test]
+    fn lists_memory_regions() {
+        let path = std::env::temp_dir().join(format!("dap-regions-{}.asm", std::process::id()));
+        fs::write(&path, ECHO).unwrap();
+        let path = path.to_str().unwrap();
+        let messages = exchange(vec![
+            request(
+                1,
+                "launch",
+                Json::object([("program", path.into()), ("stopOnEntry", true.into())]),
+            ),
+            request(2, "configurationDone", Json::Null),
+            request(
+                3,
+                "variables",
+                Json::object([("variablesReference", Json::Number(MEMORY))]),
+            ),
+            request(
+                4,
+                "variables",
+                Json::object([
+                    ("variablesReference", Json::Number(REGIONS)),
+                    ("start", Json::Number(5)),
+                    ("count", Json::Number(1)),
+                ]),
+            ),
+            request(
+                5,
+                "setBreakpoints",
+                Json::object([
+                    ("source", Json::object([("path", "other.asm".into())])),
+                    (
+                        "breakpoints",
+                        Json::Array(vec![Json::object([("line", Json::Number(2))])]),
+                    ),
+                ]),
+            ),
+        ]);
+        fs::remove_file(path).unwrap();
+        assert!(
+            messages[3]
+                .get("body")
+                .unwrap()
+                .to_string()
+                .contains("\"reason\":\"entry\"")
+        );
+        assert_eq!(
+            messages[4].get("body").unwrap().to_string(),
+            "{\"variables\":[\
+             {\"name\":\"Program\",\"value\":\"x3000-x300B\",\"variablesReference\":1000,\"indexedVariables\":12},\
+             {\"name\":\"Devices\",\"value\":\"xFE00-xFFFE\",\"variablesReference\":1001,\"indexedVariables\":5}]}"
+        );
+        assert_eq!(
+            messages[5].get("body").unwrap().to_string(),
+            "{\"variables\":[{\"name\":\"x3005 DONE\",\"value\":\"xE002 (#-8190)  LEA R0, BYE\",\
+             \"variablesReference\":0,\"memoryReference\":\"0x3005\"}]}"
+        );
+        assert!(
+            messages[6]
+                .get("body")
+                .unwrap()
+                .to_string()
+                .contains("\"verified\":false")
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! The expressions of conditional breakpoints and logpoints, over the registers, the condition
+//! codes and memory: `R0 == x41 && mem[x4000] > 10`.
+//!
+//! Operands are numbers (`x41`, `#10`, `10`), labels (their address), `R0`-`R7`, `PC`, the condition
+//! codes `n`, `z` and `p` (1 when set, 0 otherwise) and `mem[address]`. Operators, from the loosest
+//! to the tightest: `||`, `&&`, comparisons (`== != < <= > >=`, signed), `+` and `-` (wrapping),
+//! and `!` and `-` before an operand. Comparisons and logic operators give 1 or 0, and anything
+//! other than 0 is true.
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::{Symbol, lexer::parse_number, parser::register_number},
+    registers::Register,
+    vm::VMState,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(u16),
+    Register(usize),
+    /// A condition code, by its bit in `Register::Cond`.
+    Flag(u16),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+/// A parsed expression, with the text it was parsed from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub source: String,
+    node: Node,
+}
+
+impl Expression {
+    /// Parses `text`, where labels stand for their address in `symbols`.
+    pub fn parse(text: &str, symbols: &BTreeMap<String, Symbol>) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            symbols,
+        };
+        let node = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("unexpected `{token}` in `{text}`"));
+        }
+        Ok(Self {
+            source: text.trim().to_string(),
+            node,
+        })
+    }
+
+    /// The value of the expression on `vm`. Memory is read as it is, without the side effects of
+    /// the keyboard registers.
+    pub fn evaluate(&self, vm: &VMState) -> u16 {
+        evaluate(&self.node, vm)
+    }
+
+    /// Whether the expression is true (not 0) on `vm`.
+    pub fn holds(&self, vm: &VMState) -> bool {
+        self.evaluate(vm) != 0
+    }
+}
+
+fn evaluate(node: &Node, vm: &VMState) -> u16 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Register(index) => vm.registers[*index],
+        Node::Flag(bit) => u16::from(vm.registers[Register::Cond] & bit != 0),
+        Node::Memory(address) => vm.memory[evaluate(address, vm) as usize],
+        Node::Not(operand) => u16::from(evaluate(operand, vm) == 0),
+        Node::Negate(operand) => evaluate(operand, vm).wrapping_neg(),
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, vm);
+            // The logic operators only look at the right side when they need to.
+            match operator {
+                Operator::Or if left != 0 => return 1,
+                Operator::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = evaluate(right, vm);
+            let (signed_left, signed_right) = (left as i16, right as i16);
+            match operator {
+                Operator::Or | Operator::And => u16::from(right != 0),
+                Operator::Equal => u16::from(left == right),
+                Operator::NotEqual => u16::from(left != right),
+                Operator::Less => u16::from(signed_left < signed_right),
+                Operator::LessOrEqual => u16::from(signed_left <= signed_right),
+                Operator::Greater => u16::from(signed_left > signed_right),
+                Operator::GreaterOrEqual => u16::from(signed_left >= signed_right),
+                Operator::Add => left.wrapping_add(right),
+                Operator::Subtract => left.wrapping_sub(right),
+            }
+        }
+    }
+}
+
+/// Splits `text` into words (numbers, labels, registers) and operators.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    const OPERATORS: [&str; 15] = [
+        "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "(", ")", "[", "]",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let word_length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '#'))
+            .unwrap_or(rest.len());
+        let length = if word_length > 0 {
+            word_length
+        } else {
+            match OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+            {
+                Some(operator) => operator.len(),
+                None => return Err(format!("unexpected `{}` in `{text}`", &rest[..1])),
+            }
+        };
+        // `#-5` is a single number.
+        let length = match &rest[..length] {
+            "#" if rest[1..].starts_with('-') => {
+                1 + rest[1..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                    .unwrap_or(rest.len() - 1)
+            }
+            _ => length,
+        };
+        tokens.push(rest[..length].to_string());
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    symbols: &'a BTreeMap<String, Symbol>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "the expression ends too soon".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected `{expected}`, found `{token}`")),
+        }
+    }
+
+    /// Parses operands joined by the operators of one level, from left to right.
+    fn binary(
+        &mut self,
+        operators: &[(&str, Operator)],
+        operand: fn(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let mut node = operand(self)?;
+        while let Some(&(_, operator)) = operators
+            .iter()
+            .find(|(token, _)| self.peek() == Some(*token))
+        {
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(operand(self)?));
+        }
+        Ok(node)
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        self.binary(&[("||", Operator::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        self.binary(&[("&&", Operator::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("==", Operator::Equal),
+                ("!=", Operator::NotEqual),
+                ("<", Operator::Less),
+                ("<=", Operator::LessOrEqual),
+                (">", Operator::Greater),
+                (">=", Operator::GreaterOrEqual),
+            ],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[("+", Operator::Add), ("-", Operator::Subtract)],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some("!") => {
+                self.position += 1;
+                Ok(Node::Not(Box::new(self.unary()?)))
+            }
+            Some("-") => {
+                self.position += 1;
+                Ok(Node::Negate(Box::new(self.unary()?)))
+            }
+            _ => self.operand(),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+        if token == "(" {
+            let node = self.or()?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if token.eq_ignore_ascii_case("mem") {
+            self.expect("[")?;
+            let address = self.or()?;
+            self.expect("]")?;
+            return Ok(Node::Memory(Box::new(address)));
+        }
+        if token.eq_ignore_ascii_case("pc") {
+            return Ok(Node::Register(Register::PC as usize));
+        }
+        if let Some(number) = register_number(&token) {
+            return Ok(Node::Register(number as usize));
+        }
+        match token.as_str() {
+            "n" | "N" => return Ok(Node::Flag(0b100)),
+            "z" | "Z" => return Ok(Node::Flag(0b010)),
+            "p" | "P" => return Ok(Node::Flag(0b001)),
+            _ => {}
+        }
+        match parse_number(&token) {
+            Some(Ok(value)) if (-0x8000..=0xFFFF).contains(&value) => {
+                Ok(Node::Number(value as u16))
+            }
+            Some(_) => Err(format!("`{token}` is not a 16-bit number")),
+            None => match self.symbols.get(&token) {
+                Some(symbol) => Ok(Node::Number(symbol.address)),
+                None => Err(format!("`{token}` is not a number, a register or a label")),
+            },
+        }
+    }
+}
+
+/// How a value is written in a log message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// `x0041`, the default.
+    Hex,
+    /// `65`, signed.
+    Decimal,
+    /// `A`, the low byte as a character.
+    Char,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Expression, Format),
+}
+
+/// The message of a logpoint: text with expressions between braces, written in hex, or in decimal
+/// or as a character when followed by `:d` or `:c` (`R0 is {R0:c}, count is {mem[COUNT]:d}`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub source: String,
+    parts: Vec<Part>,
+}
+
+impl Message {
+    pub fn parse(text: &str, symbols: &BTreeMap<String, Symbol>) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` in `{text}`"))?
+                + start;
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let inside = &rest[start + 1..end];
+            let (expression, format) = match inside.rsplit_once(':') {
+                Some((expression, "d")) => (expression, Format::Decimal),
+                Some((expression, "c")) => (expression, Format::Char),
+                Some((_, format)) => return Err(format!("unknown format `:{format}`")),
+                None => (inside, Format::Hex),
+            };
+            parts.push(Part::Value(Expression::parse(expression, symbols)?, format));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Self {
+            source: text.to_string(),
+            parts,
+        })
+    }
+
+    /// The message with the values of its expressions on `vm`.
+    pub fn format(&self, vm: &VMState) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value(expression, format) => {
+                    let value = expression.evaluate(vm);
+                    match format {
+                        Format::Hex => format!("x{value:04X}"),
+                        Format::Decimal => (value as i16).to_string(),
+                        Format::Char => char::from(value as u8).to_string(),
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vm() -> VMState {
+        let mut vm = VMState::init().unwrap();
+        vm.registers[0] = 0x41;
+        vm.registers[1] = (-3i16) as u16;
+        vm.memory[0x4000] = 12;
+        vm
+    }
+
+    fn symbols() -> BTreeMap<String, Symbol> {
+        let mut symbols = BTreeMap::new();
+        symbols.insert(
+            "COUNT".to_string(),
+            Symbol {
+                address: 0x4000,
+                line: 1,
+                origin: None,
+            },
+        );
+        symbols
+    }
+
+    fn value(text: &str) -> u16 {
+        Expression::parse(text, &symbols()).unwrap().evaluate(&vm())
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        assert_eq!(value("R0 == x41 && mem[x4000] > 10"), 1);
+        assert_eq!(value("R0 == x41 && mem[COUNT] > #12"), 0);
+        assert_eq!(value("R1 < 0 || mem[x4000]"), 1);
+        assert_eq!(value("R1 == #-3 && R1 + 3 == 0"), 1);
+        assert_eq!(value("-(R0 - x40)"), 0xFFFF);
+        assert_eq!(value("z && !n && !p"), 1);
+        assert_eq!(value("mem[COUNT + 1 - 1]"), 12);
+        assert_eq!(value("PC"), 0x3000);
+        for text in ["R0 ==", "R0 = 1", "mem[x4000", "NOWHERE", "(R0", "R0 R1"] {
+            assert!(Expression::parse(text, &symbols()).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn formats_messages() {
+        let message = Message::parse("R0={R0:c} ({R0}), count {mem[COUNT]:d}", &symbols());
+        assert_eq!(message.unwrap().format(&vm()), "R0=A (x0041), count 12");
+        assert!(Message::parse("{R0", &symbols()).is_err());
+        assert!(Message::parse("{R0:q}", &symbols()).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! A stub for the GDB remote serial protocol, so front ends that speak it can debug programs on the
+//! VM over a TCP socket.
+//!
+//! The LC-3 addresses 16-bit words, so this is what the protocol's addressable memory unit is here:
+//! addresses and lengths count words, and every word is sent as 4 hex digits, high byte first.
+//! Registers are numbered R0-R7, PC (8) and Cond (9), 16 bits each and sent the same way.
+//!
+//! Supported packets: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `s`, `c`, `Z0`/`z0` (breakpoints),
+//! `Z2`-`Z4`/`z2`-`z4` (write, read and access watchpoints), `bs` and `bc` (reverse step and
+//! continue), `k`, `D`, `qSupported`, `qAttached`,
+//! `QStartNoAckMode` and the interrupt byte (Ctrl-C) while the program runs. Everything else gets
+//! the empty reply, which tells the client it is not supported.
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    debugger::{Breakpoint, Debugger, Resume, Stop},
+    error::VMError,
+    registers::Register,
+    watchpoint::{Access, Watchpoint},
+};
+
+/// The signal reported when the program stops at a breakpoint, after a step or when interrupted.
+const SIGTRAP: &str = "05";
+const INTERRUPT: u8 = 0x03;
+
+/// Waits for a client on `listener` and serves it until it detaches, kills the program or
+/// disconnects.
+pub fn serve(debugger: Debugger, listener: &TcpListener) -> Result<(), VMError> {
+    let (stream, _) = listener
+        .accept()
+        .map_err(|e| VMError::ProtocolError(e.to_string()))?;
+    // Packets are small and answered one at a time, so they are sent right away.
+    stream
+        .set_nodelay(true)
+        .map_err(|e| VMError::ProtocolError(e.to_string()))?;
+    let mut stub = Stub {
+        debugger,
+        reader: BufReader::new(
+            stream
+                .try_clone()
+                .map_err(|e| VMError::ProtocolError(e.to_string()))?,
+        ),
+        writer: stream,
+        acknowledge: true,
+    };
+    stub.serve()
+        .map_err(|e| VMError::ProtocolError(e.to_string()))
+}
+
+/// What the stub does after answering a packet.
+enum Next {
+    Reply(String),
+    /// Answers and closes the connection.
+    Close(String),
+}
+
+struct Stub {
+    debugger: Debugger,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Whether packets are acknowledged with `+`, which the client can turn off.
+    acknowledge: bool,
+}
+
+impl Stub {
+    fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            if self.acknowledge {
+                self.writer.write_all(b"+")?;
+            }
+            match self.answer(&packet) {
+                Next::Reply(reply) => self.send(&reply)?,
+                Next::Close(reply) => {
+                    self.send(&reply)?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the data of the next packet, skipping acknowledgements and stray interrupts. Returns
+    /// `None` once the client disconnects.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read_exact_or_eof(&mut byte)? {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                continue;
+            }
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            if self.reader.read_exact_or_eof(&mut checksum)? {
+                return Ok(None);
+            }
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected != Some(checksum_of(&data)) {
+                if self.acknowledge {
+                    self.writer.write_all(b"-")?;
+                }
+                continue;
+            }
+            return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum_of(data.as_bytes()));
+        self.writer.write_all(packet.as_bytes())?;
+        self.writer.flush()
+    }
+
+    fn answer(&mut self, packet: &str) -> Next {
+        if !packet.is_ascii() {
+            return Next::Reply(String::new());
+        }
+        let reply = match packet.split_at(packet.len().min(1)) {
+            ("?", _) => format!("S{SIGTRAP}"),
+            ("g", _) => self.debugger.vm.registers.iter().map(hex).collect(),
+            ("G", data) => match words(data) {
+                Some(values) if values.len() == Register::COUNT => {
+                    self.debugger.vm.registers.copy_from_slice(&values);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            ("p", number) => match register(number) {
+                Some(index) => hex(&self.debugger.vm.registers[index]),
+                None => "E01".to_string(),
+            },
+            ("P", assignment) => match assignment
+                .split_once('=')
+                .and_then(|(number, value)| Some((register(number)?, words(value)?)))
+            {
+                Some((index, values)) if values.len() == 1 => {
+                    self.debugger.vm.registers[index] = values[0];
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            ("m", range) => match address_and_length(range) {
+                Some((address, length)) => (0..length)
+                    .map(|offset| {
+                        hex(&self.debugger.vm.memory[address.wrapping_add(offset) as usize])
+                    })
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            ("M", write) => match write
+                .split_once(':')
+                .and_then(|(range, data)| Some((address_and_length(range)?, words(data)?)))
+            {
+                Some(((address, length), values)) if values.len() == length as usize => {
+                    for (offset, value) in values.into_iter().enumerate() {
+                        // Written as a tool, which watchpoints do not report.
+                        self.debugger.vm.memory[address.wrapping_add(offset as u16) as usize] =
+                            value;
+                    }
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            ("s", _) => self.resume(Resume::Step),
+            ("c", _) => self.resume(Resume::Continue),
+            _ if packet == "bs" => self.resume(Resume::ReverseStep),
+            _ if packet == "bc" => self.resume(Resume::ReverseContinue),
+            ("Z" | "z", point) => self.point(packet.starts_with('Z'), point),
+            ("k" | "D", _) => return Next::Close("OK".to_string()),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;ReverseStep+;ReverseContinue+".to_string()
+            }
+            _ if packet == "qAttached" => "1".to_string(),
+            _ if packet == "QStartNoAckMode" => {
+                self.acknowledge = false;
+                "OK".to_string()
+            }
+            _ => String::new(),
+        };
+        Next::Reply(reply)
+    }
+
+    /// Sets or removes (`insert`) a breakpoint or a watchpoint, from `type,address,kind`.
+    fn point(&mut self, insert: bool, point: &str) -> String {
+        let mut fields = point.splitn(3, ',');
+        let (Some(kind), Some(address), Some(length)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return "E01".to_string();
+        };
+        let Some((address, length)) = address_and_length(&format!("{address},{length}")) else {
+            return "E01".to_string();
+        };
+        let accesses: &[Access] = match kind {
+            "0" => {
+                match insert {
+                    true => self
+                        .debugger
+                        .breakpoints
+                        .insert(address, Breakpoint::default()),
+                    false => self.debugger.breakpoints.remove(&address),
+                };
+                return "OK".to_string();
+            }
+            "2" => &[Access::Write],
+            "3" => &[Access::Read],
+            "4" => &[Access::Read, Access::Write],
+            _ => return String::new(),
+        };
+        let end = address.saturating_add(length.max(1) - 1);
+        let watchpoints = &mut self.debugger.vm.watchpoints;
+        for &access in accesses {
+            let watchpoint = Watchpoint {
+                access,
+                start: address,
+                end,
+            };
+            match insert {
+                true => watchpoints.push(watchpoint),
+                false => watchpoints.retain(|existing| *existing != watchpoint),
+            }
+        }
+        "OK".to_string()
+    }
+
+    /// Runs the program and tells why it stopped. While it runs, an interrupt from the client stops
+    /// it too.
+    fn resume(&mut self, resume: Resume) -> String {
+        let reverse = matches!(resume, Resume::ReverseStep | Resume::ReverseContinue);
+        if self.debugger.finished && !reverse {
+            return "W00".to_string();
+        }
+        let reader = &mut self.reader;
+        let stop = self.debugger.resume_until(resume, || interrupted(reader));
+        match stop {
+            Stop::Stepped | Stop::Breakpoint(_) | Stop::Paused => format!("S{SIGTRAP}"),
+            Stop::Watchpoint(hits) => {
+                let kind = match hits[0].access {
+                    Access::Read => "rwatch",
+                    Access::Write | Access::Change => "watch",
+                };
+                format!("T{SIGTRAP}{kind}:{:x};", hits[0].address)
+            }
+            Stop::Halted => "W00".to_string(),
+            Stop::HistoryStart => format!("T{SIGTRAP}replaylog:begin;"),
+            Stop::Failed(error) => {
+                eprintln!("the program failed: {error:?}");
+                // SIGILL, as failures are illegal or unknown instructions and traps.
+                "X04".to_string()
+            }
+        }
+    }
+}
+
+/// Whether the client sent the interrupt byte, without waiting for it.
+fn interrupted(reader: &mut BufReader<TcpStream>) -> bool {
+    if reader.get_ref().set_nonblocking(true).is_err() {
+        return false;
+    }
+    let interrupted = match reader.fill_buf() {
+        Ok([INTERRUPT, ..]) => {
+            reader.consume(1);
+            true
+        }
+        _ => false,
+    };
+    let _ = reader.get_ref().set_nonblocking(false);
+    interrupted
+}
+
+trait ReadExactOrEof {
+    /// Fills `buffer`, returning true instead if the stream ended.
+    fn read_exact_or_eof(&mut self, buffer: &mut [u8]) -> io::Result<bool>;
+}
+
+impl<R: io::Read> ReadExactOrEof for R {
+    fn read_exact_or_eof(&mut self, buffer: &mut [u8]) -> io::Result<bool> {
+        match self.read_exact(buffer) {
+            Ok(()) => Ok(false),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(true),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Undoes the escaping of `#`, `$`, `}` and `*` as `}` followed by the byte xor 0x20.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = data.iter();
+    let mut unescaped = Vec::new();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => unescaped.push(byte),
+        }
+    }
+    unescaped
+}
+
+fn hex(word: &u16) -> String {
+    format!("{word:04x}")
+}
+
+/// Words sent as 4 hex digits each.
+fn words(data: &str) -> Option<Vec<u16>> {
+    if !data.len().is_multiple_of(4) || !data.is_ascii() {
+        return None;
+    }
+    (0..data.len())
+        .step_by(4)
+        .map(|start| u16::from_str_radix(&data[start..start + 4], 16).ok())
+        .collect()
+}
+
+fn register(number: &str) -> Option<usize> {
+    usize::from_str_radix(number, 16)
+        .ok()
+        .filter(|&index| index < Register::COUNT)
+}
+
+/// `address,length`, in hex.
+fn address_and_length(range: &str) -> Option<(u16, u16)> {
+    let (address, length) = range.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_packet_fields() {
+        assert_eq!(checksum_of(b"qSupported"), 0x37);
+        assert_eq!(unescape(b"a}\x03b"), b"a#b");
+        assert_eq!(words("3000ffff"), Some(vec![0x3000, 0xFFFF]));
+        assert_eq!(words("300"), None);
+        assert_eq!(address_and_length("3000,a"), Some((0x3000, 10)));
+        assert_eq!(register("9"), Some(9));
+        assert_eq!(register("a"), None);
+    }
+}
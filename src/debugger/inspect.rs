@@ -0,0 +1,120 @@
+//! Showing and editing the state of a VM from text, shared by the interactive tools.
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::{Symbol, lexer::parse_number, parser::register_number},
+    disassembler::disassemble,
+    registers::Register,
+    vm::VMState,
+};
+
+/// The general purpose registers, the PC and the condition codes, one per line.
+pub fn registers(vm: &VMState) -> String {
+    let registers = &vm.registers;
+    let mut out = String::new();
+    for (index, value) in registers[..8].iter().enumerate() {
+        out.push_str(&format!("R{index}: x{value:04X} (#{})\n", *value as i16));
+    }
+    out.push_str(&format!("PC: x{:04X}\n", registers[Register::PC]));
+    out.push_str(&format!("CC: {}", condition(registers[Register::Cond])));
+    out
+}
+
+/// The condition codes as the flag that is set.
+pub fn condition(cond: u16) -> &'static str {
+    match cond {
+        0b100 => "n",
+        0b010 => "z",
+        0b001 => "p",
+        _ => "?",
+    }
+}
+
+/// `count` words of memory from `address`, with their labels and the instructions they hold.
+pub fn memory(
+    vm: &VMState,
+    address: u16,
+    count: u16,
+    symbols: &BTreeMap<String, Symbol>,
+) -> String {
+    (0..count)
+        .map(|offset| {
+            let address = address.wrapping_add(offset);
+            let word = vm.memory[address as usize];
+            let label = name_of(address, symbols).map_or(String::new(), |name| format!("{name}: "));
+            format!(
+                "x{address:04X}: x{word:04X} (#{})  {label}{}",
+                word as i16,
+                describe(word, address, symbols).unwrap_or_default()
+            )
+            .trim_end()
+            .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The instruction a word holds, with labels for its targets, if it is one the assembler writes.
+pub fn describe(word: u16, address: u16, symbols: &BTreeMap<String, Symbol>) -> Option<String> {
+    disassemble(word, address, |target| name_of(target, symbols))
+}
+
+/// The label at `address`, if there is one.
+pub fn name_of(address: u16, symbols: &BTreeMap<String, Symbol>) -> Option<String> {
+    symbols
+        .iter()
+        .find(|(_, symbol)| symbol.address == address)
+        .map(|(name, _)| name.clone())
+}
+
+/// Where `address` is in the program: the closest label at or before it, with the offset from it
+/// (`LOOP` or `LOOP+2`), if there is one.
+pub fn location(address: u16, symbols: &BTreeMap<String, Symbol>) -> Option<String> {
+    let (name, symbol) = symbols
+        .iter()
+        .filter(|(_, symbol)| symbol.address <= address)
+        .max_by_key(|(_, symbol)| symbol.address)?;
+    Some(match address - symbol.address {
+        0 => name.clone(),
+        offset => format!("{name}+{offset}"),
+    })
+}
+
+/// A number (`x3000`, `#10`, `10`), or the address of a label.
+pub fn value(text: &str, symbols: &BTreeMap<String, Symbol>) -> Result<u16, String> {
+    match parse_number(text) {
+        Some(Ok(value)) if (-0x8000..=0xFFFF).contains(&value) => Ok(value as u16),
+        Some(_) => Err(format!("`{text}` is not a 16-bit number")),
+        None => symbols
+            .get(text)
+            .map(|symbol| symbol.address)
+            .ok_or_else(|| format!("`{text}` is not a number or a label")),
+    }
+}
+
+/// Sets R0-R7 or the PC to a value, or the condition codes (CC) to n, z or p.
+pub fn set_register(
+    vm: &mut VMState,
+    register: &str,
+    text: &str,
+    symbols: &BTreeMap<String, Symbol>,
+) -> Result<(), String> {
+    if register.eq_ignore_ascii_case("cc") {
+        vm.registers[Register::Cond] = match text.to_ascii_lowercase().as_str() {
+            "n" => 0b100,
+            "z" => 0b010,
+            "p" => 0b001,
+            _ => return Err("the condition codes are set to n, z or p".to_string()),
+        };
+        return Ok(());
+    }
+    let value = value(text, symbols)?;
+    if register.eq_ignore_ascii_case("pc") {
+        vm.registers[Register::PC] = value;
+    } else if let Some(number) = register_number(register) {
+        vm.registers[number as usize] = value;
+    } else {
+        return Err(format!("`{register}` is not a register"));
+    }
+    Ok(())
+}
@@ -0,0 +1,540 @@
+//! Debugger for LC-3 programs.
+//!
+//! `Debugger` runs a program on a VM under control: it stops at breakpoints and after steps, and
+//! keeps what is needed to restart the program from scratch. It does not read commands nor touch the
+//! terminal itself, so the front ends (`commands`, the command line one) decide how to talk to the
+//! user, and the program's own console keeps working through the VM's console while it runs.
+//! Breakpoints can have a condition (`expression`), skip a number of hits, or log a message instead
+//! of stopping. Watchpoints live on the VM, which records the accesses that match them; the debugger stops after
+//! the instruction that made them. `inspect` shows and edits the state of the VM from text.
+//! The VM records the history of the program, so it can also be run backwards to a breakpoint or a
+//! watchpoint, or taken to any instruction count. `tui` is a full-screen front end over `commands`.
+pub mod commands;
+pub mod dap;
+pub mod expression;
+pub mod gdb;
+pub mod inspect;
+pub mod tui;
+
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::{Assembly, Placement, Symbol, debuginfo::DebugInfo},
+    debugger::expression::{Expression, Message},
+    error::VMError,
+    history::History,
+    instruction::Instruction,
+    registers::Register,
+    vm::VMState,
+    watchpoint::Hit,
+};
+
+/// How many instructions run between two checks for a request to pause.
+const PAUSE_CHECK_EVERY: usize = 10_000;
+
+/// A program to debug: its image and, when it was assembled from source, its labels and the source
+/// lines its words come from.
+pub struct Program {
+    pub image: Vec<u8>,
+    pub symbols: BTreeMap<String, Symbol>,
+    pub placements: Vec<Placement>,
+    /// The files and lines its words come from, through includes, from the source or a debug info
+    /// file next to the binary.
+    pub debug: Option<DebugInfo>,
+}
+
+impl Program {
+    /// The program assembled from the source file at `path`, with its debug info.
+    pub fn assembled(assembly: Assembly, path: &str) -> Self {
+        let mut debug = DebugInfo::new(&assembly, path);
+        debug.read_sources();
+        Self {
+            image: assembly.to_bytes(),
+            symbols: assembly.symbols,
+            placements: assembly.placements,
+            debug: Some(debug),
+        }
+    }
+
+    /// The program image read from the binary at `path`, with the debug info next to it if there is
+    /// one.
+    pub fn binary(image: Vec<u8>, path: &str) -> Result<Self, String> {
+        let mut debug = DebugInfo::load_for(path)?;
+        if let Some(debug) = &mut debug {
+            debug.read_sources();
+        }
+        Ok(Self {
+            image,
+            symbols: BTreeMap::new(),
+            placements: Vec::new(),
+            debug,
+        })
+    }
+
+    /// The source line the word at `address` comes from.
+    pub fn line_of(&self, address: u16) -> Option<usize> {
+        self.placements
+            .iter()
+            .find(|placement| {
+                (placement.address..placement.address.saturating_add(placement.size))
+                    .contains(&address)
+            })
+            .map(|placement| placement.line)
+    }
+
+    /// The first line from `line` on that produced words, with the address of its first word.
+    pub fn address_of_line(&self, line: usize) -> Option<(usize, u16)> {
+        self.placements
+            .iter()
+            .filter(|placement| placement.line >= line && placement.size > 0)
+            .min_by_key(|placement| (placement.line, placement.address))
+            .map(|placement| (placement.line, placement.address))
+    }
+}
+
+/// How far to run when resuming.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    /// One instruction.
+    Step,
+    /// One instruction, running subroutine calls (JSR, JSRR) through to their return.
+    StepOver,
+    /// Until the current subroutine returns.
+    StepOut,
+    /// Until a breakpoint, a watchpoint or the end of the program.
+    Continue,
+    /// One instruction back.
+    ReverseStep,
+    /// Backwards until a breakpoint, a watchpoint or the start of the history.
+    ReverseContinue,
+}
+
+/// Why the program stopped.
+#[derive(Debug)]
+pub enum Stop {
+    /// The step asked for is done.
+    Stepped,
+    /// The PC reached a breakpoint, at the address inside.
+    Breakpoint(u16),
+    /// The last instruction made accesses that matched watchpoints.
+    Watchpoint(Vec<Hit>),
+    /// The program executed HALT.
+    Halted,
+    /// The VM failed.
+    Failed(VMError),
+    /// The front end asked to pause.
+    Paused,
+    /// The program was taken back to the earliest instruction of its history.
+    HistoryStart,
+}
+
+/// A breakpoint, which may stop only when a condition holds or once it was hit a number of times,
+/// or log a message and let the program go on (a logpoint).
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoint {
+    pub condition: Option<Expression>,
+    /// How many more hits are let through before the breakpoint acts again.
+    pub ignore: u32,
+    /// How many times the program got to the breakpoint with its condition holding.
+    pub hits: u32,
+    /// The message of a logpoint, written to the program's console instead of stopping.
+    pub log: Option<Message>,
+}
+
+pub struct Debugger {
+    pub vm: VMState,
+    pub program: Program,
+    pub breakpoints: BTreeMap<u16, Breakpoint>,
+    /// Whether the program halted or failed, so it has to be restarted to run again.
+    pub finished: bool,
+    /// Whether the breakpoint at the PC, if any, was already looked at since the program got there.
+    checked: bool,
+}
+
+impl Debugger {
+    /// Loads `program` on `vm`, ready to run from its origin.
+    pub fn new(vm: VMState, program: Program) -> Self {
+        let mut debugger = Self {
+            vm,
+            program,
+            breakpoints: BTreeMap::new(),
+            finished: false,
+            checked: false,
+        };
+        debugger.load();
+        debugger
+    }
+
+    /// Starts the program again on a fresh VM, keeping the console, the mode, the breakpoints and the
+    /// watchpoints.
+    pub fn restart(&mut self) -> Result<(), VMError> {
+        let mut vm = VMState::init()?;
+        std::mem::swap(&mut vm.console, &mut self.vm.console);
+        vm.strict = self.vm.strict;
+        vm.watchpoints = std::mem::take(&mut self.vm.watchpoints);
+        self.vm = vm;
+        self.load();
+        Ok(())
+    }
+
+    fn load(&mut self) {
+        self.vm.write_ixs_to_mem(self.program.image.clone());
+        if let [high, low, ..] = self.program.image[..] {
+            self.vm.registers[Register::PC] = u16::from_be_bytes([high, low]);
+        }
+        self.vm.history = Some(History::new());
+        self.vm.symbols = self.program.symbols.clone();
+        self.vm.debug = self.program.debug.clone();
+        self.finished = false;
+        self.checked = false;
+    }
+
+    /// The instruction the PC points to, read without the side effects of the keyboard registers.
+    pub fn current(&self) -> Instruction {
+        let pc = self.vm.registers[Register::PC];
+        Instruction::decode(self.vm.memory[pc as usize])
+    }
+
+    /// Runs the program until `resume` is done, a breakpoint or a watchpoint is hit or the program
+    /// ends. Breakpoints act when the program gets to them, before the instruction they are on runs,
+    /// so resuming from one moves on.
+    pub fn resume(&mut self, resume: Resume) -> Stop {
+        self.resume_until(resume, || false)
+    }
+
+    /// Resumes like `resume`, also stopping when `pause` returns true. It is called every few
+    /// thousand instructions, so front ends can look for requests while the program runs.
+    pub fn resume_until(&mut self, resume: Resume, mut pause: impl FnMut() -> bool) -> Stop {
+        if matches!(resume, Resume::ReverseStep | Resume::ReverseContinue) {
+            return self.reverse(resume, pause);
+        }
+        if self.finished {
+            return Stop::Halted;
+        }
+        // The program may be at a breakpoint it has not got to by running, like its first instruction.
+        if !self.checked && self.check_breakpoint() {
+            return Stop::Breakpoint(self.vm.registers[Register::PC]);
+        }
+        // How many subroutines deeper than where it started the program is.
+        let mut depth: i32 = 0;
+        let mut steps = 0;
+        loop {
+            let instruction = self.current();
+            self.checked = false;
+            let result = self.vm.step();
+            let hits = std::mem::take(&mut self.vm.hits);
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.finished = true;
+                    return Stop::Halted;
+                }
+                Err(error) => {
+                    self.finished = true;
+                    return Stop::Failed(error);
+                }
+            }
+            match instruction {
+                Instruction::Jsr { .. } | Instruction::Jsrr { .. } => depth += 1,
+                Instruction::Jmp { base: 7 } => depth -= 1,
+                _ => {}
+            }
+            if !hits.is_empty() {
+                return Stop::Watchpoint(hits);
+            }
+            if self.check_breakpoint() {
+                return Stop::Breakpoint(self.vm.registers[Register::PC]);
+            }
+            let done = match resume {
+                Resume::Step => true,
+                Resume::StepOver => depth <= 0,
+                Resume::StepOut => depth < 0,
+                Resume::Continue | Resume::ReverseStep | Resume::ReverseContinue => false,
+            };
+            if done {
+                return Stop::Stepped;
+            }
+            steps += 1;
+            if steps % PAUSE_CHECK_EVERY == 0 && pause() {
+                return Stop::Paused;
+            }
+        }
+    }
+
+    /// Runs the program backwards, undoing its history. Breakpoints stop it once it is back at them,
+    /// when their condition holds, without counting hits nor logging; watchpoints stop it once it
+    /// undid an access they match.
+    fn reverse(&mut self, resume: Resume, mut pause: impl FnMut() -> bool) -> Stop {
+        let mut steps = 0;
+        loop {
+            if !self.vm.step_back() {
+                return Stop::HistoryStart;
+            }
+            self.finished = false;
+            // Going forward from here runs the instruction rather than stopping at it again.
+            self.checked = true;
+            let hits = std::mem::take(&mut self.vm.hits);
+            if !hits.is_empty() {
+                return Stop::Watchpoint(hits);
+            }
+            if resume == Resume::ReverseStep {
+                return Stop::Stepped;
+            }
+            let pc = self.vm.registers[Register::PC];
+            if let Some(breakpoint) = self.breakpoints.get(&pc)
+                && breakpoint.log.is_none()
+                && breakpoint
+                    .condition
+                    .as_ref()
+                    .is_none_or(|condition| condition.holds(&self.vm))
+            {
+                return Stop::Breakpoint(pc);
+            }
+            steps += 1;
+            if steps % PAUSE_CHECK_EVERY == 0 && pause() {
+                return Stop::Paused;
+            }
+        }
+    }
+
+    /// How many instructions the program executed to get to where it is.
+    pub fn instruction_count(&self) -> u64 {
+        self.vm.history.as_ref().map_or(0, History::count)
+    }
+
+    /// Takes the program to where it was, or will be, after `count` instructions, without stopping at
+    /// breakpoints or watchpoints: the history is undone or replayed, from the closest checkpoint if
+    /// that is shorter, and the program runs past its end. A count before the start of the history
+    /// stops there.
+    pub fn go_to(&mut self, count: u64) -> Stop {
+        let VMState {
+            history: Some(history),
+            registers,
+            memory,
+            calls,
+            ..
+        } = &mut self.vm
+        else {
+            return Stop::HistoryStart;
+        };
+        let (target, reached) = match count < history.start() {
+            true => (history.start(), Stop::HistoryStart),
+            false => (count, Stop::Stepped),
+        };
+        history.restore(target, registers, memory, calls);
+        self.finished = false;
+        self.checked = true;
+        let stop = loop {
+            let current = self.instruction_count();
+            if current > target {
+                self.vm.step_back();
+            } else if current < target {
+                match self.vm.step() {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.finished = true;
+                        break Stop::Halted;
+                    }
+                    Err(error) => {
+                        self.finished = true;
+                        break Stop::Failed(error);
+                    }
+                }
+            } else {
+                break reached;
+            }
+        };
+        self.vm.hits.clear();
+        stop
+    }
+
+    /// Acts on the breakpoint at the PC, if there is one: counts the hit when its condition holds,
+    /// and logs its message if it is a logpoint. Returns whether the program has to stop.
+    fn check_breakpoint(&mut self) -> bool {
+        self.checked = true;
+        let pc = self.vm.registers[Register::PC];
+        let Some(breakpoint) = self.breakpoints.get_mut(&pc) else {
+            return false;
+        };
+        if let Some(condition) = &breakpoint.condition
+            && !condition.holds(&self.vm)
+        {
+            return false;
+        }
+        breakpoint.hits += 1;
+        if breakpoint.ignore > 0 {
+            breakpoint.ignore -= 1;
+            return false;
+        }
+        let Some(message) = &breakpoint.log else {
+            return true;
+        };
+        let line = message.format(&self.vm) + "\n";
+        // A console that fails to write is reported by the program's own output soon enough.
+        for byte in line.bytes() {
+            let _ = self.vm.console.write_char(byte);
+        }
+        let _ = self.vm.console.flush();
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, watchpoint::Watchpoint};
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        JSR DOUBLE
+        JSR DOUBLE
+DONE    HALT
+DOUBLE  ADD R0, R0, #1
+        ADD R0, R0, R0
+        RET
+        .END";
+
+    fn debugger(source: &str) -> Debugger {
+        let assembly = assemble(source).unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        let program = Program {
+            image: assembly.to_bytes(),
+            symbols: assembly.symbols,
+            placements: assembly.placements,
+            debug: None,
+        };
+        Debugger::new(vm, program)
+    }
+
+    fn pc(debugger: &Debugger) -> u16 {
+        debugger.vm.registers[Register::PC]
+    }
+
+    #[test]
+    fn steps_over_and_out_of_subroutines() {
+        let mut debugger = debugger(PROGRAM);
+        assert!(matches!(debugger.resume(Resume::Step), Stop::Stepped));
+        assert!(matches!(debugger.resume(Resume::StepOver), Stop::Stepped));
+        assert_eq!(pc(&debugger), 0x3002);
+        assert_eq!(debugger.vm.registers[Register::R0], 2);
+        debugger.resume(Resume::Step);
+        assert_eq!(pc(&debugger), 0x3004);
+        assert!(matches!(debugger.resume(Resume::StepOut), Stop::Stepped));
+        assert_eq!(pc(&debugger), 0x3003);
+        assert_eq!(debugger.vm.registers[Register::R0], 6);
+        assert!(matches!(debugger.resume(Resume::Continue), Stop::Halted));
+        assert!(debugger.finished);
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_restarts() {
+        let mut debugger = debugger(PROGRAM);
+        debugger.breakpoints.insert(0x3004, Breakpoint::default());
+        assert!(matches!(
+            debugger.resume(Resume::Continue),
+            Stop::Breakpoint(0x3004)
+        ));
+        assert!(matches!(
+            debugger.resume(Resume::Continue),
+            Stop::Breakpoint(0x3004)
+        ));
+        assert_eq!(debugger.vm.registers[Register::R0], 2);
+        debugger.restart().unwrap();
+        assert_eq!(pc(&debugger), 0x3000);
+        assert_eq!(debugger.vm.registers[Register::R0], 0);
+        assert!(matches!(debugger.resume(Resume::StepOver), Stop::Stepped));
+        // Stepping over a call stops at a breakpoint inside it.
+        assert!(matches!(
+            debugger.resume(Resume::StepOver),
+            Stop::Breakpoint(0x3004)
+        ));
+    }
+
+    #[test]
+    fn stops_after_watched_accesses() {
+        let mut debugger = debugger(PROGRAM);
+        let watchpoint = |text| Watchpoint::parse(text, &BTreeMap::new()).unwrap();
+        // JSR writes R7, not memory, so nothing stops the program.
+        debugger
+            .vm
+            .watchpoints
+            .push(watchpoint("change:x3000-x3004"));
+        assert!(matches!(debugger.resume(Resume::Continue), Stop::Halted));
+        debugger.restart().unwrap();
+        // ST R0, #0 instead of HALT, which writes over DOUBLE.
+        debugger.vm.mem_write(0x3003, 0x3000);
+        let Stop::Watchpoint(hits) = debugger.resume(Resume::Continue) else {
+            panic!("the program did not stop at the watchpoint");
+        };
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].address, hits[0].pc, hits[0].old, hits[0].new),
+            (0x3004, 0x3003, 0x1021, 6)
+        );
+        assert_eq!(pc(&debugger), 0x3004);
+    }
+
+    #[test]
+    fn runs_backwards_through_the_history() {
+        let assembly = assemble(
+            "
+        .ORIG x3000
+        GETC
+        ST R0, KEY
+        GETC
+        HALT
+KEY     .BLKW 1
+        .END",
+        )
+        .unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("ab").0);
+        let program = Program {
+            image: assembly.to_bytes(),
+            symbols: assembly.symbols,
+            placements: assembly.placements,
+            debug: None,
+        };
+        let mut debugger = Debugger::new(vm, program);
+        assert!(matches!(debugger.resume(Resume::Continue), Stop::Halted));
+        assert_eq!(debugger.instruction_count(), 4);
+
+        let watchpoint = Watchpoint::parse("write:x3004", &BTreeMap::new()).unwrap();
+        debugger.vm.watchpoints.push(watchpoint);
+        debugger.breakpoints.insert(0x3000, Breakpoint::default());
+        assert!(matches!(
+            debugger.resume(Resume::ReverseContinue),
+            Stop::Watchpoint(_)
+        ));
+        assert!(!debugger.finished);
+        assert_eq!(pc(&debugger), 0x3001);
+        assert_eq!(debugger.vm.memory[0x3004], 0);
+        assert!(matches!(
+            debugger.resume(Resume::ReverseContinue),
+            Stop::Breakpoint(0x3000)
+        ));
+        assert_eq!(debugger.vm.registers[Register::R0], 0);
+        assert!(matches!(
+            debugger.resume(Resume::ReverseStep),
+            Stop::HistoryStart
+        ));
+
+        // Going forward again replays the keys read the first time, which the console has no more of.
+        assert!(matches!(
+            debugger.resume(Resume::Continue),
+            Stop::Watchpoint(_)
+        ));
+        assert!(matches!(debugger.resume(Resume::Continue), Stop::Halted));
+        assert_eq!(debugger.vm.registers[Register::R0], u16::from(b'b'));
+
+        assert!(matches!(debugger.go_to(2), Stop::Stepped));
+        assert_eq!(pc(&debugger), 0x3002);
+        assert_eq!(debugger.vm.memory[0x3004], u16::from(b'a'));
+        // Editing the state makes the rest of the history unusable, so the program runs again.
+        debugger.vm.registers[Register::PC] = 0x3003;
+        assert!(matches!(debugger.go_to(3), Stop::Halted));
+        assert_eq!(debugger.vm.registers[Register::R0], u16::from(b'a'));
+    }
+}
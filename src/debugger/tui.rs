@@ -0,0 +1,719 @@
+//! Full-screen terminal debugger (`debug --tui`).
+//!
+//! The screen is split in panes: the code around the PC with the breakpoints marked, the registers
+//! and condition codes, the call stack, watch expressions, a memory view that can be scrolled and
+//! edited in place, and the program's own console output. Keys drive the same `Session` as the
+//! command line debugger, and any of its commands can also be typed after `:`.
+//!
+//! The screen is drawn with ANSI escape sequences on the terminal's alternate screen, which is left
+//! and the terminal restored when the debugger quits, fails, panics or is stopped with Ctrl-C.
+use std::{
+    cell::{Cell, RefCell},
+    io::Write,
+    panic::{self, PanicHookInfo},
+    rc::Rc,
+    sync::{Arc, OnceLock},
+    thread,
+};
+
+use termios::{TCSANOW, Termios, tcsetattr};
+
+use crate::{
+    console::Console,
+    debugger::{
+        Debugger,
+        commands::{Reply, Session},
+        expression::Expression,
+        inspect,
+    },
+    error::VMError,
+    flags::Flag,
+    registers::Register,
+    utils::{disable_input_buffering, get_char},
+};
+
+/// The keys, shown at the bottom of the screen.
+const KEYS: &str = "s step  n next  f finish  c continue  r/R reverse step/continue  b break  \
+                    w/W add/drop watch  tab pane  e edit  g go to  : command  q quit";
+
+/// A key, as read from the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+}
+
+/// The pane the arrow keys move in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Focus {
+    Code,
+    Memory,
+}
+
+/// What the line typed at the bottom of the screen is for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Prompt {
+    /// A command of the command line debugger.
+    Command,
+    /// An expression to watch.
+    Watch,
+    /// The new value of the word at the address.
+    Edit(u16),
+    /// The address to show in the focused pane.
+    GoTo,
+}
+
+/// A place on the screen, in characters from the top left corner.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+pub struct Tui {
+    session: Session,
+    /// The output of the program's console.
+    output: Rc<RefCell<Vec<u8>>>,
+    watches: Vec<Expression>,
+    focus: Focus,
+    /// The address the cursor of the code pane is on, where breakpoints are toggled.
+    code_cursor: u16,
+    /// The first address the memory pane shows, and the one its cursor is on.
+    memory_top: u16,
+    memory_cursor: u16,
+    /// How many words the memory pane showed the last time it was drawn.
+    memory_rows: Cell<u16>,
+    prompt: Option<(Prompt, String)>,
+    /// What the last command said.
+    message: String,
+}
+
+impl Tui {
+    /// A debugger for `debugger`, whose program's console writes to `output`.
+    pub fn new(debugger: Debugger, output: Rc<RefCell<Vec<u8>>>) -> Self {
+        let pc = debugger.vm.registers[Register::PC];
+        Self {
+            session: Session::new(debugger, false),
+            output,
+            watches: Vec::new(),
+            focus: Focus::Code,
+            code_cursor: pc,
+            memory_top: pc,
+            memory_cursor: pc,
+            memory_rows: Cell::new(8),
+            prompt: None,
+            message: "Press a key; q quits.".to_string(),
+        }
+    }
+
+    /// Acts on a key. Returns false when the debugger has to quit.
+    pub fn handle(&mut self, key: Key) -> bool {
+        if let Some((prompt, text)) = &mut self.prompt {
+            match key {
+                Key::Enter => {
+                    let (prompt, text) = (*prompt, std::mem::take(text));
+                    self.prompt = None;
+                    return self.submit(prompt, text.trim());
+                }
+                Key::Escape => self.prompt = None,
+                Key::Backspace => {
+                    text.pop();
+                }
+                Key::Char(char) => text.push(char),
+                _ => {}
+            }
+            return true;
+        }
+        let page = match self.focus {
+            Focus::Code => 16,
+            Focus::Memory => i32::from(self.memory_rows.get()),
+        };
+        match key {
+            Key::Char('q') => return false,
+            Key::Char('s') => return self.command("step"),
+            Key::Char('n') => return self.command("next"),
+            Key::Char('f') => return self.command("finish"),
+            Key::Char('c') => return self.command("continue"),
+            Key::Char('r') => return self.command("reverse-step"),
+            Key::Char('R') => return self.command("reverse-continue"),
+            Key::Char('b') => self.toggle_breakpoint(),
+            Key::Char('w') => self.prompt = Some((Prompt::Watch, String::new())),
+            Key::Char('W') => {
+                self.watches.pop();
+            }
+            Key::Char(':') => self.prompt = Some((Prompt::Command, String::new())),
+            Key::Char('g') => self.prompt = Some((Prompt::GoTo, String::new())),
+            Key::Char('e') | Key::Enter if self.focus == Focus::Memory => {
+                self.prompt = Some((Prompt::Edit(self.memory_cursor), String::new()));
+            }
+            Key::Tab => {
+                self.focus = match self.focus {
+                    Focus::Code => Focus::Memory,
+                    Focus::Memory => Focus::Code,
+                }
+            }
+            Key::Up | Key::Char('k') => self.move_cursor(-1),
+            Key::Down | Key::Char('j') => self.move_cursor(1),
+            Key::PageUp => self.move_cursor(-page),
+            Key::PageDown => self.move_cursor(page),
+            _ => {}
+        }
+        true
+    }
+
+    /// Runs a command of the command line debugger, showing what it said and the code at the PC.
+    fn command(&mut self, command: &str) -> bool {
+        self.message = match self.session.execute(command) {
+            Ok(Reply::Show(text)) => text,
+            Ok(Reply::Quit) => return false,
+            Err(message) => format!("error: {message}"),
+        };
+        self.code_cursor = self.session.debugger.vm.registers[Register::PC];
+        true
+    }
+
+    fn submit(&mut self, prompt: Prompt, text: &str) -> bool {
+        if text.is_empty() {
+            return true;
+        }
+        let symbols = &self.session.debugger.program.symbols;
+        match prompt {
+            Prompt::Command => return self.command(text),
+            Prompt::Watch => match Expression::parse(text, symbols) {
+                Ok(expression) => self.watches.push(expression),
+                Err(message) => self.message = format!("error: {message}"),
+            },
+            Prompt::Edit(address) => match inspect::value(text, symbols) {
+                Ok(word) => self.session.debugger.vm.memory[address as usize] = word,
+                Err(message) => self.message = format!("error: {message}"),
+            },
+            Prompt::GoTo => match inspect::value(text, symbols) {
+                Ok(address) => match self.focus {
+                    Focus::Code => self.code_cursor = address,
+                    Focus::Memory => {
+                        self.memory_cursor = address;
+                        self.memory_top = address;
+                    }
+                },
+                Err(message) => self.message = format!("error: {message}"),
+            },
+        }
+        true
+    }
+
+    fn toggle_breakpoint(&mut self) {
+        let address = self.code_cursor;
+        let breakpoints = &mut self.session.debugger.breakpoints;
+        if breakpoints.remove(&address).is_none() {
+            breakpoints.insert(address, Default::default());
+        }
+    }
+
+    /// Moves the cursor of the focused pane by `rows` words, scrolling the memory pane to keep it in
+    /// view.
+    fn move_cursor(&mut self, rows: i32) {
+        let moved = |address: u16| address.wrapping_add(rows as u16);
+        match self.focus {
+            Focus::Code => self.code_cursor = moved(self.code_cursor),
+            Focus::Memory => {
+                self.memory_cursor = moved(self.memory_cursor);
+                let shown = self.memory_cursor.wrapping_sub(self.memory_top);
+                if shown >= self.memory_rows.get() {
+                    self.memory_top = match rows < 0 {
+                        true => self.memory_cursor,
+                        false => self
+                            .memory_cursor
+                            .wrapping_sub(self.memory_rows.get().saturating_sub(1)),
+                    };
+                }
+            }
+        }
+    }
+
+    /// The panes of a screen of `width` by `height` characters.
+    fn layout(width: usize, height: usize) -> [Rect; 7] {
+        let left = width * 3 / 5;
+        let right = width - left;
+        let bottom = (height / 3).max(6);
+        let top = height.saturating_sub(bottom + 2);
+        let registers = 7.min(top);
+        let calls = (top - registers) / 2;
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        [
+            rect(0, 0, left, top),
+            rect(left, 0, right, registers),
+            rect(left, registers, right, calls),
+            rect(left, registers + calls, right, top - registers - calls),
+            rect(0, top, left, bottom),
+            rect(left, top, right, bottom),
+            // The message or prompt, and the keys.
+            rect(0, top + bottom, width, 2),
+        ]
+    }
+
+    /// The screen, as `height` lines of `width` characters.
+    pub fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let [code, registers, calls, watches, memory, console, status] =
+            Self::layout(width, height);
+        let mut screen = Screen::new(width, height);
+        let focused = |focus| match self.focus == focus {
+            true => "*",
+            false => "",
+        };
+        // The source line of the PC, with debug info.
+        let pc = self.session.debugger.vm.registers[Register::PC];
+        let source = self.session.debugger.program.debug.as_ref();
+        let code_title = match source.and_then(|debug| debug.line_at(pc)) {
+            Some(source) => format!(
+                "Code{} - {}:{}",
+                focused(Focus::Code),
+                source.file,
+                source.line
+            ),
+            None => format!("Code{}", focused(Focus::Code)),
+        };
+        screen.pane(
+            code,
+            &code_title,
+            &self.code_lines(code.height.saturating_sub(2)),
+        );
+        screen.pane(registers, "Registers", &self.register_lines());
+        screen.pane(calls, "Call stack", &self.call_lines());
+        screen.pane(watches, "Watch", &self.watch_lines());
+        let rows = memory.height.saturating_sub(2);
+        self.memory_rows.set(rows as u16);
+        let memory_title = format!("Memory{}", focused(Focus::Memory));
+        screen.pane(memory, &memory_title, &self.memory_lines(rows));
+        let console_lines = console_lines(&self.output.borrow(), console);
+        screen.pane(console, "Console", &console_lines);
+        let prompt = match &self.prompt {
+            Some((prompt, text)) => {
+                let label = match prompt {
+                    Prompt::Command => ":".to_string(),
+                    Prompt::Watch => "watch: ".to_string(),
+                    Prompt::Edit(address) => format!("x{address:04X} = "),
+                    Prompt::GoTo => "go to: ".to_string(),
+                };
+                format!("{label}{text}_")
+            }
+            None => self.message.lines().collect::<Vec<_>>().join(" | "),
+        };
+        screen.text(status.x, status.y, status.width, &prompt);
+        screen.text(status.x, status.y + 1, status.width, KEYS);
+        screen.lines()
+    }
+
+    fn code_lines(&self, rows: usize) -> Vec<String> {
+        let start = self.code_cursor.wrapping_sub((rows / 3) as u16);
+        (0..rows as u16)
+            .map(|row| {
+                let address = start.wrapping_add(row);
+                let cursor = match address == self.code_cursor {
+                    true => '>',
+                    false => ' ',
+                };
+                format!("{cursor}{}", self.session.line(address))
+            })
+            .collect()
+    }
+
+    fn register_lines(&self) -> Vec<String> {
+        let registers = &self.session.debugger.vm.registers;
+        let word = |register: usize| {
+            let value = registers[register];
+            format!(
+                "R{register} x{value:04X} {:<7}",
+                format!("#{}", value as i16)
+            )
+        };
+        let mut lines: Vec<String> = (0..4)
+            .map(|register| format!("{}  {}", word(register), word(register + 4)))
+            .collect();
+        let flag = |flag: Flag| u16::from(registers[Register::Cond] == flag as u16);
+        lines.push(format!(
+            "PC x{:04X}  N {} Z {} P {}  #{}",
+            registers[Register::PC],
+            flag(Flag::Neg),
+            flag(Flag::Zro),
+            flag(Flag::Pos),
+            self.session.debugger.instruction_count()
+        ));
+        lines
+    }
+
+    /// The PC and the calls it is in, innermost first.
+    fn call_lines(&self) -> Vec<String> {
+        let debugger = &self.session.debugger;
+        let symbols = &debugger.program.symbols;
+        let place = |address: u16| match inspect::location(address, symbols) {
+            Some(location) => format!("x{address:04X} {location}"),
+            None => format!("x{address:04X}"),
+        };
+        let pc = debugger.vm.registers[Register::PC];
+        let calls = debugger.vm.calls.frames();
+        std::iter::once(place(pc))
+            .chain(calls.iter().map(|frame| place(frame.call)))
+            .collect()
+    }
+
+    fn watch_lines(&self) -> Vec<String> {
+        let vm = &self.session.debugger.vm;
+        self.watches
+            .iter()
+            .map(|watch| {
+                let value = watch.evaluate(vm);
+                format!("{} = x{value:04X} (#{})", watch.source, value as i16)
+            })
+            .collect()
+    }
+
+    fn memory_lines(&self, rows: usize) -> Vec<String> {
+        let debugger = &self.session.debugger;
+        (0..rows as u16)
+            .map(|row| {
+                let address = self.memory_top.wrapping_add(row);
+                let cursor = match address == self.memory_cursor {
+                    true => '>',
+                    false => ' ',
+                };
+                let line = inspect::memory(&debugger.vm, address, 1, &debugger.program.symbols);
+                format!("{cursor}{line}")
+            })
+            .collect()
+    }
+}
+
+/// The last lines of the program's output that fit in `pane`, long lines wrapped.
+fn console_lines(output: &[u8], pane: Rect) -> Vec<String> {
+    let width = pane.width.saturating_sub(2).max(1);
+    let text = String::from_utf8_lossy(output);
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        let chars: Vec<char> = line.chars().filter(|char| !char.is_control()).collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(chars.chunks(width).map(|chunk| chunk.iter().collect()));
+    }
+    let rows = pane.height.saturating_sub(2);
+    lines.split_off(lines.len().saturating_sub(rows))
+}
+
+/// The characters of a screen being drawn.
+struct Screen {
+    cells: Vec<Vec<char>>,
+}
+
+impl Screen {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: vec![vec![' '; width]; height],
+        }
+    }
+
+    /// Writes `text` from (`x`, `y`), cut to `width` characters and to the screen.
+    fn text(&mut self, x: usize, y: usize, width: usize, text: &str) {
+        let Some(row) = self.cells.get_mut(y) else {
+            return;
+        };
+        for (offset, char) in text.chars().take(width).enumerate() {
+            if let Some(cell) = row.get_mut(x + offset) {
+                *cell = char;
+            }
+        }
+    }
+
+    /// Draws a box with `title` around `rect` and `lines` inside it.
+    fn pane(&mut self, rect: Rect, title: &str, lines: &[String]) {
+        if rect.width < 2 || rect.height < 2 {
+            return;
+        }
+        let inner = rect.width - 2;
+        let border = format!("+{}+", "-".repeat(inner));
+        self.text(rect.x, rect.y, rect.width, &border);
+        self.text(rect.x, rect.y + rect.height - 1, rect.width, &border);
+        self.text(
+            rect.x + 2,
+            rect.y,
+            inner.saturating_sub(2),
+            &format!(" {title} "),
+        );
+        for row in 1..rect.height - 1 {
+            self.text(rect.x, rect.y + row, 1, "|");
+            self.text(rect.x + rect.width - 1, rect.y + row, 1, "|");
+        }
+        for (row, line) in lines.iter().take(rect.height - 2).enumerate() {
+            self.text(rect.x + 1, rect.y + 1 + row, inner, line);
+        }
+    }
+
+    fn lines(self) -> Vec<String> {
+        self.cells
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+}
+
+/// The console of the program under the TUI: keys come from the terminal and the output goes to the
+/// console pane, which is drawn again before waiting for a key so the program's prompts show.
+struct PaneConsole {
+    output: Rc<RefCell<Vec<u8>>>,
+    /// Where the console pane is on the screen.
+    pane: Rc<Cell<Rect>>,
+}
+
+impl Console for PaneConsole {
+    fn read_char(&mut self) -> Result<u16, VMError> {
+        let pane = self.pane.get();
+        let mut text = String::new();
+        let lines = console_lines(&self.output.borrow(), pane);
+        let blank = " ".repeat(pane.width.saturating_sub(2));
+        for row in 0..pane.height.saturating_sub(2) {
+            let line = lines.get(row).map_or("", String::as_str);
+            let line: String = line
+                .chars()
+                .chain(blank.chars())
+                .take(blank.len())
+                .collect();
+            text.push_str(&format!("\x1b[{};{}H{line}", pane.y + row + 2, pane.x + 2));
+        }
+        print!("{text}");
+        std::io::stdout()
+            .flush()
+            .map_err(|e| VMError::ErrorFlushinStdout(e.to_string()))?;
+        get_char()
+    }
+
+    fn write_char(&mut self, char: u8) -> Result<(), VMError> {
+        self.output.borrow_mut().push(char);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), VMError> {
+        Ok(())
+    }
+}
+
+/// Runs the TUI on the terminal until it quits. The program's console is replaced by the console
+/// pane.
+pub fn run(mut debugger: Debugger) -> Result<(), VMError> {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let pane = Rc::new(Cell::new(Rect::default()));
+    debugger.vm.console = Box::new(PaneConsole {
+        output: Rc::clone(&output),
+        pane: Rc::clone(&pane),
+    });
+    let mut tui = Tui::new(debugger, output);
+    let _terminal = Terminal::enter()?;
+    draw_and_handle_keys(&mut tui, &pane)
+}
+
+/// Shows the cursor and leaves the alternate screen.
+const LEAVE: &str = "\x1b[?25h\x1b[?1049l";
+
+/// The terminal as it was before the TUI, for Ctrl-C and panics to put it back.
+static SETUP: OnceLock<Termios> = OnceLock::new();
+
+/// The terminal while the TUI is on: on the alternate screen with the cursor hidden, and reading
+/// keys one by one. It is put back as it was when dropped, and before a panic message or Ctrl-C
+/// quitting.
+struct Terminal {
+    /// The panic hook from before the TUI, put back when it is dropped.
+    hook: Arc<dyn Fn(&PanicHookInfo) + Sync + Send>,
+}
+
+impl Terminal {
+    fn enter() -> Result<Self, VMError> {
+        let setup = disable_input_buffering()?;
+        SETUP.get_or_init(|| setup);
+        let hook: Arc<dyn Fn(&PanicHookInfo) + Sync + Send> = Arc::from(panic::take_hook());
+        let previous = Arc::clone(&hook);
+        panic::set_hook(Box::new(move |info| {
+            leave();
+            previous(info);
+        }));
+        // SAFETY: `interrupted` only makes calls that are safe in a signal handler.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+        print!("\x1b[?1049h\x1b[?25l");
+        std::io::stdout()
+            .flush()
+            .map_err(|e| VMError::ErrorFlushinStdout(e.to_string()))?;
+        Ok(Self { hook })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // SAFETY: puts back the default action of SIGINT.
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+        // The hook cannot be changed while panicking, so it is left as it is then.
+        if !thread::panicking() {
+            let hook = Arc::clone(&self.hook);
+            panic::set_hook(Box::new(move |info| hook(info)));
+        }
+        leave();
+    }
+}
+
+/// Leaves the TUI's screen and restores the terminal. Only makes calls that are safe in a signal
+/// handler.
+fn leave() {
+    // SAFETY: writes `LEAVE`, which lives for the whole program, to standard output.
+    unsafe { libc::write(libc::STDOUT_FILENO, LEAVE.as_ptr().cast(), LEAVE.len()) };
+    if let Some(setup) = SETUP.get() {
+        let _ = tcsetattr(libc::STDIN_FILENO, TCSANOW, setup);
+    }
+}
+
+/// Ctrl-C: restores the terminal and quits as SIGINT would.
+extern "C" fn interrupted(_: libc::c_int) {
+    leave();
+    // SAFETY: ends the process right away, as the default action of SIGINT.
+    unsafe { libc::_exit(130) };
+}
+
+/// Draws the screen and acts on a key, until the TUI quits.
+fn draw_and_handle_keys(tui: &mut Tui, pane: &Cell<Rect>) -> Result<(), VMError> {
+    loop {
+        let (width, height) = size();
+        pane.set(Tui::layout(width, height)[5]);
+        let screen = tui.render(width, height).join("\r\n");
+        print!("\x1b[H{screen}");
+        std::io::stdout()
+            .flush()
+            .map_err(|e| VMError::ErrorFlushinStdout(e.to_string()))?;
+        if !tui.handle(read_key()?) {
+            return Ok(());
+        }
+    }
+}
+
+/// The size of the terminal in characters, or 80 by 24 if it cannot tell.
+fn size() -> (usize, usize) {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes the size to the `winsize` it is given.
+    let asked = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    match (asked, size.ws_col, size.ws_row) {
+        (0, columns @ 1.., rows @ 1..) => (usize::from(columns).max(60), usize::from(rows).max(20)),
+        _ => (80, 24),
+    }
+}
+
+/// Reads a key, with the escape sequences of the arrows and page keys.
+fn read_key() -> Result<Key, VMError> {
+    Ok(match get_char()? as u8 {
+        b'\n' | b'\r' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7F | 0x08 => Key::Backspace,
+        0x1B => match get_char()? as u8 {
+            b'[' => match get_char()? as u8 {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                code @ (b'5' | b'6') => {
+                    get_char()?;
+                    match code {
+                        b'5' => Key::PageUp,
+                        _ => Key::PageDown,
+                    }
+                }
+                _ => Key::Escape,
+            },
+            _ => Key::Escape,
+        },
+        byte => Key::Char(char::from(byte)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, debugger::Program, vm::VMState};
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+START   LEA R0, HELLO
+        PUTS
+        JSR TWICE
+        HALT
+TWICE   ADD R1, R1, #2
+        RET
+HELLO   .STRINGZ \"Hi\"
+        .END";
+
+    fn tui() -> Tui {
+        let assembly = assemble(PROGRAM).unwrap();
+        let mut vm = VMState::init().unwrap();
+        let (console, output) = BufferConsole::new("");
+        vm.console = Box::new(console);
+        let program = Program {
+            image: assembly.to_bytes(),
+            symbols: assembly.symbols,
+            placements: Vec::new(),
+            debug: None,
+        };
+        Tui::new(Debugger::new(vm, program), output)
+    }
+
+    fn keys(tui: &mut Tui, keys: &str) {
+        for char in keys.chars() {
+            let key = match char {
+                '\n' => Key::Enter,
+                '\t' => Key::Tab,
+                char => Key::Char(char),
+            };
+            assert!(tui.handle(key));
+        }
+    }
+
+    #[test]
+    fn steps_into_calls_and_shows_the_panes() {
+        let mut tui = tui();
+        keys(&mut tui, "gTWICE\nbcwR0 + 1\n");
+        let screen = tui.render(100, 30).join("\n");
+        assert!(screen.contains("|    x3000: E005  START:"), "{screen}");
+        assert!(screen.contains("|>*> x3004: 1262  TWICE:"), "{screen}");
+        assert!(screen.contains("x3004 TWICE"), "{screen}");
+        assert!(screen.contains("x3002 START+2"), "{screen}");
+        assert!(screen.contains("R0 + 1 = x3007 (#12295)"), "{screen}");
+        assert!(screen.contains("|Hi"), "{screen}");
+        assert!(screen.contains("breakpoint at x3004 (TWICE)"), "{screen}");
+        assert!(screen.lines().all(|line| line.chars().count() == 100));
+        assert!(!tui.handle(Key::Char('q')));
+    }
+
+    #[test]
+    fn edits_memory_in_place() {
+        let mut tui = tui();
+        keys(&mut tui, "\tgHELLO\n\nx48\n");
+        assert_eq!(tui.session.debugger.vm.memory[0x3006], 0x48);
+        keys(&mut tui, ":set R3 #7\n");
+        assert_eq!(tui.session.debugger.vm.registers[3], 7);
+        let screen = tui.render(80, 24).join("\n");
+        assert!(screen.contains(">x3006: x0048 (#72)  HELLO:"), "{screen}");
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    decompiler::ir::{Cond, Control, Lifted, Stmt, Terminator, lift},
+    image::Image,
+};
+
+/// A straight sequence of instructions with a single entry (its first instruction) and a single exit
+/// (its terminator).
+pub struct Block {
+    /// Address of the first instruction.
+    pub start: u16,
+    pub stmts: Vec<Stmt>,
+    pub term: Terminator,
+}
+
+/// A subroutine recovered from the image: everything reachable from its entry point without
+/// following calls.
+pub struct Function {
+    pub entry: u16,
+    /// Blocks sorted by address. The first one is not necessarily the entry, as code may jump backwards.
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    /// Index of the block starting at `address`, if any.
+    pub fn block_at(&self, address: u16) -> Option<usize> {
+        self.blocks.binary_search_by_key(&address, |b| b.start).ok()
+    }
+}
+
+/// Recovers every function reachable from `entry`, by following the control flow of the image and
+/// treating the targets of JSR as new functions. The function at `entry` comes first and the rest are
+/// sorted by address.
+pub fn recover_functions(image: &Image, entry: u16) -> Vec<Function> {
+    let mut pending = vec![entry];
+    let mut found: BTreeMap<u16, Function> = BTreeMap::new();
+    while let Some(address) = pending.pop() {
+        if found.contains_key(&address) {
+            continue;
+        }
+        let function = recover_function(image, address);
+        for block in &function.blocks {
+            for stmt in &block.stmts {
+                if let Stmt::Call(callee) = stmt {
+                    pending.push(*callee);
+                }
+            }
+        }
+        found.insert(address, function);
+    }
+    let mut functions = Vec::with_capacity(found.len());
+    functions.extend(found.remove(&entry));
+    functions.extend(found.into_values());
+    functions
+}
+
+/// What an instruction does to the condition codes.
+#[derive(Clone, Copy)]
+enum FlagEvent {
+    /// They now describe the value of the register.
+    Set(u8),
+    /// They can no longer be tied to a register.
+    Unknown,
+}
+
+/// A block before its branch conditions are completed with the flags information.
+struct RawBlock {
+    start: u16,
+    /// Address of the last instruction of the block.
+    last: u16,
+    stmts: Vec<Stmt>,
+    events: Vec<FlagEvent>,
+    /// `None` when the block simply runs into the next one.
+    control: Option<Control>,
+}
+
+impl RawBlock {
+    /// Addresses of the blocks that may run after this one.
+    fn successors(&self) -> Vec<u16> {
+        let next = self.last.wrapping_add(1);
+        match self.control {
+            None => vec![next],
+            Some(Control::Goto(target)) => vec![target],
+            Some(Control::Branch { target, .. }) => vec![target, next],
+            Some(_) => Vec::new(),
+        }
+    }
+}
+
+/// Recovers a single function. Blocks are delimited by the targets of branches (leaders) and by the
+/// instructions that transfer control.
+fn recover_function(image: &Image, entry: u16) -> Function {
+    // First pass: discover every reachable instruction and every branch target.
+    let mut instructions: BTreeMap<u16, Lifted> = BTreeMap::new();
+    let mut leaders = BTreeSet::from([entry]);
+    let mut pending = vec![entry];
+    while let Some(address) = pending.pop() {
+        if instructions.contains_key(&address) {
+            continue;
+        }
+        let Some(word) = image.get(address) else {
+            // Running out of the image is recorded as an invalid instruction.
+            instructions.insert(address, Lifted::Control(Control::Invalid));
+            continue;
+        };
+        let lifted = lift(word, address);
+        let next = address.wrapping_add(1);
+        match &lifted {
+            Lifted::Plain { .. } => pending.push(next),
+            Lifted::Control(Control::Goto(target)) => {
+                leaders.insert(*target);
+                pending.push(*target);
+            }
+            Lifted::Control(Control::Branch { target, .. }) => {
+                leaders.insert(*target);
+                leaders.insert(next);
+                pending.push(*target);
+                pending.push(next);
+            }
+            Lifted::Control(_) => {}
+        }
+        instructions.insert(address, lifted);
+    }
+
+    // Second pass: cut the instructions into blocks.
+    let mut raw_blocks: Vec<RawBlock> = Vec::new();
+    let mut current: Option<RawBlock> = None;
+    for (address, lifted) in instructions {
+        let continues = current
+            .as_ref()
+            .is_some_and(|block| block.last.wrapping_add(1) == address);
+        if !continues || leaders.contains(&address) {
+            raw_blocks.extend(current.take());
+        }
+        let block = current.get_or_insert_with(|| RawBlock {
+            start: address,
+            last: address,
+            stmts: Vec::new(),
+            events: Vec::new(),
+            control: None,
+        });
+        block.last = address;
+        match lifted {
+            Lifted::Plain {
+                stmt,
+                sets_flags,
+                clobbers_flags,
+            } => {
+                block.stmts.extend(stmt);
+                if clobbers_flags {
+                    block.events.push(FlagEvent::Unknown);
+                }
+                if let Some(reg) = sets_flags {
+                    block.events.push(FlagEvent::Set(reg));
+                }
+            }
+            Lifted::Control(control) => {
+                block.control = Some(control);
+                raw_blocks.extend(current.take());
+            }
+        }
+    }
+    raw_blocks.extend(current);
+
+    let flags_in = flags_at_entry(entry, &raw_blocks);
+    let blocks = raw_blocks
+        .into_iter()
+        .zip(flags_in)
+        .map(|(block, flags)| {
+            let flags = apply_events(flags, &block.events);
+            let next = block.last.wrapping_add(1);
+            let term = match block.control {
+                None => Terminator::Fallthrough(next),
+                Some(Control::Goto(target)) => Terminator::Goto(target),
+                Some(Control::Branch { nzp, target }) => Terminator::Branch {
+                    cond: Cond {
+                        nzp,
+                        subject: flags,
+                    },
+                    target,
+                    fallthrough: next,
+                },
+                Some(Control::Return) => Terminator::Return,
+                Some(Control::Halt) => Terminator::Halt,
+                Some(Control::IndirectJump(reg)) => Terminator::IndirectJump(reg),
+                Some(Control::Invalid) => Terminator::Invalid(block.last),
+            };
+            Block {
+                start: block.start,
+                stmts: block.stmts,
+                term,
+            }
+        })
+        .collect();
+    Function { entry, blocks }
+}
+
+/// The register the condition codes describe after the given events, starting from `flags`.
+fn apply_events(flags: Option<u8>, events: &[FlagEvent]) -> Option<u8> {
+    events.iter().fold(flags, |_, event| match event {
+        FlagEvent::Set(reg) => Some(*reg),
+        FlagEvent::Unknown => None,
+    })
+}
+
+/// Computes, for each block, the register the condition codes describe when the block starts: the one
+/// every predecessor agrees on, or none if they disagree. The function entry starts with unknown flags.
+fn flags_at_entry(entry: u16, blocks: &[RawBlock]) -> Vec<Option<u8>> {
+    let index: BTreeMap<u16, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.start, i))
+        .collect();
+    // `None` means the block has not been reached yet.
+    let mut flags_in: Vec<Option<Option<u8>>> = vec![None; blocks.len()];
+    let mut pending = Vec::new();
+    if let Some(&i) = index.get(&entry) {
+        flags_in[i] = Some(None);
+        pending.push(i);
+    }
+    while let Some(i) = pending.pop() {
+        let out = apply_events(flags_in[i].flatten(), &blocks[i].events);
+        for successor in blocks[i].successors() {
+            let Some(&j) = index.get(&successor) else {
+                continue;
+            };
+            let merged = match flags_in[j] {
+                None => Some(out),
+                Some(previous) if previous == out => continue,
+                Some(_) => Some(None),
+            };
+            if merged != flags_in[j] {
+                flags_in[j] = merged;
+                pending.push(j);
+            }
+        }
+    }
+    flags_in.into_iter().map(Option::flatten).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_blocks_at_branch_targets() {
+        // 0x3000 AND R0, R0, #0
+        // 0x3001 ADD R0, R0, #1   <- loop
+        // 0x3002 BRp #-2
+        // 0x3003 HALT
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x5020, 0x1021, 0x03FE, 0xF025],
+        };
+        let functions = recover_functions(&image, 0x3000);
+        assert_eq!(functions.len(), 1);
+        let blocks = &functions[0].blocks;
+        let starts: Vec<u16> = blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0x3000, 0x3001, 0x3003]);
+        assert_eq!(blocks[0].term, Terminator::Fallthrough(0x3001));
+        assert_eq!(
+            blocks[1].term,
+            Terminator::Branch {
+                cond: Cond {
+                    nzp: 0b001,
+                    subject: Some(0)
+                },
+                target: 0x3001,
+                fallthrough: 0x3003
+            }
+        );
+        assert_eq!(blocks[2].term, Terminator::Halt);
+    }
+
+    #[test]
+    fn finds_called_functions() {
+        // 0x3000 JSR #1
+        // 0x3001 HALT
+        // 0x3002 RET
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x4801, 0xF025, 0xC1C0],
+        };
+        let functions = recover_functions(&image, 0x3000);
+        let entries: Vec<u16> = functions.iter().map(|f| f.entry).collect();
+        assert_eq!(entries, vec![0x3000, 0x3002]);
+        assert_eq!(functions[1].blocks[0].term, Terminator::Return);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::instruction::{Instruction, Operand};
+
+/// An expression of the intermediate form. Registers are treated as 16 bit variables and memory
+/// as a single array `mem`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// The content of register `R<n>`.
+    Reg(u8),
+    /// A literal value, shown as a signed decimal number.
+    Const(u16),
+    /// A literal memory address, shown in hexadecimal.
+    Addr(u16),
+    /// The content of the memory position given by the inner expression.
+    Mem(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// A value produced by a service routine, like `getc()`.
+    Intrinsic(&'static str),
+}
+
+/// A statement of the intermediate form. Control flow is not a statement: it is kept in the
+/// `Terminator` of each block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    /// `R<n> = expr`
+    Assign(u8, Expr),
+    /// `mem[address] = value`
+    Store { address: Expr, value: Expr },
+    /// A call to the subroutine starting at the given address (JSR).
+    Call(u16),
+    /// A call to the subroutine whose address is in the given register (JSRR).
+    CallIndirect(u8),
+    /// A service routine invoked by TRAP that does not produce a value, like `puts(R0)`.
+    Intrinsic(String, Vec<Expr>),
+}
+
+/// A condition over the condition codes, as tested by BR. `nzp` holds the three condition bits
+/// of the instruction and `subject` the register whose value last set the condition codes, when known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cond {
+    pub nzp: u16,
+    pub subject: Option<u8>,
+}
+
+impl Cond {
+    /// The condition that holds exactly when this one does not.
+    pub fn negate(self) -> Self {
+        Cond {
+            nzp: !self.nzp & 0x7,
+            subject: self.subject,
+        }
+    }
+}
+
+/// How a basic block hands over control once its statements are executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Terminator {
+    /// Execution continues with the block starting at the given address, which follows in memory.
+    Fallthrough(u16),
+    /// Unconditional jump (BRnzp) to the given address.
+    Goto(u16),
+    /// Conditional jump (BR with some condition bits set).
+    Branch {
+        cond: Cond,
+        target: u16,
+        fallthrough: u16,
+    },
+    /// Return from subroutine (JMP R7, also known as RET).
+    Return,
+    /// The program stops (TRAP x25).
+    Halt,
+    /// Jump to the address held in a register other than R7.
+    IndirectJump(u8),
+    /// Execution reaches something that is not a valid instruction (RTI, the reserved opcode or
+    /// an address outside the image).
+    Invalid(u16),
+}
+
+/// The result of lifting one instruction.
+pub enum Lifted {
+    /// The instruction does not change control flow. It may produce a statement, and it may update the
+    /// condition codes from a register.
+    Plain {
+        stmt: Option<Stmt>,
+        sets_flags: Option<u8>,
+        /// Whether the condition codes can no longer be tied to a register after this instruction
+        /// (calls may change them).
+        clobbers_flags: bool,
+    },
+    /// The instruction ends the block.
+    Control(Control),
+}
+
+/// The control flow effect of an instruction that ends a block. Branch conditions are completed
+/// with their subject register once the flags flowing into the block are known.
+#[derive(Clone, Copy)]
+pub enum Control {
+    Goto(u16),
+    Branch { nzp: u16, target: u16 },
+    Return,
+    Halt,
+    IndirectJump(u8),
+    Invalid,
+}
+
+/// Names of the service routines of the trap vector table, indexed by `trap code - 0x20`.
+const TRAP_NAMES: [&str; 6] = ["getc", "out", "puts", "in", "putsp", "halt"];
+
+/// Lifts the instruction `instruction`, stored at `address`, into the intermediate form.
+pub fn lift(instruction: u16, address: u16) -> Lifted {
+    let decoded = Instruction::decode(instruction);
+    let target = decoded.target(address).unwrap_or_default();
+    let assign = |dest_reg: u8, expr: Expr| Lifted::Plain {
+        stmt: Some(Stmt::Assign(dest_reg, expr)),
+        sets_flags: Some(dest_reg),
+        clobbers_flags: false,
+    };
+    let store = |src_reg: u8, address: Expr| Lifted::Plain {
+        stmt: Some(Stmt::Store {
+            address,
+            value: Expr::Reg(src_reg),
+        }),
+        sets_flags: None,
+        clobbers_flags: false,
+    };
+    let call = |stmt: Stmt| Lifted::Plain {
+        stmt: Some(stmt),
+        sets_flags: None,
+        clobbers_flags: true,
+    };
+
+    match decoded {
+        Instruction::Add { dest, src, operand } | Instruction::And { dest, src, operand } => {
+            let is_add = matches!(decoded, Instruction::Add { .. });
+            let second = match operand {
+                Operand::Immediate(value) => Expr::Const(value as u16),
+                Operand::Register(reg) => Expr::Reg(reg),
+            };
+            let first = Expr::Reg(src);
+            let expr = match (is_add, second) {
+                (true, Expr::Const(0)) => first,
+                (false, Expr::Const(0)) => Expr::Const(0),
+                (false, Expr::Const(0xFFFF)) => first,
+                (true, second) => Expr::Add(Box::new(first), Box::new(second)),
+                (false, second) => Expr::And(Box::new(first), Box::new(second)),
+            };
+            if expr == Expr::Reg(dest) {
+                // Something like `ADD R1, R1, #0`: only the condition codes change.
+                return Lifted::Plain {
+                    stmt: None,
+                    sets_flags: Some(dest),
+                    clobbers_flags: false,
+                };
+            }
+            assign(dest, expr)
+        }
+        Instruction::Not { dest, src } => assign(dest, Expr::Not(Box::new(Expr::Reg(src)))),
+        Instruction::Ld { dest, .. } => assign(dest, Expr::Mem(Box::new(Expr::Addr(target)))),
+        Instruction::Ldi { dest, .. } => assign(
+            dest,
+            Expr::Mem(Box::new(Expr::Mem(Box::new(Expr::Addr(target))))),
+        ),
+        Instruction::Ldr { dest, base, offset } => {
+            assign(dest, Expr::Mem(Box::new(base_plus_offset(base, offset))))
+        }
+        Instruction::Lea { dest, .. } => assign(dest, Expr::Addr(target)),
+        Instruction::St { src, .. } => store(src, Expr::Addr(target)),
+        Instruction::Sti { src, .. } => store(src, Expr::Mem(Box::new(Expr::Addr(target)))),
+        Instruction::Str { src, base, offset } => store(src, base_plus_offset(base, offset)),
+        Instruction::Br { conditions, .. } => match conditions {
+            // No condition bits: the instruction never jumps.
+            0 => Lifted::Plain {
+                stmt: None,
+                sets_flags: None,
+                clobbers_flags: false,
+            },
+            0x7 => Lifted::Control(Control::Goto(target)),
+            nzp => Lifted::Control(Control::Branch { nzp, target }),
+        },
+        Instruction::Jmp { base: 7 } => Lifted::Control(Control::Return),
+        Instruction::Jmp { base } => Lifted::Control(Control::IndirectJump(base)),
+        Instruction::Jsr { .. } => call(Stmt::Call(target)),
+        Instruction::Jsrr { base } => call(Stmt::CallIndirect(base)),
+        Instruction::Trap { vector } => lift_trap(vector as u16),
+        Instruction::Rti(_) | Instruction::Reserved(_) => Lifted::Control(Control::Invalid),
+    }
+}
+
+/// The address expression `base + offset` of LDR and STR.
+fn base_plus_offset(base_reg: u8, offset: i16) -> Expr {
+    match offset {
+        0 => Expr::Reg(base_reg),
+        offset => Expr::Add(
+            Box::new(Expr::Reg(base_reg)),
+            Box::new(Expr::Const(offset as u16)),
+        ),
+    }
+}
+
+/// Service routines are shown as calls to functions named after them.
+fn lift_trap(trap_vector: u16) -> Lifted {
+    let name = (trap_vector as usize)
+        .checked_sub(0x20)
+        .and_then(|index| TRAP_NAMES.get(index).copied());
+    match name {
+        Some("halt") => Lifted::Control(Control::Halt),
+        Some(name @ ("getc" | "in")) => Lifted::Plain {
+            stmt: Some(Stmt::Assign(0, Expr::Intrinsic(name))),
+            sets_flags: Some(0),
+            clobbers_flags: false,
+        },
+        Some(name) => Lifted::Plain {
+            stmt: Some(Stmt::Intrinsic(name.to_string(), vec![Expr::Reg(0)])),
+            sets_flags: None,
+            clobbers_flags: false,
+        },
+        None => Lifted::Plain {
+            stmt: Some(Stmt::Intrinsic(
+                format!("trap_x{trap_vector:02X}"),
+                Vec::new(),
+            )),
+            sets_flags: None,
+            clobbers_flags: true,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lifts_immediate_add() {
+        // ADD R2, R4, #-12
+        match lift(0x1534, 0x3000) {
+            Lifted::Plain {
+                stmt: Some(Stmt::Assign(2, expr)),
+                sets_flags: Some(2),
+                ..
+            } => assert_eq!(
+                expr,
+                Expr::Add(Box::new(Expr::Reg(4)), Box::new(Expr::Const(0xFFF4)))
+            ),
+            _ => panic!("ADD should lift to an assignment"),
+        }
+    }
+
+    #[test]
+    fn lifts_branches_relative_to_next_instruction() {
+        // BRz #10
+        match lift(0x040A, 0x3000) {
+            Lifted::Control(Control::Branch { nzp, target }) => {
+                assert_eq!(nzp, 0b010);
+                assert_eq!(target, 0x300B);
+            }
+            _ => panic!("BRz should lift to a branch"),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Decompiler from LC-3 machine code to C-like pseudocode.
+//!
+//! The image is processed in four steps:
+//! - `cfg`: functions are recovered by following the control flow from the entry point, treating every
+//!   JSR target as a new function, and cut into basic blocks.
+//! - `ir`: every instruction is lifted to statements over register variables (`R0`..`R7`) and the
+//!   memory array (`mem`), and branch conditions are tied to the register that last set the condition codes.
+//! - `structure`: loops and if/else constructs are recognised from the BR patterns.
+//! - `pseudocode`: the result is printed.
+pub mod cfg;
+pub mod ir;
+pub mod pseudocode;
+pub mod structure;
+
+use crate::{
+    decompiler::{cfg::recover_functions, pseudocode::print_function, structure::structure},
+    image::Image,
+};
+
+/// Decompiles the program in `image`, starting from its origin, and returns the pseudocode of every
+/// function found.
+pub fn decompile(image: &Image) -> String {
+    let entry = image.origin;
+    let functions = recover_functions(image, entry);
+    let mut out = format!(
+        "// Decompiled from an image of {} words loaded at 0x{:04X}.\n\
+         // Registers are 16 bit variables compared as signed numbers, mem[] is the machine memory and CC\n\
+         // stands for the last value that set the condition codes when it cannot be tied to a register.\n",
+        image.words.len(),
+        image.origin
+    );
+    for function in &functions {
+        out.push('\n');
+        out.push_str(&print_function(function, &structure(function), entry));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::read_file;
+
+    #[test]
+    fn decompiles_loop_with_call() {
+        // 0x3000 LD R1, #5        ; R1 = mem[0x3006]
+        // 0x3001 JSR #3           ; loop: call 0x3005
+        // 0x3002 ADD R1, R1, #-1
+        // 0x3003 BRp #-3
+        // 0x3004 HALT
+        // 0x3005 RET
+        // 0x3006 .FILL #3
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x2205, 0x4803, 0x127F, 0x03FD, 0xF025, 0xC1C0, 0x0003],
+        };
+        let expected = "\nvoid main(void)\n\
+                        {\n    R1 = mem[0x3006];\n    do {\n        sub_3005();\n        R1 = R1 - 1;\n    } while (R1 > 0);\n    halt();\n}\n\
+                        \nvoid sub_3005(void)\n{\n    return;\n}\n";
+        let output = decompile(&image);
+        assert!(output.ends_with(expected), "{output}");
+    }
+
+    #[test]
+    fn decompiles_example_binaries() {
+        for path in ["./binary-examples/2048.obj", "./binary-examples/rogue.obj"] {
+            let image = Image::from_bytes(&read_file(path).unwrap()).unwrap();
+            let output = decompile(&image);
+            assert!(output.contains("void main(void)"));
+            assert!(output.contains("while"));
+        }
+    }
+}
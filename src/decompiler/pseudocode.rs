@@ -0,0 +1,217 @@
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::decompiler::{
+    cfg::Function,
+    ir::{Cond, Expr, Stmt},
+    structure::{LoopKind, Node},
+};
+
+/// Width of one indentation level.
+const INDENT: &str = "    ";
+
+/// Prints a structured function as C-like pseudocode. `entry` is the entry point of the whole
+/// program, which is named `main`.
+pub fn print_function(function: &Function, nodes: &[Node], entry: u16) -> String {
+    let mut labels = BTreeSet::new();
+    collect_gotos(nodes, &mut labels);
+    let printer = Printer {
+        function,
+        labels,
+        entry,
+    };
+    let mut out = String::new();
+    let _ = writeln!(out, "void {}(void)", function_name(function.entry, entry));
+    out.push_str("{\n");
+    printer.nodes(nodes, 1, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+/// The name given to the function starting at `address`.
+pub fn function_name(address: u16, entry: u16) -> String {
+    if address == entry {
+        "main".to_string()
+    } else {
+        format!("sub_{address:04X}")
+    }
+}
+
+fn collect_gotos(nodes: &[Node], labels: &mut BTreeSet<u16>) {
+    for node in nodes {
+        match node {
+            Node::Goto(target) => {
+                labels.insert(*target);
+            }
+            Node::If {
+                then, otherwise, ..
+            } => {
+                collect_gotos(then, labels);
+                collect_gotos(otherwise, labels);
+            }
+            Node::Loop { body, .. } => collect_gotos(body, labels),
+            _ => {}
+        }
+    }
+}
+
+struct Printer<'a> {
+    function: &'a Function,
+    /// Blocks that are the target of some `goto` and need a label.
+    labels: BTreeSet<u16>,
+    entry: u16,
+}
+
+impl Printer<'_> {
+    fn line(&self, depth: usize, text: &str, out: &mut String) {
+        out.push_str(&INDENT.repeat(depth));
+        out.push_str(text);
+        out.push('\n');
+    }
+
+    fn nodes(&self, nodes: &[Node], depth: usize, out: &mut String) {
+        for node in nodes {
+            self.node(node, depth, out);
+        }
+    }
+
+    fn node(&self, node: &Node, depth: usize, out: &mut String) {
+        match node {
+            Node::Block(index) => {
+                let block = &self.function.blocks[*index];
+                if self.labels.contains(&block.start) {
+                    // Labels sit one level to the left, like in most C code.
+                    self.line(
+                        depth.saturating_sub(1),
+                        &format!("{}:", label(block.start)),
+                        out,
+                    );
+                }
+                for stmt in &block.stmts {
+                    self.line(depth, &self.stmt(stmt), out);
+                }
+            }
+            Node::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.line(depth, &format!("if ({}) {{", cond_text(*cond)), out);
+                self.nodes(then, depth + 1, out);
+                if !otherwise.is_empty() {
+                    self.line(depth, "} else {", out);
+                    self.nodes(otherwise, depth + 1, out);
+                }
+                self.line(depth, "}", out);
+            }
+            Node::Loop { kind, body } => match kind {
+                LoopKind::Forever => {
+                    self.line(depth, "while (true) {", out);
+                    self.nodes(body, depth + 1, out);
+                    self.line(depth, "}", out);
+                }
+                LoopKind::DoWhile(cond) => {
+                    self.line(depth, "do {", out);
+                    self.nodes(body, depth + 1, out);
+                    self.line(depth, &format!("}} while ({});", cond_text(*cond)), out);
+                }
+            },
+            Node::Break => self.line(depth, "break;", out),
+            Node::Continue => self.line(depth, "continue;", out),
+            Node::Goto(target) => self.line(depth, &format!("goto {};", label(*target)), out),
+            Node::Return => self.line(depth, "return;", out),
+            Node::Halt => self.line(depth, "halt();", out),
+            Node::IndirectJump(reg) => self.line(depth, &format!("goto *R{reg};"), out),
+            Node::Invalid(address) => self.line(
+                depth,
+                &format!("/* invalid instruction at 0x{address:04X} */"),
+                out,
+            ),
+        }
+    }
+
+    fn stmt(&self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Assign(reg, expr) => format!("R{reg} = {};", expr_text(expr)),
+            Stmt::Store { address, value } => {
+                format!("mem[{}] = {};", expr_text(address), expr_text(value))
+            }
+            Stmt::Call(address) => format!("{}();", function_name(*address, self.entry)),
+            Stmt::CallIndirect(reg) => format!("(*R{reg})();"),
+            Stmt::Intrinsic(name, args) => {
+                let args: Vec<String> = args.iter().map(expr_text).collect();
+                format!("{name}({});", args.join(", "))
+            }
+        }
+    }
+}
+
+fn label(address: u16) -> String {
+    format!("L_{address:04X}")
+}
+
+/// Prints an expression. Sums with negative constants are shown as subtractions.
+pub fn expr_text(expr: &Expr) -> String {
+    match expr {
+        Expr::Reg(reg) => format!("R{reg}"),
+        Expr::Const(value) => (*value as i16).to_string(),
+        Expr::Addr(address) => format!("0x{address:04X}"),
+        Expr::Mem(inner) => format!("mem[{}]", expr_text(inner)),
+        Expr::Add(left, right) => match **right {
+            Expr::Const(value) if (value as i16) < 0 => {
+                format!("{} - {}", operand_text(left), (value as i16).unsigned_abs())
+            }
+            _ => format!("{} + {}", operand_text(left), operand_text(right)),
+        },
+        Expr::And(left, right) => format!("{} & {}", operand_text(left), operand_text(right)),
+        Expr::Not(inner) => format!("~{}", operand_text(inner)),
+        Expr::Intrinsic(name) => format!("{name}()"),
+    }
+}
+
+/// Prints an expression that is an operand of another one, adding parentheses when needed.
+fn operand_text(expr: &Expr) -> String {
+    match expr {
+        Expr::Add(..) | Expr::And(..) => format!("({})", expr_text(expr)),
+        _ => expr_text(expr),
+    }
+}
+
+/// Prints a branch condition as a comparison of the register that set the condition codes against zero.
+/// When that register is unknown the comparison is made against `CC`, the last value that set them.
+pub fn cond_text(cond: Cond) -> String {
+    let subject = match cond.subject {
+        Some(reg) => format!("R{reg}"),
+        None => "CC".to_string(),
+    };
+    let comparison = match cond.nzp {
+        0b100 => "< 0",
+        0b010 => "== 0",
+        0b001 => "> 0",
+        0b110 => "<= 0",
+        0b101 => "!= 0",
+        0b011 => ">= 0",
+        0b111 => return "true".to_string(),
+        _ => return "false".to_string(),
+    };
+    format!("{subject} {comparison}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prints_subtractions_and_memory() {
+        let expr = Expr::Mem(Box::new(Expr::Add(
+            Box::new(Expr::Reg(6)),
+            Box::new(Expr::Const(0xFFFF)),
+        )));
+        assert_eq!(expr_text(&expr), "mem[R6 - 1]");
+        let cond = Cond {
+            nzp: 0b011,
+            subject: Some(2),
+        };
+        assert_eq!(cond_text(cond), "R2 >= 0");
+        assert_eq!(cond_text(cond.negate()), "R2 < 0");
+    }
+}
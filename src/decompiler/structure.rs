@@ -0,0 +1,353 @@
+use crate::decompiler::{
+    cfg::Function,
+    ir::{Cond, Terminator},
+};
+
+/// The structured form of a function body, ready to be printed as pseudocode.
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    /// The statements of the block with the given index.
+    Block(usize),
+    If {
+        cond: Cond,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Loop {
+        kind: LoopKind,
+        body: Vec<Node>,
+    },
+    Break,
+    Continue,
+    Goto(u16),
+    Return,
+    Halt,
+    IndirectJump(u8),
+    Invalid(u16),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoopKind {
+    /// `while (true) { ... }`, left through `break`, `return` or `goto`.
+    Forever,
+    /// `do { ... } while (cond);`
+    DoWhile(Cond),
+}
+
+/// The innermost loop being structured: jumps to its header become `continue` and jumps to
+/// the code right after it become `break`.
+#[derive(Clone, Copy)]
+struct LoopContext {
+    header: u16,
+    exit: Option<u16>,
+}
+
+/// Turns the blocks of `function` into nested ifs and loops.
+///
+/// Blocks are laid out in address order, as most code (hand written or compiled) keeps loops and
+/// conditionals contiguous in memory. A backward jump to a block makes that block a loop header, and a
+/// forward conditional branch over a run of blocks becomes an `if` (with an `else` when that run ends
+/// with a forward jump over the next one). Any jump that does not fit these shapes becomes a `goto`.
+pub fn structure(function: &Function) -> Vec<Node> {
+    Structurer { function }.range(0, function.blocks.len(), None, None, None)
+}
+
+struct Structurer<'a> {
+    function: &'a Function,
+}
+
+impl Structurer<'_> {
+    /// Removes the blocks at the end of a loop body that have no statements and are only reached by
+    /// running into them, like a lone `BRnzp` back to the loop header. This lets `make_loop` see the
+    /// decision that really closes the loop.
+    fn drop_silent_tail(&self, mut body: Vec<Node>) -> Vec<Node> {
+        while let Some(Node::Block(index)) = body.last() {
+            let block = &self.function.blocks[*index];
+            let is_target = self.function.blocks.iter().any(|other| {
+                targets(&other.term).contains(&block.start)
+                    && !matches!(other.term, Terminator::Fallthrough(_))
+            });
+            if !block.stmts.is_empty() || is_target {
+                break;
+            }
+            body.pop();
+        }
+        body
+    }
+
+    /// Address of the block at `index`, or `follow` when the index is past the end of the range.
+    fn start_or(&self, index: usize, hi: usize, follow: Option<u16>) -> Option<u16> {
+        if index < hi {
+            Some(self.function.blocks[index].start)
+        } else {
+            follow
+        }
+    }
+
+    /// Index of the block at `address` if it is inside `lo..=hi`, where `hi` stands for the end of the
+    /// range (reached through `follow`).
+    fn index_in(&self, address: u16, lo: usize, hi: usize, follow: Option<u16>) -> Option<usize> {
+        if follow == Some(address) {
+            return Some(hi);
+        }
+        self.function
+            .block_at(address)
+            .filter(|index| (lo..hi).contains(index))
+    }
+
+    /// Structures the blocks `lo..hi`. Control reaching the end of the range continues at `follow`.
+    /// `skip_loop` is the header of the loop whose body is being structured, so it is not taken as a
+    /// new loop again.
+    fn range(
+        &self,
+        lo: usize,
+        hi: usize,
+        follow: Option<u16>,
+        context: Option<LoopContext>,
+        skip_loop: Option<usize>,
+    ) -> Vec<Node> {
+        let blocks = &self.function.blocks;
+        let mut nodes = Vec::new();
+        let mut i = lo;
+        while i < hi {
+            let start = blocks[i].start;
+
+            // Loops: the last block of the range that jumps back to this one closes the loop.
+            if skip_loop != Some(i) {
+                let latch = (i..hi)
+                    .rev()
+                    .find(|&j| targets(&blocks[j].term).contains(&start));
+                if let Some(latch) = latch {
+                    let exit = self.start_or(latch + 1, hi, follow);
+                    let body = self.range(
+                        i,
+                        latch + 1,
+                        Some(start),
+                        Some(LoopContext {
+                            header: start,
+                            exit,
+                        }),
+                        Some(i),
+                    );
+                    nodes.push(make_loop(self.drop_silent_tail(body)));
+                    i = latch + 1;
+                    continue;
+                }
+            }
+
+            nodes.push(Node::Block(i));
+            let next = self.start_or(i + 1, hi, follow);
+
+            // Conditionals: a forward branch over the blocks that follow.
+            if let Terminator::Branch {
+                cond,
+                target,
+                fallthrough,
+            } = blocks[i].term
+                && Some(fallthrough) == next
+                && i + 1 < hi
+                && target > fallthrough
+                && let Some(join) = self.index_in(target, i + 2, hi, follow)
+            {
+                let then_last = &blocks[join - 1].term;
+                let else_end = match then_last {
+                    Terminator::Goto(after) if join < hi && *after > target => {
+                        self.index_in(*after, join + 1, hi, follow)
+                    }
+                    _ => None,
+                };
+                match else_end {
+                    Some(end) => {
+                        let after = self.start_or(end, hi, follow);
+                        nodes.push(Node::If {
+                            cond: cond.negate(),
+                            then: self.range(i + 1, join, after, context, None),
+                            otherwise: self.range(join, end, after, context, None),
+                        });
+                        i = end;
+                    }
+                    None => {
+                        nodes.push(Node::If {
+                            cond: cond.negate(),
+                            then: self.range(i + 1, join, Some(target), context, None),
+                            otherwise: Vec::new(),
+                        });
+                        i = join;
+                    }
+                }
+                continue;
+            }
+
+            nodes.extend(lower_terminator(&blocks[i].term, next, context));
+            i += 1;
+        }
+        nodes
+    }
+}
+
+/// Addresses a terminator may jump to (not counting running into the next block).
+fn targets(term: &Terminator) -> Vec<u16> {
+    match term {
+        Terminator::Fallthrough(target) | Terminator::Goto(target) => vec![*target],
+        Terminator::Branch { target, .. } => vec![*target],
+        _ => Vec::new(),
+    }
+}
+
+/// Turns a jump to `target` into a node, given that control would otherwise continue at `next`.
+fn jump(target: u16, next: Option<u16>, context: Option<LoopContext>) -> Option<Node> {
+    if Some(target) == next {
+        return None;
+    }
+    match context {
+        Some(LoopContext { header, .. }) if header == target => Some(Node::Continue),
+        Some(LoopContext { exit, .. }) if exit == Some(target) => Some(Node::Break),
+        _ => Some(Node::Goto(target)),
+    }
+}
+
+/// The nodes for a terminator that was not absorbed into an if or a loop.
+fn lower_terminator(
+    term: &Terminator,
+    next: Option<u16>,
+    context: Option<LoopContext>,
+) -> Vec<Node> {
+    match *term {
+        Terminator::Fallthrough(target) | Terminator::Goto(target) => {
+            jump(target, next, context).into_iter().collect()
+        }
+        Terminator::Branch {
+            cond,
+            target,
+            fallthrough,
+        } => {
+            let mut nodes = Vec::new();
+            if let Some(node) = jump(target, Some(fallthrough), context) {
+                nodes.push(Node::If {
+                    cond,
+                    then: vec![node],
+                    otherwise: Vec::new(),
+                });
+            }
+            nodes.extend(jump(fallthrough, next, context));
+            nodes
+        }
+        Terminator::Return => vec![Node::Return],
+        Terminator::Halt => vec![Node::Halt],
+        Terminator::IndirectJump(reg) => vec![Node::IndirectJump(reg)],
+        Terminator::Invalid(address) => vec![Node::Invalid(address)],
+    }
+}
+
+/// Builds a loop out of its structured body, where reaching the end of the body goes back to the header.
+/// A body that ends deciding whether to go around again (or whether to leave) becomes a
+/// `do { } while (cond)` loop.
+fn make_loop(mut body: Vec<Node>) -> Node {
+    if body.last() == Some(&Node::Continue) {
+        body.pop();
+    }
+    let len = body.len();
+    if len >= 2
+        && body[len - 1] == Node::Break
+        && let Node::If {
+            cond,
+            then,
+            otherwise,
+        } = &body[len - 2]
+        && then.as_slice() == [Node::Continue]
+        && otherwise.is_empty()
+    {
+        let cond = *cond;
+        body.truncate(len - 2);
+        return Node::Loop {
+            kind: LoopKind::DoWhile(cond),
+            body,
+        };
+    }
+    if let Some(Node::If {
+        cond,
+        then,
+        otherwise,
+    }) = body.last()
+        && then.as_slice() == [Node::Break]
+        && otherwise.is_empty()
+    {
+        let cond = cond.negate();
+        body.pop();
+        return Node::Loop {
+            kind: LoopKind::DoWhile(cond),
+            body,
+        };
+    }
+    Node::Loop {
+        kind: LoopKind::Forever,
+        body,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{decompiler::cfg::recover_functions, image::Image};
+
+    #[test]
+    fn recognises_do_while_loops() {
+        // 0x3000 AND R0, R0, #0
+        // 0x3001 ADD R0, R0, #1   <- loop
+        // 0x3002 BRp #-2
+        // 0x3003 HALT
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x5020, 0x1021, 0x03FE, 0xF025],
+        };
+        let functions = recover_functions(&image, 0x3000);
+        let nodes = structure(&functions[0]);
+        assert_eq!(
+            nodes,
+            vec![
+                Node::Block(0),
+                Node::Loop {
+                    kind: LoopKind::DoWhile(Cond {
+                        nzp: 0b001,
+                        subject: Some(0)
+                    }),
+                    body: vec![Node::Block(1)],
+                },
+                Node::Block(2),
+                Node::Halt,
+            ]
+        );
+    }
+
+    #[test]
+    fn recognises_if_else() {
+        // 0x3000 ADD R0, R0, #0
+        // 0x3001 BRn #2           -> else
+        // 0x3002 ADD R1, R1, #1
+        // 0x3003 BRnzp #1         -> join
+        // 0x3004 ADD R1, R1, #-1  <- else
+        // 0x3005 HALT             <- join
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x1020, 0x0802, 0x1261, 0x0E01, 0x127F, 0xF025],
+        };
+        let functions = recover_functions(&image, 0x3000);
+        let nodes = structure(&functions[0]);
+        assert_eq!(
+            nodes,
+            vec![
+                Node::Block(0),
+                Node::If {
+                    cond: Cond {
+                        nzp: 0b011,
+                        subject: Some(0)
+                    },
+                    then: vec![Node::Block(1)],
+                    otherwise: vec![Node::Block(2)],
+                },
+                Node::Block(3),
+                Node::Halt,
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    image::Image,
+    instruction::Instruction,
+    lint::flow::{Flow, effects},
+};
+
+/// Turns an image back into assembly source the assembler accepts. Words in `code` become
+/// instructions and every other word a `.FILL`, so assembling the result gives the same image.
+/// Targets of PC-relative instructions inside the image get a label (`L_XXXX`); targets outside of
+/// it are written as numeric offsets.
+pub fn disassemble_image(image: &Image, code: &BTreeSet<u16>) -> String {
+    let end = image.origin as u32 + image.words.len() as u32;
+    let inside = |address: u16| (image.origin as u32..end).contains(&(address as u32));
+    let targets: BTreeSet<u16> = code
+        .iter()
+        .filter_map(|&address| pc_relative_target(image.get(address)?, address))
+        .filter(|&target| inside(target))
+        .collect();
+    let labels: BTreeMap<u16, String> = targets
+        .iter()
+        .map(|&target| (target, format!("L_{target:04X}")))
+        .collect();
+
+    let mut out = format!("        .ORIG x{:04X}\n", image.origin);
+    for (offset, word) in image.words.iter().enumerate() {
+        let address = image.origin.wrapping_add(offset as u16);
+        let label = labels.get(&address).map_or("", String::as_str);
+        let text = match code.contains(&address) {
+            true => disassemble(*word, address, |target| labels.get(&target).cloned()),
+            false => None,
+        }
+        .unwrap_or_else(|| format!(".FILL x{word:04X}"));
+        out.push_str(&format!("{label:<8}{text}\n"));
+    }
+    out.push_str("        .END\n");
+    out
+}
+
+/// The addresses that hold code, found by following the control flow from the origin of the image
+/// (into subroutines too).
+pub fn reachable_code(image: &Image) -> BTreeSet<u16> {
+    let mut code = BTreeSet::new();
+    let mut pending = vec![image.origin];
+    while let Some(address) = pending.pop() {
+        let Some(word) = image.get(address) else {
+            continue;
+        };
+        if !code.insert(address) {
+            continue;
+        }
+        let next = address.wrapping_add(1);
+        match effects(word, address).flow {
+            Flow::Next | Flow::NeverBranch | Flow::Call(None) => pending.push(next),
+            Flow::Branch(target) | Flow::Call(Some(target)) => pending.extend([next, target]),
+            Flow::Goto(target) => pending.push(target),
+            Flow::Return | Flow::Halt | Flow::IndirectJump | Flow::Invalid => {}
+        }
+    }
+    code
+}
+
+/// The address a PC-relative instruction (BR, JSR, LD, LDI, LEA, ST, STI) refers to.
+pub fn pc_relative_target(word: u16, address: u16) -> Option<u16> {
+    Instruction::decode(word).target(address)
+}
+
+/// The assembly text of one instruction, stored at `address`. `label` names the targets of
+/// PC-relative instructions; the ones without a name are written as numeric offsets. Returns `None`
+/// for words that are not an instruction the assembler would produce as they are (like RTI, the
+/// reserved opcode, BR without conditions or encodings with unused bits set).
+pub fn disassemble(
+    word: u16,
+    address: u16,
+    label: impl Fn(u16) -> Option<String>,
+) -> Option<String> {
+    let instruction = Instruction::decode(word);
+    if instruction.encode() != word
+        || !instruction.has_syntax()
+        || matches!(instruction, Instruction::Rti(_))
+    {
+        return None;
+    }
+    Some(instruction.to_assembly(address, label))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn disassembles_instructions() {
+        let none = |_| None;
+        assert_eq!(
+            disassemble(0x127F, 0x3000, none).unwrap(),
+            "ADD R1, R1, #-1"
+        );
+        assert_eq!(disassemble(0x5482, 0x3000, none).unwrap(), "AND R2, R2, R2");
+        assert_eq!(disassemble(0x03FE, 0x3000, none).unwrap(), "BRp #-2");
+        assert_eq!(
+            disassemble(0x4801, 0x3000, |t| Some(format!("L_{t:04X}"))).unwrap(),
+            "JSR L_3002"
+        );
+        assert_eq!(disassemble(0xF025, 0x3000, none).unwrap(), "HALT");
+        assert_eq!(disassemble(0x8000, 0x3000, none), None);
+        assert_eq!(disassemble(0x0005, 0x3000, none), None);
+    }
+
+    #[test]
+    fn reassembles_to_the_same_image() {
+        for path in ["binary-examples/2048.obj", "binary-examples/rogue.obj"] {
+            let image = Image::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+            let source = disassemble_image(&image, &reachable_code(&image));
+            let assembly = assemble(&source).unwrap();
+            assert_eq!(assembly.origin, image.origin);
+            assert_eq!(assembly.words, image.words, "{path}");
+        }
+    }
+}
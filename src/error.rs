@@ -1,3 +1,5 @@
+use crate::callstack::Backtrace;
+
 #[derive(Debug)]
 pub enum VMError {
     /// Wrapper for stdout.flush() errors. The original error is contained inside as a string.
@@ -15,4 +17,32 @@ pub enum VMError {
     UnrecognizedTrapCode(u16),
     /// Wrapper for Termios crate errors. The original error is contained inside as a string.
     TermiosError(String),
+    /// The program image could not be interpreted. The string explains what is wrong with it.
+    MalformedImage(String),
+    /// Wrapper for std::fs::write() errors. The original error is contained inside as a string.
+    CouldNotWriteFile(String),
+    /// The source file given as argument had errors, which are reported on stderr. The string is the path of the file.
+    AssemblyFailed(String),
+    /// An argument in the command line is not valid. The string explains which one and why.
+    InvalidArgument(String),
+    /// The linter found problems, which are reported on stdout. The number inside is how many.
+    LintFindings(usize),
+    /// `fmt --check` found files that are not formatted. The number inside is how many.
+    NotFormatted(usize),
+    /// In strict mode, the word at `address` is not a well-formed instruction: it uses the reserved
+    /// opcode or has bits the ISA fixes set to something else.
+    IllegalInstruction { address: u16, word: u16 },
+    /// A message from an editor or debugger client could not be read or an answer could not be sent.
+    /// The string explains what went wrong.
+    ProtocolError(String),
+    /// A recording of a run could not be read. The string explains what is wrong with it.
+    MalformedRecording(String),
+    /// `trace-diff` found where the runs diverge, which is reported on stdout.
+    RunsDiverge,
+    /// The instruction the program was running failed with `error`. The backtrace tells where it
+    /// was and the calls it was in.
+    Crashed {
+        error: Box<VMError>,
+        backtrace: Backtrace,
+    },
 }
@@ -0,0 +1,275 @@
+//! The execution history of the VM, so programs can be run backwards.
+//!
+//! While a `History` is set on the VM, every instruction `VMState::step` executes is recorded as a
+//! `Change`: the registers before and after it and the memory accesses it made, device registers
+//! included. Undoing a change puts the registers and the memory written back as they were, and
+//! stepping again replays the recorded change instead of executing the instruction, so the program
+//! gets the same keys it read the first time (its console output is not written again).
+//!
+//! Every `CHECKPOINT_EVERY` instructions the whole memory is saved as a checkpoint, so moving far in
+//! the history does not have to undo or replay every change in between. Only the last
+//! `CHECKPOINTS_KEPT` checkpoints are kept, with the changes since the oldest of them, which bounds
+//! the memory the history takes however long the program runs.
+//!
+//! The call stack of the VM before every change is kept with it, and with the checkpoints, so it
+//! is back as it was too.
+//!
+//! Tools editing the registers or memory are not recorded. Replaying stops at the first change
+//! that does not start from the state the edits left, and the program runs from there instead.
+use std::collections::VecDeque;
+
+use crate::{callstack::CallStack, registers::Register, vm::MEMORY_MAX};
+
+/// How many instructions run between two checkpoints.
+pub const CHECKPOINT_EVERY: u64 = 50_000;
+/// How many checkpoints are kept, the oldest being where the history starts.
+pub const CHECKPOINTS_KEPT: usize = 8;
+
+/// What a memory access recorded in a change was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    /// A read by the instruction.
+    Read,
+    /// A write by the instruction.
+    Write,
+    /// The keyboard registers updated as the keyboard status was read.
+    Device,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    /// The value before and after the access, which are the same for reads.
+    pub old: u16,
+    pub new: u16,
+}
+
+/// What executing one instruction did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The word of the instruction, which was at the PC of `before`.
+    pub instruction: u16,
+    pub before: [u16; Register::COUNT],
+    pub after: [u16; Register::COUNT],
+    /// The memory accesses, in the order they were made.
+    pub accesses: Vec<MemoryAccess>,
+    /// Whether the program was still running afterwards, which is false for HALT.
+    pub running: bool,
+}
+
+impl Change {
+    /// Whether the change can be replayed on the VM state given: its registers are those the change
+    /// started from, and so are the words it wrote.
+    fn applies_to(&self, registers: &[u16; Register::COUNT], memory: &[u16]) -> bool {
+        let writes = self
+            .accesses
+            .iter()
+            .enumerate()
+            .filter(|(_, access)| access.kind != AccessKind::Read);
+        *registers == self.before
+            && writes.clone().all(|(index, access)| {
+                // Later writes to an address start from what the earlier ones wrote.
+                let first = !writes
+                    .clone()
+                    .any(|(other, earlier)| other < index && earlier.address == access.address);
+                !first || memory[access.address as usize] == access.old
+            })
+    }
+}
+
+/// The whole state of the VM at an instruction count.
+struct Checkpoint {
+    count: u64,
+    registers: [u16; Register::COUNT],
+    memory: Box<[u16]>,
+    calls: CallStack,
+}
+
+pub struct History {
+    changes: VecDeque<Change>,
+    /// The call stack before each change.
+    calls: VecDeque<CallStack>,
+    /// The instruction count before the first change kept.
+    first: u64,
+    /// How many of the changes are done: those after it were undone and can be replayed.
+    position: usize,
+    checkpoints: VecDeque<Checkpoint>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    /// An empty history, starting at the current state of the VM as instruction 0.
+    pub fn new() -> Self {
+        Self {
+            changes: VecDeque::new(),
+            calls: VecDeque::new(),
+            first: 0,
+            position: 0,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    /// How many instructions the program executed to get to where it is.
+    pub fn count(&self) -> u64 {
+        self.first + self.position as u64
+    }
+
+    /// The earliest instruction count the program can be taken back to.
+    pub fn start(&self) -> u64 {
+        self.first
+    }
+
+    /// The latest instruction count recorded.
+    pub fn end(&self) -> u64 {
+        self.first + self.changes.len() as u64
+    }
+
+    /// Saves the state of the VM as a checkpoint if one is due at the current count, dropping the
+    /// oldest checkpoint and the changes before the next one when there are too many.
+    pub fn checkpoint(
+        &mut self,
+        registers: &[u16; Register::COUNT],
+        memory: &[u16],
+        calls: &CallStack,
+    ) {
+        let count = self.count();
+        let saved = self
+            .checkpoints
+            .back()
+            .is_some_and(|last| last.count >= count);
+        if !count.is_multiple_of(CHECKPOINT_EVERY) || saved {
+            return;
+        }
+        self.checkpoints.push_back(Checkpoint {
+            count,
+            registers: *registers,
+            memory: memory.into(),
+            calls: calls.clone(),
+        });
+        if self.checkpoints.len() > CHECKPOINTS_KEPT {
+            self.checkpoints.pop_front();
+            let start = self.checkpoints[0].count;
+            let dropped = (start - self.first) as usize;
+            self.changes.drain(..dropped);
+            self.calls.drain(..dropped);
+            self.first = start;
+            self.position -= dropped;
+        }
+    }
+
+    /// Records the change of the instruction just executed, with the call stack before it,
+    /// forgetting those that were undone.
+    pub fn push(&mut self, change: Change, calls: CallStack) {
+        self.forget_future();
+        self.changes.push_back(change);
+        self.calls.push_back(calls);
+        self.position += 1;
+    }
+
+    /// Forgets the changes that were undone, as the program went another way.
+    pub fn forget_future(&mut self) {
+        self.changes.truncate(self.position);
+        self.calls.truncate(self.position);
+        let count = self.count();
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|last| last.count > count)
+        {
+            self.checkpoints.pop_back();
+        }
+    }
+
+    /// The next change to replay, if it applies to the VM state given. A change that does not
+    /// (the state was edited since it was undone) is forgotten with all those after it.
+    pub fn redo(&mut self, registers: &[u16; Register::COUNT], memory: &[u16]) -> Option<Change> {
+        let change = self.changes.get(self.position)?;
+        if !change.applies_to(registers, memory) {
+            self.forget_future();
+            return None;
+        }
+        self.position += 1;
+        Some(change.clone())
+    }
+
+    /// The last change done, to undo, with the call stack before it.
+    pub fn undo(&mut self) -> Option<(Change, CallStack)> {
+        self.position = self.position.checked_sub(1)?;
+        let position = self.position;
+        Some((self.changes[position].clone(), self.calls[position].clone()))
+    }
+
+    /// Restores the latest checkpoint at or before `count` into `registers`, `memory` and `calls`,
+    /// if that is closer to `count` than the current instruction. Returns whether it did.
+    pub fn restore(
+        &mut self,
+        count: u64,
+        registers: &mut [u16; Register::COUNT],
+        memory: &mut [u16; MEMORY_MAX],
+        calls: &mut CallStack,
+    ) -> bool {
+        let Some(checkpoint) = self.checkpoints.iter().rev().find(|c| c.count <= count) else {
+            return false;
+        };
+        if self.count().abs_diff(count) <= count - checkpoint.count {
+            return false;
+        }
+        *registers = checkpoint.registers;
+        memory.copy_from_slice(&checkpoint.memory);
+        *calls = checkpoint.calls.clone();
+        self.position = (checkpoint.count - self.first) as usize;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_the_changes_since_the_oldest_checkpoint() {
+        let mut history = History::new();
+        let mut registers = [0; Register::COUNT];
+        let mut memory = [0; MEMORY_MAX];
+        let total = CHECKPOINT_EVERY * CHECKPOINTS_KEPT as u64 + 10;
+        for count in 0..total {
+            history.checkpoint(&registers, &memory, &CallStack::default());
+            memory[0] = count as u16;
+            let before = registers;
+            registers[0] = count as u16;
+            history.push(
+                Change {
+                    instruction: 0,
+                    before,
+                    after: registers,
+                    accesses: Vec::new(),
+                    running: true,
+                },
+                CallStack::default(),
+            );
+        }
+        assert_eq!(history.start(), CHECKPOINT_EVERY);
+        assert_eq!(history.count(), total);
+        let mut calls = CallStack::default();
+        assert!(history.restore(
+            CHECKPOINT_EVERY + 1,
+            &mut registers,
+            &mut memory,
+            &mut calls
+        ));
+        assert_eq!(history.count(), CHECKPOINT_EVERY);
+        assert_eq!(registers[0], (CHECKPOINT_EVERY - 1) as u16);
+        assert_eq!(memory[0], (CHECKPOINT_EVERY - 1) as u16);
+        assert!(history.undo().is_none());
+        assert_eq!(
+            history.redo(&registers, &memory).unwrap().after[0],
+            CHECKPOINT_EVERY as u16
+        );
+    }
+}
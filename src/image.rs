@@ -0,0 +1,56 @@
+use crate::error::VMError;
+
+/// A program image as found in LC-3 object files: the first word is the origin (the address where the
+/// program is loaded) and every following word is stored in consecutive memory positions from there on.
+/// All words come in big endian.
+pub struct Image {
+    /// The address of the first word of the program.
+    pub origin: u16,
+    /// The program words, in load order.
+    pub words: Vec<u16>,
+}
+
+impl Image {
+    /// Parses the bytes of an object file into an `Image`. A trailing odd byte is ignored, the same way
+    /// `VMState::write_ixs_to_mem` does.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMError> {
+        if bytes.len() < 2 {
+            return Err(VMError::MalformedImage(format!(
+                "expected at least 2 bytes for the origin, found {}",
+                bytes.len()
+            )));
+        }
+        let origin = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let words = bytes[2..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        Ok(Self { origin, words })
+    }
+
+    /// Returns the word loaded at `address`, if the image covers it.
+    pub fn get(&self, address: u16) -> Option<u16> {
+        let index = address.wrapping_sub(self.origin) as usize;
+        self.words.get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_origin_and_words() {
+        let image = Image::from_bytes(&[0x30, 0x00, 0x12, 0x34, 0xAB, 0xCD, 0xFF]).unwrap();
+        assert_eq!(image.origin, 0x3000);
+        assert_eq!(image.words, vec![0x1234, 0xABCD]);
+        assert_eq!(image.get(0x3001), Some(0xABCD));
+        assert_eq!(image.get(0x3002), None);
+        assert_eq!(image.get(0x2FFF), None);
+    }
+
+    #[test]
+    fn rejects_files_without_origin() {
+        assert!(Image::from_bytes(&[0x30]).is_err());
+    }
+}
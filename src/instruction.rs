@@ -0,0 +1,418 @@
+use std::fmt;
+
+use crate::{opcodes::Opcode, operations::utils::sign_extend};
+
+/// The last operand of ADD and AND: either a register or a 5-bit immediate value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    Immediate(i16),
+}
+
+/// An LC-3 instruction with its fields decoded: registers are numbers from 0 to 7 and offsets and
+/// immediate values are sign-extended. This is the one place that knows how instructions are laid out
+/// in a word; the VM executes them, the assembler encodes them and the disassembler prints them.
+///
+/// Decoding is the one the VM does, which ignores the bits the ISA leaves unused (like the bits 4 and
+/// 3 of ADD in register mode) or fixes (like the six low bits of NOT, which are all ones). Encoding
+/// writes those bits as the ISA says, so `encode` gives back the decoded word exactly when the word
+/// was well formed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    //     | 0001 | dest | src | 0 | 00 | src 2 |  or  | 0001 | dest | src | 1 | imm5 |
+    Add {
+        dest: u8,
+        src: u8,
+        operand: Operand,
+    },
+    //     | 0101 | dest | src | 0 | 00 | src 2 |  or  | 0101 | dest | src | 1 | imm5 |
+    And {
+        dest: u8,
+        src: u8,
+        operand: Operand,
+    },
+    //     | 1001 | dest | src | 111111 |
+    Not {
+        dest: u8,
+        src: u8,
+    },
+    /// `conditions` has the n, z and p bits in the same places as the condition register.
+    //     | 0000 | n | z | p | offset9 |
+    Br {
+        conditions: u16,
+        offset: i16,
+    },
+    /// RET is JMP R7.
+    //     | 1100 | 000 | base | 000000 |
+    Jmp {
+        base: u8,
+    },
+    //     | 0100 | 1 | offset11 |
+    Jsr {
+        offset: i16,
+    },
+    //     | 0100 | 0 | 00 | base | 000000 |
+    Jsrr {
+        base: u8,
+    },
+    //     | 0010 | dest | offset9 |
+    Ld {
+        dest: u8,
+        offset: i16,
+    },
+    //     | 1010 | dest | offset9 |
+    Ldi {
+        dest: u8,
+        offset: i16,
+    },
+    //     | 1110 | dest | offset9 |
+    Lea {
+        dest: u8,
+        offset: i16,
+    },
+    //     | 0011 | src | offset9 |
+    St {
+        src: u8,
+        offset: i16,
+    },
+    //     | 1011 | src | offset9 |
+    Sti {
+        src: u8,
+        offset: i16,
+    },
+    //     | 0110 | dest | base | offset6 |
+    Ldr {
+        dest: u8,
+        base: u8,
+        offset: i16,
+    },
+    //     | 0111 | src | base | offset6 |
+    Str {
+        src: u8,
+        base: u8,
+        offset: i16,
+    },
+    //     | 1111 | 0000 | trapvect8 |
+    Trap {
+        vector: u8,
+    },
+    /// With the whole word, as the low bits are not fields and are only shown in its `.FILL`.
+    //     | 1000 | 000000000000 |
+    Rti(u16),
+    /// The opcode the ISA reserves, with the whole word, which is only data.
+    //     | 1101 | ............ |
+    Reserved(u16),
+}
+
+impl Instruction {
+    /// Decodes a word the way the VM reads it.
+    pub fn decode(word: u16) -> Self {
+        let dest = ((word >> 9) & 0x7) as u8;
+        let base = ((word >> 6) & 0x7) as u8;
+        let offset = |bits: usize| sign_extend(word & ((1 << bits) - 1), bits) as i16;
+        let operand = || {
+            if (word >> 5) & 0x1 > 0 {
+                Operand::Immediate(offset(5))
+            } else {
+                Operand::Register((word & 0x7) as u8)
+            }
+        };
+        let Ok(opcode) = Opcode::try_from(word >> 12) else {
+            unreachable!("opcodes have four bits");
+        };
+        match opcode {
+            Opcode::OpADD => Self::Add {
+                dest,
+                src: base,
+                operand: operand(),
+            },
+            Opcode::OpAND => Self::And {
+                dest,
+                src: base,
+                operand: operand(),
+            },
+            Opcode::OpNOT => Self::Not { dest, src: base },
+            Opcode::OpBR => Self::Br {
+                conditions: (word >> 9) & 0x7,
+                offset: offset(9),
+            },
+            Opcode::OpJMP => Self::Jmp { base },
+            Opcode::OpJSR if (word >> 11) & 0x1 > 0 => Self::Jsr { offset: offset(11) },
+            Opcode::OpJSR => Self::Jsrr { base },
+            Opcode::OpLD => Self::Ld {
+                dest,
+                offset: offset(9),
+            },
+            Opcode::OpLDI => Self::Ldi {
+                dest,
+                offset: offset(9),
+            },
+            Opcode::OpLEA => Self::Lea {
+                dest,
+                offset: offset(9),
+            },
+            Opcode::OpST => Self::St {
+                src: dest,
+                offset: offset(9),
+            },
+            Opcode::OpSTI => Self::Sti {
+                src: dest,
+                offset: offset(9),
+            },
+            Opcode::OpLDR => Self::Ldr {
+                dest,
+                base,
+                offset: offset(6),
+            },
+            Opcode::OpSTR => Self::Str {
+                src: dest,
+                base,
+                offset: offset(6),
+            },
+            Opcode::OpTRAP => Self::Trap {
+                vector: (word & 0xFF) as u8,
+            },
+            Opcode::OpRTI => Self::Rti(word),
+            Opcode::OpRES => Self::Reserved(word),
+        }
+    }
+
+    /// Decodes a word only when it is well formed: the bits the ISA leaves unused are zero, the ones
+    /// it fixes have their values and the opcode is not the reserved one. RTI is not either, since
+    /// programs run in user mode, where it is privileged. Strict mode treats every other word as an
+    /// illegal instruction.
+    pub fn decode_strict(word: u16) -> Option<Self> {
+        match Self::decode(word) {
+            Self::Reserved(_) | Self::Rti(_) => None,
+            instruction => (instruction.encode() == word).then_some(instruction),
+        }
+    }
+
+    /// The word for this instruction, with the unused and fixed bits as the ISA says. Fields that do
+    /// not fit are cut to their size. RTI and the reserved opcode give back the word they were
+    /// decoded from.
+    pub fn encode(self) -> u16 {
+        let reg = |register: u8, shift: u16| ((register & 0x7) as u16) << shift;
+        let bits = |value: i16, bits: u16| value as u16 & ((1 << bits) - 1);
+        let operand = |operand: Operand| match operand {
+            Operand::Register(register) => reg(register, 0),
+            Operand::Immediate(value) => 0x20 | bits(value, 5),
+        };
+        let opcode = |opcode: Opcode| (opcode as u16) << 12;
+        match self {
+            Self::Add {
+                dest,
+                src,
+                operand: last,
+            } => opcode(Opcode::OpADD) | reg(dest, 9) | reg(src, 6) | operand(last),
+            Self::And {
+                dest,
+                src,
+                operand: last,
+            } => opcode(Opcode::OpAND) | reg(dest, 9) | reg(src, 6) | operand(last),
+            Self::Not { dest, src } => opcode(Opcode::OpNOT) | reg(dest, 9) | reg(src, 6) | 0x3F,
+            Self::Br { conditions, offset } => {
+                opcode(Opcode::OpBR) | (conditions & 0x7) << 9 | bits(offset, 9)
+            }
+            Self::Jmp { base } => opcode(Opcode::OpJMP) | reg(base, 6),
+            Self::Jsr { offset } => opcode(Opcode::OpJSR) | 0x0800 | bits(offset, 11),
+            Self::Jsrr { base } => opcode(Opcode::OpJSR) | reg(base, 6),
+            Self::Ld { dest, offset } => opcode(Opcode::OpLD) | reg(dest, 9) | bits(offset, 9),
+            Self::Ldi { dest, offset } => opcode(Opcode::OpLDI) | reg(dest, 9) | bits(offset, 9),
+            Self::Lea { dest, offset } => opcode(Opcode::OpLEA) | reg(dest, 9) | bits(offset, 9),
+            Self::St { src, offset } => opcode(Opcode::OpST) | reg(src, 9) | bits(offset, 9),
+            Self::Sti { src, offset } => opcode(Opcode::OpSTI) | reg(src, 9) | bits(offset, 9),
+            Self::Ldr { dest, base, offset } => {
+                opcode(Opcode::OpLDR) | reg(dest, 9) | reg(base, 6) | bits(offset, 6)
+            }
+            Self::Str { src, base, offset } => {
+                opcode(Opcode::OpSTR) | reg(src, 9) | reg(base, 6) | bits(offset, 6)
+            }
+            Self::Trap { vector } => opcode(Opcode::OpTRAP) | vector as u16,
+            Self::Rti(word) | Self::Reserved(word) => word,
+        }
+    }
+
+    /// The offset of a PC-relative instruction (BR, JSR, LD, LDI, LEA, ST, STI).
+    pub fn pc_offset(self) -> Option<i16> {
+        match self {
+            Self::Br { offset, .. }
+            | Self::Jsr { offset }
+            | Self::Ld { offset, .. }
+            | Self::Ldi { offset, .. }
+            | Self::Lea { offset, .. }
+            | Self::St { offset, .. }
+            | Self::Sti { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// The address a PC-relative instruction stored at `address` refers to.
+    pub fn target(self, address: u16) -> Option<u16> {
+        let offset = self.pc_offset()?;
+        Some(address.wrapping_add(1).wrapping_add(offset as u16))
+    }
+
+    /// Whether the assembler has syntax for this instruction. BR without conditions (which never
+    /// branches), RTI with low bits set and the reserved opcode have none, so they are written as
+    /// `.FILL`.
+    pub fn has_syntax(self) -> bool {
+        match self {
+            Self::Br { conditions: 0, .. } | Self::Reserved(_) => false,
+            Self::Rti(word) => word & 0x0FFF == 0,
+            _ => true,
+        }
+    }
+
+    /// The assembly text of the instruction, stored at `address`. `label` names the targets of
+    /// PC-relative instructions; the ones without a name are written as offsets.
+    pub fn to_assembly(self, address: u16, label: impl Fn(u16) -> Option<String>) -> String {
+        let mut text = String::new();
+        self.write(&mut text, |offset| {
+            let target = address.wrapping_add(1).wrapping_add(offset as u16);
+            label(target).unwrap_or_else(|| format!("#{offset}"))
+        })
+        .expect("writing to a string does not fail");
+        text
+    }
+
+    /// Writes the assembly text of the instruction, with `target` giving the text of PC offsets.
+    fn write(self, out: &mut impl fmt::Write, target: impl Fn(i16) -> String) -> fmt::Result {
+        let operand = |operand: Operand| match operand {
+            Operand::Register(register) => format!("R{register}"),
+            Operand::Immediate(value) => format!("#{value}"),
+        };
+        match self {
+            _ if !self.has_syntax() => write!(out, ".FILL x{:04X}", self.encode()),
+            Self::Add {
+                dest,
+                src,
+                operand: last,
+            } => {
+                write!(out, "ADD R{dest}, R{src}, {}", operand(last))
+            }
+            Self::And {
+                dest,
+                src,
+                operand: last,
+            } => {
+                write!(out, "AND R{dest}, R{src}, {}", operand(last))
+            }
+            Self::Not { dest, src } => write!(out, "NOT R{dest}, R{src}"),
+            Self::Br { conditions, offset } => {
+                let flags: String = [('n', 0x4), ('z', 0x2), ('p', 0x1)]
+                    .iter()
+                    .filter(|(_, bit)| conditions & bit > 0)
+                    .map(|(flag, _)| *flag)
+                    .collect();
+                write!(out, "BR{flags} {}", target(offset))
+            }
+            Self::Jmp { base: 7 } => write!(out, "RET"),
+            Self::Jmp { base } => write!(out, "JMP R{base}"),
+            Self::Jsr { offset } => write!(out, "JSR {}", target(offset)),
+            Self::Jsrr { base } => write!(out, "JSRR R{base}"),
+            Self::Ld { dest, offset } => write!(out, "LD R{dest}, {}", target(offset)),
+            Self::Ldi { dest, offset } => write!(out, "LDI R{dest}, {}", target(offset)),
+            Self::Lea { dest, offset } => write!(out, "LEA R{dest}, {}", target(offset)),
+            Self::St { src, offset } => write!(out, "ST R{src}, {}", target(offset)),
+            Self::Sti { src, offset } => write!(out, "STI R{src}, {}", target(offset)),
+            Self::Ldr { dest, base, offset } => write!(out, "LDR R{dest}, R{base}, #{offset}"),
+            Self::Str { src, base, offset } => write!(out, "STR R{src}, R{base}, #{offset}"),
+            Self::Trap { vector } => match vector {
+                0x20 => write!(out, "GETC"),
+                0x21 => write!(out, "OUT"),
+                0x22 => write!(out, "PUTS"),
+                0x23 => write!(out, "IN"),
+                0x24 => write!(out, "PUTSP"),
+                0x25 => write!(out, "HALT"),
+                _ => write!(out, "TRAP x{vector:02X}"),
+            },
+            Self::Rti(_) => write!(out, "RTI"),
+            Self::Reserved(_) => unreachable!("written as .FILL"),
+        }
+    }
+}
+
+/// The instruction in assembly syntax, with PC offsets as numbers (`BRz #-3`). Instructions without
+/// syntax are written as the `.FILL` of their word.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, |offset| format!("#{offset}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::{Symbol, encoder::encode_statement, parser::parse_line};
+    use std::collections::BTreeMap;
+
+    /// Whether the bits of `word` the ISA leaves unused or fixes have the right values.
+    fn well_formed(word: u16) -> bool {
+        match word >> 12 {
+            0x1 | 0x5 => (word >> 5) & 0x1 > 0 || word & 0x18 == 0,
+            0x9 => word & 0x3F == 0x3F,
+            0xC => word & 0x0E3F == 0,
+            0x4 => (word >> 11) & 0x1 > 0 || word & 0x063F == 0,
+            0xF => word & 0x0F00 == 0,
+            0x8 => word & 0x0FFF == 0,
+            0xD => word & 0x0FFF == 0,
+            _ => true,
+        }
+    }
+
+    #[test]
+    fn encodes_every_well_formed_word_back() {
+        for word in 0..=u16::MAX {
+            let instruction = Instruction::decode(word);
+            // RTI and the reserved opcode keep their whole word.
+            let kept = matches!(word >> 12, 0x8 | 0xD);
+            assert_eq!(
+                instruction.encode() == word,
+                kept || well_formed(word),
+                "{word:04X}"
+            );
+            // Whatever the unused bits were, the encoded word means the same.
+            assert_eq!(Instruction::decode(instruction.encode()), instruction);
+            let strict = well_formed(word) && !kept;
+            assert_eq!(
+                Instruction::decode_strict(word),
+                strict.then_some(instruction),
+                "{word:04X}"
+            );
+        }
+    }
+
+    #[test]
+    fn assembles_what_it_displays() {
+        let symbols = BTreeMap::<String, Symbol>::new();
+        for word in 0..=u16::MAX {
+            let instruction = Instruction::decode(word);
+            let text = instruction.to_string();
+            let line = parse_line(1, &text).unwrap();
+            let statement = line.statement.unwrap();
+            let words = encode_statement(1, &statement, 0x3000, &symbols)
+                .unwrap_or_else(|d| panic!("`{text}`: {}", d.message));
+            assert_eq!(words, [instruction.encode()], "`{text}`");
+        }
+    }
+
+    #[test]
+    fn displays_assembly() {
+        let text = |word| Instruction::decode(word).to_string();
+        assert_eq!(text(0x127F), "ADD R1, R1, #-1");
+        assert_eq!(text(0x5482), "AND R2, R2, R2");
+        assert_eq!(text(0x03FE), "BRp #-2");
+        assert_eq!(text(0xC1C0), "RET");
+        assert_eq!(text(0xF025), "HALT");
+        assert_eq!(text(0xF0FF), "TRAP xFF");
+        assert_eq!(text(0x0005), ".FILL x0005");
+        assert_eq!(text(0xD123), ".FILL xD123");
+        assert_eq!(text(0x8000), "RTI");
+        assert_eq!(text(0x8123), ".FILL x8123");
+        assert_eq!(
+            Instruction::decode(0x4801).to_assembly(0x3000, |t| Some(format!("L_{t:04X}"))),
+            "JSR L_3002"
+        );
+    }
+}
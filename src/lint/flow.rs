@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::instruction::{Instruction, Operand};
+
+/// How control continues after an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    /// To the next instruction.
+    Next,
+    /// BR with no condition bits: it never branches, so control goes to the next instruction.
+    NeverBranch,
+    /// Conditional branch to the address, or to the next instruction.
+    Branch(u16),
+    /// Unconditional branch (BRnzp).
+    Goto(u16),
+    /// A call (JSR, JSRR or TRAP) that comes back to the next instruction. The address is the callee
+    /// when it is known (JSR).
+    Call(Option<u16>),
+    /// JMP R7.
+    Return,
+    /// TRAP x25.
+    Halt,
+    /// JMP to any register other than R7.
+    IndirectJump,
+    /// RTI or the reserved opcode, which programs cannot execute.
+    Invalid,
+}
+
+/// What an instruction does with the registers and the control flow. Registers are bit masks where
+/// bit `n` stands for `Rn`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Effects {
+    pub reads: u8,
+    pub writes: u8,
+    pub flow: Flow,
+}
+
+/// Works out the effects of `instruction`, stored at `address`.
+pub fn effects(instruction: u16, address: u16) -> Effects {
+    let bit = |register: u8| 1 << register;
+    let plain = |reads, writes| Effects {
+        reads,
+        writes,
+        flow: Flow::Next,
+    };
+    let decoded = Instruction::decode(instruction);
+    let target = decoded.target(address).unwrap_or_default();
+    match decoded {
+        // `AND Rx, Ry, #0` clears the register whatever Ry holds.
+        Instruction::And {
+            dest,
+            operand: Operand::Immediate(0),
+            ..
+        } => plain(0, bit(dest)),
+        Instruction::Add { dest, src, operand } | Instruction::And { dest, src, operand } => {
+            let second = match operand {
+                Operand::Register(register) => bit(register),
+                Operand::Immediate(_) => 0,
+            };
+            plain(bit(src) | second, bit(dest))
+        }
+        Instruction::Not { dest, src } => plain(bit(src), bit(dest)),
+        Instruction::Ld { dest, .. }
+        | Instruction::Ldi { dest, .. }
+        | Instruction::Lea { dest, .. } => plain(0, bit(dest)),
+        Instruction::Ldr { dest, base, .. } => plain(bit(base), bit(dest)),
+        Instruction::St { src, .. } | Instruction::Sti { src, .. } => plain(bit(src), 0),
+        Instruction::Str { src, base, .. } => plain(bit(src) | bit(base), 0),
+        Instruction::Br { conditions, .. } => Effects {
+            reads: 0,
+            writes: 0,
+            flow: match conditions {
+                0 => Flow::NeverBranch,
+                0x7 => Flow::Goto(target),
+                _ => Flow::Branch(target),
+            },
+        },
+        Instruction::Jmp { base } => Effects {
+            reads: bit(base),
+            writes: 0,
+            flow: if base == 7 {
+                Flow::Return
+            } else {
+                Flow::IndirectJump
+            },
+        },
+        Instruction::Jsr { .. } => Effects {
+            reads: 0,
+            writes: bit(7),
+            flow: Flow::Call(Some(target)),
+        },
+        Instruction::Jsrr { base } => Effects {
+            reads: bit(base),
+            writes: bit(7),
+            flow: Flow::Call(None),
+        },
+        // TRAP: the service routines read or write R0, and the ISA stores the return address in R7.
+        Instruction::Trap { vector } => match vector {
+            0x25 => Effects {
+                reads: 0,
+                writes: 0,
+                flow: Flow::Halt,
+            },
+            0x20 | 0x23 => Effects {
+                reads: 0,
+                writes: 1 | 1 << 7,
+                flow: Flow::Call(None),
+            },
+            0x21 | 0x22 | 0x24 => Effects {
+                reads: 1,
+                writes: 1 << 7,
+                flow: Flow::Call(None),
+            },
+            _ => Effects {
+                reads: 0,
+                writes: 1 << 7,
+                flow: Flow::Call(None),
+            },
+        },
+        Instruction::Rti(_) | Instruction::Reserved(_) => Effects {
+            reads: 0,
+            writes: 0,
+            flow: Flow::Invalid,
+        },
+    }
+}
+
+/// The facts known before an instruction runs, merged over every path that reaches it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct State {
+    /// Registers written on every path from the function entry.
+    pub written: u8,
+    /// Address of an instruction that overwrote the return address in R7 (a call) on some path,
+    /// without R7 being set again afterwards.
+    pub r7_clobbered_at: Option<u16>,
+}
+
+impl State {
+    fn merge(self, other: State) -> State {
+        State {
+            written: self.written & other.written,
+            r7_clobbered_at: self.r7_clobbered_at.or(other.r7_clobbered_at),
+        }
+    }
+
+    /// The state after an instruction at `address` with effects `effects`.
+    fn after(self, address: u16, effects: &Effects) -> State {
+        let mut next = self;
+        next.written |= effects.writes;
+        if let Flow::Call(_) = effects.flow {
+            // The callee may have written any register.
+            next.written = 0xFF;
+            next.r7_clobbered_at = Some(address);
+        } else if effects.writes & (1 << 7) > 0 {
+            // Setting R7 explicitly (usually loading it back from where it was saved) restores it.
+            next.r7_clobbered_at = None;
+        }
+        next
+    }
+}
+
+/// The result of following the control flow of a function from its entry.
+pub struct FunctionFlow {
+    /// State before every reachable instruction, with its effects.
+    pub instructions: BTreeMap<u16, (State, Effects)>,
+    /// Jumps that lead to an address that does not hold code: `(from, to)`.
+    pub escapes: Vec<(u16, u16)>,
+    /// Entries of the functions it calls.
+    pub callees: BTreeSet<u16>,
+}
+
+/// Follows the control flow from `entry`, not going into calls. `word_at` returns the instruction at
+/// an address, or `None` if the address does not hold code.
+pub fn follow(entry: u16, initial: State, word_at: impl Fn(u16) -> Option<u16>) -> FunctionFlow {
+    let mut result = FunctionFlow {
+        instructions: BTreeMap::new(),
+        escapes: Vec::new(),
+        callees: BTreeSet::new(),
+    };
+    let mut pending = vec![(entry, initial, None)];
+    while let Some((address, state, from)) = pending.pop() {
+        let Some(word) = word_at(address) else {
+            if let Some(from) = from
+                && !result.escapes.contains(&(from, address))
+            {
+                result.escapes.push((from, address));
+            }
+            continue;
+        };
+        let state = match result.instructions.get(&address) {
+            Some((previous, _)) => {
+                let merged = previous.merge(state);
+                if merged == *previous {
+                    continue;
+                }
+                merged
+            }
+            None => state,
+        };
+        let effects = effects(word, address);
+        result.instructions.insert(address, (state, effects));
+        let after = state.after(address, &effects);
+        let next = address.wrapping_add(1);
+        match effects.flow {
+            Flow::Next | Flow::NeverBranch => pending.push((next, after, Some(address))),
+            Flow::Branch(target) => {
+                pending.push((target, after, Some(address)));
+                pending.push((next, after, Some(address)));
+            }
+            Flow::Goto(target) => pending.push((target, after, Some(address))),
+            Flow::Call(callee) => {
+                result.callees.extend(callee);
+                pending.push((next, after, Some(address)));
+            }
+            Flow::Return | Flow::Halt | Flow::IndirectJump | Flow::Invalid => {}
+        }
+    }
+    result.escapes.sort();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_register_usage() {
+        // ADD R2, R4, R5
+        let add = effects(0x1505, 0x3000);
+        assert_eq!(add.reads, 1 << 4 | 1 << 5);
+        assert_eq!(add.writes, 1 << 2);
+        // AND R0, R0, #0 does not depend on R0.
+        assert_eq!(effects(0x5020, 0x3000).reads, 0);
+        // STR R1, R3, #2
+        assert_eq!(effects(0x72C2, 0x3000).reads, 1 << 1 | 1 << 3);
+        // BR with no condition bits.
+        assert_eq!(effects(0x0005, 0x3000).flow, Flow::NeverBranch);
+    }
+
+    #[test]
+    fn merges_states_of_joining_paths() {
+        // 0x3000 BRz #1
+        // 0x3001 AND R1, R1, #0
+        // 0x3002 HALT
+        let words = [0x0401, 0x5260, 0xF025];
+        let flow = follow(
+            0x3000,
+            State {
+                written: 0,
+                r7_clobbered_at: None,
+            },
+            |address| words.get(address.wrapping_sub(0x3000) as usize).copied(),
+        );
+        // R1 is only written on one of the paths that reach HALT.
+        assert_eq!(flow.instructions[&0x3002].0.written, 0);
+        assert!(flow.escapes.is_empty());
+    }
+}
@@ -0,0 +1,403 @@
+//! Linter for LC-3 programs, working either on assembly source or on the code recovered from an
+//! object file. Every check has a name, so it can be turned off from the command line or, in
+//! assembly source, with comments:
+//! - `; lint: allow(check-name, ...)` at the end of a line silences those checks for that line.
+//! - `; lint: allow-file(check-name, ...)` anywhere silences them for the whole file.
+pub mod flow;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use crate::{
+    assembler::{Assembly, Diagnostic, encoder::assemble_lines_in, parser::Line, parser::parse},
+    image::Image,
+    json::Json,
+    lint::flow::{Flow, FunctionFlow, State, follow},
+};
+
+/// The checks the linter runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    /// A subroutine returns after a call (JSR, JSRR or TRAP) overwrote R7 and it was not restored.
+    R7Clobbered,
+    /// A BR instruction with none of the n, z, p bits set, which never branches.
+    EmptyBranch,
+    /// Execution runs into data, past the end of the program or into an invalid instruction.
+    FallIntoData,
+    /// A register is read before anything is written to it.
+    UninitializedRegister,
+    /// A label is defined but never used. The label at the origin is the entry point of the
+    /// program, which is not used from inside it.
+    UnusedLabel,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [
+        Check::R7Clobbered,
+        Check::EmptyBranch,
+        Check::FallIntoData,
+        Check::UninitializedRegister,
+        Check::UnusedLabel,
+    ];
+
+    /// The name used to refer to the check in the output and when silencing it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::R7Clobbered => "r7-clobbered",
+            Check::EmptyBranch => "empty-branch",
+            Check::FallIntoData => "fall-into-data",
+            Check::UninitializedRegister => "uninitialized-register",
+            Check::UnusedLabel => "unused-label",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Check> {
+        Check::ALL.into_iter().find(|check| check.name() == name)
+    }
+}
+
+/// Something a check found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub check: Check,
+    pub address: Option<u16>,
+    /// Source line, when linting assembly source.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("check", self.check.name().into()),
+            ("line", self.line.into()),
+            ("address", self.address.into()),
+            ("message", self.message.clone().into()),
+        ])
+    }
+}
+
+/// Lints assembly source that lives in `dir`, so it can include the files next to it. Fails with the
+/// assembler errors if the source does not assemble. Checks silenced with comments are left out of
+/// the result.
+pub fn lint_source(source: &str, dir: Option<&Path>) -> Result<Vec<Finding>, Vec<Diagnostic>> {
+    let (lines, diagnostics) = parse(source);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    let assembly = assemble_lines_in(&lines, dir)?;
+
+    let mut line_of = BTreeMap::new();
+    let mut code = BTreeSet::new();
+    for placement in &assembly.placements {
+        for offset in 0..placement.size {
+            let address = placement.address.wrapping_add(offset);
+            line_of.insert(address, placement.line);
+            if !placement.data {
+                code.insert(address);
+            }
+        }
+    }
+
+    let image = Image {
+        origin: assembly.origin,
+        words: assembly.words.clone(),
+    };
+    let mut findings = lint_code(&image, |address| code.contains(&address));
+    for finding in &mut findings {
+        finding.line = finding.address.and_then(|a| line_of.get(&a).copied());
+    }
+    // Labels of included files are meant to be used by many programs, which need not use them all.
+    let own_labels: BTreeSet<&String> = lines
+        .iter()
+        .filter_map(|line| line.label.as_ref().map(|label| &label.name))
+        .collect();
+    findings.extend(unused_labels(&assembly, &own_labels));
+    findings.sort_by_key(|f| (f.line, f.address));
+
+    let allowed = Allowed::from_comments(&lines);
+    findings.retain(|finding| !allowed.silences(finding));
+    Ok(findings)
+}
+
+/// Lints the code recovered from an object file. Every word of the image may be code.
+pub fn lint_image(image: &Image) -> Vec<Finding> {
+    lint_code(image, |_| true)
+}
+
+/// Runs the flow-based checks over every function reachable from the origin of `image`. `is_code`
+/// tells which addresses of the image hold instructions.
+fn lint_code(image: &Image, is_code: impl Fn(u16) -> bool) -> Vec<Finding> {
+    let word_at = |address: u16| image.get(address).filter(|_| is_code(address));
+    let mut findings = Vec::new();
+    let mut analysed = BTreeSet::new();
+    let mut pending = vec![image.origin];
+    while let Some(entry) = pending.pop() {
+        if !analysed.insert(entry) {
+            continue;
+        }
+        let is_main = entry == image.origin;
+        // Nothing is known about the registers a subroutine receives, so they all count as written.
+        let initial = State {
+            written: if is_main { 0 } else { 0xFF },
+            r7_clobbered_at: None,
+        };
+        let function = follow(entry, initial, word_at);
+        check_function(&function, is_main, &mut findings);
+        pending.extend(function.callees.iter().copied());
+    }
+    findings.sort_by_key(|f| f.address);
+    findings.dedup();
+    findings
+}
+
+fn check_function(function: &FunctionFlow, is_main: bool, findings: &mut Vec<Finding>) {
+    for (&address, (state, effects)) in &function.instructions {
+        let unwritten = effects.reads & !state.written;
+        if is_main && unwritten != 0 {
+            let registers: Vec<String> = (0..8)
+                .filter(|r| unwritten & (1 << r) > 0)
+                .map(|r| format!("R{r}"))
+                .collect();
+            findings.push(Finding {
+                check: Check::UninitializedRegister,
+                address: Some(address),
+                line: None,
+                message: format!(
+                    "{} may be read before anything is written to it",
+                    registers.join(", ")
+                ),
+            });
+        }
+        match effects.flow {
+            Flow::NeverBranch => findings.push(Finding {
+                check: Check::EmptyBranch,
+                address: Some(address),
+                line: None,
+                message: "BR without any of the n, z, p conditions never branches".to_string(),
+            }),
+            Flow::Return => {
+                if let Some(call) = state.r7_clobbered_at {
+                    findings.push(Finding {
+                        check: Check::R7Clobbered,
+                        address: Some(address),
+                        line: None,
+                        message: format!(
+                            "RET uses R7 after the call at x{call:04X} overwrote the return address"
+                        ),
+                    });
+                }
+            }
+            Flow::Invalid => findings.push(Finding {
+                check: Check::FallIntoData,
+                address: Some(address),
+                line: None,
+                message: format!(
+                    "execution reaches x{address:04X}, which is not a valid instruction"
+                ),
+            }),
+            _ => {}
+        }
+    }
+    for (from, to) in &function.escapes {
+        findings.push(Finding {
+            check: Check::FallIntoData,
+            address: Some(*from),
+            line: None,
+            message: format!(
+                "execution continues from x{from:04X} into x{to:04X}, which is not code"
+            ),
+        });
+    }
+}
+
+/// Labels among `candidates` that nothing refers to, other than the entry point.
+fn unused_labels(assembly: &Assembly, candidates: &BTreeSet<&String>) -> Vec<Finding> {
+    let used: BTreeSet<&String> = assembly.references.iter().map(|r| &r.symbol).collect();
+    assembly
+        .symbols
+        .iter()
+        .filter(|(name, symbol)| {
+            candidates.contains(name) && !used.contains(name) && symbol.address != assembly.origin
+        })
+        .map(|(name, symbol)| Finding {
+            check: Check::UnusedLabel,
+            address: Some(symbol.address),
+            line: Some(symbol.line),
+            message: format!("label `{name}` is never used"),
+        })
+        .collect()
+}
+
+/// Checks silenced by comments in the source.
+struct Allowed {
+    file: BTreeSet<Check>,
+    lines: BTreeMap<usize, BTreeSet<Check>>,
+}
+
+impl Allowed {
+    fn from_comments(lines: &[Line]) -> Self {
+        let mut allowed = Allowed {
+            file: BTreeSet::new(),
+            lines: BTreeMap::new(),
+        };
+        for line in lines {
+            let Some(comment) = &line.comment else {
+                continue;
+            };
+            let Some(rest) = comment.text.trim().strip_prefix("lint:") else {
+                continue;
+            };
+            let rest = rest.trim();
+            let (scope, names) = if let Some(names) = rest.strip_prefix("allow-file(") {
+                (&mut allowed.file, names)
+            } else if let Some(names) = rest.strip_prefix("allow(") {
+                (allowed.lines.entry(line.number).or_default(), names)
+            } else {
+                continue;
+            };
+            let names = names.split(')').next().unwrap_or_default();
+            scope.extend(names.split(',').filter_map(|n| Check::from_name(n.trim())));
+        }
+        allowed
+    }
+
+    fn silences(&self, finding: &Finding) -> bool {
+        self.file.contains(&finding.check)
+            || finding
+                .line
+                .and_then(|line| self.lines.get(&line))
+                .is_some_and(|checks| checks.contains(&finding.check))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn checks(findings: &[Finding]) -> Vec<(&'static str, Option<usize>)> {
+        findings.iter().map(|f| (f.check.name(), f.line)).collect()
+    }
+
+    #[test]
+    fn finds_common_mistakes() {
+        let source = "\
+        .ORIG x3000
+        ADD R1, R2, #1
+        JSR SUB
+        LEA R0, MSG
+        PUTS
+        BRnzp MSG
+SUB     ST R0, SAVE
+        OUT
+        LD R0, SAVE
+        RET
+SAVE    .BLKW 1
+MSG     .STRINGZ \"hi\"
+UNUSED  .FILL #0
+        .END";
+        let findings = lint_source(source, None).unwrap();
+        assert_eq!(
+            checks(&findings),
+            vec![
+                ("uninitialized-register", Some(2)),
+                ("fall-into-data", Some(6)),
+                ("r7-clobbered", Some(10)),
+                ("unused-label", Some(13)),
+            ]
+        );
+        assert_eq!(
+            findings[2].message,
+            "RET uses R7 after the call at x3006 overwrote the return address"
+        );
+    }
+
+    #[test]
+    fn accepts_saved_return_address() {
+        let source = "\
+        .ORIG x3000
+        JSR SUB
+        HALT
+SUB     ST R7, SAVE
+        GETC
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+        .END";
+        assert_eq!(lint_source(source, None).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn accepts_an_unused_entry_label() {
+        let source = "\
+        .ORIG x3000
+MAIN    HALT
+        .END";
+        assert_eq!(lint_source(source, None).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn includes_files_next_to_the_source() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-lint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("dbl.asm"), "DBL ADD R0, R0, R0\n    RET\n").unwrap();
+        let source = ".ORIG x3000\nAND R0, R0, #0\nJSR DBL\nHALT\n.INCLUDE \"dbl.asm\"\n.END";
+        let findings = lint_source(source, Some(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(findings.unwrap(), Vec::new());
+        assert!(lint_source(source, None).is_err());
+    }
+
+    #[test]
+    fn tells_included_data_from_code() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-lint-data-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("table.asm"), "TABLE .FILL #0\n").unwrap();
+        // Execution runs off the end of the code into the included data.
+        let source = ".ORIG x3000\nLD R0, TABLE\n.INCLUDE \"table.asm\"\n.END";
+        let findings = lint_source(source, Some(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            checks(&findings.unwrap()),
+            vec![("fall-into-data", Some(2))]
+        );
+    }
+
+    #[test]
+    fn silences_checks_with_comments() {
+        let source = "\
+        ; lint: allow-file(unused-label)
+        .ORIG x3000
+        ADD R0, R0, #1 ; lint: allow(uninitialized-register)
+        ADD R1, R1, #1
+        HALT
+UNUSED  .FILL #0
+        .END";
+        let findings = lint_source(source, None).unwrap();
+        assert_eq!(checks(&findings), vec![("uninitialized-register", Some(4))]);
+    }
+
+    #[test]
+    fn lints_object_code() {
+        // 0x3000 BR (no condition bits) #0
+        // 0x3001 RTI
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x0000, 0x8000],
+        };
+        let findings = lint_image(&image);
+        let found: Vec<(&str, Option<u16>)> = findings
+            .iter()
+            .map(|f| (f.check.name(), f.address))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("empty-branch", Some(0x3000)),
+                ("fall-into-data", Some(0x3001))
+            ]
+        );
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt::Write;
+
+use crate::{
+    assembler::{
+        Assembly,
+        encoder::assemble_lines,
+        lexer::Span,
+        parser::{DIRECTIVES, Line, MNEMONICS, OperandKind, Statement, branch_flags, parse},
+    },
+    lint::lint_source,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem to show in the editor. Lines start at 0, like in the protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    /// Columns it refers to. `None` stands for the whole line.
+    pub span: Option<Span>,
+    pub severity: Severity,
+    pub message: String,
+    /// Name of the lint check, for warnings.
+    pub code: Option<&'static str>,
+}
+
+/// A place in the document: a line (starting at 0) and the columns inside it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompletionKind {
+    Instruction,
+    Directive,
+    Label,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+}
+
+/// A label, listed as a symbol of the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub location: Location,
+    /// Whether the label names code (as opposed to data made by `.FILL`, `.BLKW` or `.STRINGZ`).
+    pub is_code: bool,
+    /// Its address, when the document assembles.
+    pub address: Option<u16>,
+}
+
+/// What the cursor is on.
+enum Target<'a> {
+    Label(&'a str),
+    Statement(&'a Line, &'a Statement),
+}
+
+/// An open document, analysed with the assembler. Everything the server answers comes from here.
+pub struct Document {
+    pub text: String,
+    lines: Vec<Line>,
+    problems: Vec<Problem>,
+    assembly: Option<Assembly>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let (lines, diagnostics) = parse(&text);
+        let mut problems: Vec<Problem> = diagnostics
+            .into_iter()
+            .map(|d| error(d.line, d.span, d.message))
+            .collect();
+        // Encoding needs every line, so a document with syntax errors is not assembled.
+        let mut assembly = None;
+        if problems.is_empty() {
+            match assemble_lines(&lines) {
+                Ok(assembled) => assembly = Some(assembled),
+                Err(diagnostics) => problems.extend(
+                    diagnostics
+                        .into_iter()
+                        .map(|d| error(d.line, d.span, d.message)),
+                ),
+            }
+        }
+        if assembly.is_some()
+            && let Ok(findings) = lint_source(&text, None)
+        {
+            let lines: Vec<&str> = text.lines().collect();
+            problems.extend(findings.into_iter().filter_map(|finding| {
+                let line = finding.line?;
+                let width = lines.get(line - 1).map_or(0, |text| text.chars().count());
+                let indent = lines.get(line - 1).map_or(0, |text| {
+                    text.chars().take_while(|c| c.is_whitespace()).count()
+                });
+                Some(Problem {
+                    line: line - 1,
+                    span: Some(Span {
+                        start: indent,
+                        end: width,
+                    }),
+                    severity: Severity::Warning,
+                    message: finding.message,
+                    code: Some(finding.check.name()),
+                })
+            }));
+        }
+        Document {
+            text,
+            lines,
+            problems,
+            assembly,
+        }
+    }
+
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Where the label under the cursor is defined.
+    pub fn definition(&self, line: usize, column: usize) -> Option<Location> {
+        let Some(Target::Label(name)) = self.target(line, column) else {
+            return None;
+        };
+        self.definition_of(name)
+    }
+
+    /// Every use of the label under the cursor, and its definition if `include_declaration` is set.
+    pub fn references(
+        &self,
+        line: usize,
+        column: usize,
+        include_declaration: bool,
+    ) -> Vec<Location> {
+        let Some(Target::Label(name)) = self.target(line, column) else {
+            return Vec::new();
+        };
+        let mut locations = Vec::new();
+        if include_declaration {
+            locations.extend(self.definition_of(name));
+        }
+        for line in &self.lines {
+            let operands = line.statement.iter().flat_map(|s| &s.operands);
+            for operand in operands {
+                if matches!(&operand.kind, OperandKind::Label(used) if used == name) {
+                    locations.push(Location {
+                        line: line.number - 1,
+                        span: operand.span,
+                    });
+                }
+            }
+        }
+        locations
+    }
+
+    /// Markdown describing what is under the cursor: what an instruction does and how it was
+    /// encoded, or where a label points to.
+    pub fn hover(&self, line: usize, column: usize) -> Option<(String, Location)> {
+        match self.target(line, column)? {
+            Target::Label(name) => {
+                let definition = self.definition_of(name)?;
+                let mut text = format!("**{name}**");
+                if let Some(symbol) = self.assembly.as_ref().and_then(|a| a.symbols.get(name)) {
+                    let _ = write!(text, " = `x{:04X}`", symbol.address);
+                }
+                let _ = write!(text, "\n\nDefined at line {}", definition.line + 1);
+                if let Some(source) = self.text.lines().nth(definition.line) {
+                    let _ = write!(text, ":\n```lc3\n{}\n```", source.trim());
+                }
+                let span = self.span_at(line, column)?;
+                Some((text, Location { line, span }))
+            }
+            Target::Statement(source_line, statement) => {
+                let name = statement.name();
+                let mut text = match describe(&name) {
+                    Some((syntax, semantics)) => format!("**{syntax}**\n\n{semantics}"),
+                    None => format!("**{name}**"),
+                };
+                if let Some(assembly) = &self.assembly
+                    && let Some(placement) = assembly
+                        .placements
+                        .iter()
+                        .find(|p| p.line == source_line.number)
+                {
+                    let words = assembly.words_of(placement);
+                    text.push_str("\n\n");
+                    for (offset, word) in words.iter().enumerate().take(4) {
+                        let _ = writeln!(
+                            text,
+                            "`x{:04X}`: `x{word:04X}` `{}`  ",
+                            placement.address.wrapping_add(offset as u16),
+                            binary(*word)
+                        );
+                    }
+                    if words.len() > 4 {
+                        let _ = writeln!(text, "... ({} words in total)", words.len());
+                    }
+                }
+                Some((
+                    text.trim_end().to_string(),
+                    Location {
+                        line,
+                        span: statement.span,
+                    },
+                ))
+            }
+        }
+    }
+
+    /// Everything that may be written at a position: mnemonics, directives and the labels of the
+    /// document. The editor narrows the list down with what has been typed.
+    pub fn completions(&self) -> Vec<Completion> {
+        let mut completions: Vec<Completion> = MNEMONICS
+            .iter()
+            .map(|mnemonic| Completion {
+                label: mnemonic.to_string(),
+                kind: CompletionKind::Instruction,
+                detail: describe(mnemonic)
+                    .map_or_else(String::new, |(syntax, _)| syntax.to_string()),
+            })
+            .collect();
+        completions.extend(DIRECTIVES.iter().map(|directive| Completion {
+            label: directive.to_string(),
+            kind: CompletionKind::Directive,
+            detail: describe(directive).map_or_else(String::new, |(syntax, _)| syntax.to_string()),
+        }));
+        completions.extend(self.symbols().into_iter().map(|symbol| Completion {
+            detail: match symbol.address {
+                Some(address) => format!("label at x{address:04X}"),
+                None => "label".to_string(),
+            },
+            label: symbol.name,
+            kind: CompletionKind::Label,
+        }));
+        completions
+    }
+
+    /// The labels defined in the document, in source order.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let label = line.label.as_ref()?;
+                // A label alone on its line names whatever comes next.
+                let statement = self.lines[index..]
+                    .iter()
+                    .find_map(|l| l.statement.as_ref());
+                let is_code = !statement.is_some_and(|s| is_data(&s.name()));
+                Some(DocumentSymbol {
+                    name: label.name.clone(),
+                    location: Location {
+                        line: line.number - 1,
+                        span: label.span,
+                    },
+                    is_code,
+                    address: self
+                        .assembly
+                        .as_ref()
+                        .and_then(|a| a.symbols.get(&label.name))
+                        .map(|symbol| symbol.address),
+                })
+            })
+            .collect()
+    }
+
+    fn definition_of(&self, name: &str) -> Option<Location> {
+        self.lines.iter().find_map(|line| {
+            let label = line.label.as_ref().filter(|label| label.name == name)?;
+            Some(Location {
+                line: line.number - 1,
+                span: label.span,
+            })
+        })
+    }
+
+    fn line(&self, line: usize) -> Option<&Line> {
+        self.lines.iter().find(|l| l.number == line + 1)
+    }
+
+    fn target(&self, line: usize, column: usize) -> Option<Target<'_>> {
+        let source_line = self.line(line)?;
+        let contains = |span: Span| span.start <= column && column <= span.end;
+        if let Some(label) = &source_line.label
+            && contains(label.span)
+        {
+            return Some(Target::Label(&label.name));
+        }
+        let statement = source_line.statement.as_ref()?;
+        if contains(statement.span) {
+            return Some(Target::Statement(source_line, statement));
+        }
+        statement
+            .operands
+            .iter()
+            .find_map(|operand| match &operand.kind {
+                OperandKind::Label(name) if contains(operand.span) => Some(Target::Label(name)),
+                _ => None,
+            })
+    }
+
+    fn span_at(&self, line: usize, column: usize) -> Option<Span> {
+        let source_line = self.line(line)?;
+        let contains = |span: &Span| span.start <= column && column <= span.end;
+        source_line
+            .label
+            .iter()
+            .map(|label| label.span)
+            .chain(
+                source_line
+                    .statement
+                    .iter()
+                    .flat_map(|s| s.operands.iter().map(|o| o.span)),
+            )
+            .find(contains)
+    }
+}
+
+fn error(line: usize, span: Option<Span>, message: String) -> Problem {
+    Problem {
+        line: line - 1,
+        span,
+        severity: Severity::Error,
+        message,
+        code: None,
+    }
+}
+
+fn is_data(name: &str) -> bool {
+    matches!(name, ".FILL" | ".BLKW" | ".STRINGZ")
+}
+
+fn binary(word: u16) -> String {
+    let binary = format!("{word:016b}");
+    let nibbles: Vec<&str> = (0..4).map(|i| &binary[i * 4..i * 4 + 4]).collect();
+    nibbles.join(" ")
+}
+
+/// The syntax and the meaning of an instruction or directive (given in upper case).
+pub fn describe(name: &str) -> Option<(&'static str, &'static str)> {
+    let description = match name {
+        "ADD" => (
+            "ADD DR, SR1, SR2 | ADD DR, SR1, imm5",
+            "DR = SR1 + SR2, or SR1 plus the sign-extended 5-bit immediate. Sets the condition codes.",
+        ),
+        "AND" => (
+            "AND DR, SR1, SR2 | AND DR, SR1, imm5",
+            "DR = SR1 & SR2, or SR1 and the sign-extended 5-bit immediate. Sets the condition codes.",
+        ),
+        "NOT" => (
+            "NOT DR, SR",
+            "DR = ~SR (bitwise complement). Sets the condition codes.",
+        ),
+        "JMP" => ("JMP BaseR", "PC = BaseR."),
+        "RET" => (
+            "RET",
+            "PC = R7: returns from a subroutine. The same as `JMP R7`.",
+        ),
+        "JSR" => (
+            "JSR LABEL",
+            "R7 = PC, then PC = PC + the sign-extended 11-bit offset: calls a subroutine.",
+        ),
+        "JSRR" => (
+            "JSRR BaseR",
+            "R7 = PC, then PC = BaseR: calls a subroutine.",
+        ),
+        "LD" => (
+            "LD DR, LABEL",
+            "DR = mem[PC + offset9]. Sets the condition codes.",
+        ),
+        "LDI" => (
+            "LDI DR, LABEL",
+            "DR = mem[mem[PC + offset9]]: loads through a pointer. Sets the condition codes.",
+        ),
+        "LDR" => (
+            "LDR DR, BaseR, offset6",
+            "DR = mem[BaseR + offset6]. Sets the condition codes.",
+        ),
+        "LEA" => (
+            "LEA DR, LABEL",
+            "DR = PC + offset9: the address of the label, without reading memory.",
+        ),
+        "ST" => ("ST SR, LABEL", "mem[PC + offset9] = SR."),
+        "STI" => (
+            "STI SR, LABEL",
+            "mem[mem[PC + offset9]] = SR: stores through a pointer.",
+        ),
+        "STR" => ("STR SR, BaseR, offset6", "mem[BaseR + offset6] = SR."),
+        "RTI" => (
+            "RTI",
+            "Returns from an interrupt. Programs running in user mode cannot use it.",
+        ),
+        "TRAP" => (
+            "TRAP trapvect8",
+            "R7 = PC, then PC = mem[trapvect8]: calls an operating system service routine.",
+        ),
+        "GETC" => (
+            "GETC",
+            "TRAP x20: reads a character from the keyboard into R0, without echoing it.",
+        ),
+        "OUT" => (
+            "OUT",
+            "TRAP x21: writes the character in R0 to the console.",
+        ),
+        "PUTS" => (
+            "PUTS",
+            "TRAP x22: writes the string that starts at the address in R0, one character per word.",
+        ),
+        "IN" => (
+            "IN",
+            "TRAP x23: prompts for a character, echoes it and stores it in R0.",
+        ),
+        "PUTSP" => (
+            "PUTSP",
+            "TRAP x24: writes the string that starts at the address in R0, two characters per word.",
+        ),
+        "HALT" => ("HALT", "TRAP x25: stops the machine."),
+        ".ORIG" => (
+            ".ORIG address",
+            "The program is placed starting at `address`.",
+        ),
+        ".FILL" => (
+            ".FILL value",
+            "One word holding `value` (a number or a label).",
+        ),
+        ".BLKW" => (".BLKW count", "`count` words set to zero."),
+        ".STRINGZ" => (
+            ".STRINGZ \"text\"",
+            "One word per character of `text`, followed by a zero word.",
+        ),
+        ".INCLUDE" => (
+            ".INCLUDE \"file\"",
+            "The lines of `file` go here. `std/<module>.asm` names a module of the standard library; other files are relative to this one.",
+        ),
+        ".END" => (
+            ".END",
+            "The end of the program: the rest of the file is ignored.",
+        ),
+        _ if branch_flags(name).is_some() => (
+            "BR[n][z][p] LABEL",
+            "PC = PC + offset9 if the condition codes match any of the n (negative), z (zero), p (positive) flags given. BR alone branches always.",
+        ),
+        _ => return None,
+    };
+    Some(description)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "\
+        .ORIG x3000
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+        BRp LOOP
+        LEA R0, MSG
+        PUTS
+        HALT
+MSG     .STRINGZ \"Hi\"
+        .END";
+
+    #[test]
+    fn finds_definitions_and_references() {
+        let document = Document::new(SOURCE.to_string());
+        // On `LOOP` in `BRp LOOP`.
+        let definition = document.definition(3, 13).unwrap();
+        assert_eq!(definition.line, 2);
+        assert_eq!(definition.span, Span { start: 0, end: 4 });
+
+        let references = document.references(2, 1, true);
+        let lines: Vec<usize> = references.iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert_eq!(document.references(2, 1, false).len(), 1);
+        assert!(document.definition(1, 9).is_none());
+    }
+
+    #[test]
+    fn describes_instructions_and_labels() {
+        let document = Document::new(SOURCE.to_string());
+        let (text, location) = document.hover(2, 9).unwrap();
+        assert!(text.starts_with("**ADD DR, SR1, SR2"));
+        assert!(text.contains("`x3001`: `x1261` `0001 0010 0110 0001`"));
+        assert_eq!(location.span, Span { start: 8, end: 11 });
+
+        let (text, _) = document.hover(4, 17).unwrap();
+        assert!(text.starts_with("**MSG** = `x3006`"));
+        assert!(text.contains("MSG     .STRINGZ \"Hi\""));
+    }
+
+    #[test]
+    fn reports_problems_and_symbols() {
+        let document = Document::new(SOURCE.replace("BRp LOOP", "BRp LOP"));
+        assert_eq!(document.problems().len(), 1);
+        assert_eq!(document.problems()[0].line, 3);
+        assert_eq!(document.problems()[0].severity, Severity::Error);
+
+        let document = Document::new(SOURCE.replace("AND R1, R1, #0", "ADD R1, R2, #0"));
+        let warning = &document.problems()[0];
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.code, Some("uninitialized-register"));
+
+        let symbols = document.symbols();
+        let found: Vec<(&str, bool, Option<u16>)> = symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.is_code, s.address))
+            .collect();
+        assert_eq!(
+            found,
+            vec![("LOOP", true, Some(0x3001)), ("MSG", false, Some(0x3006))]
+        );
+        let completions = document.completions();
+        assert!(completions.iter().any(|c| c.label == ".STRINGZ"));
+        assert!(
+            completions
+                .iter()
+                .any(|c| c.label == "MSG" && c.detail == "label at x3006")
+        );
+    }
+}
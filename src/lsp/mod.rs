@@ -0,0 +1,474 @@
+//! Language server for LC-3 assembly, speaking the Language Server Protocol over stdio.
+//!
+//! Documents are analysed with the assembler and the linter every time they change (`analysis`), and
+//! the server answers from that analysis: diagnostics, go to definition, find references, hover,
+//! completion and document symbols. Columns are counted in characters, which is what the protocol
+//! expects for the ASCII sources LC-3 programs are written in.
+pub mod analysis;
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    assembler::lexer::Span,
+    error::VMError,
+    json::Json,
+    lsp::analysis::{CompletionKind, Document, Location, Severity},
+};
+
+/// Error codes defined by JSON-RPC and the protocol.
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Serves the editor connected to `input` and `output` until it sends `exit` or closes the input.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> Result<(), VMError> {
+    let mut server = Server {
+        documents: BTreeMap::new(),
+        output,
+        shutting_down: false,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        match message.get("id") {
+            Some(id) => server.request(id, method, params)?,
+            None if method == "exit" => break,
+            None => server.notification(method, params)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reads one message: headers, an empty line and a body of `Content-Length` bytes. Returns `None` at
+/// the end of the input. The debug adapter frames its messages the same way.
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, VMError> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        let read = input
+            .read_line(&mut header)
+            .map_err(|e| VMError::ProtocolError(e.to_string()))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| VMError::ProtocolError("missing the Content-Length header".to_string()))?;
+    let mut body = vec![0; length];
+    input
+        .read_exact(&mut body)
+        .map_err(|e| VMError::ProtocolError(e.to_string()))?;
+    let body = String::from_utf8(body).map_err(|e| VMError::ProtocolError(e.to_string()))?;
+    Json::parse(&body).map(Some).map_err(VMError::ProtocolError)
+}
+
+struct Server<W: Write> {
+    /// Open documents by URI.
+    documents: BTreeMap<String, Document>,
+    output: W,
+    shutting_down: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Json) -> Result<(), VMError> {
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| VMError::ProtocolError(e.to_string()))
+    }
+
+    fn request(&mut self, id: &Json, method: &str, params: &Json) -> Result<(), VMError> {
+        let result = if self.shutting_down {
+            Err((INVALID_REQUEST, "the server is shutting down".to_string()))
+        } else {
+            self.answer(method, params)
+        };
+        let response = match result {
+            Ok(result) => Json::object([
+                ("jsonrpc", "2.0".into()),
+                ("id", id.clone()),
+                ("result", result),
+            ]),
+            Err((code, message)) => Json::object([
+                ("jsonrpc", "2.0".into()),
+                ("id", id.clone()),
+                (
+                    "error",
+                    Json::object([("code", Json::Number(code)), ("message", message.into())]),
+                ),
+            ]),
+        };
+        self.send(response)
+    }
+
+    fn answer(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shutting_down = true;
+                Json::Null
+            }
+            "textDocument/definition" => {
+                let (uri, document) = self.document(params)?;
+                let (line, column) = position(params);
+                document
+                    .definition(line, column)
+                    .map_or(Json::Null, |location| location_json(uri, location))
+            }
+            "textDocument/references" => {
+                let (uri, document) = self.document(params)?;
+                let (line, column) = position(params);
+                let include_declaration = params
+                    .get("context")
+                    .and_then(|c| c.get("includeDeclaration"))
+                    .and_then(Json::as_bool)
+                    .unwrap_or(false);
+                Json::Array(
+                    document
+                        .references(line, column, include_declaration)
+                        .into_iter()
+                        .map(|location| location_json(uri, location))
+                        .collect(),
+                )
+            }
+            "textDocument/hover" => {
+                let (_, document) = self.document(params)?;
+                let (line, column) = position(params);
+                match document.hover(line, column) {
+                    Some((text, location)) => Json::object([
+                        (
+                            "contents",
+                            Json::object([("kind", "markdown".into()), ("value", text.into())]),
+                        ),
+                        ("range", range(location.line, location.span)),
+                    ]),
+                    None => Json::Null,
+                }
+            }
+            "textDocument/completion" => {
+                let (_, document) = self.document(params)?;
+                Json::Array(
+                    document
+                        .completions()
+                        .into_iter()
+                        .map(|completion| {
+                            // Keyword for instructions and directives, Variable for labels, in the
+                            // protocol's CompletionItemKind.
+                            let kind = match completion.kind {
+                                CompletionKind::Instruction | CompletionKind::Directive => 14,
+                                CompletionKind::Label => 6,
+                            };
+                            Json::object([
+                                ("label", completion.label.into()),
+                                ("kind", Json::Number(kind)),
+                                ("detail", completion.detail.into()),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            "textDocument/documentSymbol" => {
+                let (_, document) = self.document(params)?;
+                Json::Array(
+                    document
+                        .symbols()
+                        .into_iter()
+                        .map(|symbol| {
+                            // Function or Variable in the protocol's SymbolKind.
+                            let kind = if symbol.is_code { 12 } else { 13 };
+                            let detail = symbol
+                                .address
+                                .map_or_else(String::new, |address| format!("x{address:04X}"));
+                            let range = range(symbol.location.line, symbol.location.span);
+                            Json::object([
+                                ("name", symbol.name.into()),
+                                ("detail", detail.into()),
+                                ("kind", Json::Number(kind)),
+                                ("range", range.clone()),
+                                ("selectionRange", range),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            _ => return Err((METHOD_NOT_FOUND, format!("unknown method `{method}`"))),
+        };
+        Ok(result)
+    }
+
+    /// The open document the request in `params` is about, with its URI.
+    fn document<'a>(&self, params: &'a Json) -> Result<(&'a str, &Document), (i64, String)> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|d| d.get("uri"))
+            .and_then(Json::as_str)
+            .ok_or((INVALID_PARAMS, "missing textDocument.uri".to_string()))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or((INVALID_PARAMS, format!("{uri} is not open")))?;
+        Ok((uri, document))
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Result<(), VMError> {
+        let document = params.get("textDocument");
+        let Some(uri) = document.and_then(|d| d.get("uri")).and_then(Json::as_str) else {
+            return Ok(());
+        };
+        let uri = uri.to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = document
+                    .and_then(|d| d.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or_default();
+                self.documents
+                    .insert(uri.clone(), Document::new(text.to_string()));
+            }
+            "textDocument/didChange" => {
+                // The server asks for full synchronisation, so the last change holds the whole text.
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                if let Some(text) = text {
+                    self.documents
+                        .insert(uri.clone(), Document::new(text.to_string()));
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+            }
+            _ => return Ok(()),
+        }
+        self.publish_diagnostics(&uri)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> Result<(), VMError> {
+        let diagnostics = self.documents.get(uri).map_or_else(Vec::new, |document| {
+            let lines: Vec<&str> = document.text.lines().collect();
+            document
+                .problems()
+                .iter()
+                .map(|problem| {
+                    let span = problem.span.unwrap_or(Span {
+                        start: 0,
+                        end: lines.get(problem.line).map_or(0, |l| l.chars().count()),
+                    });
+                    let severity = match problem.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    };
+                    let mut diagnostic = Json::object([
+                        ("range", range(problem.line, span)),
+                        ("severity", Json::Number(severity)),
+                        (
+                            "source",
+                            if problem.code.is_some() {
+                                "lc3-lint"
+                            } else {
+                                "lc3-assembler"
+                            }
+                            .into(),
+                        ),
+                        ("message", problem.message.clone().into()),
+                    ]);
+                    if let (Some(code), Json::Object(fields)) = (problem.code, &mut diagnostic) {
+                        fields.push(("code".to_string(), code.into()));
+                    }
+                    diagnostic
+                })
+                .collect()
+        });
+        self.send(Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                Json::object([
+                    ("uri", uri.into()),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ]))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // Full document synchronisation.
+                ("textDocumentSync", Json::Number(1)),
+                ("definitionProvider", true.into()),
+                ("referencesProvider", true.into()),
+                ("hoverProvider", true.into()),
+                (
+                    "completionProvider",
+                    Json::object([("triggerCharacters", Json::Array(vec![".".into()]))]),
+                ),
+                ("documentSymbolProvider", true.into()),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", "basic-vm".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+fn range(line: usize, span: Span) -> Json {
+    let position =
+        |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    Json::object([("start", position(span.start)), ("end", position(span.end))])
+}
+
+/// The line and column of the `position` in `params`, 0 when not given.
+fn position(params: &Json) -> (usize, usize) {
+    let position = params.get("position");
+    let line = position
+        .and_then(|p| p.get("line"))
+        .and_then(Json::as_i64)
+        .unwrap_or(0) as usize;
+    let column = position
+        .and_then(|p| p.get("character"))
+        .and_then(Json::as_i64)
+        .unwrap_or(0) as usize;
+    (line, column)
+}
+
+fn location_json(uri: &str, location: Location) -> Json {
+    Json::object([
+        ("uri", uri.into()),
+        ("range", range(location.line, location.span)),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(message: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{message}", message.len())
+    }
+
+    /// Runs the server over `messages` and returns everything it sent.
+    fn session(messages: &[&str]) -> Vec<Json> {
+        let input: String = messages.iter().map(|m| frame(m)).collect();
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut sent = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            sent.push(message);
+        }
+        sent
+    }
+
+    #[test]
+    fn answers_an_editor_session() {
+        let sent = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.asm","languageId":"lc3","version":1,"text":".ORIG x3000\nLOOP BRnzp LOOP\nBR NOWHERE\n.END\n"}}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.asm","version":2},"contentChanges":[{"text":".ORIG x3000\nLOOP BRnzp LOOP\n.END\n"}]}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.asm"},"position":{"line":1,"character":12}}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.asm"}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.asm"}}}"#,
+            r#"{"jsonrpc":"2.0","id":5,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+        assert_eq!(sent.len(), 7);
+        let capabilities = sent[0].get("result").unwrap().get("capabilities").unwrap();
+        assert_eq!(
+            capabilities.get("hoverProvider").and_then(Json::as_bool),
+            Some(true)
+        );
+
+        // Diagnostics after opening, and again after fixing the undefined label.
+        let diagnostics = |message: &Json| {
+            message
+                .get("params")
+                .and_then(|p| p.get("diagnostics"))
+                .and_then(Json::as_array)
+                .map(<[Json]>::len)
+        };
+        assert_eq!(diagnostics(&sent[1]), Some(1));
+        assert_eq!(diagnostics(&sent[2]), Some(0));
+
+        assert_eq!(
+            sent[3].get("result").unwrap().to_string(),
+            r#"{"uri":"file:///a.asm","range":{"start":{"line":1,"character":0},"end":{"line":1,"character":4}}}"#
+        );
+        let symbols = sent[4].get("result").and_then(Json::as_array).unwrap();
+        assert_eq!(symbols[0].get("name").and_then(Json::as_str), Some("LOOP"));
+        assert_eq!(
+            sent[5]
+                .get("error")
+                .and_then(|e| e.get("code"))
+                .and_then(Json::as_i64),
+            Some(METHOD_NOT_FOUND)
+        );
+        assert_eq!(sent[6].get("result"), Some(&Json::Null));
+    }
+
+    #[test]
+    fn tells_unknown_methods_from_missing_params() {
+        let sent = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"workspace/symbol"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover"}"#,
+        ]);
+        let code = |message: &Json| {
+            message
+                .get("error")
+                .and_then(|e| e.get("code"))
+                .and_then(Json::as_i64)
+        };
+        assert_eq!(code(&sent[0]), Some(METHOD_NOT_FOUND));
+        assert_eq!(code(&sent[1]), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn gives_codes_to_lint_diagnostics_only() {
+        let sent = session(&[
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.asm","text":".ORIG x3000\nHALT\nDATA .FILL 1\n.END\n"}}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///b.asm","text":".ORIG x3000\nBR NOWHERE\n.END\n"}}}"#,
+        ]);
+        let diagnostic = |message: &Json| {
+            message
+                .get("params")
+                .and_then(|p| p.get("diagnostics"))
+                .and_then(Json::as_array)
+                .and_then(|diagnostics| diagnostics.first())
+                .cloned()
+                .unwrap()
+        };
+        let lint = diagnostic(&sent[0]);
+        assert_eq!(lint.get("source").and_then(Json::as_str), Some("lc3-lint"));
+        assert!(lint.get("code").and_then(Json::as_str).is_some());
+        let error = diagnostic(&sent[1]);
+        assert_eq!(
+            error.get("source").and_then(Json::as_str),
+            Some("lc3-assembler")
+        );
+        assert_eq!(error.get("code"), None);
+    }
+}
@@ -1,39 +1,57 @@
 use std::env;
 
+mod assembler;
+mod callstack;
+mod cli;
+mod compiler;
+mod console;
+mod debugger;
+mod decompiler;
+mod disassembler;
 mod error;
 mod flags;
+mod history;
+mod image;
+mod instruction;
+mod json;
+mod lint;
+mod lsp;
 mod opcodes;
 mod operations;
+mod optimizer;
+mod recording;
 mod registers;
+mod repl;
+mod stdlib;
 mod utils;
 mod vm;
+mod watch;
+mod watchpoint;
 
 use crate::error::VMError;
 
-use crate::utils::read_file;
 use crate::vm::VMState;
 
 fn main() -> Result<(), VMError> {
-    let expected_arguments_len = 2;
-
-    // Get terminal arguments to obtain the path to the binary file to be executed.
+    // Get terminal arguments. The first one is the path of the executable itself, the second one is either
+    // a subcommand or the path to the binary file to be executed.
     let console_args: Vec<_> = env::args().collect();
-    // Arguments length must be two - the first argument is for cargo and the second should be the path.
-    if console_args.len() != expected_arguments_len {
-        return Err(VMError::WrongArgumentsLen(
-            expected_arguments_len,
-            console_args.len(),
-        ));
+    match console_args.get(1).map(String::as_str) {
+        Some("assemble") => cli::assemble_command(&console_args[2..]),
+        Some("decompile") => cli::decompile_command(&console_args[2..]),
+        Some("lint") => cli::lint_command(&console_args[2..]),
+        Some("fmt") => cli::fmt_command(&console_args[2..]),
+        Some("lsp") => cli::lsp_command(&console_args[2..]),
+        Some("opt") => cli::opt_command(&console_args[2..]),
+        Some("compile") => cli::compile_command(&console_args[2..]),
+        Some("stdlib") => cli::stdlib_command(&console_args[2..]),
+        Some("repl") => cli::repl_command(&console_args[2..]),
+        Some("debug") => cli::debug_command(&console_args[2..]),
+        Some("dap") => cli::dap_command(&console_args[2..]),
+        Some("query") => cli::query_command(&console_args[2..]),
+        Some("trace-diff") => cli::trace_diff_command(&console_args[2..]),
+        Some("run") => cli::run_command(&console_args[2..]),
+        // Without a subcommand, the arguments are those of `run`.
+        _ => cli::run_command(&console_args[1..]),
     }
-    let path = console_args[1].clone();
-
-    // Read the file.
-    let file = read_file(&path)?;
-
-    // Initialize VM state with default values
-    let mut vm = VMState::init()?;
-
-    vm.run(file)?;
-
-    Ok(())
 }
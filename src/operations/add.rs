@@ -1,8 +1,4 @@
-use crate::{
-    VMState,
-    error::VMError,
-    operations::utils::{sign_extend, update_flags},
-};
+use crate::{VMState, error::VMError, instruction::Operand, operations::utils::update_flags};
 
 /// Handler for instruction ADD, that adds two numbers and stores the result in the destination register.
 /// ADD supports two modes: immediate mode, where the second addend is given by the instruction itself
@@ -13,18 +9,16 @@ use crate::{
 //     Register mode:
 //         | ADD opcode (0001) | destination reg | first addend reg | imm flag (0) | unused | second addend reg |
 //         |   4 bits          |      3 bits     |    3 bits        | 1 bit        | 2 bits |   3 bits          |
-pub fn handle_add(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
-    let dest_reg = ((instruction >> 9) & 0x7) as usize;
-    let src_reg_1 = ((instruction >> 6) & 0x7) as usize;
-    let imm_mode = ((instruction >> 5) & 0x1) > 0;
-    if imm_mode {
-        // Immediate mode
-        let imm_operand = sign_extend(instruction & 0x1F, 5);
-        vm.registers[dest_reg] = vm.registers[src_reg_1].wrapping_add(imm_operand);
-    } else {
-        // Register mode
-        let src_reg_2 = (instruction & 0x7) as usize;
-        vm.registers[dest_reg] = vm.registers[src_reg_1].wrapping_add(vm.registers[src_reg_2]);
+pub fn handle_add(dest: u8, src: u8, operand: Operand, vm: &mut VMState) -> Result<(), VMError> {
+    let (dest_reg, src_reg_1) = (dest as usize, src as usize);
+    match operand {
+        Operand::Immediate(imm_operand) => {
+            vm.registers[dest_reg] = vm.registers[src_reg_1].wrapping_add(imm_operand as u16);
+        }
+        Operand::Register(src_reg_2) => {
+            vm.registers[dest_reg] =
+                vm.registers[src_reg_1].wrapping_add(vm.registers[src_reg_2 as usize]);
+        }
     }
     update_flags(vm, vm.registers[dest_reg])?;
     Ok(())
@@ -40,14 +34,12 @@ mod test {
     #[test]
     fn add_register_mode_pos() {
         let mut vm = VMState::init().unwrap();
-        // ADD  R0  R1  RegMode Emtpy bits  R2
-        // 0001 000 001 0       00          010
-        let add_ix: u16 = 0x1042;
+        // ADD R0, R1, R2
         vm.registers[Register::R1] = 30;
         vm.registers[Register::R2] = 25;
         assert_eq!(vm.registers[Register::R0], 0);
         assert_eq!(vm.registers[Register::Cond], Flag::Zro.try_into().unwrap());
-        let res = handle_add(add_ix, &mut vm);
+        let res = handle_add(0, 1, Operand::Register(2), &mut vm);
         assert!(res.is_ok());
         assert_eq!(vm.registers[Register::R0], 55);
         assert_eq!(vm.registers[Register::Cond], Flag::Pos.try_into().unwrap());
@@ -56,14 +48,13 @@ mod test {
     #[test]
     fn add_register_mode_neg() {
         let mut vm = VMState::init().unwrap();
-        let add_ix: u16 = 0x1042;
         vm.registers[Register::R1] = 30;
         // The complement of -20
         vm.registers[Register::R2] = 65516;
 
         assert_eq!(vm.registers[Register::R0], 0);
         assert_eq!(vm.registers[Register::Cond], Flag::Zro.try_into().unwrap());
-        let res = handle_add(add_ix, &mut vm);
+        let res = handle_add(0, 1, Operand::Register(2), &mut vm);
         assert!(res.is_ok());
         // The result of 30 + (-20)
         assert_eq!(vm.registers[Register::R0], 10);
@@ -73,13 +64,12 @@ mod test {
     #[test]
     fn add_register_mode_neg_2() {
         let mut vm = VMState::init().unwrap();
-        let add_ix: u16 = 0x1042;
         vm.registers[Register::R1] = 30;
         // The complement of -150
         vm.registers[Register::R2] = 65386;
         assert_eq!(vm.registers[Register::R0], 0);
         assert_eq!(vm.registers[Register::Cond], Flag::Zro.try_into().unwrap());
-        let res = handle_add(add_ix, &mut vm);
+        let res = handle_add(0, 1, Operand::Register(2), &mut vm);
         assert!(res.is_ok());
         // The complement of -120 which is the result of 30 + (-150)
         assert_eq!(vm.registers[Register::R0], 65416);
@@ -90,11 +80,9 @@ mod test {
     #[test]
     fn add_immediate_mode_pos() {
         let mut vm = VMState::init().unwrap();
-        // ADD  R2  R4  IMM 10
-        // 0001 010 100 1   01010
-        let add_ix: u16 = 0x152A;
+        // ADD R2, R4, #10
         vm.registers[Register::R4] = 20;
-        let res = handle_add(add_ix, &mut vm);
+        let res = handle_add(2, 4, Operand::Immediate(10), &mut vm);
         assert!(res.is_ok());
         assert_eq!(vm.registers[Register::R2], 30);
     }
@@ -102,11 +90,9 @@ mod test {
     #[test]
     fn add_immediate_mode_neg() {
         let mut vm = VMState::init().unwrap();
-        // ADD  R2  R4  IMM -12 (complemento)
-        // 0001 010 100 1   10100
-        let add_ix: u16 = 0x1534;
+        // ADD R2, R4, #-12
         vm.registers[Register::R4] = 20;
-        let res = handle_add(add_ix, &mut vm);
+        let res = handle_add(2, 4, Operand::Immediate(-12), &mut vm);
         assert!(res.is_ok());
         assert_eq!(vm.registers[Register::R2], 8);
     }
@@ -1,10 +1,4 @@
-use std::cmp::Ordering;
-
-use crate::{
-    VMState,
-    error::VMError,
-    operations::utils::{sign_extend, update_flags},
-};
+use crate::{VMState, error::VMError, instruction::Operand, operations::utils::update_flags};
 
 /// Handler for instruction AND, that performs _bitwise and_ of two numbers and stores the result in the destination register.
 /// AND supports two modes: immediate mode, where the second operand is given by the instruction itself, and register mode,
@@ -15,17 +9,11 @@ use crate::{
 ///     Register mode:
 ///         | AND opcode (0101) | destination reg | 1st operand reg | imm flag (0) | unused | 2nd operand reg |
 ///         |   4 bits          |      3 bits     |    3 bits       | 1 bit        | 2 bits |   3 bits        |
-pub fn handle_and(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
-    let dest_reg = ((instruction >> 9) & 0x7) as usize;
-    let src_reg_1 = ((instruction >> 6) & 0x7) as usize;
-    let imm_mode = (instruction >> 5) & 0x1;
-
-    let second_value = match imm_mode.cmp(&0) {
-        Ordering::Greater => sign_extend(instruction & 0x1F, 5),
-        _ => {
-            let src_reg_2 = (instruction & 0x7) as usize;
-            vm.registers[src_reg_2]
-        }
+pub fn handle_and(dest: u8, src: u8, operand: Operand, vm: &mut VMState) -> Result<(), VMError> {
+    let (dest_reg, src_reg_1) = (dest as usize, src as usize);
+    let second_value = match operand {
+        Operand::Immediate(value) => value as u16,
+        Operand::Register(src_reg_2) => vm.registers[src_reg_2 as usize],
     };
     vm.registers[dest_reg] = vm.registers[src_reg_1] & second_value;
     update_flags(vm, vm.registers[dest_reg])?;
@@ -34,17 +22,15 @@ pub fn handle_and(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
 
 #[cfg(test)]
 mod test {
-    use crate::{VMState, operations::and::handle_and, registers::Register};
+    use crate::{VMState, instruction::Operand, operations::and::handle_and, registers::Register};
 
     #[test]
     fn immediate_mode_and() {
         let mut vm = VMState::init().unwrap();
-        // AND  R5  R6  IMM 3
-        // 0101 101 110 1   00011
-        let and_ix = 0x5BA3;
+        // AND R5, R6, #3
         vm.registers[Register::R6] = 10;
 
-        let res = handle_and(and_ix, &mut vm);
+        let res = handle_and(5, 6, Operand::Immediate(3), &mut vm);
         assert!(res.is_ok());
         // 10 & 3 =
         // 0000 0000 0000 1010
@@ -56,13 +42,11 @@ mod test {
     #[test]
     fn register_mode_and() {
         let mut vm = VMState::init().unwrap();
-        // AND  R0  R1  REG Unused R2
-        // 0101 000 001 0   00     010
-        let and_ix = 0x5042;
+        // AND R0, R1, R2
         vm.registers[Register::R1] = 1234;
         vm.registers[Register::R2] = 734;
 
-        let res = handle_and(and_ix, &mut vm);
+        let res = handle_and(0, 1, Operand::Register(2), &mut vm);
         assert!(res.is_ok());
 
         // 1234 0000 0100 1101 0010
@@ -1,19 +1,14 @@
-use crate::{
-    VMState,
-    error::VMError,
-    operations::utils::{sign_extend, update_flags},
-    registers::Register,
-};
+use crate::{VMState, error::VMError, operations::utils::update_flags, registers::Register};
 
 /// Handler for instruction LOAD, that loads the content of a calculated memory address
 /// into the destination register. The calculated address is obtained by adding the PC offset
 /// to the current PC content.
 //         | LD opcode (0010) | destination reg | PC offset |
 //         |   4 bits         |     3 bits      |   9 bits  |
-pub fn handle_ld(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
-    let dest_reg = ((instruction >> 9) & 0x7) as usize;
-    let pc_offset = sign_extend(instruction & 0x1FF, 9);
-    vm.registers[dest_reg] = vm.mem_read(vm.registers[Register::PC].wrapping_add(pc_offset))?;
+pub fn handle_ld(dest: u8, pc_offset: i16, vm: &mut VMState) -> Result<(), VMError> {
+    let dest_reg = dest as usize;
+    vm.registers[dest_reg] =
+        vm.mem_read(vm.registers[Register::PC].wrapping_add(pc_offset as u16))?;
     update_flags(vm, vm.registers[dest_reg])?;
     Ok(())
 }
@@ -29,11 +24,9 @@ mod test {
         let memory_address = pc_value.wrapping_add(offset_u16);
         let mut vm = VMState::init().unwrap();
         vm.mem_write(memory_address, 50);
-        // LD   DestReg PCOffset
-        // 0010 001     000000100
-        let ld_ix: u16 = 0x2204;
+        // LD R1, #4
         assert_eq!(vm.registers[Register::PC], 0x3000); // Default init value for PC
-        let res = handle_ld(ld_ix, &mut vm);
+        let res = handle_ld(1, 4, &mut vm);
         assert!(res.is_ok());
         assert_eq!(vm.registers[Register::R1], 50);
     }
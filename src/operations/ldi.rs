@@ -1,19 +1,13 @@
-use crate::{
-    VMState,
-    error::VMError,
-    operations::utils::{sign_extend, update_flags},
-    registers::Register,
-};
+use crate::{VMState, error::VMError, operations::utils::update_flags, registers::Register};
 
 /// Handler for instruction LOAD INDIRECT. This instruction calculates a memory address
 /// by adding the PC offset to the current PC content. The content of this calculated address
 /// will be the memory address of the actual content to be loaded to the destination register.
 //         | LDI opcode (1010) | destination reg | PC offset |
 //         |   4 bits          |     3 bits      |   9 bits  |
-pub fn handle_ldi(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
-    let dest_reg = ((instruction >> 9) & 0x7) as usize;
-    let pc_offset = sign_extend(instruction & 0x1FF, 9);
-    let first_addr = vm.registers[Register::PC].wrapping_add(pc_offset);
+pub fn handle_ldi(dest: u8, pc_offset: i16, vm: &mut VMState) -> Result<(), VMError> {
+    let dest_reg = dest as usize;
+    let first_addr = vm.registers[Register::PC].wrapping_add(pc_offset as u16);
     let final_addr = vm.mem_read(first_addr)?;
     vm.registers[dest_reg] = vm.mem_read(final_addr)?;
     update_flags(vm, vm.registers[dest_reg])?;
@@ -38,10 +32,8 @@ mod test {
 
         assert_eq!(vm.registers[Register::PC], pc_content);
         assert_eq!(vm.registers[Register::R1], 0); // Still has nothing
-        // LDI  DestReg PCOffset
-        // 1010 001     011111111
-        let ldi_ix = 0xA2FF;
-        let res = handle_ldi(ldi_ix, &mut vm);
+        // LDI R1, #255
+        let res = handle_ldi(1, 0xFF, &mut vm);
         assert!(res.is_ok());
         assert_eq!(vm.registers[Register::R1], random_content);
     }
@@ -4,9 +4,8 @@ use crate::{VMState, error::VMError, operations::utils::update_flags};
 /// stores its result in the destination register.
 //         | NOT opcode (1001) | destination reg | source reg | unused |
 //         |   4 bits          |     3 bits      |   3 bits   | 6 bits |
-pub fn handle_not(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
-    let dest_reg = ((instruction >> 9) & 0x7) as usize;
-    let src_reg = ((instruction >> 6) & 0x7) as usize;
+pub fn handle_not(dest: u8, src: u8, vm: &mut VMState) -> Result<(), VMError> {
+    let (dest_reg, src_reg) = (dest as usize, src as usize);
     vm.registers[dest_reg] = !vm.registers[src_reg];
     update_flags(vm, vm.registers[dest_reg])?;
     Ok(())
@@ -20,11 +19,9 @@ mod test {
     #[test]
     fn inverts_register_with_not() {
         let mut vm = VMState::init().unwrap();
-        // NOT  R1  R1  FILL
-        // 1001 001 001 111111
-        let not_ix = 0x927F;
+        // NOT R1, R1
         vm.registers[Register::R1] = 0;
-        let res = handle_not(not_ix, &mut vm);
+        let res = handle_not(1, 1, &mut vm);
         assert!(res.is_ok());
         //  0 - 0000 0000 0000 0000
         // !0 - 1111 1111 1111 1111 - MAX VALUE 2^16 - 1 = 65.535
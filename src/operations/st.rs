@@ -1,16 +1,14 @@
-use crate::{VMState, error::VMError, operations::utils::sign_extend, registers::Register};
+use crate::{VMState, error::VMError, registers::Register};
 
 /// Handler for instruction STORE. The content in the source register gets stored in
 /// the calculated address obtained by adding the PC offset given by the instruction to
 /// the current PC content.
 //         | ST opcode (0011) | source reg | PC offset |
 //         |   4 bits         |  3 bits    |   9 bits  |
-pub fn handle_st(instruction: u16, vm: &mut VMState) -> Result<(), VMError> {
-    let src_reg = ((instruction >> 9) & 0x7) as usize;
-    let pc_offset = sign_extend(instruction & 0x1FF, 9);
+pub fn handle_st(src: u8, pc_offset: i16, vm: &mut VMState) -> Result<(), VMError> {
     vm.mem_write(
-        vm.registers[Register::PC].wrapping_add(pc_offset),
-        vm.registers[src_reg],
+        vm.registers[Register::PC].wrapping_add(pc_offset as u16),
+        vm.registers[src as usize],
     );
     Ok(())
 }
@@ -28,11 +26,9 @@ mod test {
         let pc_offset = 10;
         let pc_content = vm.registers[Register::PC];
 
-        // ST   src_reg pc_offset
-        // 0011 011     000001010
-        let st_ix = 0x360A;
+        // ST R3, #10
         assert_eq!(vm.mem_read(pc_content.wrapping_add(pc_offset)).unwrap(), 0); // Memory Address is empty
-        let res = handle_st(st_ix, &mut vm);
+        let res = handle_st(3, 10, &mut vm);
         assert!(res.is_ok());
         assert_eq!(
             vm.mem_read(pc_content.wrapping_add(pc_offset)).unwrap(),
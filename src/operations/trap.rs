@@ -1,20 +1,18 @@
-use crate::{
-    VMState, error::VMError, operations::utils::update_flags, registers::Register, utils::get_char,
-};
+use crate::{VMState, error::VMError, operations::utils::update_flags, registers::Register};
 
 /// Handler for instruction TRAP, that is related with I/O interactions. There are
 /// different types of traps that are executed differently.
 //         | TRAP opcode (1111)| unused | Trap Type |
 //         |   4 bits          | 4 bits | 8 bits    |
-pub fn handle_trap(instruction: u16, vm: &mut VMState, running: &mut bool) -> Result<(), VMError> {
-    match TrapCode::try_from(instruction & 0xFF)? {
+pub fn handle_trap(vector: u8, vm: &mut VMState, running: &mut bool) -> Result<(), VMError> {
+    match TrapCode::try_from(vector as u16)? {
         TrapCode::Getc => handle_getc(vm)?,
         TrapCode::Out => handle_out(vm)?,
         TrapCode::PutSp => handle_putsp(vm)?,
         TrapCode::In => handle_in(vm)?,
         TrapCode::Puts => handle_puts(vm)?,
         TrapCode::Halt => {
-            println!("Halt execution");
+            print_str(vm, "Halt execution\n")?;
             *running = false;
         }
     }
@@ -53,9 +51,9 @@ impl TryFrom<u16> for TrapCode {
     }
 }
 
-/// Gets a character from standard input and stores it in R0.
+/// Gets a character from the keyboard and stores it in R0.
 fn handle_getc(vm: &mut VMState) -> Result<(), VMError> {
-    let char = get_char()?;
+    let char = vm.console.read_char()?;
     vm.registers[Register::R0] = char;
     update_flags(vm, char)?;
     Ok(())
@@ -64,8 +62,7 @@ fn handle_getc(vm: &mut VMState) -> Result<(), VMError> {
 /// Prints the character stored in the first byte of R0.
 fn handle_out(vm: &mut VMState) -> Result<(), VMError> {
     let char = vm.registers[Register::R0].to_le_bytes()[0];
-    print_char(char);
-    Ok(())
+    vm.console.write_char(char)
 }
 
 /// Prints two characters per memory address, one per each byte.
@@ -74,9 +71,9 @@ fn handle_putsp(vm: &mut VMState) -> Result<(), VMError> {
     let mut content = vm.mem_read(memory_address)?;
     while content != 0 {
         let bytes: [u8; 2] = content.to_le_bytes();
-        print_char(bytes[0]);
+        vm.console.write_char(bytes[0])?;
         if bytes[1] != b'\0' {
-            print_char(bytes[1]);
+            vm.console.write_char(bytes[1])?;
         }
         memory_address = memory_address.wrapping_add(1);
         content = vm.mem_read(memory_address)?;
@@ -84,12 +81,12 @@ fn handle_putsp(vm: &mut VMState) -> Result<(), VMError> {
     Ok(())
 }
 
-/// Gets a character from standard input echoing it to terminal.
+/// Gets a character from the keyboard echoing it to the display.
 fn handle_in(vm: &mut VMState) -> Result<(), VMError> {
-    print!("\n\rEnter a character: \n\r");
-    let char = get_char()?;
+    print_str(vm, "\nEnter a character: \n")?;
+    let char = vm.console.read_char()?;
     vm.registers[Register::R0] = char;
-    print!("{}", char as u8 as char);
+    vm.console.write_char(char as u8)?;
     update_flags(vm, char)?;
     Ok(())
 }
@@ -99,17 +96,15 @@ fn handle_puts(vm: &mut VMState) -> Result<(), VMError> {
     let mut memory_address = vm.registers[Register::R0];
     let mut content = vm.mem_read(memory_address)?;
     while content != 0 {
-        print_char(content.to_le_bytes()[0]);
+        vm.console.write_char(content.to_le_bytes()[0])?;
         memory_address = memory_address.wrapping_add(1);
         content = vm.mem_read(memory_address)?;
     }
     Ok(())
 }
 
-fn print_char(char: u8) {
-    if char == 0x0A {
-        print!("\n\r");
-    } else {
-        print!("{}", char as char);
-    }
+/// Writes `text` to the VM's console.
+pub fn print_str(vm: &mut VMState, text: &str) -> Result<(), VMError> {
+    text.bytes()
+        .try_for_each(|char| vm.console.write_char(char))
 }
@@ -0,0 +1,281 @@
+//! Peephole optimizer for LC-3 programs.
+//!
+//! It works on the lines the assembler parses: object files are first turned back into source with
+//! the disassembler. Rewrites (`rules`) are applied one at a time until none matches, and every one
+//! keeps what the program does:
+//! - `ADD Rx, Rx, #0` right after an instruction that set the condition codes from Rx is removed;
+//! - chains of `ADD Rx, Rx, #n` are merged into the ADD before them while the sum fits;
+//! - branches to the next instruction and instructions after an unconditional jump are removed;
+//! - branches to an unconditional `BR` go to its target directly, and `BRnzp` to a `RET` returns.
+//!
+//! Removing instructions moves the code after them, which is only safe when every reference to an
+//! address goes through a label. Programs with numeric PC offsets, and object files with data that
+//! may point into the program or with jumps through registers, only get the rewrites that keep
+//! every word in place.
+pub mod rules;
+
+use std::path::Path;
+
+use crate::{
+    assembler::{
+        Assembly, Diagnostic,
+        encoder::assemble_lines_in,
+        formatter::format_lines,
+        parser::{Line, OperandKind, Statement, branch_flags, parse},
+    },
+    disassembler::{disassemble_image, reachable_code},
+    image::Image,
+    instruction::Instruction,
+    lint::flow::{Flow, effects},
+    optimizer::rules::{Rewrite, rewrite_once},
+};
+
+/// How big a program is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Size {
+    pub instructions: usize,
+    pub words: usize,
+}
+
+/// The result of optimizing a program.
+#[derive(Debug)]
+pub struct Optimized {
+    /// The optimized program, as formatted source.
+    pub source: String,
+    pub assembly: Assembly,
+    /// The changes made, in the order they were made, with the address the rewritten line had.
+    pub rewrites: Vec<(Rewrite, Option<u16>)>,
+    pub before: Size,
+    pub after: Size,
+}
+
+/// Optimizes assembly source that lives in `dir`, so it can include the files next to it. Fails
+/// with the assembler errors if it does not assemble.
+pub fn optimize_source(source: &str, dir: Option<&Path>) -> Result<Optimized, Vec<Diagnostic>> {
+    let (lines, diagnostics) = parse(source);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    let keep_layout = lines
+        .iter()
+        .filter_map(|line| line.statement.as_ref())
+        .any(has_numeric_pc_offset);
+    optimize_lines(lines, keep_layout, dir)
+}
+
+/// Optimizes an object file, working on its disassembly. Only the code reachable from the origin is
+/// touched.
+pub fn optimize_image(image: &Image) -> Result<Optimized, Vec<Diagnostic>> {
+    let code = reachable_code(image);
+    let end = image.origin as u32 + image.words.len() as u32;
+    let may_point_inside = image.words.iter().enumerate().any(|(offset, word)| {
+        let address = image.origin.wrapping_add(offset as u16);
+        !code.contains(&address) && (image.origin as u32..end).contains(&(*word as u32))
+    });
+    let jumps_through_registers = code.iter().any(|&address| {
+        let word = image.get(address).unwrap_or_default();
+        let flow = effects(word, address).flow;
+        flow == Flow::IndirectJump || matches!(Instruction::decode(word), Instruction::Jsrr { .. })
+    });
+    let (lines, diagnostics) = parse(&disassemble_image(image, &code));
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    let keep_layout = may_point_inside
+        || jumps_through_registers
+        || lines
+            .iter()
+            .filter_map(|line| line.statement.as_ref())
+            .any(has_numeric_pc_offset);
+    optimize_lines(lines, keep_layout, None)
+}
+
+fn optimize_lines(
+    mut lines: Vec<Line>,
+    keep_layout: bool,
+    dir: Option<&Path>,
+) -> Result<Optimized, Vec<Diagnostic>> {
+    let original = assemble_lines_in(&lines, dir)?;
+    let before = size(&lines, &original);
+    let address_of = |line: usize| {
+        original
+            .placements
+            .iter()
+            .find(|placement| placement.line == line && placement.origin.is_none())
+            .map(|placement| placement.address)
+    };
+
+    let mut rewrites = Vec::new();
+    // Every rewrite makes the program smaller or shortens a chain of jumps, so this is only a guard
+    // against branches that jump to each other in a loop.
+    while rewrites.len() < 10_000
+        && let Some(rewrite) = rewrite_once(&mut lines, keep_layout, dir)
+    {
+        let address = address_of(rewrite.line);
+        rewrites.push((rewrite, address));
+    }
+
+    let source = format_lines(&lines);
+    let (lines, _) = parse(&source);
+    let assembly = assemble_lines_in(&lines, dir)?;
+    Ok(Optimized {
+        source,
+        after: size(&lines, &assembly),
+        assembly,
+        rewrites,
+        before,
+    })
+}
+
+fn size(lines: &[Line], assembly: &Assembly) -> Size {
+    Size {
+        instructions: lines
+            .iter()
+            .filter_map(|line| line.statement.as_ref())
+            .filter(|statement| !statement.name().starts_with('.'))
+            .count(),
+        words: assembly.words.len(),
+    }
+}
+
+/// Whether the statement refers to an address with a numeric PC offset instead of a label.
+fn has_numeric_pc_offset(statement: &Statement) -> bool {
+    let name = statement.name();
+    let pc_relative = branch_flags(&name).is_some()
+        || matches!(name.as_str(), "JSR" | "LD" | "LDI" | "LEA" | "ST" | "STI");
+    pc_relative
+        && statement
+            .operands
+            .last()
+            .is_some_and(|operand| matches!(operand.kind, OperandKind::Number(_)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptions(optimized: &Optimized) -> Vec<(usize, &str)> {
+        optimized
+            .rewrites
+            .iter()
+            .map(|(rewrite, _)| (rewrite.line, rewrite.description.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn removes_redundant_sequences() {
+        let source = "\
+        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #5
+        ADD R1, R1, #5
+        ADD R1, R1, #0
+LOOP    ADD R1, R1, #-1
+        BRz DONE
+        BRnzp NEXT
+NEXT    BRnzp LOOP
+DONE    BRnzp END
+        ADD R2, R2, #1
+END     HALT
+        .END";
+        let optimized = optimize_source(source, None).unwrap();
+        assert_eq!(
+            descriptions(&optimized),
+            vec![
+                (
+                    5,
+                    "removed `ADD R1, R1, #0`: the condition codes already reflect R1"
+                ),
+                (4, "merged `ADD R1, R1, #5` into the ADD before it"),
+                (
+                    8,
+                    "removed `BRnzp NEXT`, which branches to the next instruction"
+                ),
+                (11, "removed `ADD R2, R2, #1`, which can never run"),
+                (7, "`BRz DONE` now branches to `END` directly"),
+            ]
+        );
+        assert_eq!(
+            optimized.before,
+            Size {
+                instructions: 11,
+                words: 11
+            }
+        );
+        assert_eq!(
+            optimized.after,
+            Size {
+                instructions: 7,
+                words: 7
+            }
+        );
+        assert!(optimized.source.contains("ADD   R1, R1, #10"));
+    }
+
+    #[test]
+    fn threads_jumps_to_jumps() {
+        let source = "\
+        .ORIG x3000
+LOOP    ADD R0, R0, #-1
+        BRp A
+        BRnzp B
+A       BRnzp LOOP
+B       BRnzp EXIT
+EXIT    RET
+        .END";
+        let optimized = optimize_source(source, None).unwrap();
+        let lines: Vec<&str> = optimized.source.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "        .ORIG x3000",
+                "LOOP    ADD   R0, R0, #-1",
+                "        BRp   LOOP",
+                "        RET",
+                "A       BRnzp LOOP",
+                "B       RET",
+                "EXIT    RET",
+                "        .END",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_layout_with_numeric_offsets() {
+        let source = ".ORIG x3000\nADD R0, R0, #1\nADD R0, R0, #0\nBRp #-3\nHALT\n.END";
+        let optimized = optimize_source(source, None).unwrap();
+        assert!(optimized.rewrites.is_empty());
+        assert_eq!(optimized.before, optimized.after);
+    }
+
+    #[test]
+    fn includes_files_next_to_the_source() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-optimize-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("dbl.asm"), "DBL ADD R0, R0, R0\n    RET\n").unwrap();
+        let source = ".ORIG x3000\nADD R0, R0, #1\nADD R0, R0, #0\nJSR DBL\nHALT\n.INCLUDE \"dbl.asm\"\n.END";
+        let optimized = optimize_source(source, Some(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(descriptions(&optimized.unwrap()).len(), 1);
+        assert!(optimize_source(source, None).is_err());
+    }
+
+    #[test]
+    fn optimizes_object_files() {
+        // 0x3000 AND R0, R0, #0
+        // 0x3001 ADD R0, R0, #2
+        // 0x3002 ADD R0, R0, #3
+        // 0x3003 OUT
+        // 0x3004 HALT
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x5020, 0x1022, 0x1023, 0xF021, 0xF025],
+        };
+        let optimized = optimize_image(&image).unwrap();
+        assert_eq!(optimized.rewrites[0].1, Some(0x3002));
+        assert_eq!(
+            optimized.assembly.words,
+            vec![0x5020, 0x1025, 0xF021, 0xF025]
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::path::Path;
+
+use crate::assembler::{
+    Assembly,
+    encoder::assemble_lines_in,
+    lexer::Span,
+    parser::{Line, Operand, OperandKind, Statement, branch_flags},
+};
+
+/// A change the optimizer made. `line` is the line of the source it was made on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rewrite {
+    pub line: usize,
+    pub description: String,
+}
+
+/// A rewrite of the statement of `current`, which can only be reached from the one of `previous`.
+type Rule = fn(&mut Vec<Line>, usize, usize) -> Option<Rewrite>;
+
+/// Applies the first rewrite that matches anywhere in `lines`, if any. When `keep_layout` is set,
+/// only rewrites that leave every word at its address are considered. Included files are looked up
+/// in `dir`.
+pub fn rewrite_once(
+    lines: &mut Vec<Line>,
+    keep_layout: bool,
+    dir: Option<&Path>,
+) -> Option<Rewrite> {
+    if !keep_layout {
+        let pairs = fallthrough_pairs(lines);
+        let rules: [Rule; 4] = [
+            redundant_flag_update,
+            merge_immediate_adds,
+            branch_to_next,
+            unreachable_after_jump,
+        ];
+        for rule in rules {
+            for &(previous, current) in &pairs {
+                if let Some(rewrite) = rule(lines, previous, current) {
+                    return Some(rewrite);
+                }
+            }
+        }
+    }
+    let assembly = assemble_lines_in(lines, dir).ok()?;
+    (0..lines.len()).find_map(|index| thread_jump(lines, index, &assembly))
+}
+
+/// Pairs of lines `(previous, current)` where the statement of `current` can only be reached from
+/// the statement of `previous`: nothing in between and no label on `current` that something could
+/// jump to.
+fn fallthrough_pairs(lines: &[Line]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut previous = None;
+    for (index, line) in lines.iter().enumerate() {
+        if line.label.is_some() {
+            previous = None;
+        }
+        let Some(statement) = &line.statement else {
+            continue;
+        };
+        if let Some(previous) = previous {
+            pairs.push((previous, index));
+        }
+        previous = (!statement.name().starts_with('.')).then_some(index);
+    }
+    pairs
+}
+
+/// `ADD Rx, Rx, #0` (or `AND Rx, Rx, Rx`) only sets the condition codes from Rx. Right after an
+/// instruction that wrote Rx, they already are.
+fn redundant_flag_update(
+    lines: &mut Vec<Line>,
+    previous: usize,
+    current: usize,
+) -> Option<Rewrite> {
+    let statement = lines[current].statement.as_ref()?;
+    let target = match (statement.name().as_str(), operands(statement).as_slice()) {
+        ("ADD", [Register(a), Register(b), Number(0)]) if a == b => *a,
+        ("AND", [Register(a), Register(b), Register(c)]) if a == b && b == c => *a,
+        _ => return None,
+    };
+    if sets_flags_from(lines[previous].statement.as_ref()?) != Some(target) {
+        return None;
+    }
+    let text = statement_text(statement);
+    let line = lines[current].number;
+    remove_statement(lines, current);
+    Some(Rewrite {
+        line,
+        description: format!("removed `{text}`: the condition codes already reflect R{target}"),
+    })
+}
+
+/// `ADD Rx, Ry, #a` followed by `ADD Rx, Rx, #b` is `ADD Rx, Ry, #(a + b)`, when the sum fits.
+fn merge_immediate_adds(lines: &mut Vec<Line>, previous: usize, current: usize) -> Option<Rewrite> {
+    let first = lines[previous].statement.as_ref()?;
+    let second = lines[current].statement.as_ref()?;
+    if first.name() != "ADD" || second.name() != "ADD" {
+        return None;
+    }
+    let (first_operands, second_operands) = (operands(first), operands(second));
+    let [Register(dest), Register(source), Number(a)] = first_operands.as_slice() else {
+        return None;
+    };
+    let [Register(x), Register(y), Number(b)] = second_operands.as_slice() else {
+        return None;
+    };
+    let sum = a + b;
+    if x != dest || y != dest || !(-16..=15).contains(&sum) {
+        return None;
+    }
+    let (dest, source) = (*dest, *source);
+    let text = statement_text(second);
+    let line = lines[current].number;
+    let merged = instruction("ADD", vec![register(dest), register(source), number(sum)]);
+    lines[previous].statement = Some(merged);
+    remove_statement(lines, current);
+    Some(Rewrite {
+        line,
+        description: format!("merged `{text}` into the ADD before it"),
+    })
+}
+
+/// A branch to the instruction right after it does nothing.
+fn branch_to_next(lines: &mut Vec<Line>, _previous: usize, current: usize) -> Option<Rewrite> {
+    let statement = lines[current].statement.as_ref()?;
+    branch_flags(&statement.name())?;
+    let values = operands(statement);
+    let [Label(target)] = values.as_slice() else {
+        return None;
+    };
+    let next = lines[current + 1..]
+        .iter()
+        .take_while(|line| line.statement.is_none())
+        .chain(
+            lines[current + 1..]
+                .iter()
+                .find(|line| line.statement.is_some()),
+        )
+        .any(|line| {
+            line.label
+                .as_ref()
+                .is_some_and(|label| &label.name == target)
+        });
+    if !next {
+        return None;
+    }
+    let text = statement_text(statement);
+    let line = lines[current].number;
+    remove_statement(lines, current);
+    Some(Rewrite {
+        line,
+        description: format!("removed `{text}`, which branches to the next instruction"),
+    })
+}
+
+/// An instruction without a label right after an unconditional jump can never run.
+fn unreachable_after_jump(
+    lines: &mut Vec<Line>,
+    previous: usize,
+    current: usize,
+) -> Option<Rewrite> {
+    if !is_unconditional(lines[previous].statement.as_ref()?) {
+        return None;
+    }
+    let statement = lines[current].statement.as_ref()?;
+    if statement.name().starts_with('.') {
+        return None;
+    }
+    let text = statement_text(statement);
+    let line = lines[current].number;
+    remove_statement(lines, current);
+    Some(Rewrite {
+        line,
+        description: format!("removed `{text}`, which can never run"),
+    })
+}
+
+/// A branch to an unconditional `BR` can go straight to where that one goes, and an unconditional
+/// branch to a `RET` can return itself.
+fn thread_jump(lines: &mut [Line], index: usize, assembly: &Assembly) -> Option<Rewrite> {
+    let statement = lines[index].statement.as_ref()?;
+    let nzp = branch_flags(&statement.name())?;
+    let values = operands(statement);
+    let [Label(target)] = values.as_slice() else {
+        return None;
+    };
+    let destination = statement_at_label(lines, target)?;
+    let text = statement_text(statement);
+    let line = lines[index].number;
+
+    if destination.name() == "RET" && nzp == 0b111 {
+        lines[index].statement = Some(instruction("RET", Vec::new()));
+        return Some(Rewrite {
+            line,
+            description: format!("replaced `{text}` with the RET it jumps to"),
+        });
+    }
+    if branch_flags(&destination.name()) != Some(0b111) {
+        return None;
+    }
+    let Some(Label(next)) = operands(destination).pop() else {
+        return None;
+    };
+    if &next == target {
+        return None;
+    }
+    // The branch must still reach its new target. Other rewrites only bring code closer together.
+    let address = assembly
+        .placements
+        .iter()
+        .find(|placement| placement.line == line && placement.origin.is_none())?
+        .address;
+    let offset = assembly.symbols.get(&next)?.address as i32 - (address as i32 + 1);
+    if !(-256..256).contains(&offset) {
+        return None;
+    }
+    let mut retargeted = statement.clone();
+    retargeted.operands = vec![label(&next)];
+    lines[index].statement = Some(retargeted);
+    Some(Rewrite {
+        line,
+        description: format!("`{text}` now branches to `{next}` directly"),
+    })
+}
+
+/// The statement a label names: the one on its line or, for a label alone on its line, the next one.
+fn statement_at_label<'a>(lines: &'a [Line], name: &str) -> Option<&'a Statement> {
+    let start = lines
+        .iter()
+        .position(|line| line.label.as_ref().is_some_and(|label| label.name == name))?;
+    lines[start..]
+        .iter()
+        .find_map(|line| line.statement.as_ref())
+}
+
+/// The register whose value sets the condition codes after `statement`, if it sets them.
+fn sets_flags_from(statement: &Statement) -> Option<u8> {
+    match statement.name().as_str() {
+        "ADD" | "AND" | "NOT" | "LD" | "LDI" | "LDR" | "LEA" => match operands(statement).first() {
+            Some(Register(reg)) => Some(*reg),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_unconditional(statement: &Statement) -> bool {
+    let name = statement.name();
+    matches!(name.as_str(), "JMP" | "RET" | "HALT")
+        || branch_flags(&name) == Some(0b111)
+        || (name == "TRAP" && matches!(operands(statement).as_slice(), [Number(0x25)]))
+}
+
+/// Drops the statement of a line, and the line itself when nothing else is left on it.
+fn remove_statement(lines: &mut Vec<Line>, index: usize) {
+    let line = &mut lines[index];
+    line.statement = None;
+    if line.label.is_none() && line.comment.is_none() {
+        lines.remove(index);
+    }
+}
+
+/// Operand values, easier to match on than operands.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Register(u8),
+    Number(i32),
+    Label(String),
+    Other,
+}
+
+use Value::{Label, Number, Register};
+
+fn operands(statement: &Statement) -> Vec<Value> {
+    statement
+        .operands
+        .iter()
+        .map(|operand| match &operand.kind {
+            OperandKind::Register(reg) => Register(*reg),
+            OperandKind::Number(value) => Number(*value),
+            OperandKind::Label(name) => Label(name.clone()),
+            OperandKind::String(_) => Value::Other,
+        })
+        .collect()
+}
+
+fn statement_text(statement: &Statement) -> String {
+    let operands: Vec<&str> = statement.operands.iter().map(|o| o.text.as_str()).collect();
+    format!("{} {}", statement.mnemonic, operands.join(", "))
+        .trim_end()
+        .to_string()
+}
+
+const NO_SPAN: Span = Span { start: 0, end: 0 };
+
+fn instruction(mnemonic: &str, operands: Vec<Operand>) -> Statement {
+    Statement {
+        mnemonic: mnemonic.to_string(),
+        span: NO_SPAN,
+        operands,
+    }
+}
+
+fn register(reg: u8) -> Operand {
+    Operand {
+        kind: OperandKind::Register(reg),
+        text: format!("R{reg}"),
+        span: NO_SPAN,
+    }
+}
+
+fn number(value: i32) -> Operand {
+    Operand {
+        kind: OperandKind::Number(value),
+        text: format!("#{value}"),
+        span: NO_SPAN,
+    }
+}
+
+fn label(name: &str) -> Operand {
+    Operand {
+        kind: OperandKind::Label(name.to_string()),
+        text: name.to_string(),
+        span: NO_SPAN,
+    }
+}
@@ -0,0 +1,317 @@
+//! Finding where two recorded runs of a program part ways (`trace-diff`).
+//!
+//! Both runs start at the start of their program, so the instructions are aligned by their count:
+//! the `n`th instruction of one run is compared with the `n`th of the other. The first one whose
+//! word, registers, condition codes or memory accesses differ is reported, with the instructions
+//! around it in both runs. A run that ends while the other goes on diverges there too.
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::Symbol,
+    debugger::inspect,
+    history::{AccessKind, Change, MemoryAccess},
+    recording::Recording,
+    registers::Register,
+};
+
+/// A recording with the name it is shown with.
+pub struct Run<'a> {
+    pub name: &'a str,
+    pub recording: &'a Recording,
+}
+
+/// Compares the runs, showing up to `context` instructions before and after the first divergence.
+/// Returns the text saying the runs are the same, or as an error the one showing where they differ.
+pub fn diff(a: &Run, b: &Run, context: usize) -> Result<String, String> {
+    let (changes_a, changes_b) = (&a.recording.changes, &b.recording.changes);
+    let start = match (changes_a.first(), changes_b.first()) {
+        (Some(first_a), Some(first_b)) => registers(&first_a.before, &first_b.before, a, b),
+        _ => Vec::new(),
+    };
+    if !start.is_empty() {
+        return Err(format!(
+            "{} and {} start from different registers:\n{}",
+            a.name,
+            b.name,
+            start.join("\n")
+        ));
+    }
+    let common = changes_a.len().min(changes_b.len());
+    let divergence = (0..common).find_map(|index| {
+        let differences = differences(&changes_a[index], &changes_b[index], a, b);
+        (!differences.is_empty()).then_some((index, differences))
+    });
+    let (index, differences) = match divergence {
+        Some(divergence) => divergence,
+        None if changes_a.len() == changes_b.len() => {
+            return match (&a.recording.failure, &b.recording.failure) {
+                (failure_a, failure_b) if failure_a == failure_b => Ok(format!(
+                    "the runs are the same: {common} instructions, then {}",
+                    ending(a.recording)
+                )),
+                _ => Err(format!(
+                    "the runs are the same for their {common} instructions, then {} {} and {} {}",
+                    a.name,
+                    ending(a.recording),
+                    b.name,
+                    ending(b.recording)
+                )),
+            };
+        }
+        None => {
+            let (short, long) = match changes_a.len() < changes_b.len() {
+                true => (a, b),
+                false => (b, a),
+            };
+            let difference = format!(
+                "  {} {} after {common} instructions, {} goes on",
+                short.name,
+                ending(short.recording),
+                long.name
+            );
+            (common, vec![difference])
+        }
+    };
+    let pc = match (changes_a.get(index), changes_b.get(index)) {
+        (Some(change), _) => place(change.before[Register::PC], &a.recording.symbols),
+        (None, Some(change)) => place(change.before[Register::PC], &b.recording.symbols),
+        (None, None) => unreachable!("runs diverge at an instruction one of them has"),
+    };
+    let mut text = format!(
+        "{} and {} diverge at instruction #{}, {pc}:\n{}",
+        a.name,
+        b.name,
+        index + 1,
+        differences.join("\n")
+    );
+    for run in [a, b] {
+        text.push_str(&format!("\n{}:", run.name));
+        let changes = &run.recording.changes;
+        let first = index.saturating_sub(context);
+        let shown = changes.iter().enumerate().take(index + context + 1);
+        for (shown, change) in shown.skip(first) {
+            let marker = match shown == index {
+                true => ">",
+                false => " ",
+            };
+            text.push_str(&format!(
+                "\n{marker} {}",
+                step(shown, change, &run.recording.symbols)
+            ));
+        }
+        if index >= changes.len() {
+            text.push_str(&format!("\n> {}", ending(run.recording)));
+        }
+    }
+    Err(text)
+}
+
+/// The differences between the same instruction of both runs, one per line.
+fn differences(change_a: &Change, change_b: &Change, a: &Run, b: &Run) -> Vec<String> {
+    let mut differences = Vec::new();
+    let pc = change_a.before[Register::PC];
+    if change_a.instruction != change_b.instruction {
+        let describe = |change: &Change, symbols| {
+            inspect::describe(change.instruction, pc, symbols)
+                .unwrap_or_else(|| format!(".FILL x{:04X}", change.instruction))
+        };
+        differences.push(format!(
+            "  instruction: {} in {}, {} in {}",
+            describe(change_a, &a.recording.symbols),
+            a.name,
+            describe(change_b, &b.recording.symbols),
+            b.name
+        ));
+    }
+    differences.extend(registers(&change_a.after, &change_b.after, a, b));
+    if change_a.accesses != change_b.accesses {
+        differences.push(format!(
+            "  memory: {} in {}, {} in {}",
+            accesses(&change_a.accesses),
+            a.name,
+            accesses(&change_b.accesses),
+            b.name
+        ));
+    }
+    differences
+}
+
+/// The registers and condition codes that differ, one per line.
+fn registers(
+    registers_a: &[u16; Register::COUNT],
+    registers_b: &[u16; Register::COUNT],
+    a: &Run,
+    b: &Run,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    for register in 0..8 {
+        let (value_a, value_b) = (registers_a[register], registers_b[register]);
+        if value_a != value_b {
+            differences.push(format!(
+                "  R{register}: x{value_a:04X} in {}, x{value_b:04X} in {}",
+                a.name, b.name
+            ));
+        }
+    }
+    let (pc_a, pc_b) = (registers_a[Register::PC], registers_b[Register::PC]);
+    if pc_a != pc_b {
+        differences.push(format!(
+            "  PC: {} in {}, {} in {}",
+            place(pc_a, &a.recording.symbols),
+            a.name,
+            place(pc_b, &b.recording.symbols),
+            b.name
+        ));
+    }
+    let (cc_a, cc_b) = (registers_a[Register::Cond], registers_b[Register::Cond]);
+    if cc_a != cc_b {
+        differences.push(format!(
+            "  CC: {} in {}, {} in {}",
+            inspect::condition(cc_a),
+            a.name,
+            inspect::condition(cc_b),
+            b.name
+        ));
+    }
+    differences
+}
+
+fn accesses(accesses: &[MemoryAccess]) -> String {
+    if accesses.is_empty() {
+        return "no access".to_string();
+    }
+    accesses
+        .iter()
+        .map(|access| match access.kind {
+            AccessKind::Read => format!("read x{:04X}: x{:04X}", access.address, access.old),
+            AccessKind::Write | AccessKind::Device => format!(
+                "wrote x{:04X}: x{:04X} -> x{:04X}",
+                access.address, access.old, access.new
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// An address with where it is in the program, like `x3005 (LOOP+1)`.
+fn place(address: u16, symbols: &BTreeMap<String, Symbol>) -> String {
+    match inspect::location(address, symbols) {
+        Some(location) => format!("x{address:04X} ({location})"),
+        None => format!("x{address:04X}"),
+    }
+}
+
+/// The instruction at `index`, numbered from 1, with where it is and its disassembly.
+fn step(index: usize, change: &Change, symbols: &BTreeMap<String, Symbol>) -> String {
+    let pc = change.before[Register::PC];
+    let text = inspect::describe(change.instruction, pc, symbols)
+        .unwrap_or_else(|| format!(".FILL x{:04X}", change.instruction));
+    format!("#{} {} {text}", index + 1, place(pc, symbols))
+}
+
+/// How the recorded run ended.
+fn ending(recording: &Recording) -> String {
+    match (&recording.failure, recording.changes.last()) {
+        (Some(failure), _) => format!("failed with {failure}"),
+        (None, Some(last)) if !last.running => "halted".to_string(),
+        (None, _) => "stopped being recorded".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        assembler::assemble,
+        console::BufferConsole,
+        recording::{Recorder, RecordingWriter},
+        vm::VMState,
+    };
+
+    /// Adds STEP to COUNT three times.
+    fn recording(step: i16) -> Recording {
+        let source = format!(
+            "
+        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    LD R0, COUNT
+        ADD R0, R0, #{step}
+        ST R0, COUNT
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #0
+        .END"
+        );
+        let assembly = assemble(&source).unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        let mut output = Vec::new();
+        let mut writer = RecordingWriter::new(&mut output, &assembly.symbols).unwrap();
+        loop {
+            let change = vm.step_recorded().unwrap();
+            writer.record(&change).unwrap();
+            if !change.running {
+                break;
+            }
+        }
+        writer.finish(&Ok(())).unwrap();
+        Recording::parse(&String::from_utf8(output).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reports_the_first_divergence() {
+        let (one, two) = (recording(1), recording(2));
+        let a = Run {
+            name: "one.rec",
+            recording: &one,
+        };
+        let b = Run {
+            name: "two.rec",
+            recording: &two,
+        };
+        assert_eq!(
+            diff(&a, &b, 1).unwrap_err(),
+            "one.rec and two.rec diverge at instruction #4, x3003 (LOOP+1):\n  \
+             instruction: ADD R0, R0, #1 in one.rec, ADD R0, R0, #2 in two.rec\n  \
+             R0: x0001 in one.rec, x0002 in two.rec\n\
+             one.rec:\n  \
+             #3 x3002 (LOOP) LD R0, COUNT\n\
+             > #4 x3003 (LOOP+1) ADD R0, R0, #1\n  \
+             #5 x3004 (LOOP+2) ST R0, COUNT\n\
+             two.rec:\n  \
+             #3 x3002 (LOOP) LD R0, COUNT\n\
+             > #4 x3003 (LOOP+1) ADD R0, R0, #2\n  \
+             #5 x3004 (LOOP+2) ST R0, COUNT"
+        );
+        assert_eq!(
+            diff(&a, &a, 1).unwrap(),
+            "the runs are the same: 18 instructions, then halted"
+        );
+    }
+
+    #[test]
+    fn reports_a_run_that_ends_first() {
+        let full = recording(1);
+        let mut cut = recording(1);
+        cut.changes.truncate(5);
+        let a = Run {
+            name: "full.rec",
+            recording: &full,
+        };
+        let b = Run {
+            name: "cut.rec",
+            recording: &cut,
+        };
+        let text = diff(&a, &b, 1).unwrap_err();
+        assert!(text.starts_with(
+            "full.rec and cut.rec diverge at instruction #6, x3005 (LOOP+3):\n  \
+             cut.rec stopped being recorded after 5 instructions, full.rec goes on\n"
+        ));
+        assert!(
+            text.ends_with("cut.rec:\n  #5 x3004 (LOOP+2) ST R0, COUNT\n> stopped being recorded")
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Recordings of whole runs, written as the program runs and read back to answer questions about
+//! them (`query`) without running it again.
+//!
+//! A recording is a text file. It starts with a header and the labels of the program, then the
+//! registers the program started with, then one line per executed instruction with its word, the
+//! registers after it and the memory accesses it made, and ends with how the program ended:
+//!
+//! ```text
+//! lc3-recording 1
+//! label COUNT x3006 7
+//! start 0000 0000 0000 0000 0000 0000 0000 0000 3000 0002
+//! step 3003 0005 0000 0000 0000 0000 0000 0000 0000 3003 0001 w3006:0004:0005
+//! halted
+//! ```
+//!
+//! Registers are R0-R7, PC and Cond, in hex. Accesses are `r<address>:<value>` for reads,
+//! `w<address>:<old>:<new>` for writes and `d<address>:<old>:<new>` for the keyboard registers
+//! updated as the keyboard status is read. The last line is `halted`, or `failed <error>`.
+pub mod diff;
+pub mod query;
+pub mod trace;
+pub mod vcd;
+
+use std::{collections::BTreeMap, io::Write};
+
+use crate::{
+    assembler::Symbol,
+    error::VMError,
+    history::{AccessKind, Change, MemoryAccess},
+    registers::Register,
+};
+
+const HEADER: &str = "lc3-recording 1";
+
+/// Something that keeps what the instructions executed by `VMState::execute` did.
+pub trait Recorder {
+    /// Keeps the change of one executed instruction.
+    fn record(&mut self, change: &Change) -> Result<(), VMError>;
+    /// Called once the program halted or failed, with how it ended.
+    fn finish(&mut self, result: &Result<(), VMError>) -> Result<(), VMError>;
+}
+
+/// Writes a recording to `output` as the program runs.
+pub struct RecordingWriter<W: Write> {
+    output: W,
+    /// Whether the registers the program started with were written.
+    started: bool,
+}
+
+impl<W: Write> RecordingWriter<W> {
+    /// Starts a recording of a program with the labels in `symbols`.
+    pub fn new(mut output: W, symbols: &BTreeMap<String, Symbol>) -> Result<Self, VMError> {
+        let mut header = format!("{HEADER}\n");
+        for (name, symbol) in symbols {
+            header.push_str(&format!(
+                "label {name} x{:04X} {}\n",
+                symbol.address, symbol.line
+            ));
+        }
+        output
+            .write_all(header.as_bytes())
+            .map_err(|e| VMError::CouldNotWriteFile(e.to_string()))?;
+        Ok(Self {
+            output,
+            started: false,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), VMError> {
+        writeln!(self.output, "{line}").map_err(|e| VMError::CouldNotWriteFile(e.to_string()))
+    }
+}
+
+impl<W: Write> Recorder for RecordingWriter<W> {
+    fn record(&mut self, change: &Change) -> Result<(), VMError> {
+        if !self.started {
+            self.started = true;
+            self.write(&format!("start {}", words(&change.before)))?;
+        }
+        let mut line = format!("step {:04x} {}", change.instruction, words(&change.after));
+        for access in &change.accesses {
+            let (address, old, new) = (access.address, access.old, access.new);
+            line.push_str(&match access.kind {
+                AccessKind::Read => format!(" r{address:04x}:{old:04x}"),
+                AccessKind::Write => format!(" w{address:04x}:{old:04x}:{new:04x}"),
+                AccessKind::Device => format!(" d{address:04x}:{old:04x}:{new:04x}"),
+            });
+        }
+        self.write(&line)
+    }
+
+    fn finish(&mut self, result: &Result<(), VMError>) -> Result<(), VMError> {
+        match result {
+            Ok(()) => self.write("halted")?,
+            Err(error) => self.write(&format!("failed {error:?}"))?,
+        }
+        self.output
+            .flush()
+            .map_err(|e| VMError::CouldNotWriteFile(e.to_string()))
+    }
+}
+
+/// A recording read back.
+#[derive(Debug)]
+pub struct Recording {
+    pub symbols: BTreeMap<String, Symbol>,
+    /// What every instruction did, in the order they ran.
+    pub changes: Vec<Change>,
+    /// Why the program failed, if it did. `None` also when the recording stops before the end.
+    pub failure: Option<String>,
+}
+
+impl Recording {
+    /// Reads a recording, reporting the first line that is not well-formed.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(format!(
+                "not a recording: it does not start with `{HEADER}`"
+            ));
+        }
+        let mut recording = Recording {
+            symbols: BTreeMap::new(),
+            changes: Vec::new(),
+            failure: None,
+        };
+        let mut registers = None;
+        for (index, line) in lines {
+            let wrong = || format!("line {}: `{line}` is not well-formed", index + 1);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["label", name, address, line] => {
+                    let address = address.strip_prefix('x').and_then(hex).ok_or_else(wrong)?;
+                    let line = line.parse().map_err(|_| wrong())?;
+                    recording.symbols.insert(
+                        name.to_string(),
+                        Symbol {
+                            address,
+                            line,
+                            origin: None,
+                        },
+                    );
+                }
+                ["start", ..] => registers = Some(parse_registers(&fields[1..]).ok_or_else(wrong)?),
+                ["step", instruction, ..] if fields.len() >= 2 + Register::COUNT => {
+                    let before = registers.ok_or_else(wrong)?;
+                    let after =
+                        parse_registers(&fields[2..2 + Register::COUNT]).ok_or_else(wrong)?;
+                    let accesses = fields[2 + Register::COUNT..]
+                        .iter()
+                        .map(|access| parse_access(access))
+                        .collect::<Option<_>>()
+                        .ok_or_else(wrong)?;
+                    recording.changes.push(Change {
+                        instruction: hex(instruction).ok_or_else(wrong)?,
+                        before,
+                        after,
+                        accesses,
+                        running: true,
+                    });
+                    registers = Some(after);
+                }
+                ["halted"] => {
+                    if let Some(last) = recording.changes.last_mut() {
+                        last.running = false;
+                    }
+                }
+                ["failed", ..] => {
+                    recording.failure = Some(line["failed".len()..].trim().to_string());
+                }
+                [] => {}
+                _ => return Err(wrong()),
+            }
+        }
+        Ok(recording)
+    }
+}
+
+fn words(registers: &[u16; Register::COUNT]) -> String {
+    registers
+        .iter()
+        .map(|word| format!("{word:04x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn parse_registers(fields: &[&str]) -> Option<[u16; Register::COUNT]> {
+    let words: Vec<u16> = fields
+        .iter()
+        .map(|field| hex(field))
+        .collect::<Option<_>>()?;
+    words.try_into().ok()
+}
+
+fn parse_access(text: &str) -> Option<MemoryAccess> {
+    let kind = match text.chars().next()? {
+        'r' => AccessKind::Read,
+        'w' => AccessKind::Write,
+        'd' => AccessKind::Device,
+        _ => return None,
+    };
+    let values: Vec<u16> = text[1..].split(':').map(hex).collect::<Option<_>>()?;
+    let (address, old, new) = match (kind, &values[..]) {
+        (AccessKind::Read, &[address, value]) => (address, value, value),
+        (AccessKind::Write | AccessKind::Device, &[address, old, new]) => (address, old, new),
+        _ => return None,
+    };
+    Some(MemoryAccess {
+        kind,
+        address,
+        old,
+        new,
+    })
+}
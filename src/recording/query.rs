@@ -0,0 +1,246 @@
+//! Questions answered from a recording, about the whole run at once.
+//!
+//! - `last-write <addr>`: the last instruction that wrote `addr`, with what it wrote;
+//! - `writes <addr>`: every instruction that wrote `addr`;
+//! - `values <reg> [in <label>]`: every value R0-R7 took, only while the subroutine at `label` (or
+//!   one it calls) runs if given, with the value it had on each call;
+//! - `first-negative [reg]`: the first instruction that computed a negative value, into `reg` if
+//!   given.
+//!
+//! Addresses are numbers or labels of the program. Instructions are numbered from 1 in the order
+//! they ran, so `#N` is the one after which the debugger's `goto N` leaves the program.
+use crate::{
+    assembler::parser::register_number, callstack::CallStack, debugger::inspect,
+    history::AccessKind, instruction::Instruction, recording::Recording, registers::Register,
+};
+
+/// The questions `answer` knows, for error messages.
+pub const QUESTIONS: &str = "last-write <addr>, writes <addr>, values <reg> [in <label>] and \
+                             first-negative [reg]";
+
+/// Answers the question made of the words in `question`.
+pub fn answer(recording: &Recording, question: &[&str]) -> Result<String, String> {
+    let symbols = &recording.symbols;
+    let address = |text: &str| inspect::value(text, symbols);
+    let register = |text: &str| {
+        register_number(text)
+            .map(usize::from)
+            .ok_or_else(|| format!("`{text}` is not one of R0-R7"))
+    };
+    match question {
+        ["last-write", text] => {
+            let address = address(text)?;
+            Ok(writes(recording, address)
+                .pop()
+                .unwrap_or_else(|| format!("x{address:04X} was never written")))
+        }
+        ["writes", text] => {
+            let address = address(text)?;
+            let writes = writes(recording, address);
+            Ok(match writes.is_empty() {
+                true => format!("x{address:04X} was never written"),
+                false => writes.join("\n"),
+            })
+        }
+        ["values", name] => Ok(values(recording, register(name)?, None)),
+        ["values", name, "in", label] => {
+            let subroutine = (label.to_string(), address(label)?);
+            Ok(values(recording, register(name)?, Some(subroutine)))
+        }
+        ["first-negative"] => Ok(first_negative(recording, None)),
+        ["first-negative", name] => Ok(first_negative(recording, Some(register(name)?))),
+        _ => Err(format!(
+            "unknown question `{}`; the questions are {QUESTIONS}",
+            question.join(" ")
+        )),
+    }
+}
+
+/// The writes to `address`, in order.
+fn writes(recording: &Recording, address: u16) -> Vec<String> {
+    let mut writes = Vec::new();
+    for (index, change) in recording.changes.iter().enumerate() {
+        for access in &change.accesses {
+            if access.kind == AccessKind::Write && access.address == address {
+                writes.push(format!(
+                    "{} wrote x{address:04X}: x{:04X} -> x{:04X}",
+                    step(recording, index),
+                    access.old,
+                    access.new
+                ));
+            }
+        }
+    }
+    writes
+}
+
+/// The values `register` took, while the subroutine at the address given runs if there is one.
+fn values(recording: &Recording, register: usize, subroutine: Option<(String, u16)>) -> String {
+    let mut lines = Vec::new();
+    let mut calls = CallStack::default();
+    for (index, change) in recording.changes.iter().enumerate() {
+        let pc = change.before[Register::PC];
+        let instruction = Instruction::decode(change.instruction);
+        calls.enter(pc, &instruction);
+        let inside = subroutine
+            .as_ref()
+            .is_none_or(|&(_, address)| calls.is_in(address));
+        let (before, after) = (change.before[register], change.after[register]);
+        if inside && before != after {
+            lines.push(format!(
+                "{}: R{register} = {}",
+                step(recording, index),
+                word(after)
+            ));
+        }
+        let called = matches!(
+            instruction,
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. }
+        );
+        let target = change.after[Register::PC];
+        if let Some((name, _)) = subroutine.as_ref().filter(|(_, a)| called && *a == target) {
+            lines.push(format!(
+                "{} calls {name} with R{register} = {}",
+                step(recording, index),
+                word(after)
+            ));
+        }
+        calls.leave(pc, &instruction, &change.after);
+    }
+    if !lines.is_empty() {
+        return lines.join("\n");
+    }
+    match subroutine {
+        Some((name, _)) => format!("{name} was never called"),
+        None => format!("R{register} never changed"),
+    }
+}
+
+/// The first instruction that computed a negative value into a register, or into `register`.
+fn first_negative(recording: &Recording, register: Option<usize>) -> String {
+    for (index, change) in recording.changes.iter().enumerate() {
+        let dest = match Instruction::decode(change.instruction) {
+            Instruction::Add { dest, .. }
+            | Instruction::And { dest, .. }
+            | Instruction::Not { dest, .. }
+            | Instruction::Ld { dest, .. }
+            | Instruction::Ldi { dest, .. }
+            | Instruction::Ldr { dest, .. }
+            | Instruction::Lea { dest, .. } => usize::from(dest),
+            _ => continue,
+        };
+        let value = change.after[dest];
+        if register.is_none_or(|register| register == dest) && (value as i16) < 0 {
+            return format!("{}: R{dest} = {}", step(recording, index), word(value));
+        }
+    }
+    match register {
+        Some(register) => format!("no instruction computed a negative value into R{register}"),
+        None => "no instruction computed a negative value".to_string(),
+    }
+}
+
+/// The instruction at `index`, numbered from 1, with its address and its disassembly.
+fn step(recording: &Recording, index: usize) -> String {
+    let change = &recording.changes[index];
+    let pc = change.before[Register::PC];
+    let text = inspect::describe(change.instruction, pc, &recording.symbols)
+        .unwrap_or_else(|| format!(".FILL x{:04X}", change.instruction));
+    format!("#{} x{pc:04X} {text}", index + 1)
+}
+
+fn word(value: u16) -> String {
+    format!("x{value:04X} (#{})", value as i16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        assembler::assemble,
+        console::BufferConsole,
+        recording::{Recorder, RecordingWriter},
+        vm::VMState,
+    };
+
+    /// Counts down from 2 in DOWN, which is called twice, storing every count in COUNT.
+    const PROGRAM: &str = "
+        .ORIG x3000
+        JSR DOWN
+        JSR DOWN
+        HALT
+DOWN    AND R3, R3, #0
+        ADD R3, R3, #2
+AGAIN   ADD R3, R3, #-1
+        ST R3, COUNT
+        BRzp AGAIN
+        RET
+COUNT   .FILL #9
+        .END";
+
+    fn recording() -> Recording {
+        let assembly = assemble(PROGRAM).unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        let mut output = Vec::new();
+        let mut writer = RecordingWriter::new(&mut output, &assembly.symbols).unwrap();
+        loop {
+            let change = vm.step_recorded().unwrap();
+            writer.record(&change).unwrap();
+            if !change.running {
+                break;
+            }
+        }
+        writer.finish(&Ok(())).unwrap();
+        Recording::parse(&String::from_utf8(output).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn answers_questions_about_a_run() {
+        let recording = recording();
+        assert_eq!(recording.changes.len(), 27);
+        assert!(!recording.changes[26].running);
+        let ask = |question: &str| {
+            let words: Vec<_> = question.split_whitespace().collect();
+            answer(&recording, &words).unwrap()
+        };
+        assert_eq!(
+            ask("last-write COUNT"),
+            "#24 x3006 ST R3, COUNT wrote x3009: x0000 -> xFFFF"
+        );
+        assert_eq!(ask("writes x3009").lines().count(), 6);
+        assert_eq!(
+            ask("values R3 in DOWN"),
+            "#1 x3000 JSR DOWN calls DOWN with R3 = x0000 (#0)\n\
+             #3 x3004 ADD R3, R3, #2: R3 = x0002 (#2)\n\
+             #4 x3005 ADD R3, R3, #-1: R3 = x0001 (#1)\n\
+             #7 x3005 ADD R3, R3, #-1: R3 = x0000 (#0)\n\
+             #10 x3005 ADD R3, R3, #-1: R3 = xFFFF (#-1)\n\
+             #14 x3001 JSR DOWN calls DOWN with R3 = xFFFF (#-1)\n\
+             #15 x3003 AND R3, R3, #0: R3 = x0000 (#0)\n\
+             #16 x3004 ADD R3, R3, #2: R3 = x0002 (#2)\n\
+             #17 x3005 ADD R3, R3, #-1: R3 = x0001 (#1)\n\
+             #20 x3005 ADD R3, R3, #-1: R3 = x0000 (#0)\n\
+             #23 x3005 ADD R3, R3, #-1: R3 = xFFFF (#-1)"
+        );
+        assert_eq!(ask("values R4"), "R4 never changed");
+        assert_eq!(
+            ask("first-negative"),
+            "#10 x3005 ADD R3, R3, #-1: R3 = xFFFF (#-1)"
+        );
+        assert!(answer(&recording, &["values", "PC"]).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_recordings() {
+        assert!(Recording::parse("step 1021").is_err());
+        let error = Recording::parse("lc3-recording 1\nstep 1021 0000").unwrap_err();
+        assert_eq!(error, "line 2: `step 1021 0000` is not well-formed");
+        let recording = Recording::parse("lc3-recording 1\nfailed UnrecognizedTrapCode(9)");
+        assert_eq!(
+            recording.unwrap().failure.as_deref(),
+            Some("UnrecognizedTrapCode(9)")
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Execution traces: a line per executed instruction, for people (text) or tools (JSON Lines).
+//!
+//! Every line has the instruction count, the PC, the word and its disassembly, the registers the
+//! instruction wrote with their new values, the memory it read and wrote, and the condition codes
+//! after it:
+//!
+//! ```text
+//! #19 x3002 3003 ST R0, COUNT; wrote x3006: x0004 -> x0005; CC p
+//! ```
+//!
+//! ```text
+//! {"count":19,"pc":12290,"word":12291,"instruction":"ST R0, COUNT","registers":{},"reads":[],
+//!  "writes":[{"address":12294,"old":4,"new":5}],"cc":"p"}
+//! ```
+//!
+//! With debug info, the source file and line of the instruction follow its disassembly, as
+//! `main.asm:12` in text and as a `source` object with `file` and `line` in JSON.
+//!
+//! Long runs can be cut down to the instructions at some addresses, or run while some subroutines
+//! (or those they call) are active. Instructions keep their count in the whole run either way.
+use std::{collections::BTreeMap, io::Write};
+
+use crate::{
+    assembler::{Symbol, debuginfo::DebugInfo},
+    callstack::CallStack,
+    debugger::inspect,
+    error::VMError,
+    history::{AccessKind, Change},
+    instruction::Instruction,
+    json::Json,
+    recording::Recorder,
+    registers::Register,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    JsonLines,
+}
+
+impl Format {
+    /// `text` or `jsonl`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "text" => Ok(Format::Text),
+            "jsonl" => Ok(Format::JsonLines),
+            _ => Err(format!("`{text}` is not a trace format; use text or jsonl")),
+        }
+    }
+}
+
+/// Which instructions to trace. Without ranges nor subroutines, all of them; otherwise those that
+/// match any of them.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Addresses of instructions, from start to end, both included.
+    pub ranges: Vec<(u16, u16)>,
+    /// Addresses of subroutines, whose instructions are traced while they run.
+    pub subroutines: Vec<u16>,
+}
+
+/// Writes the trace of a run to `output`.
+pub struct Tracer<W: Write> {
+    output: W,
+    format: Format,
+    filter: Filter,
+    symbols: BTreeMap<String, Symbol>,
+    debug: Option<DebugInfo>,
+    /// How many instructions ran.
+    count: u64,
+    /// The calls not returned from yet.
+    calls: CallStack,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(
+        output: W,
+        format: Format,
+        filter: Filter,
+        symbols: BTreeMap<String, Symbol>,
+        debug: Option<DebugInfo>,
+    ) -> Self {
+        Self {
+            output,
+            format,
+            filter,
+            symbols,
+            debug,
+            count: 0,
+            calls: CallStack::default(),
+        }
+    }
+
+    fn traces(&self, pc: u16) -> bool {
+        let Filter {
+            ranges,
+            subroutines,
+        } = &self.filter;
+        (ranges.is_empty() && subroutines.is_empty())
+            || ranges
+                .iter()
+                .any(|(start, end)| (start..=end).contains(&&pc))
+            || subroutines
+                .iter()
+                .any(|&subroutine| self.calls.is_in(subroutine))
+    }
+
+    fn line(&self, change: &Change) -> String {
+        let pc = change.before[Register::PC];
+        let text = inspect::describe(change.instruction, pc, &self.symbols)
+            .unwrap_or_else(|| format!(".FILL x{:04X}", change.instruction));
+        let registers = written(change);
+        let cc = inspect::condition(change.after[Register::Cond]);
+        let source = self.debug.as_ref().and_then(|debug| debug.line_at(pc));
+        match self.format {
+            Format::Text => {
+                let mut parts = vec![format!(
+                    "#{} x{pc:04X} {:04X} {text}",
+                    self.count, change.instruction
+                )];
+                if let Some(source) = source {
+                    parts.push(format!("{}:{}", source.file, source.line));
+                }
+                for register in registers {
+                    parts.push(format!("R{register} = x{:04X}", change.after[register]));
+                }
+                for access in &change.accesses {
+                    parts.push(match access.kind {
+                        AccessKind::Read => {
+                            format!("read x{:04X}: x{:04X}", access.address, access.old)
+                        }
+                        AccessKind::Write | AccessKind::Device => format!(
+                            "wrote x{:04X}: x{:04X} -> x{:04X}",
+                            access.address, access.old, access.new
+                        ),
+                    });
+                }
+                parts.push(format!("CC {cc}"));
+                parts.join("; ")
+            }
+            Format::JsonLines => {
+                let registers = registers
+                    .into_iter()
+                    .map(|register| (format!("R{register}"), change.after[register].into()))
+                    .collect();
+                let (reads, writes): (Vec<_>, Vec<_>) = change
+                    .accesses
+                    .iter()
+                    .partition(|access| access.kind == AccessKind::Read);
+                let reads = reads
+                    .into_iter()
+                    .map(|access| {
+                        Json::object([
+                            ("address", access.address.into()),
+                            ("value", access.old.into()),
+                        ])
+                    })
+                    .collect();
+                let writes = writes
+                    .into_iter()
+                    .map(|access| {
+                        Json::object([
+                            ("address", access.address.into()),
+                            ("old", access.old.into()),
+                            ("new", access.new.into()),
+                        ])
+                    })
+                    .collect();
+                let mut line = vec![
+                    ("count".to_string(), Json::Number(self.count as i64)),
+                    ("pc".to_string(), pc.into()),
+                    ("word".to_string(), change.instruction.into()),
+                    ("instruction".to_string(), text.into()),
+                ];
+                if let Some(source) = source {
+                    line.push((
+                        "source".to_string(),
+                        Json::object([
+                            ("file", source.file.as_str().into()),
+                            ("line", source.line.into()),
+                        ]),
+                    ));
+                }
+                line.extend([
+                    ("registers".to_string(), Json::Object(registers)),
+                    ("reads".to_string(), Json::Array(reads)),
+                    ("writes".to_string(), Json::Array(writes)),
+                    ("cc".to_string(), cc.into()),
+                ]);
+                Json::Object(line).to_string()
+            }
+        }
+    }
+}
+
+impl<W: Write> Recorder for Tracer<W> {
+    fn record(&mut self, change: &Change) -> Result<(), VMError> {
+        self.count += 1;
+        let pc = change.before[Register::PC];
+        let instruction = Instruction::decode(change.instruction);
+        self.calls.enter(pc, &instruction);
+        if self.traces(pc) {
+            let line = self.line(change);
+            writeln!(self.output, "{line}")
+                .map_err(|e| VMError::CouldNotWriteFile(e.to_string()))?;
+        }
+        self.calls.leave(pc, &instruction, &change.after);
+        Ok(())
+    }
+
+    fn finish(&mut self, _: &Result<(), VMError>) -> Result<(), VMError> {
+        self.output
+            .flush()
+            .map_err(|e| VMError::CouldNotWriteFile(e.to_string()))
+    }
+}
+
+/// The general purpose registers the instruction wrote: its destination, if it has one, and any
+/// other it changed (like R7 for calls and R0 for traps).
+fn written(change: &Change) -> Vec<usize> {
+    let destination = match Instruction::decode(change.instruction) {
+        Instruction::Add { dest, .. }
+        | Instruction::And { dest, .. }
+        | Instruction::Not { dest, .. }
+        | Instruction::Ld { dest, .. }
+        | Instruction::Ldi { dest, .. }
+        | Instruction::Ldr { dest, .. }
+        | Instruction::Lea { dest, .. } => Some(usize::from(dest)),
+        _ => None,
+    };
+    (0..8)
+        .filter(|&register| {
+            destination == Some(register) || change.before[register] != change.after[register]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, vm::VMState};
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        LD R0, COUNT
+        JSR TWICE
+        ST R0, COUNT
+        HALT
+TWICE   ADD R0, R0, R0
+        RET
+COUNT   .FILL #-3
+        .END";
+
+    fn trace(format: Format, filter: Filter) -> String {
+        let assembly = assemble(PROGRAM).unwrap();
+        let debug = match format {
+            Format::Text => None,
+            Format::JsonLines => Some(DebugInfo::new(&assembly, "twice.asm")),
+        };
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        let mut output = Vec::new();
+        let mut tracer = Tracer::new(&mut output, format, filter, assembly.symbols, debug);
+        loop {
+            let change = vm.step_recorded().unwrap();
+            tracer.record(&change).unwrap();
+            if !change.running {
+                break;
+            }
+        }
+        tracer.finish(&Ok(())).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn traces_every_instruction_as_text() {
+        assert_eq!(
+            trace(Format::Text, Filter::default()),
+            "#1 x3000 2005 LD R0, COUNT; R0 = xFFFD; read x3006: xFFFD; CC n\n\
+             #2 x3001 4802 JSR TWICE; R7 = x3002; CC n\n\
+             #3 x3004 1000 ADD R0, R0, R0; R0 = xFFFA; CC n\n\
+             #4 x3005 C1C0 RET; CC n\n\
+             #5 x3002 3003 ST R0, COUNT; wrote x3006: xFFFD -> xFFFA; CC n\n\
+             #6 x3003 F025 HALT; CC n\n"
+        );
+    }
+
+    #[test]
+    fn filters_by_address_and_subroutine() {
+        let filter = Filter {
+            ranges: vec![(0x3002, 0x3002)],
+            subroutines: vec![0x3004],
+        };
+        let trace = trace(Format::JsonLines, filter);
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "{\"count\":3,\"pc\":12292,\"word\":4096,\"instruction\":\"ADD R0, R0, R0\",\
+             \"source\":{\"file\":\"twice.asm\",\"line\":7},\"registers\":{\"R0\":65530},\"reads\":[],\"writes\":[],\"cc\":\"n\"}"
+        );
+        assert!(lines[1].starts_with("{\"count\":4,"));
+        assert!(lines[2].contains("\"writes\":[{\"address\":12294,\"old\":65533,\"new\":65530}]"));
+    }
+}
@@ -0,0 +1,288 @@
+//! Value Change Dump (VCD) export of a run, to look at it in waveform viewers like GTKWave.
+//!
+//! The dump has the register file (R0-R7), the PC and the N, Z and P flags, the memory words asked
+//! for and the keyboard device registers, with one time step per executed instruction: the values
+//! at time `n` are those after the `n`th instruction, and time 0 has those the program started with.
+use std::io::Write;
+
+use crate::{
+    error::VMError,
+    flags::Flag,
+    history::Change,
+    recording::Recorder,
+    registers::{MemoryRegister, Register},
+};
+
+/// A memory word to dump, under `name`, with the value it has when the program starts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Probe {
+    pub name: String,
+    pub address: u16,
+    pub initial: u16,
+}
+
+/// Where the value of a signal comes from.
+#[derive(Clone, Copy)]
+enum Source {
+    Register(usize),
+    /// A condition flag, by its bit in Cond.
+    Flag(u16),
+    Memory(u16),
+}
+
+struct Signal {
+    name: String,
+    /// The short code the value changes use for it.
+    code: String,
+    source: Source,
+    /// The last value written, or the initial one of memory words.
+    value: u16,
+}
+
+impl Signal {
+    fn width(&self) -> u8 {
+        match self.source {
+            Source::Flag(_) => 1,
+            Source::Register(_) | Source::Memory(_) => 16,
+        }
+    }
+
+    fn change(&self) -> String {
+        match self.source {
+            Source::Flag(_) => format!("{}{}", self.value, self.code),
+            Source::Register(_) | Source::Memory(_) => {
+                format!("b{:016b} {}", self.value, self.code)
+            }
+        }
+    }
+}
+
+/// Writes the dump of a run to `output` as the program runs.
+pub struct VcdWriter<W: Write> {
+    output: W,
+    /// The signals, grouped by their scope.
+    scopes: Vec<(&'static str, Vec<Signal>)>,
+    /// How many instructions ran.
+    time: u64,
+}
+
+impl<W: Write> VcdWriter<W> {
+    /// Starts a dump of the registers, the words of `probes` and the keyboard registers, which are
+    /// zero when the VM starts.
+    pub fn new(output: W, probes: Vec<Probe>) -> Self {
+        let registers = (0..8)
+            .map(|register| (format!("R{register}"), Source::Register(register)))
+            .chain([("PC".to_string(), Source::Register(Register::PC as usize))])
+            .chain([
+                ("N".to_string(), Source::Flag(Flag::Neg as u16)),
+                ("Z".to_string(), Source::Flag(Flag::Zro as u16)),
+                ("P".to_string(), Source::Flag(Flag::Pos as u16)),
+            ])
+            .map(|(name, source)| (name, source, 0))
+            .collect();
+        let memory = probes
+            .into_iter()
+            .map(|probe| (probe.name, Source::Memory(probe.address), probe.initial))
+            .collect();
+        let devices = vec![
+            (
+                "KBSR".to_string(),
+                Source::Memory(MemoryRegister::Kbsr as u16),
+                0,
+            ),
+            (
+                "KBDR".to_string(),
+                Source::Memory(MemoryRegister::Kbdr as u16),
+                0,
+            ),
+        ];
+        let mut count = 0;
+        let scopes = [
+            ("registers", registers),
+            ("memory", memory),
+            ("devices", devices),
+        ]
+        .into_iter()
+        .map(|(scope, signals): (_, Vec<_>)| {
+            let signals = signals
+                .into_iter()
+                .map(|(name, source, value)| {
+                    count += 1;
+                    Signal {
+                        name,
+                        code: code(count - 1),
+                        source,
+                        value,
+                    }
+                })
+                .collect();
+            (scope, signals)
+        })
+        .collect();
+        Self {
+            output,
+            scopes,
+            time: 0,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), VMError> {
+        self.output
+            .write_all(text.as_bytes())
+            .map_err(|e| VMError::CouldNotWriteFile(e.to_string()))
+    }
+
+    /// The declarations of the signals and their values at time 0, with the registers in
+    /// `registers`.
+    fn header(&mut self, registers: &[u16; Register::COUNT]) -> String {
+        let mut text = String::from(
+            "$version basic-vm $end\n\
+             $comment one time step per executed instruction $end\n\
+             $timescale 1 ns $end\n\
+             $scope module lc3 $end\n",
+        );
+        for (scope, signals) in &self.scopes {
+            if signals.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("$scope module {scope} $end\n"));
+            for signal in signals {
+                text.push_str(&format!(
+                    "$var wire {} {} {} $end\n",
+                    signal.width(),
+                    signal.code,
+                    signal.name
+                ));
+            }
+            text.push_str("$upscope $end\n");
+        }
+        text.push_str("$upscope $end\n$enddefinitions $end\n#0\n$dumpvars\n");
+        for signal in self.scopes.iter_mut().flat_map(|(_, signals)| signals) {
+            if let Some(value) = register_value(signal.source, registers) {
+                signal.value = value;
+            }
+            text.push_str(&signal.change());
+            text.push('\n');
+        }
+        text.push_str("$end\n");
+        text
+    }
+}
+
+impl<W: Write> Recorder for VcdWriter<W> {
+    fn record(&mut self, change: &Change) -> Result<(), VMError> {
+        if self.time == 0 {
+            let header = self.header(&change.before);
+            self.write(&header)?;
+        }
+        self.time += 1;
+        let mut text = format!("#{}\n", self.time);
+        for signal in self.scopes.iter_mut().flat_map(|(_, signals)| signals) {
+            let value = match signal.source {
+                Source::Memory(address) => change
+                    .accesses
+                    .iter()
+                    .rev()
+                    .find(|access| access.address == address)
+                    .map(|access| access.new),
+                source => register_value(source, &change.after),
+            };
+            if let Some(value) = value.filter(|value| *value != signal.value) {
+                signal.value = value;
+                text.push_str(&signal.change());
+                text.push('\n');
+            }
+        }
+        self.write(&text)
+    }
+
+    fn finish(&mut self, _: &Result<(), VMError>) -> Result<(), VMError> {
+        // A last time step, so viewers show the values after the last instruction for a step too.
+        let end = format!("#{}\n", self.time + 1);
+        self.write(&end)?;
+        self.output
+            .flush()
+            .map_err(|e| VMError::CouldNotWriteFile(e.to_string()))
+    }
+}
+
+/// The value of a register or flag signal, which is 0 or 1 for flags.
+fn register_value(source: Source, registers: &[u16; Register::COUNT]) -> Option<u16> {
+    match source {
+        Source::Register(register) => Some(registers[register]),
+        Source::Flag(flag) => Some(u16::from(registers[Register::Cond] == flag)),
+        Source::Memory(_) => None,
+    }
+}
+
+/// The identifier code of the `index`th signal, made of the printable characters VCD allows.
+fn code(mut index: usize) -> String {
+    let mut code = String::new();
+    loop {
+        code.push(char::from(b'!' + (index % 94) as u8));
+        index /= 94;
+        if index == 0 {
+            return code;
+        }
+        index -= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assembler::assemble, console::BufferConsole, vm::VMState};
+
+    #[test]
+    fn dumps_a_step_per_instruction() {
+        let assembly = assemble(
+            "
+        .ORIG x3000
+        LD R0, COUNT
+        ADD R0, R0, #1
+        ST R0, COUNT
+        HALT
+COUNT   .FILL #-1
+        .END",
+        )
+        .unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        let mut output = Vec::new();
+        let probe = Probe {
+            name: "COUNT".to_string(),
+            address: 0x3004,
+            initial: 0xFFFF,
+        };
+        let mut writer = VcdWriter::new(&mut output, vec![probe]);
+        loop {
+            let change = vm.step_recorded().unwrap();
+            writer.record(&change).unwrap();
+            if !change.running {
+                break;
+            }
+        }
+        writer.finish(&Ok(())).unwrap();
+        let dump = String::from_utf8(output).unwrap();
+        assert!(dump.contains("$scope module memory $end\n$var wire 16 - COUNT $end\n"));
+        assert!(dump.contains("$var wire 1 * N $end\n"));
+        let changes = &dump[dump.find("#1\n").unwrap()..];
+        assert_eq!(
+            changes,
+            "#1\nb1111111111111111 !\nb0011000000000001 )\n1*\n0+\n\
+             #2\nb0000000000000000 !\nb0011000000000010 )\n0*\n1+\n\
+             #3\nb0011000000000011 )\nb0000000000000000 -\n\
+             #4\nb0011000000000100 )\n\
+             #5\n"
+        );
+    }
+
+    #[test]
+    fn makes_distinct_codes() {
+        assert_eq!(code(0), "!");
+        assert_eq!(code(93), "~");
+        assert_eq!(code(94), "!!");
+        assert_eq!(code(95), "\"!");
+    }
+}
@@ -0,0 +1,399 @@
+//! Interactive assembly REPL.
+//!
+//! Every line of LC-3 assembly typed is assembled at the next free address and, when it is an
+//! instruction, executed right away on a VM that lives as long as the session, printing what it
+//! changed: registers, condition codes and memory. Labels written on a line, or defined with
+//! `:label`, can be used by the lines after it. Lines starting with `:` are commands to look at and
+//! edit the state of the VM (see `HELP`).
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::{
+        Symbol, assemble_in,
+        encoder::encode_statement,
+        parser::{is_identifier, parse_line},
+    },
+    debugger::inspect,
+    error::VMError,
+    registers::Register,
+    vm::VMState,
+};
+
+pub const HELP: &str = "\
+Type LC-3 assembly to run it one line at a time. Commands:
+  :regs                  shows the registers and the condition codes
+  :mem <addr> [count]    shows `count` words of memory from `addr` (8 by default)
+  :set <reg> <value>     sets R0-R7, PC or CC (to n, z or p)
+  :poke <addr> <value>   sets a word of memory
+  :label <name> [addr]   defines a label, at the next free address by default
+  :labels                lists the labels
+  :org <addr>            sets where the next lines go
+  :step [count]          executes `count` instructions from the PC (1 by default)
+  :run                   executes from the PC until HALT
+  :reset                 starts over with a fresh VM
+  :help                  shows this help
+  :quit                  leaves
+Addresses and values are numbers (x3000, #10, 10) or labels.";
+
+/// How many instructions `:run` executes before giving up, so a program that never halts does not
+/// hang the session.
+const RUN_LIMIT: usize = 1_000_000;
+
+/// Where lines go when the session starts.
+const DEFAULT_ORIGIN: u16 = 0x3000;
+
+pub struct Repl {
+    pub vm: VMState,
+    /// Where the next line goes.
+    cursor: u16,
+    symbols: BTreeMap<String, Symbol>,
+    /// How many lines were entered, to number them.
+    lines: usize,
+}
+
+/// What a line did.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Text to show, which may be empty.
+    Show(String),
+    Quit,
+}
+
+impl Repl {
+    pub fn new(vm: VMState) -> Self {
+        let mut repl = Self {
+            vm,
+            cursor: DEFAULT_ORIGIN,
+            symbols: BTreeMap::new(),
+            lines: 0,
+        };
+        repl.vm.registers[Register::PC] = DEFAULT_ORIGIN;
+        repl
+    }
+
+    /// The prompt, which shows where the next line goes.
+    pub fn prompt(&self) -> String {
+        format!("x{:04X}> ", self.cursor)
+    }
+
+    /// Handles one line of input. Errors are returned as the text to show.
+    pub fn eval(&mut self, input: &str) -> Result<Reply, String> {
+        self.lines += 1;
+        let input = input.trim();
+        if let Some(command) = input.strip_prefix(':') {
+            return self.command(command);
+        }
+        self.assembly(input).map(Reply::Show)
+    }
+
+    fn assembly(&mut self, input: &str) -> Result<String, String> {
+        let line = parse_line(self.lines, input).map_err(|d| d.message)?;
+        let Some(statement) = &line.statement else {
+            // A label alone names the next free address.
+            if let Some(label) = &line.label {
+                self.define(&label.name, self.cursor)?;
+                return Ok(format!("{} = x{:04X}", label.name, self.cursor));
+            }
+            return Ok(String::new());
+        };
+        match statement.name().as_str() {
+            ".ORIG" => {
+                let [operand] = statement.operands.as_slice() else {
+                    return Err(".ORIG expects an address".to_string());
+                };
+                self.cursor = self.value(&operand.text)?;
+                return Ok(String::new());
+            }
+            ".INCLUDE" => return self.include(input),
+            _ => {}
+        }
+
+        // The label is defined first, so the line can refer to itself.
+        if let Some(label) = &line.label {
+            self.define(&label.name, self.cursor)?;
+        }
+        let words =
+            encode_statement(self.lines, statement, self.cursor, &self.symbols).map_err(|d| {
+                if let Some(label) = &line.label {
+                    self.symbols.remove(&label.name);
+                }
+                d.message
+            })?;
+        let address = self.cursor;
+        for (offset, word) in words.iter().enumerate() {
+            self.vm
+                .mem_write(address.wrapping_add(offset as u16), *word);
+        }
+        self.cursor = address.wrapping_add(words.len() as u16);
+        if statement.name().starts_with('.') {
+            return Ok(format!(
+                "x{address:04X}: {} word{}",
+                words.len(),
+                if words.len() == 1 { "" } else { "s" }
+            ));
+        }
+
+        let mut out = format!(
+            "x{address:04X}: {:04X}  {}\n",
+            words[0],
+            inspect::describe(words[0], address, &self.symbols).unwrap_or_default()
+        );
+        self.vm.registers[Register::PC] = address;
+        out.push_str(&self.execute(1)?);
+        Ok(out)
+    }
+
+    /// Assembles a file of the standard library at the next free address, adding its labels.
+    fn include(&mut self, input: &str) -> Result<String, String> {
+        let source = format!(".ORIG x{:04X}\n{input}\n.END", self.cursor);
+        let assembly = assemble_in(&source, None).map_err(|diagnostics| {
+            diagnostics
+                .iter()
+                .map(|d| d.message.clone())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })?;
+        if let Some(name) = assembly
+            .symbols
+            .keys()
+            .find(|name| self.symbols.contains_key(*name))
+        {
+            return Err(format!("label `{name}` is already defined"));
+        }
+        for (offset, word) in assembly.words.iter().enumerate() {
+            self.vm
+                .mem_write(self.cursor.wrapping_add(offset as u16), *word);
+        }
+        let start = self.cursor;
+        self.cursor = self.cursor.wrapping_add(assembly.words.len() as u16);
+        let count = assembly.symbols.len();
+        for (name, symbol) in assembly.symbols {
+            self.symbols.insert(
+                name,
+                Symbol {
+                    address: symbol.address,
+                    line: self.lines,
+                    origin: None,
+                },
+            );
+        }
+        Ok(format!(
+            "x{start:04X}: {} words, {count} labels",
+            assembly.words.len()
+        ))
+    }
+
+    fn command(&mut self, command: &str) -> Result<Reply, String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let show = match (name, args.as_slice()) {
+            ("help" | "h", []) => HELP.to_string(),
+            ("quit" | "q" | "exit", []) => return Ok(Reply::Quit),
+            ("regs" | "r", []) => inspect::registers(&self.vm),
+            ("mem" | "m", [address, rest @ ..]) if rest.len() <= 1 => {
+                let address = self.value(address)?;
+                let count = match rest {
+                    [count] => self.value(count)?,
+                    _ => 8,
+                };
+                inspect::memory(&self.vm, address, count, &self.symbols)
+            }
+            ("set", [register, value]) => {
+                inspect::set_register(&mut self.vm, register, value, &self.symbols)?;
+                inspect::registers(&self.vm)
+            }
+            ("poke", [address, value]) => {
+                let address = self.value(address)?;
+                let value = self.value(value)?;
+                self.vm.mem_write(address, value);
+                inspect::memory(&self.vm, address, 1, &self.symbols)
+            }
+            ("label", [name, rest @ ..]) if rest.len() <= 1 => {
+                let address = match rest {
+                    [address] => self.value(address)?,
+                    _ => self.cursor,
+                };
+                self.define(name, address)?;
+                format!("{name} = x{address:04X}")
+            }
+            ("labels", []) => self
+                .symbols
+                .iter()
+                .map(|(name, symbol)| format!("{name} = x{:04X}", symbol.address))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ("org", [address]) => {
+                self.cursor = self.value(address)?;
+                String::new()
+            }
+            ("step" | "s", rest @ ([] | [_])) => {
+                let count = match rest {
+                    [count] => self.value(count)? as usize,
+                    _ => 1,
+                };
+                self.execute(count)?
+            }
+            ("run", []) => self.execute(RUN_LIMIT)?,
+            ("reset", []) => {
+                let mut vm = VMState::init().map_err(|e| format!("{e:?}"))?;
+                std::mem::swap(&mut vm.console, &mut self.vm.console);
+                *self = Repl::new(vm);
+                String::new()
+            }
+            _ => return Err(format!("unknown command `:{command}`; try :help")),
+        };
+        Ok(Reply::Show(show))
+    }
+
+    /// Executes up to `count` instructions from the PC, stopping at HALT, and describes what
+    /// changed.
+    fn execute(&mut self, count: usize) -> Result<String, String> {
+        let registers = self.vm.registers;
+        let memory = self.vm.memory.to_vec();
+        let mut halted = false;
+        let mut executed = 0;
+        while executed < count {
+            executed += 1;
+            match self.vm.step() {
+                Ok(true) => {}
+                Ok(false) => {
+                    halted = true;
+                    break;
+                }
+                Err(error) => return Err(describe_error(&error)),
+            }
+        }
+
+        let mut lines = Vec::new();
+        for (index, (before, after)) in registers.iter().zip(self.vm.registers).enumerate() {
+            if *before == after {
+                continue;
+            }
+            lines.push(match index {
+                0..=7 => format!(
+                    "R{index}: x{before:04X} -> x{after:04X} (#{})",
+                    after as i16
+                ),
+                8 => format!("PC: x{before:04X} -> x{after:04X}"),
+                _ => format!(
+                    "CC: {} -> {}",
+                    inspect::condition(*before),
+                    inspect::condition(after)
+                ),
+            });
+        }
+        for (address, before) in memory.iter().enumerate() {
+            let after = self.vm.memory[address];
+            if *before != after {
+                lines.push(format!(
+                    "[x{address:04X}]: x{before:04X} -> x{after:04X} (#{})",
+                    after as i16
+                ));
+            }
+        }
+        if count > 1 {
+            lines.push(format!("executed {executed} instructions"));
+        }
+        if halted {
+            lines.push("halted".to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// A number, or the address of a label.
+    fn value(&self, text: &str) -> Result<u16, String> {
+        inspect::value(text, &self.symbols)
+    }
+
+    fn define(&mut self, name: &str, address: u16) -> Result<(), String> {
+        if !is_identifier(name) {
+            return Err(format!("invalid label name `{name}`"));
+        }
+        self.symbols.insert(
+            name.to_string(),
+            Symbol {
+                address,
+                line: self.lines,
+                origin: None,
+            },
+        );
+        Ok(())
+    }
+}
+
+fn describe_error(error: &VMError) -> String {
+    format!("the VM stopped: {error:?}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::BufferConsole;
+
+    fn repl() -> Repl {
+        let mut vm = VMState::init().unwrap();
+        vm.console = Box::new(BufferConsole::new("").0);
+        Repl::new(vm)
+    }
+
+    fn show(repl: &mut Repl, input: &str) -> String {
+        match repl.eval(input) {
+            Ok(Reply::Show(text)) => text,
+            other => panic!("unexpected reply to `{input}`: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn runs_lines_and_shows_changes() {
+        let mut repl = repl();
+        assert_eq!(
+            show(&mut repl, "ADD R0, R0, #5"),
+            "x3000: 1025  ADD R0, R0, #5\nR0: x0000 -> x0005 (#5)\nPC: x3000 -> x3001\nCC: z -> p"
+        );
+        assert_eq!(repl.prompt(), "x3001> ");
+        let out = show(&mut repl, "NOT R1, R0");
+        assert!(out.ends_with("R1: x0000 -> xFFFA (#-6)\nPC: x3001 -> x3002\nCC: p -> n"));
+        show(&mut repl, ":set R2 x4000");
+        let out = show(&mut repl, "STR R0, R2, #1");
+        assert!(out.ends_with("[x4001]: x0000 -> x0005 (#5)"), "{out}");
+    }
+
+    #[test]
+    fn defines_labels_for_later_lines() {
+        let mut repl = repl();
+        show(&mut repl, ":label DATA x3100");
+        show(&mut repl, ":poke DATA #42");
+        let out = show(&mut repl, "LD R3, DATA");
+        assert!(out.contains("LD R3, DATA"));
+        assert!(out.contains("R3: x0000 -> x002A (#42)"));
+        show(&mut repl, ":org x3050");
+        assert_eq!(show(&mut repl, "TEXT .STRINGZ \"hi\""), "x3050: 3 words");
+        let out = show(&mut repl, "LEA R0, TEXT");
+        assert!(out.contains("R0: x0000 -> x3050"), "{out}");
+        assert!(show(&mut repl, ":labels").contains("TEXT = x3050"));
+        assert_eq!(
+            repl.eval("BRnzp NOWHERE"),
+            Err("undefined label `NOWHERE`".to_string())
+        );
+    }
+
+    #[test]
+    fn runs_stored_programs_and_libraries() {
+        let mut repl = repl();
+        show(&mut repl, ":org x3400");
+        show(&mut repl, ".INCLUDE \"std/math.asm\"");
+        show(&mut repl, ":org x3000");
+        show(&mut repl, ":set R0 #6");
+        show(&mut repl, ":set R1 #7");
+        let out = show(&mut repl, "JSR STD_MUL");
+        assert!(out.contains("PC: x3000 -> x3400"), "{out}");
+        // The subroutine runs until HALT, which comes right after the JSR.
+        show(&mut repl, ":poke x3001 xF025");
+        let out = show(&mut repl, ":run");
+        assert!(out.contains("R0: x0006 -> x002A (#42)"), "{out}");
+        assert!(out.ends_with("halted"));
+        assert_eq!(repl.eval(":quit"), Ok(Reply::Quit));
+        assert!(repl.eval(":bogus").is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//! The standard library of LC-3 routines shipped with the assembler.
+//!
+//! Its modules are assembly files in `stdlib/`, built into the binary, that a program brings in
+//! with `.INCLUDE "std/<module>.asm"`, usually after its last instruction. Routines take their
+//! arguments in R0, R1 and R2, return their results in R0 (and R1 for a second one) and are called
+//! with `JSR`; every other register except R7 keeps its value. Their labels, and the ones they use
+//! inside, start with `STD_`.
+//!
+//! The library has a version of its own. `std@<version>/<module>.asm` only includes the module
+//! when the bundled library is compatible with `version`: same major version, and at least the
+//! minor and patch versions given.
+
+/// The version of the bundled library.
+pub const VERSION: &str = "1.0.0";
+
+/// A module of the library.
+pub struct Module {
+    /// The name of its file, without `.asm`.
+    pub name: &'static str,
+    pub source: &'static str,
+}
+
+impl Module {
+    /// What the module is for, from the first line of its header.
+    pub fn summary(&self) -> &'static str {
+        let first = self.source.lines().next().unwrap_or_default();
+        first.split_once(": ").map_or("", |(_, summary)| summary)
+    }
+
+    /// The routines the module provides, with what each of them does, from its header.
+    pub fn routines(&self) -> Vec<(&'static str, String)> {
+        let mut routines: Vec<(&'static str, String)> = Vec::new();
+        for line in self.source.lines().take_while(|line| line.starts_with(';')) {
+            let text = line.trim_start_matches(';').trim();
+            match text.split_once(char::is_whitespace) {
+                Some((name, description)) if name.starts_with("STD_") => {
+                    routines.push((name, description.trim().to_string()));
+                }
+                _ if line.starts_with(";  ") && !text.is_empty() => {
+                    if let Some((_, description)) = routines.last_mut() {
+                        description.push(' ');
+                        description.push_str(text);
+                    }
+                }
+                _ => {}
+            }
+        }
+        routines
+    }
+}
+
+pub const MODULES: [Module; 5] = [
+    Module {
+        name: "math",
+        source: include_str!("../stdlib/math.asm"),
+    },
+    Module {
+        name: "convert",
+        source: include_str!("../stdlib/convert.asm"),
+    },
+    Module {
+        name: "string",
+        source: include_str!("../stdlib/string.asm"),
+    },
+    Module {
+        name: "memory",
+        source: include_str!("../stdlib/memory.asm"),
+    },
+    Module {
+        name: "heap",
+        source: include_str!("../stdlib/heap.asm"),
+    },
+];
+
+/// Finds the module an `.INCLUDE` names. Gives `None` for names outside of the library, and fails
+/// for names inside it that do not match a module of a compatible version.
+pub fn find(include: &str) -> Result<Option<&'static Module>, String> {
+    let Some((library, file)) = include.split_once('/') else {
+        return Ok(None);
+    };
+    let required = match library.split_once('@') {
+        Some(("std", version)) => Some(version),
+        None if library == "std" => None,
+        _ => return Ok(None),
+    };
+    if let Some(required) = required
+        && !is_compatible(required)?
+    {
+        return Err(format!(
+            "the bundled standard library is version {VERSION}, which is not compatible with {required}"
+        ));
+    }
+    let name = file.strip_suffix(".asm").unwrap_or(file);
+    match MODULES.iter().find(|module| module.name == name) {
+        Some(module) => Ok(Some(module)),
+        None => Err(format!(
+            "the standard library has no module `{name}`; it has {}",
+            MODULES
+                .map(|module| format!("`{}`", module.name))
+                .join(", ")
+        )),
+    }
+}
+
+/// Whether the bundled library can stand in for the version `required`, given as `major`,
+/// `major.minor` or `major.minor.patch`.
+fn is_compatible(required: &str) -> Result<bool, String> {
+    let parse = |version: &str| -> Option<Vec<u16>> {
+        let parts: Option<Vec<u16>> = version.split('.').map(|part| part.parse().ok()).collect();
+        parts.filter(|parts| (1..=3).contains(&parts.len()))
+    };
+    let required =
+        parse(required).ok_or_else(|| format!("`{required}` is not a library version"))?;
+    let current = parse(VERSION).expect("the library version is valid");
+    Ok(required[0] == current[0] && required[1..] <= current[1..required.len()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        assembler::{Assembly, assemble},
+        console::BufferConsole,
+        vm::VMState,
+    };
+
+    /// Values the registers that are not arguments start with, to check they are kept.
+    const UNTOUCHED: [u16; 4] = [0x1234, 0x2345, 0x3456, 0x4567];
+
+    struct Call {
+        vm: VMState,
+        assembly: Assembly,
+        /// What the routine printed.
+        output: String,
+    }
+
+    impl Call {
+        fn register(&self, index: usize) -> i16 {
+            self.vm.registers[index] as i16
+        }
+
+        /// The word at `label`.
+        fn word(&self, label: &str) -> u16 {
+            self.vm.memory[self.assembly.symbols[label].address as usize]
+        }
+
+        /// The zero-terminated string at `address`.
+        fn string_at(&self, address: u16) -> String {
+            self.vm.memory[address as usize..]
+                .iter()
+                .take_while(|word| **word != 0)
+                .map(|word| *word as u8 as char)
+                .collect()
+        }
+    }
+
+    /// Calls `routine` with R0 to R2 set to `args` (numbers or labels defined in `data`), and checks
+    /// that R2 to R6 are kept.
+    fn call(module: &str, routine: &str, args: [&str; 3], data: &str) -> Call {
+        let source = format!(
+            "        .ORIG x3000
+        LD R0, ARG0
+        LD R1, ARG1
+        LD R2, ARG2
+        LD R3, KEPT3
+        LD R4, KEPT4
+        LD R5, KEPT5
+        LD R6, KEPT6
+        JSR {routine}
+        HALT
+ARG0    .FILL {}
+ARG1    .FILL {}
+ARG2    .FILL {}
+KEPT3   .FILL x{:04X}
+KEPT4   .FILL x{:04X}
+KEPT5   .FILL x{:04X}
+KEPT6   .FILL x{:04X}
+{data}
+        .INCLUDE \"std/{module}.asm\"
+        .END",
+            args[0], args[1], args[2], UNTOUCHED[0], UNTOUCHED[1], UNTOUCHED[2], UNTOUCHED[3]
+        );
+        let assembly = assemble(&source).unwrap();
+        let mut vm = VMState::init().unwrap();
+        vm.write_ixs_to_mem(assembly.to_bytes());
+        let (console, output) = BufferConsole::new("");
+        vm.console = Box::new(console);
+        vm.execute().unwrap();
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        let call = Call {
+            vm,
+            assembly,
+            output: output.strip_suffix("Halt execution\n").unwrap().to_string(),
+        };
+        assert_eq!(
+            call.vm.registers[2],
+            call.word("ARG2"),
+            "{routine} changed R2"
+        );
+        assert_eq!(
+            &call.vm.registers[3..7],
+            &UNTOUCHED,
+            "{routine} changed R3-R6"
+        );
+        call
+    }
+
+    fn result(module: &str, routine: &str, a: i16, b: i16) -> (i16, i16) {
+        let call = call(
+            module,
+            routine,
+            [&format!("#{a}"), &format!("#{b}"), "#0"],
+            "",
+        );
+        (call.register(0), call.register(1))
+    }
+
+    #[test]
+    fn multiplies_and_divides() {
+        assert_eq!(result("math", "STD_MUL", 123, -45).0, 123 * -45);
+        assert_eq!(
+            result("math", "STD_MUL", 300, 300).0,
+            300i16.wrapping_mul(300)
+        );
+        for (a, b) in [
+            (17, 5),
+            (-17, 5),
+            (17, -5),
+            (-17, -5),
+            (32767, 1),
+            (-32768, 1),
+            (-32768, -32768),
+            (1000, -32768),
+            (12345, 7),
+        ] {
+            assert_eq!(
+                result("math", "STD_DIV", a, b),
+                (a.wrapping_div(b), a.wrapping_rem(b)),
+                "{a} / {b}"
+            );
+        }
+        assert_eq!(result("math", "STD_DIV", 42, 0), (0, 42));
+        // STD_MOD keeps R1.
+        assert_eq!(result("math", "STD_MOD", -23, 4), (-3, 4));
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(result("math", "STD_SHL", 3, 4).0, 48);
+        assert_eq!(result("math", "STD_SHL", 1, 16).0, 0);
+        assert_eq!(result("math", "STD_SHR", -16, 2).0, (0xFFF0u16 >> 2) as i16);
+        assert_eq!(result("math", "STD_SHR", 0x1234, 0), (0x1234, 0));
+        assert_eq!(result("math", "STD_SHR", -1, 20).0, 0);
+        assert_eq!(result("math", "STD_ASR", -16, 2), (-4, 2));
+        assert_eq!(result("math", "STD_ASR", 100, 3).0, 12);
+        assert_eq!(result("math", "STD_ASR", -32768, 15).0, -1);
+    }
+
+    #[test]
+    fn converts_numbers_to_text_and_back() {
+        for value in [0, 7, -7, 100, 32767, -32768, -1050] {
+            let call = call(
+                "convert",
+                "STD_ITOA",
+                [&format!("#{value}"), "BUFFER", "#0"],
+                "BUFFER  .BLKW 7",
+            );
+            assert_eq!(call.vm.registers[0], call.word("ARG1"));
+            assert_eq!(call.string_at(call.vm.registers[0]), value.to_string());
+        }
+        for (text, value, length) in [("-321x", -321, 4), ("+15", 15, 3), ("abc", 0, 0)] {
+            let call = call(
+                "convert",
+                "STD_ATOI",
+                ["TEXT", "#0", "#0"],
+                &format!("TEXT    .STRINGZ \"{text}\""),
+            );
+            assert_eq!(call.register(0), value, "{text}");
+            assert_eq!(call.vm.registers[1] - call.word("ARG0"), length, "{text}");
+        }
+        let call = call("convert", "STD_PRINT_DEC", ["#-2024", "#9", "#0"], "");
+        assert_eq!(call.output, "-2024");
+        assert_eq!((call.register(0), call.register(1)), (-2024, 9));
+    }
+
+    #[test]
+    fn handles_strings() {
+        let data = "A       .STRINGZ \"apple\"\nB       .STRINGZ \"apply\"\nCOPY    .BLKW 6";
+        assert_eq!(
+            call("string", "STD_STRLEN", ["A", "#0", "#0"], data).register(0),
+            5
+        );
+        let compared = call("string", "STD_STRCMP", ["A", "B", "#0"], data);
+        assert_eq!(compared.register(0), 'e' as i16 - 'y' as i16);
+        assert_eq!(compared.vm.registers[1], compared.word("ARG1"));
+        assert_eq!(
+            call("string", "STD_STRCMP", ["A", "A", "#0"], data).register(0),
+            0
+        );
+        let copied = call("string", "STD_STRCPY", ["COPY", "B", "#0"], data);
+        assert_eq!(copied.string_at(copied.vm.registers[0]), "apply");
+    }
+
+    #[test]
+    fn fills_and_copies_memory() {
+        let data = "FIRST   .BLKW 1\nBLOCK   .FILL #1\n        .FILL #2\n        .FILL #3\n        .FILL #4\n        .FILL #5";
+        let words = |call: &Call, label: &str| {
+            let start = call.assembly.symbols[label].address as usize;
+            call.vm.memory[start..start + 5].to_vec()
+        };
+        let call_with = |routine: &str, args: [&str; 3]| {
+            let call = call("memory", routine, args, data);
+            assert_eq!(
+                call.vm.registers[..2],
+                [call.word("ARG0"), call.word("ARG1")]
+            );
+            call
+        };
+        let filled = call_with("STD_MEMSET", ["BLOCK", "#9", "#3"]);
+        assert_eq!(words(&filled, "BLOCK"), vec![9, 9, 9, 4, 5]);
+        // Overlapping copies, in both directions.
+        let copied = call_with("STD_MEMCPY", ["FIRST", "BLOCK", "#5"]);
+        assert_eq!(words(&copied, "FIRST"), vec![1, 2, 3, 4, 5]);
+        let copied = call_with("STD_MEMCPY", ["BLOCK", "FIRST", "#5"]);
+        assert_eq!(words(&copied, "BLOCK"), vec![0, 1, 2, 3, 4]);
+        assert_eq!(
+            call_with("STD_MEMSET", ["BLOCK", "#9", "#0"]).word("BLOCK"),
+            1
+        );
+    }
+
+    #[test]
+    fn allocates_and_frees_heap_blocks() {
+        // Allocates A and B, frees A and allocates C, which reuses it. Then frees B and C and
+        // allocates D, which only fits once they are merged, and finally asks for too much.
+        let program = "
+TEST    ST R7, SAVE
+        LD R0, HEAP
+        LD R1, HEAPSIZE
+        JSR STD_HEAP_INIT
+        AND R0, R0, #0
+        ADD R0, R0, #4
+        JSR STD_MALLOC
+        ST R0, A
+        AND R0, R0, #0
+        ADD R0, R0, #4
+        JSR STD_MALLOC
+        ST R0, B
+        LD R0, A
+        JSR STD_FREE
+        AND R0, R0, #0
+        ADD R0, R0, #2
+        JSR STD_MALLOC
+        ST R0, C
+        LD R0, B
+        JSR STD_FREE
+        LD R0, C
+        JSR STD_FREE
+        AND R0, R0, #0
+        ADD R0, R0, #9
+        JSR STD_MALLOC
+        ST R0, D
+        AND R0, R0, #0
+        ADD R0, R0, #15
+        JSR STD_MALLOC
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+HEAP    .FILL x5000
+HEAPSIZE .FILL #12
+A       .BLKW 1
+B       .BLKW 1
+C       .BLKW 1
+D       .BLKW 1";
+        let call = call("heap", "TEST", ["#0", "#0", "#0"], program);
+        assert_eq!(call.word("A"), 0x5001);
+        assert_eq!(call.word("B"), 0x5006);
+        assert_eq!(call.word("C"), 0x5001);
+        assert_eq!(call.word("D"), 0x5001);
+        assert_eq!(call.register(0), 0, "there is no room for 15 words");
+        // D took the whole heap: 10 words, as splitting would leave a single word.
+        assert_eq!(call.vm.memory[0x5000], 0x8000 | 10);
+        assert_eq!(call.vm.memory[0x500B], 0);
+    }
+
+    #[test]
+    fn finds_modules_by_version() {
+        assert_eq!(find("std/math.asm").unwrap().unwrap().name, "math");
+        assert_eq!(find("std@1/heap").unwrap().unwrap().name, "heap");
+        assert!(find("std@1.0.0/string.asm").unwrap().is_some());
+        assert!(find("std@1.1/math.asm").is_err());
+        assert!(find("std@0.9/math.asm").is_err());
+        assert!(find("std/nothing.asm").is_err());
+        assert!(find("lib/math.asm").unwrap().is_none());
+        assert_eq!(MODULES[0].summary(), "multiplication, division and shifts.");
+        let routines = MODULES[0].routines();
+        assert_eq!(routines[0].0, "STD_MUL");
+        assert!(
+            routines[1]
+                .1
+                .ends_with("leaves the dividend as the remainder.")
+        );
+    }
+}
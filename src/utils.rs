@@ -25,7 +25,7 @@ pub fn get_char() -> Result<u16, VMError> {
 /// The purpose of this function is to disable input buffering in the termial running the VM, so every input byte gets sent individually.
 /// It returns the previous state of the terminal so it can be restored after the VM finishes running.
 pub fn disable_input_buffering() -> Result<Termios, VMError> {
-    let fd = std::io::stdin().lock().as_raw_fd();
+    let fd = std::io::stdin().as_raw_fd();
     let mut termios = Termios::from_fd(fd).map_err(|e| VMError::TermiosError(e.to_string()))?;
     let original_setup = termios;
     termios.c_lflag &= !ICANON & !ECHO;
@@ -37,7 +37,7 @@ pub fn disable_input_buffering() -> Result<Termios, VMError> {
 /// that provides the original configurations before changing them as return value. This function should use that value to restore the terminal
 /// setup to the initial one.
 pub fn restore_terminal(termios: Termios) -> Result<(), VMError> {
-    let fd = std::io::stdin().lock().as_raw_fd();
+    let fd = std::io::stdin().as_raw_fd();
     tcsetattr(fd, TCSANOW, &termios).map_err(|e| VMError::TermiosError(e.to_string()))?;
     Ok(())
 }
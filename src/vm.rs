@@ -1,11 +1,9 @@
-use std::io::Write;
-
-use crate::opcodes::Opcode::{self, *};
+use crate::instruction::Instruction;
 use crate::operations::add::handle_add;
 use crate::operations::and::handle_and;
 use crate::operations::br::handle_br;
 use crate::operations::jmp::handle_jmp;
-use crate::operations::jsr::handle_jsr;
+use crate::operations::jsr::{handle_jsr, handle_jsrr};
 use crate::operations::ld::handle_ld;
 use crate::operations::ldi::handle_ldi;
 use crate::operations::ldr::handle_ldr;
@@ -14,13 +12,21 @@ use crate::operations::not::handle_not;
 use crate::operations::st::handle_st;
 use crate::operations::sti::handle_sti;
 use crate::operations::str::handle_str;
-use crate::operations::trap::handle_trap;
+use crate::operations::trap::{handle_trap, print_str};
 use crate::registers::Register::*;
+use std::collections::BTreeMap;
+
 use crate::{
+    assembler::{Symbol, debuginfo::DebugInfo},
+    callstack::CallStack,
+    console::{Console, Terminal},
     error::VMError,
     flags::Flag,
+    history::{AccessKind, Change, History, MemoryAccess},
+    recording::Recorder,
     registers::{MemoryRegister, Register},
-    utils::{disable_input_buffering, get_char, restore_terminal},
+    utils::{disable_input_buffering, restore_terminal},
+    watchpoint::{self, Hit, Watchpoint},
 };
 
 /// Memory size for LC-3 architecture, where each memory position stores a 16 bit value. [See more here.](https://www.jmeiners.com/lc3-vm/#lc-3-architecture)
@@ -33,6 +39,29 @@ pub struct VMState {
     pub memory: [u16; MEMORY_MAX],
     /// A fixed size array representing the registers of the VM.
     pub registers: [u16; Register::COUNT],
+    /// The keyboard and the display of the VM. It is the terminal unless a tool replaces it.
+    pub console: Box<dyn Console>,
+    /// Whether words that are not well-formed instructions stop the program with
+    /// `VMError::IllegalInstruction` instead of running with their unused bits ignored.
+    pub strict: bool,
+    /// The memory accesses to report while executing instructions.
+    pub watchpoints: Vec<Watchpoint>,
+    /// The accesses that matched a watchpoint, in order, until whoever runs the VM takes them.
+    pub hits: Vec<Hit>,
+    /// The address and the word of the instruction being executed, if any.
+    executing: Option<(u16, u16)>,
+    /// The instructions executed, so they can be undone. Nothing is recorded unless it is set.
+    pub history: Option<History>,
+    /// The memory accesses of the instruction being recorded.
+    recording: Option<Vec<MemoryAccess>>,
+    /// What `execute` gives every instruction it executes to, like a recording file.
+    pub recorders: Vec<Box<dyn Recorder>>,
+    /// The calls and traps the program is in.
+    pub calls: CallStack,
+    /// The labels of the program, if they are known, to name addresses in backtraces.
+    pub symbols: BTreeMap<String, Symbol>,
+    /// The source lines of the program, if they are known, for backtraces.
+    pub debug: Option<DebugInfo>,
 }
 impl VMState {
     /// Acts as the constructor of the VMState, initiating it with default values: the memory starts empty (filled with zeros in each position)
@@ -42,6 +71,17 @@ impl VMState {
         let mut vm = Self {
             memory: [0; MEMORY_MAX],
             registers: [0; Register::COUNT],
+            console: Box::new(Terminal),
+            strict: false,
+            watchpoints: Vec::new(),
+            hits: Vec::new(),
+            executing: None,
+            history: None,
+            recording: None,
+            recorders: Vec::new(),
+            calls: CallStack::default(),
+            symbols: BTreeMap::new(),
+            debug: None,
         };
         vm.registers[Register::Cond] = Flag::Zro.try_into()?;
         vm.registers[Register::PC] = 0x3000; // Set PC to starting position. 0x3000 is the default.
@@ -65,33 +105,82 @@ impl VMState {
             // We take two bytes at a time.
             let content =
                 u16::from_be_bytes([parsed_file[file_index], parsed_file[file_index + 1]]);
-            self.mem_write(offset, content);
+            self.memory[offset as usize] = content;
             file_index += 2;
             offset += 1;
         }
     }
 
     /// Writes the content passed as `val` inside the memory position given by `address`.
+    /// Writes made by an instruction are checked against the watchpoints.
     pub fn mem_write(&mut self, address: u16, val: u16) {
+        let old = self.memory[address as usize];
         self.memory[address as usize] = val;
+        self.record(AccessKind::Write, address, old, val);
+        self.watch(address, true, old, val);
     }
 
     /// Reads the content of the memory in a specific position. If the address to be read is the corresponding to the
-    /// memory register `Keyboard Status (Kbsr)`, the VM tries to read a character from stdin. In case it reads something
+    /// memory register `Keyboard Status (Kbsr)`, the VM tries to read a character from the console. In case it reads something
     /// it stores the new value in the other memory register `Keyboard Data (Kbdr)`, otherwise it stores 0.
+    /// Reads made by an instruction are checked against the watchpoints.
     pub fn mem_read(&mut self, address: u16) -> Result<u16, VMError> {
+        let value = self.load(address)?;
+        self.record(AccessKind::Read, address, value, value);
+        self.watch(address, false, value, value);
+        Ok(value)
+    }
+
+    /// Reads memory like `mem_read`, without checking the watchpoints.
+    fn load(&mut self, address: u16) -> Result<u16, VMError> {
         if address == MemoryRegister::Kbsr.try_into()? {
-            let char = get_char()?;
+            let char = self.console.read_char()?;
             if char != 0 {
-                self.memory[MemoryRegister::Kbsr] = 1 << 15;
-                self.memory[MemoryRegister::Kbdr] = char;
+                self.set_device(MemoryRegister::Kbsr as u16, 1 << 15);
+                self.set_device(MemoryRegister::Kbdr as u16, char);
             } else {
-                self.memory[MemoryRegister::Kbsr] = 0;
+                self.set_device(MemoryRegister::Kbsr as u16, 0);
             }
         }
         Ok(self.memory[address as usize])
     }
 
+    /// Updates a device register, recording it in the history.
+    fn set_device(&mut self, address: u16, value: u16) {
+        let old = self.memory[address as usize];
+        self.memory[address as usize] = value;
+        self.record(AccessKind::Device, address, old, value);
+    }
+
+    /// Records a memory access of the instruction being executed, if the history is on.
+    fn record(&mut self, kind: AccessKind, address: u16, old: u16, new: u16) {
+        if let Some(accesses) = &mut self.recording {
+            accesses.push(MemoryAccess {
+                kind,
+                address,
+                old,
+                new,
+            });
+        }
+    }
+
+    /// Records the access to `address` if it matches a watchpoint and an instruction made it.
+    fn watch(&mut self, address: u16, write: bool, old: u16, new: u16) {
+        let Some((pc, instruction)) = self.executing else {
+            return;
+        };
+        if let Some(access) = watchpoint::matching(&self.watchpoints, address, write, old, new) {
+            self.hits.push(Hit {
+                access,
+                address,
+                pc,
+                instruction,
+                old,
+                new,
+            });
+        }
+    }
+
     /// Runs the virtual machine and executes instruction loop.
     pub fn run(&mut self, file_vec: Vec<u8>) -> Result<(), VMError> {
         // We disable input buffering (keys will be detected as soon as they are pressed and they will not be echoed).
@@ -101,46 +190,185 @@ impl VMState {
         // Write the obtained instructions from the file into VM's memory
         self.write_ixs_to_mem(file_vec);
 
-        // Set the running flag to true - only HALT instruction will set it to false and stop the execution loop.
-        let mut running = true;
+        let result = self.execute();
+
+        // When the program is finished, restore terminal to its original configuration. This is done even if the
+        // program failed, so the terminal is usable again.
+        restore_terminal(original_terminal_setup)?;
+        result
+    }
 
-        // Execution loop.
-        while running {
-            // Get the next instruction from memory - its address is stored in the PC register.
-            let ix: u16 = self.mem_read(self.registers[PC])?;
-            // Update the Program Counter to store the next ix address.
-            self.registers[PC] += 1;
-            // Decode instruction opcode.
-            let opcode = Opcode::try_from(ix >> 12)?;
-
-            // Handle opcode.
-            match opcode {
-                OpADD => handle_add(ix, self)?,
-                OpAND => handle_and(ix, self)?,
-                OpNOT => handle_not(ix, self)?,
-                OpBR => handle_br(ix, self)?,
-                OpJMP => handle_jmp(ix, self)?,
-                OpJSR => handle_jsr(ix, self)?,
-                OpLD => handle_ld(ix, self)?,
-                OpLDI => handle_ldi(ix, self)?,
-                OpLDR => handle_ldr(ix, self)?,
-                OpLEA => handle_lea(ix, self)?,
-                OpST => handle_st(ix, self)?,
-                OpSTI => handle_sti(ix, self)?,
-                OpSTR => handle_str(ix, self)?,
-                OpTRAP => handle_trap(ix, self, &mut running)?,
-                OpRES => println!("Opcode is RES"), // Unused
-                OpRTI => println!("Opcode is RTI"), // Unused
+    /// Executes instructions from the current PC until the program halts. Unlike `run`, it neither loads a
+    /// program nor touches the terminal, so it works with any console.
+    /// Every instruction is given to the recorders, which are told how the program ended.
+    pub fn execute(&mut self) -> Result<(), VMError> {
+        // Only HALT instruction stops the execution loop. Watchpoint hits are logged as they happen.
+        let result = loop {
+            let running = match self.recorders.is_empty() {
+                true => self.step(),
+                false => self.step_recorded().and_then(|change| {
+                    for recorder in &mut self.recorders {
+                        recorder.record(&change)?;
+                    }
+                    Ok(change.running)
+                }),
+            };
+            for hit in self.hits.drain(..) {
+                eprintln!("[watchpoint] {hit}");
+            }
+            match running {
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(error) => break Err(error),
             }
+        };
+        for recorder in &mut self.recorders {
+            recorder.finish(&result)?;
+        }
+        result
+    }
+
+    /// Executes the instruction the PC points to. Returns whether the program is still running afterwards, which
+    /// is false once it executes HALT.
+    /// With the history on, the instruction is recorded, or replayed if it was undone.
+    pub fn step(&mut self) -> Result<bool, VMError> {
+        let Some(history) = &mut self.history else {
+            return self.execute_next();
+        };
+        if let Some(change) = history.redo(&self.registers, &self.memory) {
+            return Ok(self.replay(change));
+        }
+        history.checkpoint(&self.registers, &self.memory, &self.calls);
+        let calls = self.calls.clone();
+        // A failed instruction is not recorded, as it cannot be replayed.
+        let change = self.step_recorded()?;
+        let running = change.running;
+        if let Some(history) = &mut self.history {
+            history.push(change, calls);
+        }
+        Ok(running)
+    }
+
+    /// Executes the instruction the PC points to like `step` without the history, returning what it
+    /// did.
+    pub fn step_recorded(&mut self) -> Result<Change, VMError> {
+        let before = self.registers;
+        let instruction = self.memory[before[PC] as usize];
+        self.recording = Some(Vec::new());
+        let result = self.execute_next();
+        let accesses = self.recording.take().unwrap_or_default();
+        Ok(Change {
+            instruction,
+            before,
+            after: self.registers,
+            accesses,
+            running: result?,
+        })
+    }
 
-            // If operation was I/O force output to be delivered right away.
-            std::io::stdout()
-                .flush()
-                .map_err(|e| VMError::ErrorFlushinStdout(e.to_string()))?;
+    /// Does again what an undone instruction did, reporting its watched accesses again. Returns
+    /// whether the program was still running after it.
+    fn replay(&mut self, change: Change) -> bool {
+        self.executing = Some((change.before[PC], change.instruction));
+        for access in &change.accesses {
+            self.memory[access.address as usize] = access.new;
+            if access.kind != AccessKind::Device {
+                let write = access.kind == AccessKind::Write;
+                self.watch(access.address, write, access.old, access.new);
+            }
         }
+        self.executing = None;
+        let (address, instruction) = (change.before[PC], Instruction::decode(change.instruction));
+        self.calls.enter(address, &instruction);
+        self.calls.leave(address, &instruction, &change.after);
+        self.registers = change.after;
+        change.running
+    }
 
-        // When the program is finished, restore terminal to its original configuration.
-        restore_terminal(original_terminal_setup)?;
+    /// Undoes the last instruction recorded in the history, reporting its watched accesses as hits.
+    /// Returns false if there is none.
+    pub fn step_back(&mut self) -> bool {
+        let Some((change, calls)) = self.history.as_mut().and_then(History::undo) else {
+            return false;
+        };
+        self.calls = calls;
+        for access in change.accesses.iter().rev() {
+            self.memory[access.address as usize] = access.old;
+        }
+        self.registers = change.before;
+        self.executing = Some((change.before[PC], change.instruction));
+        for access in &change.accesses {
+            if access.kind != AccessKind::Device {
+                let write = access.kind == AccessKind::Write;
+                self.watch(access.address, write, access.old, access.new);
+            }
+        }
+        self.executing = None;
+        true
+    }
+
+    /// Executes the instruction the PC points to, without the history. Its errors come with the
+    /// backtrace of the program.
+    fn execute_next(&mut self) -> Result<bool, VMError> {
+        let address = self.registers[PC];
+        self.execute_at(address).map_err(|error| VMError::Crashed {
+            backtrace: self.calls.backtrace(
+                address,
+                self.memory[address as usize],
+                &self.symbols,
+                self.debug.as_ref(),
+            ),
+            error: Box::new(error),
+        })
+    }
+
+    /// Executes the instruction at `address`, which the PC points to.
+    fn execute_at(&mut self, address: u16) -> Result<bool, VMError> {
+        let mut running = true;
+        // Get the next instruction from memory.
+        let ix: u16 = self.load(address)?;
+        // Update the Program Counter to store the next ix address.
+        self.registers[PC] = self.registers[PC].wrapping_add(1);
+        // Decode the instruction and execute it.
+        let instruction = if self.strict {
+            Instruction::decode_strict(ix)
+                .ok_or(VMError::IllegalInstruction { address, word: ix })?
+        } else {
+            Instruction::decode(ix)
+        };
+        self.executing = Some((address, ix));
+        self.calls.enter(address, &instruction);
+        let result = self.dispatch(instruction, &mut running);
+        self.executing = None;
+        result?;
+        self.calls.leave(address, &instruction, &self.registers);
+
+        // If operation was I/O force output to be delivered right away.
+        self.console.flush()?;
+        Ok(running)
+    }
+
+    /// Executes a decoded instruction, clearing `running` if it halts the program.
+    fn dispatch(&mut self, instruction: Instruction, running: &mut bool) -> Result<(), VMError> {
+        match instruction {
+            Instruction::Add { dest, src, operand } => handle_add(dest, src, operand, self)?,
+            Instruction::And { dest, src, operand } => handle_and(dest, src, operand, self)?,
+            Instruction::Not { dest, src } => handle_not(dest, src, self)?,
+            Instruction::Br { conditions, offset } => handle_br(conditions, offset, self)?,
+            Instruction::Jmp { base } => handle_jmp(base, self)?,
+            Instruction::Jsr { offset } => handle_jsr(offset, self)?,
+            Instruction::Jsrr { base } => handle_jsrr(base, self)?,
+            Instruction::Ld { dest, offset } => handle_ld(dest, offset, self)?,
+            Instruction::Ldi { dest, offset } => handle_ldi(dest, offset, self)?,
+            Instruction::Ldr { dest, base, offset } => handle_ldr(dest, base, offset, self)?,
+            Instruction::Lea { dest, offset } => handle_lea(dest, offset, self)?,
+            Instruction::St { src, offset } => handle_st(src, offset, self)?,
+            Instruction::Sti { src, offset } => handle_sti(src, offset, self)?,
+            Instruction::Str { src, base, offset } => handle_str(src, base, offset, self)?,
+            Instruction::Trap { vector } => handle_trap(vector, self, running)?,
+            Instruction::Reserved(_) => print_str(self, "Opcode is RES\n")?, // Unused
+            Instruction::Rti(_) => print_str(self, "Opcode is RTI\n")?,      // Unused
+        }
         Ok(())
     }
 }
@@ -150,6 +378,7 @@ mod test {
     use super::*;
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn writes_ix_to_memory() {
         let mut vm = VMState::init().unwrap();
         let origin: u16 = 0x3000;
@@ -165,4 +394,86 @@ mod test {
         assert_eq!(vm.memory[origin as usize], first_ix);
         assert_eq!(vm.memory[(origin + 1) as usize], second_ix);
     }
+
+    #[test]
+    fn records_watched_accesses_of_instructions() {
+        let mut vm = VMState::init().unwrap();
+        vm.watchpoints = vec![
+            Watchpoint::parse("write:x3010", &Default::default()).unwrap(),
+            Watchpoint::parse("read:x3000-x3010", &Default::default()).unwrap(),
+        ];
+        vm.mem_write(0x3000, 0x2007); // LD R0, #7 (x3008)
+        vm.mem_write(0x3001, 0x300E); // ST R0, #14 (x3010)
+        vm.memory[0x3008] = 5;
+        vm.step().unwrap();
+        vm.step().unwrap();
+        // Neither the fetches nor the writes made before running count.
+        assert_eq!(
+            vm.hits,
+            [
+                Hit {
+                    access: watchpoint::Access::Read,
+                    address: 0x3008,
+                    pc: 0x3000,
+                    instruction: 0x2007,
+                    old: 5,
+                    new: 5,
+                },
+                Hit {
+                    access: watchpoint::Access::Write,
+                    address: 0x3010,
+                    pc: 0x3001,
+                    instruction: 0x300E,
+                    old: 0,
+                    new: 5,
+                },
+            ]
+        );
+        assert_eq!(
+            vm.hits[1].to_string(),
+            "x3001 (ST R0, #14) wrote x3010: x0000 -> x0005"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_instructions_in_strict_mode() {
+        let mut vm = VMState::init().unwrap();
+        // NOT R0, R0 with the six low bits clear instead of all set.
+        vm.mem_write(0x3000, 0x9000);
+        assert!(vm.step().is_ok());
+
+        let mut vm = VMState::init().unwrap();
+        vm.strict = true;
+        vm.mem_write(0x3000, 0x903F);
+        vm.mem_write(0x3001, 0x9000);
+        assert!(vm.step().is_ok());
+        let Err(VMError::Crashed { error, backtrace }) = vm.step() else {
+            panic!("the malformed instruction ran");
+        };
+        assert!(matches!(
+            *error,
+            VMError::IllegalInstruction {
+                address: 0x3001,
+                word: 0x9000
+            }
+        ));
+        assert_eq!(backtrace.0, ["at x3001 .FILL x9000"]);
+    }
+
+    #[test]
+    fn rejects_rti_in_strict_mode() {
+        let mut vm = VMState::init().unwrap();
+        vm.strict = true;
+        vm.mem_write(0x3000, 0x8000);
+        let Err(VMError::Crashed { error, .. }) = vm.step() else {
+            panic!("RTI ran in user mode");
+        };
+        assert!(matches!(
+            *error,
+            VMError::IllegalInstruction {
+                address: 0x3000,
+                word: 0x8000
+            }
+        ));
+    }
 }
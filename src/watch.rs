@@ -0,0 +1,266 @@
+//! Watch mode for `run --watch`: runs a program and starts it again from scratch every time its
+//! file, or a file it includes, changes, until the user stops it with Ctrl-C.
+//!
+//! The files are polled for changes, both while the program runs (every few thousand instructions,
+//! and while it waits for a key) and after it halts or fails. Keys are read from stdin by a thread
+//! of their own, so a program waiting for input can still be restarted; keys typed before a run
+//! starts are dropped. The terminal is put back into its normal mode between runs, so messages and
+//! errors print as usual.
+use std::{
+    cell::Cell,
+    fs,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    console::{Console, Terminal},
+    error::VMError,
+    utils::{disable_input_buffering, get_char, restore_terminal},
+    vm::VMState,
+};
+
+/// How often the files are checked while nothing else is happening.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How many instructions run between two checks of the files.
+const CHECK_EVERY: usize = 10_000;
+
+/// Runs the program in `path` every time it changes, in strict mode if `strict`. `load` turns the
+/// file into a program image and the paths of the files it includes, reporting its own errors; a
+/// file that does not load is tried again once it, or a file it included last time, changes.
+pub fn watch(
+    path: &str,
+    strict: bool,
+    load: impl Fn(&str) -> Result<(Vec<u8>, Vec<String>), VMError>,
+) -> Result<(), VMError> {
+    let keys = Rc::new(spawn_key_reader());
+    let mut files = vec![path.to_string()];
+    loop {
+        // The files are looked at before loading, so changes made while it loads are not missed.
+        let mut watcher = Watcher::new(&files);
+        let loaded = load(path);
+        if let Ok((_, included)) = &loaded {
+            files.truncate(1);
+            files.extend(included.iter().cloned());
+            watcher.add(included);
+        }
+        let watcher = Rc::new(watcher);
+        match loaded {
+            Ok((image, _)) => {
+                println!("[watch] running {path}");
+                match run(image, strict, &watcher, &keys) {
+                    Ok(true) => {
+                        // The program may have been stopped in the middle of a line.
+                        println!("\n[watch] {path} changed, restarting");
+                        continue;
+                    }
+                    Ok(false) => println!("[watch] the program halted"),
+                    Err(error) => println!("\n[watch] the program failed: {error:?}"),
+                }
+            }
+            Err(VMError::AssemblyFailed(_)) => {}
+            Err(error) => println!("[watch] could not load {path}: {error:?}"),
+        }
+        println!("[watch] waiting for {path} to change (Ctrl-C to quit)");
+        while !watcher.check() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Runs `image` on a fresh VM until it halts or one of the files changes. Returns whether it was
+/// stopped because a file changed.
+fn run(
+    image: Vec<u8>,
+    strict: bool,
+    watcher: &Rc<Watcher>,
+    keys: &Rc<Receiver<u8>>,
+) -> Result<bool, VMError> {
+    // Keys typed while no program was running are not meant for this one.
+    while keys.try_recv().is_ok() {}
+    let mut vm = VMState::init()?;
+    vm.strict = strict;
+    vm.console = Box::new(WatchConsole {
+        keys: Rc::clone(keys),
+        watcher: Rc::clone(watcher),
+    });
+    vm.write_ixs_to_mem(image);
+
+    let original_terminal_setup = disable_input_buffering()?;
+    let mut result = Ok(false);
+    let mut steps = 0;
+    loop {
+        steps += 1;
+        if watcher.changed.get() || (steps % CHECK_EVERY == 0 && watcher.check()) {
+            result = Ok(true);
+            break;
+        }
+        match vm.step() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+    restore_terminal(original_terminal_setup)?;
+    result
+}
+
+/// Starts a thread that sends every byte read from stdin.
+fn spawn_key_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(char) = get_char() {
+            if sender.send(char as u8).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Tells whether any of a few files changed since they were added to it.
+struct Watcher {
+    /// Each file with the time it was last modified when it was added.
+    files: Vec<(String, Option<SystemTime>)>,
+    /// Whether a change was already seen, so it is only looked for once.
+    changed: Cell<bool>,
+}
+
+impl Watcher {
+    fn new(paths: &[String]) -> Self {
+        let mut watcher = Self {
+            files: Vec::new(),
+            changed: Cell::new(false),
+        };
+        watcher.add(paths);
+        watcher
+    }
+
+    /// Starts watching the files of `paths` that are not watched yet.
+    fn add(&mut self, paths: &[String]) {
+        for path in paths {
+            if !self.files.iter().any(|(file, _)| file == path) {
+                self.files.push((path.clone(), modified(path)));
+            }
+        }
+    }
+
+    /// Looks at the files again and tells whether any of them changed.
+    fn check(&self) -> bool {
+        if !self.changed.get()
+            && self
+                .files
+                .iter()
+                .any(|(path, time)| modified(path) != *time)
+        {
+            self.changed.set(true);
+        }
+        self.changed.get()
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The terminal, with keys coming from the reader thread so waiting for one can be cut short when
+/// a file changes. It then reads as if no key was pressed, and the run stops before the next
+/// instruction.
+struct WatchConsole {
+    keys: Rc<Receiver<u8>>,
+    watcher: Rc<Watcher>,
+}
+
+impl Console for WatchConsole {
+    fn read_char(&mut self) -> Result<u16, VMError> {
+        loop {
+            if self.watcher.check() {
+                return Ok(0);
+            }
+            match self.keys.recv_timeout(POLL_INTERVAL) {
+                Ok(byte) => return Ok(u16::from(byte)),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(VMError::CouldNotReadChar("stdin was closed".to_string()));
+                }
+            }
+        }
+    }
+
+    fn write_char(&mut self, char: u8) -> Result<(), VMError> {
+        Terminal.write_char(char)
+    }
+
+    fn flush(&mut self) -> Result<(), VMError> {
+        Terminal.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn sees_when_the_file_changes() {
+        let path = std::env::temp_dir().join(format!("basic-vm-watch-{}.asm", std::process::id()));
+        fs::write(&path, "HALT").unwrap();
+        let files = [path.to_string_lossy().into_owned()];
+        let watcher = Watcher::new(&files);
+        assert!(!watcher.check());
+        // Two writes in a row may get the same modification time, so it is set instead.
+        let file = File::options().write(true).open(&path).unwrap();
+        let before = file.metadata().unwrap().modified().unwrap();
+        file.set_modified(before + Duration::from_secs(1)).unwrap();
+        assert!(watcher.check());
+        // A change stays seen, even if the file looks the same again.
+        file.set_modified(before).unwrap();
+        assert!(watcher.check());
+        // A file that goes away changed too.
+        let watcher = Watcher::new(&files);
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.check());
+    }
+
+    #[test]
+    fn sees_when_an_added_file_changes() {
+        let dir = std::env::temp_dir().join(format!("basic-vm-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.asm");
+        let included = dir.join("lib.asm");
+        fs::write(&main, "HALT").unwrap();
+        fs::write(&included, "RET").unwrap();
+        let mut watcher = Watcher::new(&[main.to_string_lossy().into_owned()]);
+        watcher.add(&[included.to_string_lossy().into_owned()]);
+        assert!(!watcher.check());
+        let file = File::options().write(true).open(&included).unwrap();
+        let before = file.metadata().unwrap().modified().unwrap();
+        file.set_modified(before + Duration::from_secs(1)).unwrap();
+        assert!(watcher.check());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stops_waiting_for_a_key_when_the_file_changes() {
+        let (sender, keys) = mpsc::channel();
+        let watcher = Rc::new(Watcher::new(&["basic-vm-watch-missing.asm".to_string()]));
+        let mut console = WatchConsole {
+            keys: Rc::new(keys),
+            watcher: Rc::clone(&watcher),
+        };
+        sender.send(b'a').unwrap();
+        assert_eq!(console.read_char().unwrap(), u16::from(b'a'));
+        watcher.changed.set(true);
+        sender.send(b'b').unwrap();
+        assert_eq!(console.read_char().unwrap(), 0);
+    }
+}
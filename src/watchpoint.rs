@@ -0,0 +1,206 @@
+//! Memory watchpoints: addresses whose reads, writes or changes by the running program are reported.
+//!
+//! The VM checks its watchpoints on every access made through `VMState::mem_read` and
+//! `VMState::mem_write` while executing an instruction, and records the accesses that match as
+//! `Hit`s. Fetching instructions, loading a program and tools editing memory do not count. What is
+//! done with the hits is up to whoever runs the VM: the debugger stops, a batch run logs them.
+use std::{collections::BTreeMap, fmt};
+
+use crate::{assembler::Symbol, debugger::inspect, instruction::Instruction};
+
+/// The accesses a watchpoint reports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Writes that change the value stored.
+    Change,
+}
+
+impl Access {
+    fn name(self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Change => "change",
+        }
+    }
+}
+
+/// A watchpoint on the addresses from `start` to `end`, both included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub access: Access,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Watchpoint {
+    /// Parses `<read|write|change>:<range>`, where the range is an address or `start-end`, as
+    /// numbers or labels.
+    pub fn parse(text: &str, symbols: &BTreeMap<String, Symbol>) -> Result<Self, String> {
+        let (access, range) = text
+            .split_once(':')
+            .ok_or_else(|| format!("`{text}` is not <read|write|change>:<range>"))?;
+        let access = parse_access(access)?;
+        let (start, end) = parse_range(range, symbols)?;
+        Ok(Self { access, start, end })
+    }
+
+    fn covers(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{} x{:04X}", self.access.name(), self.start),
+            false => write!(
+                f,
+                "{} x{:04X}-x{:04X}",
+                self.access.name(),
+                self.start,
+                self.end
+            ),
+        }
+    }
+}
+
+/// `read`, `write` or `change`.
+pub fn parse_access(text: &str) -> Result<Access, String> {
+    match text {
+        "read" => Ok(Access::Read),
+        "write" => Ok(Access::Write),
+        "change" => Ok(Access::Change),
+        _ => Err(format!("`{text}` is not read, write or change")),
+    }
+}
+
+/// An address, or the addresses from `start` to `end` (both included) written `start-end`.
+pub fn parse_range(text: &str, symbols: &BTreeMap<String, Symbol>) -> Result<(u16, u16), String> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (
+            inspect::value(start, symbols)?,
+            inspect::value(end, symbols)?,
+        ),
+        None => {
+            let address = inspect::value(text, symbols)?;
+            (address, address)
+        }
+    };
+    if end < start {
+        return Err(format!("the range `{text}` ends before it starts"));
+    }
+    Ok((start, end))
+}
+
+/// An access that matched a watchpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// The kind of watchpoint it matched: `Change` only when no write watchpoint covers the address.
+    pub access: Access,
+    pub address: u16,
+    /// The address and the word of the instruction that made the access.
+    pub pc: u16,
+    pub instruction: u16,
+    /// The value before and after the access, which are the same for reads.
+    pub old: u16,
+    pub new: u16,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instruction = Instruction::decode(self.instruction);
+        match self.access {
+            Access::Read => write!(
+                f,
+                "x{:04X} ({instruction}) read x{:04X}: x{:04X}",
+                self.pc, self.address, self.old
+            ),
+            Access::Write | Access::Change => write!(
+                f,
+                "x{:04X} ({instruction}) wrote x{:04X}: x{:04X} -> x{:04X}",
+                self.pc, self.address, self.old, self.new
+            ),
+        }
+    }
+}
+
+/// The access to report for reading or writing `address`, if a watchpoint covers it. Writing is a
+/// change when `old` and `new` differ.
+pub fn matching(
+    watchpoints: &[Watchpoint],
+    address: u16,
+    write: bool,
+    old: u16,
+    new: u16,
+) -> Option<Access> {
+    let covering = || {
+        watchpoints
+            .iter()
+            .filter(|watchpoint| watchpoint.covers(address))
+    };
+    if !write {
+        return covering()
+            .any(|watchpoint| watchpoint.access == Access::Read)
+            .then_some(Access::Read);
+    }
+    if covering().any(|watchpoint| watchpoint.access == Access::Write) {
+        return Some(Access::Write);
+    }
+    (old != new && covering().any(|watchpoint| watchpoint.access == Access::Change))
+        .then_some(Access::Change)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_watchpoints() {
+        let mut symbols = BTreeMap::new();
+        symbols.insert(
+            "COUNT".to_string(),
+            Symbol {
+                address: 0x3010,
+                line: 1,
+                origin: None,
+            },
+        );
+        let watchpoint = Watchpoint::parse("change:COUNT", &symbols).unwrap();
+        assert_eq!(watchpoint.to_string(), "change x3010");
+        let watchpoint = Watchpoint::parse("read:x4000-x400F", &symbols).unwrap();
+        assert_eq!(watchpoint.to_string(), "read x4000-x400F");
+        assert!(Watchpoint::parse("read:x4000-x3000", &symbols).is_err());
+        assert!(Watchpoint::parse("touch:x4000", &symbols).is_err());
+        assert!(Watchpoint::parse("x4000", &symbols).is_err());
+    }
+
+    #[test]
+    fn matches_accesses() {
+        let watchpoints = [
+            Watchpoint {
+                access: Access::Read,
+                start: 0x4000,
+                end: 0x4001,
+            },
+            Watchpoint {
+                access: Access::Change,
+                start: 0x4001,
+                end: 0x4001,
+            },
+        ];
+        assert_eq!(
+            matching(&watchpoints, 0x4000, false, 1, 1),
+            Some(Access::Read)
+        );
+        assert_eq!(matching(&watchpoints, 0x4002, false, 1, 1), None);
+        assert_eq!(matching(&watchpoints, 0x4000, true, 1, 2), None);
+        assert_eq!(matching(&watchpoints, 0x4001, true, 1, 1), None);
+        assert_eq!(
+            matching(&watchpoints, 0x4001, true, 1, 2),
+            Some(Access::Change)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+; std/convert.asm: conversions between numbers and decimal text.
+;
+; STD_ITOA       Writes R0 as a signed decimal number, ending with a zero word, to the buffer at
+;                R1, which must have room for 7 words. R0 = R1.
+; STD_ATOI       Reads a signed decimal number from the string at R0: an optional `-` or `+`
+;                followed by digits. R0 = the number (wrapping around past 16 bits) and R1 = the
+;                address of the first character after it.
+; STD_PRINT_DEC  Prints R0 as a signed decimal number.
+;
+; Every register other than the results and R7 keeps its value.
+
+; The digits are found by adding powers of ten to the negative of the number, which works for
+; -32768 as well.
+STD_ITOA           ST    R1, STD_ITOA_R1
+                   ST    R2, STD_ITOA_R2
+                   ST    R3, STD_ITOA_R3
+                   ST    R4, STD_ITOA_R4
+                   ST    R5, STD_ITOA_R5
+                   ADD   R2, R1, #0             ; where the next character goes
+                   ADD   R0, R0, #0
+                   BRn   STD_ITOA_NEG
+                   NOT   R0, R0
+                   ADD   R0, R0, #1
+                   BRnzp STD_ITOA_START
+STD_ITOA_NEG       LD    R4, STD_ITOA_MINUS
+                   STR   R4, R2, #0
+                   ADD   R2, R2, #1
+STD_ITOA_START     LEA   R3, STD_ITOA_POWERS
+                   AND   R4, R4, #0
+                   ST    R4, STD_ITOA_SEEN
+STD_ITOA_POWER     LDR   R4, R3, #0
+                   BRz   STD_ITOA_LAST
+                   ADD   R3, R3, #1
+                   AND   R5, R5, #0             ; the digit
+STD_ITOA_COUNT     ADD   R1, R0, R4
+                   BRp   STD_ITOA_DIGIT
+                   ADD   R0, R1, #0
+                   ADD   R5, R5, #1
+                   BRnzp STD_ITOA_COUNT
+STD_ITOA_DIGIT     ADD   R5, R5, #0
+                   BRp   STD_ITOA_WRITE
+                   LD    R1, STD_ITOA_SEEN      ; leading zeros are left out
+                   BRz   STD_ITOA_POWER
+STD_ITOA_WRITE     LD    R1, STD_ITOA_ZERO
+                   ADD   R1, R1, R5
+                   STR   R1, R2, #0
+                   ADD   R2, R2, #1
+                   ST    R1, STD_ITOA_SEEN
+                   BRnzp STD_ITOA_POWER
+STD_ITOA_LAST      NOT   R0, R0
+                   ADD   R0, R0, #1
+                   LD    R1, STD_ITOA_ZERO
+                   ADD   R1, R1, R0
+                   STR   R1, R2, #0
+                   AND   R1, R1, #0
+                   STR   R1, R2, #1
+                   LD    R1, STD_ITOA_R1
+                   ADD   R0, R1, #0
+                   LD    R2, STD_ITOA_R2
+                   LD    R3, STD_ITOA_R3
+                   LD    R4, STD_ITOA_R4
+                   LD    R5, STD_ITOA_R5
+                   RET
+STD_ITOA_POWERS    .FILL #10000
+                   .FILL #1000
+                   .FILL #100
+                   .FILL #10
+                   .FILL #0
+STD_ITOA_MINUS     .FILL x2D
+STD_ITOA_ZERO      .FILL x30
+STD_ITOA_SEEN      .BLKW #1
+STD_ITOA_R1        .BLKW #1
+STD_ITOA_R2        .BLKW #1
+STD_ITOA_R3        .BLKW #1
+STD_ITOA_R4        .BLKW #1
+STD_ITOA_R5        .BLKW #1
+
+STD_ATOI           ST    R2, STD_ATOI_R2
+                   ST    R3, STD_ATOI_R3
+                   ST    R4, STD_ATOI_R4
+                   ADD   R1, R0, #0             ; the next character
+                   AND   R0, R0, #0             ; the number
+                   AND   R4, R4, #0             ; whether it is negative
+                   LDR   R2, R1, #0
+                   LD    R3, STD_ATOI_MINUS
+                   ADD   R3, R2, R3
+                   BRnp  STD_ATOI_PLUS
+                   ADD   R4, R4, #1
+                   ADD   R1, R1, #1
+                   BRnzp STD_ATOI_DIGIT
+STD_ATOI_PLUS      LD    R3, STD_ATOI_PLUSSIGN
+                   ADD   R3, R2, R3
+                   BRnp  STD_ATOI_DIGIT
+                   ADD   R1, R1, #1
+STD_ATOI_DIGIT     LDR   R2, R1, #0
+                   LD    R3, STD_ATOI_ZERO
+                   ADD   R2, R2, R3
+                   BRn   STD_ATOI_END
+                   ADD   R3, R2, #-10
+                   BRzp  STD_ATOI_END
+                   ADD   R3, R0, R0             ; R0 * 10 + the digit
+                   ADD   R0, R3, R3
+                   ADD   R0, R0, R0
+                   ADD   R0, R0, R3
+                   ADD   R0, R0, R2
+                   ADD   R1, R1, #1
+                   BRnzp STD_ATOI_DIGIT
+STD_ATOI_END       ADD   R4, R4, #0
+                   BRz   STD_ATOI_DONE
+                   NOT   R0, R0
+                   ADD   R0, R0, #1
+STD_ATOI_DONE      LD    R2, STD_ATOI_R2
+                   LD    R3, STD_ATOI_R3
+                   LD    R4, STD_ATOI_R4
+                   RET
+STD_ATOI_MINUS     .FILL #-45
+STD_ATOI_PLUSSIGN  .FILL #-43
+STD_ATOI_ZERO      .FILL #-48
+STD_ATOI_R2        .BLKW #1
+STD_ATOI_R3        .BLKW #1
+STD_ATOI_R4        .BLKW #1
+
+STD_PRINT_DEC      ST    R0, STD_PRINT_DEC_R0
+                   ST    R1, STD_PRINT_DEC_R1
+                   ST    R7, STD_PRINT_DEC_R7
+                   LEA   R1, STD_PRINT_DEC_TEXT
+                   JSR   STD_ITOA
+                   PUTS
+                   LD    R0, STD_PRINT_DEC_R0
+                   LD    R1, STD_PRINT_DEC_R1
+                   LD    R7, STD_PRINT_DEC_R7
+                   RET
+STD_PRINT_DEC_R0   .BLKW #1
+STD_PRINT_DEC_R1   .BLKW #1
+STD_PRINT_DEC_R7   .BLKW #1
+STD_PRINT_DEC_TEXT .BLKW #7
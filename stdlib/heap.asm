@@ -0,0 +1,89 @@
+; std/heap.asm: a first-fit heap allocator.
+;
+; STD_HEAP_INIT  Makes the R1 words starting at R0 the heap. R1 must be at least 3.
+; STD_MALLOC     R0 = the address of a new block of R0 words, or 0 when there is no room (or when
+;                R0 is not positive, or the heap was not set up).
+; STD_FREE       Gives back the block at R0, which STD_MALLOC returned. 0 is ignored.
+;
+; Every block starts with a header word holding its size, with the top bit set while it is in use,
+; and a header of 0 ends the heap. Free blocks next to each other are merged as STD_MALLOC walks
+; past them. Every register other than the results and R7 keeps its value.
+
+STD_HEAP_INIT   ST    R2, STD_HEAP_R2
+                ST    R0, STD_HEAP_BASE
+                ADD   R2, R1, #-2
+                STR   R2, R0, #0        ; a single free block
+                ADD   R2, R0, R1
+                AND   R0, R0, #0
+                STR   R0, R2, #-1       ; the end of the heap
+                LD    R0, STD_HEAP_BASE
+                LD    R2, STD_HEAP_R2
+                RET
+
+STD_MALLOC      ST    R1, STD_HEAP_R1
+                ST    R2, STD_HEAP_R2
+                ST    R3, STD_HEAP_R3
+                ST    R4, STD_HEAP_R4
+                ST    R5, STD_HEAP_R5
+                ADD   R1, R0, #0        ; the size wanted
+                BRnz  STD_MALLOC_FAIL
+                LD    R2, STD_HEAP_BASE ; the header of the block looked at
+                BRz   STD_MALLOC_FAIL
+STD_MALLOC_LOOP LDR   R3, R2, #0
+                BRz   STD_MALLOC_FAIL
+                BRn   STD_MALLOC_NEXT
+STD_MALLOC_JOIN ADD   R4, R2, R3
+                LDR   R5, R4, #1        ; the header of the block after it
+                BRnz  STD_MALLOC_FIT
+                ADD   R3, R3, R5
+                ADD   R3, R3, #1
+                STR   R3, R2, #0
+                BRnzp STD_MALLOC_JOIN
+STD_MALLOC_FIT  NOT   R4, R1
+                ADD   R4, R4, #1
+                ADD   R4, R3, R4        ; the words to spare
+                BRn   STD_MALLOC_NEXT
+                ADD   R5, R4, #-2
+                BRn   STD_MALLOC_TAKE   ; too few to make another block
+                ADD   R5, R2, R1
+                ADD   R4, R4, #-1
+                STR   R4, R5, #1
+                ADD   R3, R1, #0
+STD_MALLOC_TAKE LD    R4, STD_HEAP_USED
+                ADD   R3, R3, R4
+                STR   R3, R2, #0
+                ADD   R0, R2, #1
+                BRnzp STD_MALLOC_DONE
+STD_MALLOC_NEXT LD    R4, STD_HEAP_SIZE
+                AND   R3, R3, R4
+                ADD   R2, R2, R3
+                ADD   R2, R2, #1
+                BRnzp STD_MALLOC_LOOP
+STD_MALLOC_FAIL AND   R0, R0, #0
+STD_MALLOC_DONE LD    R1, STD_HEAP_R1
+                LD    R2, STD_HEAP_R2
+                LD    R3, STD_HEAP_R3
+                LD    R4, STD_HEAP_R4
+                LD    R5, STD_HEAP_R5
+                RET
+
+STD_FREE        ST    R1, STD_HEAP_R1
+                ST    R2, STD_HEAP_R2
+                ADD   R0, R0, #0
+                BRz   STD_FREE_DONE
+                LDR   R1, R0, #-1
+                LD    R2, STD_HEAP_SIZE
+                AND   R1, R1, R2
+                STR   R1, R0, #-1
+STD_FREE_DONE   LD    R1, STD_HEAP_R1
+                LD    R2, STD_HEAP_R2
+                RET
+
+STD_HEAP_BASE   .FILL #0
+STD_HEAP_USED   .FILL x8000
+STD_HEAP_SIZE   .FILL x7FFF
+STD_HEAP_R1     .BLKW #1
+STD_HEAP_R2     .BLKW #1
+STD_HEAP_R3     .BLKW #1
+STD_HEAP_R4     .BLKW #1
+STD_HEAP_R5     .BLKW #1
@@ -0,0 +1,183 @@
+; std/math.asm: multiplication, division and shifts.
+;
+; STD_MUL   R0 = R0 * R1, keeping the low 16 bits of the product.
+; STD_DIV   R0 = R0 / R1 and R1 = R0 % R1, signed and rounding toward zero like C. Dividing by
+;           zero gives a quotient of 0 and leaves the dividend as the remainder.
+; STD_MOD   R0 = R0 % R1, with the sign of the dividend.
+; STD_SHL   R0 = R0 << R1.
+; STD_SHR   R0 = R0 >> R1, shifting in zeros.
+; STD_ASR   R0 = R0 >> R1, shifting in copies of the sign bit.
+;
+; Shifting by 16 or more shifts every bit out, and by 0 or less leaves R0 as it is. Every register
+; other than the results and R7 keeps its value.
+
+STD_MUL       ST    R2, STD_MUL_R2
+              ST    R3, STD_MUL_R3
+              ST    R4, STD_MUL_R4
+              AND   R2, R2, #0        ; the product
+              AND   R3, R3, #0
+              ADD   R3, R3, #1        ; the bit of R1 looked at
+STD_MUL_LOOP  AND   R4, R1, R3
+              BRz   STD_MUL_NEXT
+              ADD   R2, R2, R0
+STD_MUL_NEXT  ADD   R0, R0, R0
+              ADD   R3, R3, R3
+              BRnp  STD_MUL_LOOP
+              ADD   R0, R2, #0
+              LD    R2, STD_MUL_R2
+              LD    R3, STD_MUL_R3
+              LD    R4, STD_MUL_R4
+              RET
+STD_MUL_R2    .BLKW #1
+STD_MUL_R3    .BLKW #1
+STD_MUL_R4    .BLKW #1
+
+; Long division of the magnitudes, one bit at a time: the dividend is shifted into the remainder
+; from the top, and the quotient bits are shifted into the dividend from the bottom.
+STD_DIV       ST    R2, STD_DIV_R2
+              ST    R3, STD_DIV_R3
+              ST    R4, STD_DIV_R4
+              ST    R5, STD_DIV_R5
+              AND   R2, R2, #0
+              ST    R2, STD_DIV_QSIGN
+              ST    R2, STD_DIV_RSIGN
+              ADD   R1, R1, #0
+              BRz   STD_DIV_ZERO
+              BRp   STD_DIV_B
+              NOT   R1, R1
+              ADD   R1, R1, #1
+              NOT   R2, R2
+              ST    R2, STD_DIV_QSIGN
+STD_DIV_B     ADD   R0, R0, #0
+              BRzp  STD_DIV_A
+              NOT   R0, R0
+              ADD   R0, R0, #1
+              LD    R2, STD_DIV_QSIGN
+              NOT   R2, R2
+              ST    R2, STD_DIV_QSIGN
+              AND   R2, R2, #0
+              ADD   R2, R2, #1
+              ST    R2, STD_DIV_RSIGN
+STD_DIV_A     AND   R2, R2, #0        ; the remainder
+              AND   R3, R3, #0
+              ADD   R3, R3, #15
+              ADD   R3, R3, #1        ; one step per bit
+              NOT   R4, R1
+              ADD   R4, R4, #1        ; minus the divisor
+STD_DIV_LOOP  ADD   R2, R2, R2
+              ADD   R0, R0, #0
+              BRzp  STD_DIV_SHIFT
+              ADD   R2, R2, #1
+STD_DIV_SHIFT ADD   R0, R0, R0
+              ; Subtract the divisor when the remainder is at least as big, comparing them as
+              ; unsigned numbers: when only one has the top bit set, that one is bigger.
+              ADD   R2, R2, #0
+              BRn   STD_DIV_RTOP
+              ADD   R1, R1, #0
+              BRn   STD_DIV_NEXT
+              BRnzp STD_DIV_CMP
+STD_DIV_RTOP  ADD   R1, R1, #0
+              BRzp  STD_DIV_SUB
+STD_DIV_CMP   ADD   R5, R2, R4
+              BRn   STD_DIV_NEXT
+STD_DIV_SUB   ADD   R2, R2, R4
+              ADD   R0, R0, #1
+STD_DIV_NEXT  ADD   R3, R3, #-1
+              BRp   STD_DIV_LOOP
+              ADD   R1, R2, #0
+              LD    R2, STD_DIV_QSIGN
+              BRz   STD_DIV_QPOS
+              NOT   R0, R0
+              ADD   R0, R0, #1
+STD_DIV_QPOS  LD    R2, STD_DIV_RSIGN
+              BRz   STD_DIV_DONE
+              NOT   R1, R1
+              ADD   R1, R1, #1
+              BRnzp STD_DIV_DONE
+STD_DIV_ZERO  ADD   R1, R0, #0
+              AND   R0, R0, #0
+STD_DIV_DONE  LD    R2, STD_DIV_R2
+              LD    R3, STD_DIV_R3
+              LD    R4, STD_DIV_R4
+              LD    R5, STD_DIV_R5
+              RET
+STD_DIV_R2    .BLKW #1
+STD_DIV_R3    .BLKW #1
+STD_DIV_R4    .BLKW #1
+STD_DIV_R5    .BLKW #1
+STD_DIV_QSIGN .BLKW #1
+STD_DIV_RSIGN .BLKW #1
+
+STD_MOD       ST    R1, STD_MOD_R1
+              ST    R7, STD_MOD_R7
+              JSR   STD_DIV
+              ADD   R0, R1, #0
+              LD    R1, STD_MOD_R1
+              LD    R7, STD_MOD_R7
+              RET
+STD_MOD_R1    .BLKW #1
+STD_MOD_R7    .BLKW #1
+
+STD_SHL       ST    R1, STD_SHL_R1
+              ADD   R1, R1, #0
+              BRnz  STD_SHL_DONE
+STD_SHL_LOOP  ADD   R0, R0, R0
+              ADD   R1, R1, #-1
+              BRp   STD_SHL_LOOP
+STD_SHL_DONE  LD    R1, STD_SHL_R1
+              RET
+STD_SHL_R1    .BLKW #1
+
+; Copies every bit from position R1 up to the position R1 lower in the result.
+STD_SHR       ST    R1, STD_SHR_R1
+              ST    R2, STD_SHR_R2
+              ST    R3, STD_SHR_R3
+              ST    R4, STD_SHR_R4
+              AND   R2, R2, #0        ; the result
+              ADD   R1, R1, #0
+              BRnz  STD_SHR_END
+              AND   R3, R3, #0
+              ADD   R3, R3, #1        ; the bit of R0 copied
+STD_SHR_SKIP  ADD   R3, R3, R3
+              BRz   STD_SHR_DONE
+              ADD   R1, R1, #-1
+              BRp   STD_SHR_SKIP
+              ADD   R1, R1, #1        ; the bit of the result it is copied to
+STD_SHR_BIT   AND   R4, R0, R3
+              BRz   STD_SHR_NEXT
+              ADD   R2, R2, R1
+STD_SHR_NEXT  ADD   R1, R1, R1
+              ADD   R3, R3, R3
+              BRnp  STD_SHR_BIT
+STD_SHR_DONE  ADD   R0, R2, #0
+STD_SHR_END   LD    R1, STD_SHR_R1
+              LD    R2, STD_SHR_R2
+              LD    R3, STD_SHR_R3
+              LD    R4, STD_SHR_R4
+              RET
+STD_SHR_R1    .BLKW #1
+STD_SHR_R2    .BLKW #1
+STD_SHR_R3    .BLKW #1
+STD_SHR_R4    .BLKW #1
+
+STD_ASR       ST    R2, STD_ASR_R2
+              ST    R7, STD_ASR_R7
+              ADD   R2, R0, #0        ; keep the sign
+              JSR   STD_SHR
+              ADD   R2, R2, #0
+              BRzp  STD_ASR_DONE
+              ADD   R1, R1, #0
+              BRnz  STD_ASR_DONE
+              ; Set the top R1 bits: R0 | ~(xFFFF >> R1).
+              ADD   R2, R0, #0
+              AND   R0, R0, #0
+              ADD   R0, R0, #-1
+              JSR   STD_SHR
+              NOT   R2, R2
+              AND   R0, R0, R2
+              NOT   R0, R0
+STD_ASR_DONE  LD    R2, STD_ASR_R2
+              LD    R7, STD_ASR_R7
+              RET
+STD_ASR_R2    .BLKW #1
+STD_ASR_R7    .BLKW #1
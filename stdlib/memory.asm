@@ -0,0 +1,57 @@
+; std/memory.asm: blocks of memory.
+;
+; STD_MEMSET  Sets the R2 words starting at R0 to R1.
+; STD_MEMCPY  Copies the R2 words starting at R1 to R0. The blocks may overlap.
+;
+; Nothing happens when R2 is 0 or negative. Every register other than R7 keeps its value.
+
+STD_MEMSET      ST    R0, STD_MEMSET_R0
+                ST    R2, STD_MEMSET_R2
+                ADD   R2, R2, #0
+                BRnz  STD_MEMSET_DONE
+STD_MEMSET_LOOP STR   R1, R0, #0
+                ADD   R0, R0, #1
+                ADD   R2, R2, #-1
+                BRp   STD_MEMSET_LOOP
+STD_MEMSET_DONE LD    R0, STD_MEMSET_R0
+                LD    R2, STD_MEMSET_R2
+                RET
+STD_MEMSET_R0   .BLKW #1
+STD_MEMSET_R2   .BLKW #1
+
+; Copies from the end when the destination is after the source, so an overlapping source is read
+; before it is overwritten.
+STD_MEMCPY      ST    R0, STD_MEMCPY_R0
+                ST    R1, STD_MEMCPY_R1
+                ST    R2, STD_MEMCPY_R2
+                ST    R3, STD_MEMCPY_R3
+                ADD   R2, R2, #0
+                BRnz  STD_MEMCPY_DONE
+                NOT   R3, R1
+                ADD   R3, R3, #1
+                ADD   R3, R0, R3
+                BRp   STD_MEMCPY_BACK
+STD_MEMCPY_FWD  LDR   R3, R1, #0
+                STR   R3, R0, #0
+                ADD   R0, R0, #1
+                ADD   R1, R1, #1
+                ADD   R2, R2, #-1
+                BRp   STD_MEMCPY_FWD
+                BRnzp STD_MEMCPY_DONE
+STD_MEMCPY_BACK ADD   R0, R0, R2
+                ADD   R1, R1, R2
+STD_MEMCPY_PREV ADD   R0, R0, #-1
+                ADD   R1, R1, #-1
+                LDR   R3, R1, #0
+                STR   R3, R0, #0
+                ADD   R2, R2, #-1
+                BRp   STD_MEMCPY_PREV
+STD_MEMCPY_DONE LD    R0, STD_MEMCPY_R0
+                LD    R1, STD_MEMCPY_R1
+                LD    R2, STD_MEMCPY_R2
+                LD    R3, STD_MEMCPY_R3
+                RET
+STD_MEMCPY_R0   .BLKW #1
+STD_MEMCPY_R1   .BLKW #1
+STD_MEMCPY_R2   .BLKW #1
+STD_MEMCPY_R3   .BLKW #1
@@ -0,0 +1,64 @@
+; std/string.asm: zero-terminated strings, one character per word.
+;
+; STD_STRLEN  R0 = the number of characters of the string at R0.
+; STD_STRCMP  Compares the strings at R0 and R1. R0 = 0 when they are equal, or else the first
+;             character of R0 that differs minus the one of R1 (negative when R0 sorts first).
+; STD_STRCPY  Copies the string at R1, with its ending zero, to R0. R0 keeps its value.
+;
+; Every register other than the results and R7 keeps its value.
+
+STD_STRLEN      ST    R1, STD_STRLEN_R1
+                ST    R2, STD_STRLEN_R2
+                ADD   R1, R0, #0
+                AND   R0, R0, #0
+STD_STRLEN_LOOP LDR   R2, R1, #0
+                BRz   STD_STRLEN_DONE
+                ADD   R0, R0, #1
+                ADD   R1, R1, #1
+                BRnzp STD_STRLEN_LOOP
+STD_STRLEN_DONE LD    R1, STD_STRLEN_R1
+                LD    R2, STD_STRLEN_R2
+                RET
+STD_STRLEN_R1   .BLKW #1
+STD_STRLEN_R2   .BLKW #1
+
+STD_STRCMP      ST    R1, STD_STRCMP_R1
+                ST    R2, STD_STRCMP_R2
+                ST    R3, STD_STRCMP_R3
+STD_STRCMP_LOOP LDR   R2, R0, #0
+                LDR   R3, R1, #0
+                NOT   R3, R3
+                ADD   R3, R3, #1
+                ADD   R3, R2, R3
+                BRnp  STD_STRCMP_DONE
+                ADD   R2, R2, #0        ; both strings ended
+                BRz   STD_STRCMP_DONE
+                ADD   R0, R0, #1
+                ADD   R1, R1, #1
+                BRnzp STD_STRCMP_LOOP
+STD_STRCMP_DONE ADD   R0, R3, #0
+                LD    R1, STD_STRCMP_R1
+                LD    R2, STD_STRCMP_R2
+                LD    R3, STD_STRCMP_R3
+                RET
+STD_STRCMP_R1   .BLKW #1
+STD_STRCMP_R2   .BLKW #1
+STD_STRCMP_R3   .BLKW #1
+
+STD_STRCPY      ST    R1, STD_STRCPY_R1
+                ST    R2, STD_STRCPY_R2
+                ST    R3, STD_STRCPY_R3
+                ADD   R3, R0, #0
+STD_STRCPY_LOOP LDR   R2, R1, #0
+                STR   R2, R3, #0
+                ADD   R1, R1, #1
+                ADD   R3, R3, #1
+                ADD   R2, R2, #0
+                BRnp  STD_STRCPY_LOOP
+                LD    R1, STD_STRCPY_R1
+                LD    R2, STD_STRCPY_R2
+                LD    R3, STD_STRCPY_R3
+                RET
+STD_STRCPY_R1   .BLKW #1
+STD_STRCPY_R2   .BLKW #1
+STD_STRCPY_R3   .BLKW #1
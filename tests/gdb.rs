@@ -0,0 +1,125 @@
+//! Drives `run --gdb` with a minimal GDB remote protocol client over a local socket.
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+};
+
+const PROGRAM: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #5
+LOOP    ADD R0, R0, #-1
+        BRp LOOP
+        ST R0, RESULT
+        LEA R0, TEXT
+        PUTS
+        HALT
+RESULT  .FILL #7
+TEXT    .STRINGZ \"ok\"
+        .END
+";
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    /// Sends a packet and returns the data of the reply, acknowledging both ways.
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:02x}").unwrap();
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+', "the stub did not acknowledge `{data}`");
+        self.stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'$');
+        let mut reply = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        let expected = reply.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{expected:02x}")
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+#[test]
+fn debugs_a_program_over_the_remote_protocol() {
+    let path = std::env::temp_dir().join(format!("gdb-stub-{}.asm", std::process::id()));
+    fs::write(&path, PROGRAM).unwrap();
+    let mut vm = Command::new(env!("CARGO_BIN_EXE_basic-vm"))
+        .args(["run", path.to_str().unwrap(), "--gdb", "0"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(vm.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let address = line
+        .trim()
+        .strip_prefix("waiting for a GDB client on ")
+        .unwrap_or_else(|| panic!("unexpected output: {line}"));
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut client = Client { stream };
+
+    assert_eq!(
+        client.request("qSupported:swbreak+"),
+        "PacketSize=4000;ReverseStep+;ReverseContinue+"
+    );
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(
+        client.request("g"),
+        "0000000000000000000000000000000030000002"
+    );
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+
+    // Run the loop to the end, then change R0 before it is stored.
+    assert_eq!(client.request("Z0,3004,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p8"), "3004");
+    assert_eq!(client.request("p0"), "0000");
+    assert_eq!(client.request("P0=0009"), "OK");
+    assert_eq!(client.request("z0,3004,1"), "OK");
+
+    assert_eq!(client.request("Z2,3008,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:3008;");
+    assert_eq!(client.request("m3008,3"), "0009006f006b");
+    assert_eq!(client.request("z2,3008,1"), "OK");
+
+    // Write "no" over the text before it is printed.
+    assert_eq!(client.request("M3009,2:006e006f"), "OK");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p8"), "3006");
+    assert_eq!(client.request("c"), "W00");
+
+    // Back from the end to HALT, then to the start, where R0 is 0 again.
+    assert_eq!(client.request("bs"), "S05");
+    assert_eq!(client.request("p8"), "3007");
+    assert_eq!(client.request("bc"), "T05replaylog:begin;");
+    assert_eq!(client.request("p8"), "3000");
+    assert_eq!(client.request("p0"), "0000");
+    // Running again replays the history, without printing again.
+    assert_eq!(client.request("c"), "W00");
+    assert_eq!(client.request("k"), "OK");
+
+    let output = vm.wait_with_output().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("no"));
+}